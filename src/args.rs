@@ -0,0 +1,56 @@
+use std::collections::BTreeMap;
+use std::fmt::{self, Debug};
+use std::sync::Arc;
+
+/// What an `ArgAssigner` gets to know about a host.
+pub struct HostInfo<'a> {
+    /// Host as given.
+    pub hostname: &'a str,
+    /// Inventory vars of the host.
+    pub vars: &'a BTreeMap<String, String>,
+}
+
+pub type AssignFn = Arc<dyn Fn(usize, &HostInfo) -> Vec<String> + Send + Sync>;
+
+/// Generates per-host arguments appended to the command of a run, each quoted as one
+/// word, e.g. to give every host its shard number.
+///
+/// The index passed along is the position of the host in the input, from 0.
+#[derive(Clone)]
+pub enum ArgAssigner {
+    /// Hands out the values in turn, one per host, starting over when they run out.
+    RoundRobin(Vec<String>),
+    /// The value of this inventory var, nothing for hosts without it.
+    FromMetadataKey(String),
+    Custom(AssignFn),
+}
+
+impl ArgAssigner {
+    pub fn custom<F>(f: F) -> Self
+    where
+        F: Fn(usize, &HostInfo) -> Vec<String> + Send + Sync + 'static,
+    {
+        ArgAssigner::Custom(Arc::new(f))
+    }
+
+    pub fn assign(&self, index: usize, host: &HostInfo) -> Vec<String> {
+        match self {
+            ArgAssigner::RoundRobin(values) if values.is_empty() => Vec::new(),
+            ArgAssigner::RoundRobin(values) => vec![values[index % values.len()].clone()],
+            ArgAssigner::FromMetadataKey(key) => host.vars.get(key).cloned().into_iter().collect(),
+            ArgAssigner::Custom(f) => f(index, host),
+        }
+    }
+}
+
+impl Debug for ArgAssigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ArgAssigner::RoundRobin(values) => f.debug_tuple("RoundRobin").field(values).finish(),
+            ArgAssigner::FromMetadataKey(key) => {
+                f.debug_tuple("FromMetadataKey").field(key).finish()
+            }
+            ArgAssigner::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
@@ -0,0 +1,226 @@
+use crate::progress::{HostProgress, Permit, ProgressTracker};
+use crate::redact::REDACTED;
+use crate::response::{ErrorKind, HostError};
+use crate::semaphore::Semaphore;
+use crate::transport::{Transport, TransportError, TransportErrorKind};
+use serde::{Deserialize, Serialize};
+use std::env;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+/// One way of authenticating, tried in order as part of an auth chain.
+///
+/// Debug output shows `REDACTED` in place of passwords and passphrases.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(tag = "method", rename_all = "snake_case", deny_unknown_fields)]
+pub enum AuthMethod {
+    /// First identity of the running ssh-agent.
+    Agent,
+    /// Private key file, optionally encrypted.
+    KeyFile {
+        path: PathBuf,
+        passphrase: Option<String>,
+    },
+    Password {
+        password: String,
+    },
+}
+
+impl AuthMethod {
+    /// Short name recorded in `ConnectionInfo::auth_method`.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuthMethod::Agent => "agent",
+            AuthMethod::KeyFile { .. } => "key_file",
+            AuthMethod::Password { .. } => "password",
+        }
+    }
+
+    /// Whether the server advertised a scheme this method can use.
+    fn offered_by(&self, server_methods: &str) -> bool {
+        let offered = |scheme| server_methods.split(',').any(|m| m == scheme);
+        match self {
+            AuthMethod::Agent | AuthMethod::KeyFile { .. } => offered("publickey"),
+            AuthMethod::Password { .. } => offered("password"),
+        }
+    }
+}
+
+impl fmt::Debug for AuthMethod {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AuthMethod::Agent => f.write_str("Agent"),
+            AuthMethod::KeyFile { path, passphrase } => f
+                .debug_struct("KeyFile")
+                .field("path", path)
+                .field("passphrase", &passphrase.as_ref().map(|_| REDACTED))
+                .finish(),
+            AuthMethod::Password { .. } => f
+                .debug_struct("Password")
+                .field("password", &REDACTED)
+                .finish(),
+        }
+    }
+}
+
+/// Parses the inventory form: `agent`, `key_file:<path>` or `password:<password>`.
+impl FromStr for AuthMethod {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.splitn(2, ':');
+        match (parts.next(), parts.next()) {
+            (Some("agent"), None) => Ok(AuthMethod::Agent),
+            (Some("key_file"), Some(path)) => Ok(AuthMethod::KeyFile {
+                path: PathBuf::from(path),
+                passphrase: None,
+            }),
+            (Some("password"), Some(password)) => Ok(AuthMethod::Password {
+                password: password.to_string(),
+            }),
+            _ => Err(format!("Unknown auth method: {}", s)),
+        }
+    }
+}
+
+/// Parses a comma separated auth chain, e.g. `agent,key_file:/root/.ssh/fallback`.
+pub fn parse_auth_chain(s: &str) -> Result<Vec<AuthMethod>, String> {
+    s.split(',').map(|m| m.trim().parse()).collect()
+}
+
+/// What keeps the ssh-agent from being reached, as far as the environment tells, and what
+/// to do instead.
+///
+/// libssh2 reaches agents through `SSH_AUTH_SOCK` on Unix and only Pageant on Windows;
+/// the OpenSSH for Windows agent on `\\.\pipe\openssh-ssh-agent` is out of its reach.
+pub fn agent_unreachable_hint() -> String {
+    let fallback = "or put a key_file method in the auth chain, e.g. \
+                    auth_chain = [{ method = \"key_file\", path = \"<private key>\" }]";
+    if cfg!(windows) {
+        return format!(
+            "no Pageant running; the OpenSSH for Windows agent (named pipe) is not supported, \
+             start Pageant with the key loaded {}",
+            fallback
+        );
+    }
+    match env::var_os("SSH_AUTH_SOCK") {
+        None => format!("SSH_AUTH_SOCK is not set; start ssh-agent {}", fallback),
+        Some(sock) if !Path::new(&sock).exists() => format!(
+            "SSH_AUTH_SOCK points to {}, which does not exist; restart ssh-agent {}",
+            Path::new(&sock).display(),
+            fallback
+        ),
+        Some(_) => format!("the agent did not answer; check it is running {}", fallback),
+    }
+}
+
+fn error_kind(method: &AuthMethod, e: &TransportError) -> ErrorKind {
+    match e.kind {
+        TransportErrorKind::Timeout => ErrorKind::AuthTimeout,
+        TransportErrorKind::Rejected => ErrorKind::Auth,
+        _ if *method == AuthMethod::Agent => ErrorKind::Agent,
+        _ => ErrorKind::Auth,
+    }
+}
+
+/// Method of the chain which authenticated, see `authenticate`.
+pub(crate) struct Authenticated {
+    pub(crate) method: &'static str,
+    /// Round trip to the ssh-agent, when the agent was tried.
+    pub(crate) agent_latency: Option<Duration>,
+}
+
+/// Tries `chain` in order until one method authenticates `user`.
+///
+/// Methods the server does not offer for `user` are skipped without an attempt, and the
+/// chain stops at the first error which leaves the session unusable, as trying further
+/// methods would only add failed attempts on the server. A permit of
+/// `agent_permits` is held only while the agent is being used, and waiting for it is tracked in `progress`.
+/// The agent round trip, connecting to the agent included but not the wait for the lock,
+/// is added to the agent latency of `tracker`, failed ones too.
+pub(crate) fn authenticate(
+    sess: &dyn Transport,
+    user: &str,
+    chain: &[AuthMethod],
+    agent_permits: &Semaphore,
+    tracker: &ProgressTracker,
+    progress: Option<&HostProgress>,
+) -> Result<Authenticated, HostError> {
+    let server_methods = sess.auth_methods(user).map_err(|e| {
+        HostError::new(
+            if e.kind == TransportErrorKind::Timeout {
+                ErrorKind::AuthTimeout
+            } else {
+                ErrorKind::Auth
+            },
+            format!("Failed listing auth methods: {}", e),
+        )
+    })?;
+    if sess.authenticated() {
+        return Ok(Authenticated {
+            method: "none",
+            agent_latency: None,
+        });
+    }
+
+    let mut agent_latency = None;
+    let mut failures = Vec::new();
+    let mut last_kind = ErrorKind::Auth;
+    for method in chain {
+        if !method.offered_by(&server_methods) {
+            continue;
+        }
+        let result = match method {
+            AuthMethod::Agent => {
+                let _guard = match progress {
+                    Some(progress) => progress.wait_for(Permit::Agent, || agent_permits.access()),
+                    None => agent_permits.access(),
+                };
+                let start = Instant::now();
+                let result = sess.auth_agent(user);
+                let latency = start.elapsed();
+                tracker.agent_round_trip(latency);
+                agent_latency = Some(latency);
+                result
+            }
+            AuthMethod::KeyFile { path, passphrase } => {
+                sess.auth_key_file(user, path, passphrase.as_deref())
+            }
+            AuthMethod::Password { password } => sess.auth_password(user, password),
+        };
+        match result {
+            Ok(()) => {
+                return Ok(Authenticated {
+                    method: method.name(),
+                    agent_latency,
+                })
+            }
+            Err(e) => {
+                last_kind = error_kind(method, &e);
+                if *method == AuthMethod::Agent && e.kind == TransportErrorKind::AgentUnreachable {
+                    failures.push(format!("agent: unreachable, {}", agent_unreachable_hint()));
+                } else {
+                    failures.push(format!("{}: {}", method.name(), e));
+                }
+                if e.is_fatal() {
+                    break;
+                }
+            }
+        }
+    }
+    if failures.is_empty() {
+        return Err(HostError::new(
+            ErrorKind::Auth,
+            format!(
+                "No auth method of the chain is offered by the server (offered: {})",
+                server_methods
+            ),
+        ));
+    }
+    Err(HostError::new(
+        last_kind,
+        format!("Authentication failed: {}", failures.join("; ")),
+    ))
+}
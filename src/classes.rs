@@ -0,0 +1,45 @@
+use std::collections::{BTreeMap, VecDeque};
+
+/// Reorders `items` so their classes take turns: each round, every class still holding
+/// items gives up to its weight of them, in their input order. Classes go in the order
+/// they first appear; those missing from `weights`, and items without a class, weigh 1.
+///
+/// With `weights` of `{fast: 3}`, the input `slow1 slow2 slow3 fast1 fast2 fast3 fast4`
+/// runs as `slow1 fast1 fast2 fast3 slow2 fast4 slow3`.
+pub(crate) fn interleave<T, F>(items: Vec<T>, weights: &BTreeMap<String, u32>, class: F) -> Vec<T>
+where
+    F: Fn(&T) -> Option<&str>,
+{
+    let len = items.len();
+    let mut queues: Vec<(u32, VecDeque<T>)> = Vec::new();
+    let mut index: BTreeMap<Option<String>, usize> = BTreeMap::new();
+    for item in items {
+        let key = class(&item).map(str::to_string);
+        let i = match index.get(&key) {
+            Some(i) => *i,
+            None => {
+                let weight = key
+                    .as_ref()
+                    .and_then(|k| weights.get(k))
+                    .copied()
+                    .unwrap_or(1);
+                queues.push((weight, VecDeque::new()));
+                index.insert(key, queues.len() - 1);
+                queues.len() - 1
+            }
+        };
+        queues[i].1.push_back(item);
+    }
+    let mut ordered = Vec::with_capacity(len);
+    while ordered.len() < len {
+        for (weight, queue) in &mut queues {
+            for _ in 0..*weight {
+                match queue.pop_front() {
+                    Some(item) => ordered.push(item),
+                    None => break,
+                }
+            }
+        }
+    }
+    ordered
+}
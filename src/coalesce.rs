@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// When records buffered by a `Coalescer` are written out: on whichever comes first of
+/// `max_records` records, `max_bytes` bytes, or the oldest of them having waited
+/// `max_delay_ms`. Whatever is left is written at the end of the run, cancelled runs
+/// included.
+///
+/// A writer killed mid-run loses the records of its current batch: fewer than
+/// `max_records`, and only those of the last `max_delay_ms`. Records are written out
+/// whole, so the file never ends in the middle of one. A low `max_delay_ms` writes
+/// nearly every record as it comes, for following the file with `tail -f`.
+///
+/// ```toml
+/// [output.flush]
+/// max_records = 100
+/// max_bytes = 1_048_576
+/// max_delay_ms = 1000
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FlushPolicy {
+    pub max_records: usize,
+    pub max_bytes: usize,
+    pub max_delay_ms: u64,
+}
+
+impl Default for FlushPolicy {
+    fn default() -> Self {
+        FlushPolicy {
+            max_records: 100,
+            max_bytes: 1 << 20,
+            max_delay_ms: 1000,
+        }
+    }
+}
+
+impl FlushPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_records == 0 {
+            return Err("output.flush.max_records must be at least 1".to_string());
+        }
+        if self.max_bytes == 0 {
+            return Err("output.flush.max_bytes must be at least 1".to_string());
+        }
+        Ok(())
+    }
+}
+
+/// What a `Coalescer` wrote out so far.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct FlushStats {
+    pub flushes: u64,
+    pub records: u64,
+    pub bytes: u64,
+}
+
+impl FlushStats {
+    /// Records per flush, 0 before the first one.
+    pub fn average_batch(&self) -> f64 {
+        if self.flushes == 0 {
+            0.0
+        } else {
+            self.records as f64 / self.flushes as f64
+        }
+    }
+}
+
+/// Buffers serialized records until its `FlushPolicy` says to write them out.
+///
+/// The coalescer only keeps the records and the clock; the caller writes out what `take`
+/// returns whenever `due` says so, and once more at the end.
+pub struct Coalescer {
+    policy: FlushPolicy,
+    records: Vec<Vec<u8>>,
+    bytes: usize,
+    /// Arrival of the oldest buffered record.
+    oldest: Option<Instant>,
+    stats: FlushStats,
+}
+
+impl Coalescer {
+    pub fn new(policy: FlushPolicy) -> Self {
+        Coalescer {
+            policy,
+            records: Vec::new(),
+            bytes: 0,
+            oldest: None,
+            stats: FlushStats::default(),
+        }
+    }
+
+    pub fn push(&mut self, record: Vec<u8>) {
+        self.oldest.get_or_insert_with(Instant::now);
+        self.bytes += record.len();
+        self.records.push(record);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    /// Whether the buffered records are to be written out now.
+    pub fn due(&self) -> bool {
+        self.records.len() >= self.policy.max_records
+            || self.bytes >= self.policy.max_bytes
+            || self.time_left() == Some(Duration::from_secs(0))
+    }
+
+    /// Time until the oldest buffered record has waited its limit, `None` when there is
+    /// none.
+    pub fn time_left(&self) -> Option<Duration> {
+        let limit = Duration::from_millis(self.policy.max_delay_ms);
+        self.oldest
+            .map(|oldest| limit.checked_sub(oldest.elapsed()).unwrap_or_default())
+    }
+
+    /// Takes the buffered records, in arrival order, counting them as one flush.
+    pub fn take(&mut self) -> Vec<Vec<u8>> {
+        if !self.records.is_empty() {
+            self.stats.flushes += 1;
+            self.stats.records += self.records.len() as u64;
+            self.stats.bytes += self.bytes as u64;
+        }
+        self.bytes = 0;
+        self.oldest = None;
+        std::mem::take(&mut self.records)
+    }
+
+    pub fn stats(&self) -> FlushStats {
+        self.stats
+    }
+}
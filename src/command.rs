@@ -0,0 +1,91 @@
+use crate::shell::shell_quote;
+use std::fmt::{self, Display};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    /// Rendered as a single shell word, whatever it contains.
+    Word(String),
+    /// Inserted untouched, so the remote shell interprets it.
+    Raw(String),
+}
+
+/// Command line for a remote POSIX shell, built from separately quoted words.
+///
+/// `RemoteCommand::new("systemctl").arg("restart").arg(unit)` renders `unit` as one word
+/// however many quotes, `$()` or newlines it contains.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RemoteCommand {
+    parts: Vec<Part>,
+}
+
+impl RemoteCommand {
+    pub fn new<S: Into<String>>(program: S) -> Self {
+        RemoteCommand {
+            parts: vec![Part::Word(program.into())],
+        }
+    }
+
+    /// Command string handed to the remote shell verbatim, as before `RemoteCommand`.
+    pub fn raw<S: Into<String>>(command: S) -> Self {
+        RemoteCommand {
+            parts: vec![Part::Raw(command.into())],
+        }
+    }
+
+    /// Appends one argument, quoted so the remote shell sees it as exactly one word.
+    pub fn arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.parts.push(Part::Word(arg.into()));
+        self
+    }
+
+    pub fn args<I, S>(mut self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.parts
+            .extend(args.into_iter().map(|a| Part::Word(a.into())));
+        self
+    }
+
+    /// Appends shell syntax (pipes, redirections, ...) without quoting it.
+    pub fn raw_arg<S: Into<String>>(mut self, arg: S) -> Self {
+        self.parts.push(Part::Raw(arg.into()));
+        self
+    }
+}
+
+/// Words made only of these characters mean the same to the shell quoted or not.
+pub(crate) fn needs_quoting(word: &str) -> bool {
+    word.is_empty()
+        || !word
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "-_./=:@,+%".contains(c))
+}
+
+impl Display for RemoteCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, part) in self.parts.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            match part {
+                Part::Word(w) if needs_quoting(w) => f.write_str(&shell_quote(w))?,
+                Part::Word(w) | Part::Raw(w) => f.write_str(w)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl From<String> for RemoteCommand {
+    fn from(s: String) -> Self {
+        RemoteCommand::raw(s)
+    }
+}
+
+impl From<&str> for RemoteCommand {
+    fn from(s: &str) -> Self {
+        RemoteCommand::raw(s)
+    }
+}
@@ -0,0 +1,300 @@
+use crate::response::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Lines of unchanged output shown around each change of a diff.
+const DIFF_CONTEXT: usize = 3;
+/// Largest line count product diffed line by line; above it the whole output is shown as
+/// replaced.
+const MAX_DIFF_CELLS: usize = 4_000_000;
+
+/// What is compared of a host's result. Volatile fields such as times and attempt counts
+/// are left out, so only the output and the exit code tell whether a host changed. When
+/// both runs hashed the output with the same algorithm, the hashes are compared instead,
+/// as the kept output may be truncated.
+#[derive(Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct ComparedResult {
+    pub hostname: String,
+    #[serde(rename = "result")]
+    pub output: String,
+    #[serde(default)]
+    pub exit_code: Option<i32>,
+    #[serde(default)]
+    pub output_hash: Option<String>,
+    /// Whether the host succeeded.
+    pub status: bool,
+}
+
+impl From<&Response> for ComparedResult {
+    fn from(response: &Response) -> Self {
+        ComparedResult {
+            hostname: response.hostname.clone(),
+            output: response.result.clone(),
+            exit_code: response.exit_code,
+            output_hash: response.output_hash.clone(),
+            status: response.status,
+        }
+    }
+}
+
+impl ComparedResult {
+    fn same_output(&self, other: &ComparedResult) -> bool {
+        let algorithm = |hash: &str| hash.split(':').next().unwrap_or("").to_string();
+        match (&self.output_hash, &other.output_hash) {
+            (Some(a), Some(b)) if algorithm(a) == algorithm(b) => a == b,
+            _ => self.output == other.output,
+        }
+    }
+}
+
+/// Loads the results of a previous run, saved either as a JSON array or as NDJSON like the
+/// incremental output.
+pub fn load_results(path: &Path) -> Result<Vec<ComparedResult>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    let parsed = if content.trim_start().starts_with('[') {
+        serde_json::from_str(&content).map_err(|e| e.to_string())
+    } else {
+        content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty())
+            .map(|(i, line)| {
+                serde_json::from_str(line).map_err(|e| format!("line {}: {}", i + 1, e))
+            })
+            .collect()
+    };
+    parsed.map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Host whose output or exit code differs from the previous run.
+#[derive(Serialize, Debug, Clone)]
+pub struct ChangedHost {
+    pub hostname: String,
+    pub previous_exit_code: Option<i32>,
+    pub exit_code: Option<i32>,
+    /// Unified diff of the previous output against the current one.
+    pub diff: String,
+}
+
+/// Differences between the results of a run and a previous one, matched by host name.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct DiffReport {
+    pub previous: PathBuf,
+    pub changed: Vec<ChangedHost>,
+    /// Succeeded before, failed now.
+    pub newly_failing: Vec<String>,
+    /// Failed before, succeeded now.
+    pub newly_succeeding: Vec<String>,
+    pub only_previous: Vec<String>,
+    pub only_current: Vec<String>,
+    pub unchanged: usize,
+}
+
+impl DiffReport {
+    pub fn new(previous_path: &Path, previous: &[ComparedResult], current: &[Response]) -> Self {
+        let mut report = DiffReport {
+            previous: previous_path.to_path_buf(),
+            ..DiffReport::default()
+        };
+        let mut previous: BTreeMap<&str, &ComparedResult> = previous
+            .iter()
+            .map(|result| (result.hostname.as_str(), result))
+            .collect();
+        for current in current.iter().map(ComparedResult::from) {
+            let before = match previous.remove(current.hostname.as_str()) {
+                Some(before) => before,
+                None => {
+                    report.only_current.push(current.hostname);
+                    continue;
+                }
+            };
+            match (before.status, current.status) {
+                (true, false) => report.newly_failing.push(current.hostname.clone()),
+                (false, true) => report.newly_succeeding.push(current.hostname.clone()),
+                _ => {}
+            }
+            if before.same_output(&current) && before.exit_code == current.exit_code {
+                report.unchanged += 1;
+            } else {
+                report.changed.push(ChangedHost {
+                    diff: unified_diff(&before.output, &current.output),
+                    previous_exit_code: before.exit_code,
+                    exit_code: current.exit_code,
+                    hostname: current.hostname,
+                });
+            }
+        }
+        report.only_previous = previous.keys().map(|host| host.to_string()).collect();
+        report.changed.sort_by(|a, b| a.hostname.cmp(&b.hostname));
+        report.newly_failing.sort();
+        report.newly_succeeding.sort();
+        report.only_current.sort();
+        report
+    }
+
+    /// Whether `hostname` changed in any way: output, exit code, outcome, or being new.
+    pub fn is_changed(&self, hostname: &str) -> bool {
+        self.changed.iter().any(|c| c.hostname == hostname)
+            || self.newly_failing.iter().any(|h| h == hostname)
+            || self.newly_succeeding.iter().any(|h| h == hostname)
+            || self.only_current.iter().any(|h| h == hostname)
+    }
+}
+
+/// Summary for the console, one section per kind of difference.
+impl Display for DiffReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Compared to {}: {} changed, {} unchanged, {} newly failing, {} newly succeeding, \
+             {} only before, {} only now",
+            self.previous.display(),
+            self.changed.len(),
+            self.unchanged,
+            self.newly_failing.len(),
+            self.newly_succeeding.len(),
+            self.only_previous.len(),
+            self.only_current.len()
+        )?;
+        let sections = [
+            ("Newly failing", &self.newly_failing),
+            ("Newly succeeding", &self.newly_succeeding),
+            ("Only before", &self.only_previous),
+            ("Only now", &self.only_current),
+        ];
+        for (title, hosts) in sections.iter() {
+            if !hosts.is_empty() {
+                writeln!(f, "{}: {}", title, hosts.join(", "))?;
+            }
+        }
+        for changed in &self.changed {
+            write!(
+                f,
+                "--- {} (before)\n+++ {} (now)\n",
+                changed.hostname, changed.hostname
+            )?;
+            if changed.previous_exit_code != changed.exit_code {
+                writeln!(
+                    f,
+                    "exit code {:?} -> {:?}",
+                    changed.previous_exit_code, changed.exit_code
+                )?;
+            }
+            f.write_str(&changed.diff)?;
+        }
+        Ok(())
+    }
+}
+
+/// Hunks of a line diff of `old` against `new`, without file headers.
+pub fn unified_diff(old: &str, new: &str) -> String {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let ops = diff_lines(&old, &new);
+    let mut out = String::new();
+    let mut i = 0;
+    while i < ops.len() {
+        if let Op::Same(..) = ops[i] {
+            i += 1;
+            continue;
+        }
+        // A hunk runs from context before the first change to context after the last
+        // change which is not separated from the next one by more than twice the context.
+        let start = i.saturating_sub(DIFF_CONTEXT);
+        let mut end = i;
+        let mut same_run = 0;
+        while end < ops.len() && same_run <= 2 * DIFF_CONTEXT {
+            match ops[end] {
+                Op::Same(..) => same_run += 1,
+                _ => same_run = 0,
+            }
+            end += 1;
+        }
+        let end = end - same_run.saturating_sub(DIFF_CONTEXT);
+        let hunk = &ops[start..end];
+        let (old_start, new_start) = hunk[0].position();
+        let old_len = hunk
+            .iter()
+            .filter(|op| !matches!(op, Op::Insert(..)))
+            .count();
+        let new_len = hunk
+            .iter()
+            .filter(|op| !matches!(op, Op::Delete(..)))
+            .count();
+        out += &format!(
+            "@@ -{},{} +{},{} @@\n",
+            // An empty range is given by the line before it.
+            old_start + (old_len > 0) as usize,
+            old_len,
+            new_start + (new_len > 0) as usize,
+            new_len
+        );
+        for op in hunk {
+            match op {
+                Op::Same(o, _) => out += &format!(" {}\n", old[*o]),
+                Op::Delete(o, _) => out += &format!("-{}\n", old[*o]),
+                Op::Insert(_, n) => out += &format!("+{}\n", new[*n]),
+            }
+        }
+        i = end;
+    }
+    out
+}
+
+/// Step of a line diff, with the positions in the old and the new lines it is at.
+#[derive(Debug, Clone, Copy)]
+enum Op {
+    Same(usize, usize),
+    Delete(usize, usize),
+    Insert(usize, usize),
+}
+
+impl Op {
+    fn position(self) -> (usize, usize) {
+        match self {
+            Op::Same(o, n) | Op::Delete(o, n) | Op::Insert(o, n) => (o, n),
+        }
+    }
+}
+
+/// Longest common subsequence diff. Inputs too large for the table are diffed as all of
+/// `old` deleted and all of `new` inserted.
+fn diff_lines(old: &[&str], new: &[&str]) -> Vec<Op> {
+    let (n, m) = (old.len(), new.len());
+    if n.saturating_mul(m) > MAX_DIFF_CELLS {
+        return (0..n)
+            .map(|o| Op::Delete(o, 0))
+            .chain((0..m).map(|k| Op::Insert(n, k)))
+            .collect();
+    }
+    // lcs[i][j] is the LCS length of old[i..] and new[j..].
+    let mut lcs = vec![vec![0u32; m + 1]; n + 1];
+    for i in (0..n).rev() {
+        for j in (0..m).rev() {
+            lcs[i][j] = if old[i] == new[j] {
+                lcs[i + 1][j + 1] + 1
+            } else {
+                lcs[i + 1][j].max(lcs[i][j + 1])
+            };
+        }
+    }
+    let mut ops = Vec::with_capacity(n.max(m));
+    let (mut i, mut j) = (0, 0);
+    while i < n || j < m {
+        if i < n && j < m && old[i] == new[j] {
+            ops.push(Op::Same(i, j));
+            i += 1;
+            j += 1;
+        } else if i < n && (j == m || lcs[i + 1][j] >= lcs[i][j + 1]) {
+            ops.push(Op::Delete(i, j));
+            i += 1;
+        } else {
+            ops.push(Op::Insert(i, j));
+            j += 1;
+        }
+    }
+    ops
+}
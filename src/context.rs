@@ -0,0 +1,231 @@
+use crate::response::Response;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+/// What a `RunContext` knows about one host.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostContext {
+    /// Facts by dotted name, e.g. `os_release.id`.
+    pub facts: BTreeMap<String, String>,
+    /// Exit code of the last command run on the host, when it got that far.
+    pub exit_code: Option<i32>,
+    /// Whether the guard check of the last run skipped the command, when there was one.
+    pub guard_skipped: Option<bool>,
+    /// Facts not stored because the host had reached the size cap of the context.
+    pub dropped_facts: u64,
+    pub updated: SystemTime,
+}
+
+impl HostContext {
+    fn new() -> Self {
+        HostContext {
+            facts: BTreeMap::new(),
+            exit_code: None,
+            guard_skipped: None,
+            dropped_facts: 0,
+            updated: SystemTime::now(),
+        }
+    }
+
+    fn fact_bytes(&self) -> usize {
+        self.facts
+            .iter()
+            .map(|(name, value)| name.len() + value.len())
+            .sum()
+    }
+}
+
+/// Data gathered about hosts over the successive runs of a process, by host name as given.
+///
+/// Runs of props given the same context record the exit code and guard outcome of each
+/// host and, with `ParallelSshPropsBuilder::gather_facts`, facts parsed from its output.
+/// Commands of later runs refer to facts as `{{facts.os_release.id}}`. Facts of a host take
+/// at most `max_bytes`, names included; facts beyond that are dropped and counted.
+pub struct RunContext {
+    max_bytes: usize,
+    hosts: Mutex<BTreeMap<String, HostContext>>,
+}
+
+/// Hosts of a `RunContext`, as saved between invocations.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct ContextSnapshot {
+    pub hosts: BTreeMap<String, HostContext>,
+}
+
+impl RunContext {
+    pub fn new(max_bytes: usize) -> Self {
+        RunContext {
+            max_bytes,
+            hosts: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// Context with the hosts of `snapshot` updated within `ttl`, so only the others need
+    /// their facts gathered again.
+    pub fn from_snapshot(snapshot: ContextSnapshot, max_bytes: usize, ttl: Duration) -> Self {
+        let now = SystemTime::now();
+        let hosts = snapshot
+            .hosts
+            .into_iter()
+            .filter(|(_, host)| {
+                now.duration_since(host.updated)
+                    .map_or(true, |age| age < ttl)
+            })
+            .collect();
+        RunContext {
+            max_bytes,
+            hosts: Mutex::new(hosts),
+        }
+    }
+
+    pub fn snapshot(&self) -> ContextSnapshot {
+        ContextSnapshot {
+            hosts: self.hosts.lock().unwrap().clone(),
+        }
+    }
+
+    pub fn host(&self, hostname: &str) -> Option<HostContext> {
+        self.hosts.lock().unwrap().get(hostname).cloned()
+    }
+
+    /// Facts of `hostname`, empty for a host the context does not know.
+    pub fn facts(&self, hostname: &str) -> BTreeMap<String, String> {
+        self.host(hostname)
+            .map(|host| host.facts)
+            .unwrap_or_default()
+    }
+
+    /// Sets fact `name` of `hostname`, failing when it would take the host over the cap.
+    pub fn set_fact(&self, hostname: &str, name: &str, value: &str) -> Result<(), String> {
+        let mut hosts = self.hosts.lock().unwrap();
+        let host = hosts
+            .entry(hostname.to_string())
+            .or_insert_with(HostContext::new);
+        let replaced = host.facts.get(name).map_or(0, |old| name.len() + old.len());
+        if host.fact_bytes() - replaced + name.len() + value.len() > self.max_bytes {
+            host.dropped_facts += 1;
+            return Err(format!(
+                "{}: fact {} would take the host over {} bytes",
+                hostname, name, self.max_bytes
+            ));
+        }
+        host.facts.insert(name.to_string(), value.to_string());
+        host.updated = SystemTime::now();
+        Ok(())
+    }
+
+    /// Records what the run of `response` found; with `gather_as`, the `KEY=VALUE` lines
+    /// of a successful output become the facts `<gather_as>.<key>`, replacing those
+    /// gathered before under the same name.
+    pub(crate) fn record(&self, response: &Response, gather_as: Option<&str>) {
+        let prefix = {
+            let mut hosts = self.hosts.lock().unwrap();
+            let host = hosts
+                .entry(response.hostname.clone())
+                .or_insert_with(HostContext::new);
+            host.exit_code = response.exit_code;
+            host.guard_skipped = response.guard.as_ref().map(|guard| guard.skipped);
+            host.updated = SystemTime::now();
+            match gather_as {
+                Some(prefix) if response.status => {
+                    let stale = format!("{}.", prefix);
+                    host.facts.retain(|name, _| !name.starts_with(&stale));
+                    prefix
+                }
+                _ => return,
+            }
+        };
+        for (key, value) in response.result.lines().filter_map(parse_fact_line) {
+            let name = format!("{}.{}", prefix, key.to_lowercase());
+            // Counted in `dropped_facts`.
+            let _ = self.set_fact(&response.hostname, &name, value);
+        }
+    }
+
+    /// `command` with the `{{facts.NAME}}` placeholders replaced by the facts of
+    /// `hostname`. Other `{{...}}` text, e.g. a `docker ps --format '{{.Names}}'`, is kept.
+    pub fn render(&self, hostname: &str, command: &str) -> Result<String, String> {
+        if !command.contains("{{") {
+            return Ok(command.to_string());
+        }
+        let facts = self.facts(hostname);
+        let mut out = String::with_capacity(command.len());
+        let mut rest = command;
+        while let Some(start) = rest.find("{{") {
+            out.push_str(&rest[..start]);
+            let after = &rest[start + 2..];
+            let name = after
+                .find("}}")
+                .and_then(|end| Some((after[..end].trim().strip_prefix("facts.")?, end)));
+            match name {
+                Some((name, end)) => {
+                    let value = facts
+                        .get(name)
+                        .ok_or_else(|| format!("{}: no fact {}", hostname, name))?;
+                    out.push_str(value);
+                    rest = &after[end + 2..];
+                }
+                None => {
+                    out.push_str("{{");
+                    rest = after;
+                }
+            }
+        }
+        out.push_str(rest);
+        Ok(out)
+    }
+}
+
+/// `KEY=VALUE` line of e.g. `/etc/os-release`, the value unquoted.
+fn parse_fact_line(line: &str) -> Option<(&str, &str)> {
+    let mut kv = line.splitn(2, '=');
+    let key = kv.next()?.trim();
+    let value = kv.next()?.trim();
+    if key.is_empty() || !key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') {
+        return None;
+    }
+    let value = value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .or_else(|| value.strip_prefix('\'').and_then(|v| v.strip_suffix('\'')))
+        .unwrap_or(value);
+    Some((key, value))
+}
+
+/// The `facts` config table, e.g.
+///
+/// ```toml
+/// [facts]
+/// gather_as = "os_release"
+/// cache = "facts.json"
+/// ttl_secs = 86400
+/// ```
+///
+/// A run of `cat /etc/os-release` then lets the command of the next invocation use
+/// `{{facts.os_release.id}}`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FactsConfig {
+    /// Prefix of the facts parsed from the output of this run, none when unset.
+    pub gather_as: Option<String>,
+    /// File the facts are kept in between invocations.
+    pub cache: Option<PathBuf>,
+    /// Age up to which the facts of a host in `cache` are used.
+    pub ttl_secs: u64,
+    /// Cap of the facts of one host, names included.
+    pub max_bytes: usize,
+}
+
+impl Default for FactsConfig {
+    fn default() -> Self {
+        FactsConfig {
+            gather_as: None,
+            cache: None,
+            ttl_secs: 86400,
+            max_bytes: 64 * 1024,
+        }
+    }
+}
@@ -0,0 +1,171 @@
+use serde::Serialize;
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Limits set through a `RunHandle`, as listed by `RunHandle::changes`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct LimitChange {
+    pub at: SystemTime,
+    pub max_connections: usize,
+    /// Hosts started per second, `None` when not limited.
+    pub connect_rate: Option<f64>,
+}
+
+struct LimitState {
+    max_connections: usize,
+    connect_rate: Option<f64>,
+    running: usize,
+    next_start: Instant,
+    changes: Vec<LimitChange>,
+}
+
+struct Limits {
+    ceiling: usize,
+    state: Mutex<LimitState>,
+    freed: Condvar,
+}
+
+/// Live limits of the props it came from, see `ParallelSshProps::handle`.
+///
+/// Hosts start only while fewer than `max_connections` of them are running and, with a
+/// connect rate, no sooner than its interval after the previous start. Lowering the limit
+/// never cancels hosts in flight: hosts finishing free no slot for new ones until the
+/// running ones are below the new limit. The limit is at most the number of threads the
+/// props were built with, the size of their worker pool.
+///
+/// Clones control the same props.
+#[derive(Clone)]
+pub struct RunHandle {
+    limits: Arc<Limits>,
+}
+
+impl RunHandle {
+    pub(crate) fn new(max_connections: usize, connect_rate: Option<f64>) -> Result<Self, String> {
+        check_rate(connect_rate)?;
+        let max_connections = max_connections.max(1);
+        Ok(RunHandle {
+            limits: Arc::new(Limits {
+                ceiling: max_connections,
+                state: Mutex::new(LimitState {
+                    max_connections,
+                    connect_rate,
+                    running: 0,
+                    next_start: Instant::now(),
+                    changes: Vec::new(),
+                }),
+                freed: Condvar::new(),
+            }),
+        })
+    }
+
+    fn state(&self) -> MutexGuard<'_, LimitState> {
+        self.limits.state.lock().unwrap()
+    }
+
+    /// Sets the hosts run at once, clamped to between 1 and `ceiling`; returns the limit
+    /// set.
+    pub fn set_max_connections(&self, n: usize) -> usize {
+        let n = n.max(1).min(self.limits.ceiling);
+        let mut state = self.state();
+        if state.max_connections != n {
+            state.max_connections = n;
+            Self::record(&mut state);
+            self.limits.freed.notify_all();
+        }
+        n
+    }
+
+    /// Sets the hosts started per second, `None` to start them as fast as slots free up.
+    pub fn set_connect_rate(&self, rate: Option<f64>) -> Result<(), String> {
+        check_rate(rate)?;
+        let mut state = self.state();
+        if state.connect_rate != rate {
+            state.connect_rate = rate;
+            state.next_start = Instant::now();
+            Self::record(&mut state);
+            self.limits.freed.notify_all();
+        }
+        Ok(())
+    }
+
+    fn record(state: &mut LimitState) {
+        let change = LimitChange {
+            at: SystemTime::now(),
+            max_connections: state.max_connections,
+            connect_rate: state.connect_rate,
+        };
+        state.changes.push(change);
+    }
+
+    pub fn max_connections(&self) -> usize {
+        self.state().max_connections
+    }
+
+    /// Highest `max_connections` can go.
+    pub fn ceiling(&self) -> usize {
+        self.limits.ceiling
+    }
+
+    pub fn connect_rate(&self) -> Option<f64> {
+        self.state().connect_rate
+    }
+
+    /// Hosts holding a slot right now, over the limit for a while after lowering it.
+    pub fn running(&self) -> usize {
+        self.state().running
+    }
+
+    /// Limits set so far, oldest first.
+    pub fn changes(&self) -> Vec<LimitChange> {
+        self.state().changes.clone()
+    }
+
+    /// Waits for a slot under the current limits, held until the returned guard is
+    /// dropped.
+    pub(crate) fn acquire(&self) -> Slot<'_> {
+        let mut state = self.state();
+        while state.running >= state.max_connections {
+            state = self.limits.freed.wait(state).unwrap();
+        }
+        state.running += 1;
+        let start = match state.connect_rate {
+            Some(rate) => {
+                let start = state.next_start.max(Instant::now());
+                state.next_start = start + Duration::from_secs_f64(1.0 / rate);
+                start
+            }
+            None => Instant::now(),
+        };
+        drop(state);
+        let now = Instant::now();
+        if start > now {
+            thread::sleep(start - now);
+        }
+        Slot { handle: self }
+    }
+}
+
+fn check_rate(rate: Option<f64>) -> Result<(), String> {
+    match rate {
+        Some(rate) if !rate.is_finite() || rate <= 0.0 => {
+            Err(format!("connect_rate must be above 0, got {}", rate))
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Slot of one running host, freed on drop.
+pub(crate) struct Slot<'a> {
+    handle: &'a RunHandle,
+}
+
+impl Drop for Slot<'_> {
+    fn drop(&mut self) {
+        let mut state = self.handle.state();
+        state.running -= 1;
+        if state.running < state.max_connections {
+            self.handle.limits.freed.notify_one();
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::response::Response;
+use crate::scheduler::ParallelSshProps;
+use std::collections::HashMap;
+use std::mem;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+
+/// Address, user and final command line of a host; hosts with the same key run once.
+pub(crate) type DedupKey = (SocketAddr, String, String);
+
+enum Slot {
+    /// The first host is running; the names of aliases arriving meanwhile.
+    Running(Vec<String>),
+    Done(Response),
+}
+
+/// Tracks which addresses of a run already have a command running, so aliases of one
+/// machine share a single execution.
+///
+/// Duplicates are detected as hosts are picked up, so it works on streamed hosts too.
+#[derive(Default)]
+pub(crate) struct Deduplicator {
+    slots: Mutex<HashMap<DedupKey, Slot>>,
+}
+
+impl Deduplicator {
+    /// Registers `hostname` under `key`. Returns whether the caller should run it; an
+    /// alias gets its response sent by the first host of the key, or right away when that
+    /// one is done already.
+    pub(crate) fn claim(&self, key: &DedupKey, hostname: &str, props: &ParallelSshProps) -> bool {
+        let mut slots = self.slots.lock().unwrap();
+        let response = match slots.get_mut(key) {
+            None => {
+                slots.insert(key.clone(), Slot::Running(Vec::new()));
+                return true;
+            }
+            Some(Slot::Running(aliases)) => {
+                aliases.push(hostname.to_string());
+                return false;
+            }
+            Some(Slot::Done(response)) => alias_response(response, hostname),
+        };
+        drop(slots);
+        props.send_response(response);
+        false
+    }
+
+    /// Stores the response of the first host of `key` and sends one for each alias
+    /// waiting on it.
+    pub(crate) fn finish(&self, key: DedupKey, response: &Response, props: &ParallelSshProps) {
+        let aliases = match self
+            .slots
+            .lock()
+            .unwrap()
+            .insert(key, Slot::Done(response.clone()))
+        {
+            Some(Slot::Running(aliases)) => aliases,
+            _ => Vec::new(),
+        };
+        for alias in aliases {
+            props.send_response(alias_response(response, &alias));
+        }
+    }
+}
+
+fn alias_response(response: &Response, hostname: &str) -> Response {
+    let mut alias = response.clone();
+    alias.deduplicated_with = Some(mem::replace(&mut alias.hostname, hostname.to_string()));
+    alias
+}
@@ -0,0 +1,261 @@
+use crate::command::RemoteCommand;
+use crate::inventory::{check_bind, check_host, HostOptions};
+use crate::local::{local_process, local_target};
+use crate::response::{ErrorKind, HostError, HostStatus, HostTimings, Response};
+use crate::scheduler::{host_command, host_commands, run_host, ParallelSshProps};
+use crate::session::HostFacts;
+use crate::target::IntoTarget;
+use crate::timeouts::{Timeouts, DEFAULT_PHASE_TIMEOUT};
+use serde::Serialize;
+use std::fmt::{self, Display};
+use std::time::{Duration, Instant};
+
+/// Step of a host run, as timed by `run_single`.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    /// Resolving the host and probing its port.
+    Precheck,
+    Connect,
+    Handshake,
+    HostKey,
+    Auth,
+    SkipCheck,
+    Guard,
+    Command,
+    Upload,
+    Fetch,
+    PostCondition,
+}
+
+impl Display for Step {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Step::Precheck => "resolving and probing",
+            Step::Connect => "connecting",
+            Step::Handshake => "handshake",
+            Step::HostKey => "host key check",
+            Step::Auth => "authentication",
+            Step::SkipCheck => "skip check",
+            Step::Guard => "guard check",
+            Step::Command => "command",
+            Step::Upload => "upload",
+            Step::Fetch => "fetch",
+            Step::PostCondition => "post condition",
+        })
+    }
+}
+
+/// One step of one attempt.
+#[derive(Serialize, Debug, Clone)]
+pub struct StepTiming {
+    pub attempt: u32,
+    pub step: Step,
+    /// Start of the step, from the start of `run_single`.
+    pub offset: Duration,
+    pub duration: Duration,
+    /// Limit configured for the step, `None` when it has none of its own.
+    pub limit: Option<Duration>,
+    /// How the step failed, `None` when it succeeded.
+    pub error_kind: Option<ErrorKind>,
+}
+
+/// Algorithms negotiated in the SSH handshake, `_cs` from client to server and `_sc` from
+/// server to client.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Algorithms {
+    pub kex: Option<String>,
+    pub host_key: Option<String>,
+    pub cipher_cs: Option<String>,
+    pub cipher_sc: Option<String>,
+    pub mac_cs: Option<String>,
+    pub mac_sc: Option<String>,
+    pub compression_cs: Option<String>,
+    pub compression_sc: Option<String>,
+}
+
+/// Result of `ParallelSshProps::run_single`.
+#[derive(Debug)]
+pub struct DetailedResponse {
+    /// The response a run would have produced for the host, attempt history included.
+    pub response: Response,
+    /// Every step of every attempt, in the order they ran.
+    pub steps: Vec<StepTiming>,
+    /// Algorithms of the last handshake.
+    pub algorithms: Option<Algorithms>,
+    /// Why the host failed: the step it failed in, caused by the failure itself.
+    pub error: Option<anyhow::Error>,
+}
+
+impl DetailedResponse {
+    fn new(response: Response, log: StepLog) -> Self {
+        let error = match response.error_kind {
+            Some(kind) if response.outcome == HostStatus::Failed => {
+                let cause = anyhow::Error::new(HostError::new(kind, response.result.clone()));
+                Some(match log.steps.last().filter(|s| s.error_kind.is_some()) {
+                    Some(s) => cause.context(format!("{} failed on {}", s.step, response.hostname)),
+                    None => cause.context(format!("{} failed", response.hostname)),
+                })
+            }
+            _ => None,
+        };
+        DetailedResponse {
+            response,
+            steps: log.steps,
+            algorithms: log.algorithms,
+            error,
+        }
+    }
+}
+
+/// Steps recorded for `run_single`.
+pub(crate) struct StepLog {
+    started: Instant,
+    attempt: u32,
+    steps: Vec<StepTiming>,
+    /// Port probe limit, `None` through a proxy, which is not probed.
+    probe: Option<Duration>,
+    timeouts: Timeouts,
+    /// Whether the connection goes through a proxy, which has a default connect limit.
+    proxied: bool,
+    pub(crate) algorithms: Option<Algorithms>,
+}
+
+impl StepLog {
+    fn new(props: &ParallelSshProps) -> Self {
+        let proxied = props.proxy.is_some();
+        StepLog {
+            started: Instant::now(),
+            attempt: 1,
+            steps: Vec::new(),
+            probe: Some(props.timeout_socket).filter(|_| !proxied),
+            timeouts: props.timeouts,
+            proxied,
+            algorithms: None,
+        }
+    }
+
+    /// Limit `step` runs under with the props of the log.
+    fn limit(&self, step: Step) -> Option<Duration> {
+        let phase = |limit: Option<Duration>| Some(limit.unwrap_or(DEFAULT_PHASE_TIMEOUT));
+        match step {
+            Step::Precheck => self.probe,
+            Step::Connect if self.proxied => phase(self.timeouts.connect),
+            Step::Connect => self.timeouts.connect,
+            Step::Handshake => phase(self.timeouts.handshake),
+            Step::Auth => phase(self.timeouts.auth),
+            Step::Command => self.timeouts.read_total,
+            _ => None,
+        }
+    }
+
+    /// Attempt the steps recorded from now on belong to.
+    pub(crate) fn set_attempt(&mut self, attempt: u32) {
+        self.attempt = attempt;
+    }
+
+    fn record<T>(&mut self, step: Step, start: Instant, result: &Result<T, HostError>) {
+        self.steps.push(StepTiming {
+            attempt: self.attempt,
+            step,
+            offset: start - self.started,
+            duration: start.elapsed(),
+            limit: self.limit(step),
+            error_kind: result.as_ref().err().map(|e| e.kind),
+        });
+    }
+}
+
+/// Runs `f` as `step`, timing it when `log` is set.
+pub(crate) fn timed<T, F>(log: &mut Option<StepLog>, step: Step, f: F) -> Result<T, HostError>
+where
+    F: FnOnce() -> Result<T, HostError>,
+{
+    let start = Instant::now();
+    let result = f();
+    if let Some(log) = log {
+        log.record(step, start, &result);
+    }
+    result
+}
+
+impl ParallelSshProps {
+    /// Runs `command` on `host` alone, returning its response together with every step of
+    /// every attempt timed, the negotiated algorithms and the error chain.
+    ///
+    /// Meant for finding out why one host misbehaves. The host runs as it would in a run,
+    /// retry policy, guard and host key store included, but without waiting for a read
+    /// permit, and its response is not sent to the props' stream.
+    pub async fn run_single<A, C>(&self, host: A, command: C) -> DetailedResponse
+    where
+        A: IntoTarget,
+        C: Into<RemoteCommand>,
+    {
+        let props = ParallelSshProps {
+            read_permits: None,
+            ..self.clone()
+        };
+        let options = HostOptions::default();
+        let host = host.into_target();
+        let assigned_args = props.assign_args(0, &host.to_string(), &options);
+        let (command, render_error) = host_command(
+            &host,
+            &command.into().to_string(),
+            &assigned_args,
+            &options,
+            &props,
+        );
+        let mut log = StepLog::new(&props);
+        let start = Instant::now();
+        let mut timings = HostTimings::default();
+        let process = local_process(&host, &options, &props);
+        let target = if process.is_some() {
+            local_target(&host)
+        } else {
+            check_host(
+                &host,
+                props.proxy.as_ref(),
+                &props.dns_cache,
+                props.session_pool.as_deref(),
+                props.timeout_socket,
+                &mut timings,
+            )
+            .await
+            .and_then(|t| check_bind(t, &props))
+        };
+        log.record(Step::Precheck, start, &target);
+        let target = match render_error {
+            Some(e) => target.and(Err(e)),
+            None => target,
+        };
+        smol::unblock(move || {
+            let mut commands = host_commands(command, &options, &props);
+            commands.process = process;
+            let mut facts = HostFacts {
+                steps: Some(log),
+                timings,
+                ..HostFacts::default()
+            };
+            let response = run_host(
+                host,
+                target,
+                &commands,
+                &options,
+                assigned_args,
+                &props,
+                &mut facts,
+            );
+            DetailedResponse::new(response, facts.steps.expect("kept by run_host"))
+        })
+        .await
+    }
+
+    /// Blocking form of `run_single`.
+    pub fn run_single_blocking<A, C>(&self, host: A, command: C) -> DetailedResponse
+    where
+        A: IntoTarget,
+        C: Into<RemoteCommand>,
+    {
+        smol::run(self.run_single(host, command))
+    }
+}
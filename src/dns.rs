@@ -0,0 +1,95 @@
+use crate::response::{ErrorKind, HostError};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
+use std::net::{SocketAddr, ToSocketAddrs};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+const SHARDS: usize = 16;
+
+/// Hit and miss counters of a `DnsCache`.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct DnsCacheStats {
+    pub hits: u64,
+    pub misses: u64,
+}
+
+struct Entry {
+    result: Result<SocketAddr, String>,
+    expires: Instant,
+}
+
+/// Name resolution cache shared by all host tasks of a run.
+///
+/// Entries are spread over independently locked shards so concurrent lookups of different
+/// names rarely contend, and the resolver itself is called without holding a lock. Failed
+/// lookups are cached for `negative_ttl` so a dead name is not re-queried by every host.
+pub struct DnsCache {
+    shards: Vec<Mutex<HashMap<String, Entry>>>,
+    ttl: Duration,
+    negative_ttl: Duration,
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl DnsCache {
+    pub fn new(ttl: Duration, negative_ttl: Duration) -> Self {
+        DnsCache {
+            shards: (0..SHARDS).map(|_| Mutex::new(HashMap::new())).collect(),
+            ttl,
+            negative_ttl,
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+        }
+    }
+
+    fn shard(&self, name: &str) -> &Mutex<HashMap<String, Entry>> {
+        let mut hasher = DefaultHasher::new();
+        name.hash(&mut hasher);
+        &self.shards[hasher.finish() as usize % SHARDS]
+    }
+
+    /// Resolves a `host:port` name to its first address.
+    pub fn resolve(&self, name: &str) -> Result<SocketAddr, HostError> {
+        let shard = self.shard(name);
+        if let Some(entry) = shard.lock().unwrap().get(name) {
+            if entry.expires > Instant::now() {
+                self.hits.fetch_add(1, Ordering::Relaxed);
+                return entry
+                    .result
+                    .clone()
+                    .map_err(|e| HostError::new(ErrorKind::Dns, e));
+            }
+        }
+        self.misses.fetch_add(1, Ordering::Relaxed);
+
+        let result = match name.to_socket_addrs() {
+            Ok(mut addrs) => addrs
+                .next()
+                .ok_or_else(|| "Failed converting address".to_string()),
+            Err(e) => Err(e.to_string()),
+        };
+        let ttl = if result.is_ok() {
+            self.ttl
+        } else {
+            self.negative_ttl
+        };
+        shard.lock().unwrap().insert(
+            name.to_string(),
+            Entry {
+                result: result.clone(),
+                expires: Instant::now() + ttl,
+            },
+        );
+        result.map_err(|e| HostError::new(ErrorKind::Dns, e))
+    }
+
+    pub fn stats(&self) -> DnsCacheStats {
+        DnsCacheStats {
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+        }
+    }
+}
@@ -0,0 +1,377 @@
+use crate::command::RemoteCommand;
+use crate::inventory::prepare_command;
+use crate::output_hash::{OutputHashAlgorithm, OutputHasher};
+use crate::response::{ErrorKind, HostError};
+use crate::session::{ssh_error_kind, start_command, HostSession, POLL_MAX, POLL_MIN};
+use crate::timeouts::{Timeouts, DEFAULT_PHASE_TIMEOUT};
+use serde::{Deserialize, Serialize};
+use ssh2::{Channel, Session};
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Block size of ranged reads; chunks start and end on a block boundary.
+const BLOCK_SIZE: u64 = 1 << 20;
+
+/// How `HostSession::download` fetches a file.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(default)]
+pub struct DownloadOptions {
+    /// Ranges the file is split into, each read on its own channel; 1 for a single stream.
+    pub chunks: usize,
+    /// Chunks in flight at once. Bounds the channels of this host only, apart from how
+    /// many hosts the props run at once.
+    pub chunk_parallelism: usize,
+}
+
+impl Default for DownloadOptions {
+    fn default() -> Self {
+        DownloadOptions {
+            chunks: 1,
+            chunk_parallelism: 4,
+        }
+    }
+}
+
+/// How a file was fetched.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DownloadStrategy {
+    SingleStream,
+    /// Ranged reads with `dd`, in `chunks` parts.
+    Chunked {
+        chunks: usize,
+    },
+}
+
+/// What `HostSession::download` did.
+#[derive(Serialize, Debug, Clone)]
+pub struct DownloadReport {
+    pub remote_path: String,
+    pub local_path: PathBuf,
+    pub bytes: u64,
+    pub strategy: DownloadStrategy,
+    /// Why a single stream was used although chunks were asked for.
+    pub fallback: Option<String>,
+    pub duration: Duration,
+    /// Bytes per second over the whole download.
+    pub throughput: u64,
+    /// `sha256:<hex>` of the local file.
+    pub checksum: String,
+    /// Whether `checksum` was compared with the host's one, which needs `sha256sum` there.
+    pub verified: bool,
+}
+
+/// Part of the file read by one command.
+struct Chunk {
+    command: String,
+    offset: u64,
+    len: u64,
+}
+
+/// Chunk being read from its channel.
+struct InFlight<'a> {
+    chunk: &'a Chunk,
+    channel: Channel,
+    received: u64,
+    open: [bool; 2],
+}
+
+impl HostSession {
+    /// Fetches `remote_path` into `local_path`, in `options.chunks` ranged reads over
+    /// channels of this session when the host has `dd`, in a single stream otherwise.
+    ///
+    /// The local file is preallocated, sparse, to the remote size. When the host has
+    /// `sha256sum`, the file is verified against its checksum and fails with
+    /// `ErrorKind::ChecksumMismatch` when they differ.
+    pub fn download(
+        &self,
+        remote_path: &str,
+        local_path: &Path,
+        options: &DownloadOptions,
+    ) -> Result<DownloadReport, HostError> {
+        let start = Instant::now();
+        let probe = self.run(
+            RemoteCommand::raw(format!(
+                "wc -c < {path} || exit 1; \
+                 command -v dd >/dev/null && echo dd; \
+                 command -v sha256sum >/dev/null && echo sha256sum",
+                path = RemoteCommand::new(remote_path)
+            )),
+            None,
+        )?;
+        let mut lines = probe.output.lines();
+        let size: u64 = match (probe.exit_code, lines.next().map(|l| l.trim().parse())) {
+            (0, Some(Ok(size))) => size,
+            _ => {
+                return Err(HostError::new(
+                    ErrorKind::Read,
+                    format!("Failed reading the size of {}", remote_path),
+                ))
+            }
+        };
+        let tools: Vec<&str> = lines.map(str::trim).collect();
+        let blocks = (size + BLOCK_SIZE - 1) / BLOCK_SIZE;
+        let chunks = (options.chunks as u64).min(blocks).max(1);
+        let fallback = if options.chunks <= 1 {
+            None
+        } else if !tools.contains(&"dd") {
+            Some("dd is missing on the host".to_string())
+        } else if chunks == 1 {
+            Some("the file fits in one block".to_string())
+        } else {
+            None
+        };
+        let (strategy, parts) = if options.chunks > 1 && fallback.is_none() {
+            let per_chunk = (blocks + chunks - 1) / chunks;
+            let parts = (0..blocks)
+                .step_by(per_chunk as usize)
+                .map(|skip| Chunk {
+                    command: RemoteCommand::new("dd")
+                        .arg(format!("if={}", remote_path))
+                        .arg(format!("bs={}", BLOCK_SIZE))
+                        .arg(format!("skip={}", skip))
+                        .arg(format!("count={}", per_chunk))
+                        .to_string(),
+                    offset: skip * BLOCK_SIZE,
+                    len: (per_chunk * BLOCK_SIZE).min(size - skip * BLOCK_SIZE),
+                })
+                .collect::<Vec<_>>();
+            let strategy = DownloadStrategy::Chunked {
+                chunks: parts.len(),
+            };
+            (strategy, parts)
+        } else {
+            let part = Chunk {
+                command: RemoteCommand::new("cat")
+                    .arg("--")
+                    .arg(remote_path)
+                    .to_string(),
+                offset: 0,
+                len: size,
+            };
+            (DownloadStrategy::SingleStream, vec![part])
+        };
+        // Started first and read last, so the host hashes while the file is transferred.
+        let remote_sum = if tools.contains(&"sha256sum") {
+            let command = RemoteCommand::new("sha256sum")
+                .arg("--")
+                .arg(remote_path)
+                .to_string();
+            Some(start_command(
+                &self.sess,
+                &self.prepare(command),
+                &self.props.timeouts,
+                None,
+                self.escalation.as_ref(),
+            )?)
+        } else {
+            None
+        };
+        let mut file = File::create(local_path)
+            .and_then(|file| file.set_len(size).map(|_| file))
+            .map_err(|e| local_error(local_path, e))?;
+        for batch in parts.chunks(options.chunk_parallelism.max(1)) {
+            let mut in_flight = batch
+                .iter()
+                .map(|chunk| {
+                    let channel = start_command(
+                        &self.sess,
+                        &self.prepare(chunk.command.clone()),
+                        &self.props.timeouts,
+                        None,
+                        self.escalation.as_ref(),
+                    )?;
+                    Ok(InFlight {
+                        chunk,
+                        channel,
+                        received: 0,
+                        open: [true, true],
+                    })
+                })
+                .collect::<Result<Vec<_>, HostError>>()?;
+            self.read_chunks(&mut in_flight, &mut file, local_path)?;
+            for InFlight {
+                chunk,
+                channel,
+                received,
+                ..
+            } in in_flight
+            {
+                close(&self.sess, channel, &self.props.timeouts, |code| {
+                    format!("{} exited with {}", chunk.command, code)
+                })?;
+                if received != chunk.len {
+                    return Err(HostError::new(
+                        ErrorKind::Read,
+                        format!(
+                            "Got {} of {} bytes at offset {} of {}",
+                            received, chunk.len, chunk.offset, remote_path
+                        ),
+                    ));
+                }
+            }
+        }
+        file.flush().map_err(|e| local_error(local_path, e))?;
+        let checksum = file_checksum(local_path)?;
+        let verified = match remote_sum {
+            Some(mut channel) => {
+                self.sess
+                    .set_timeout(Timeouts::session_ms(self.props.timeouts.read_idle));
+                let mut output = String::new();
+                channel.read_to_string(&mut output).map_err(|e| {
+                    HostError::new(
+                        ErrorKind::Read,
+                        format!("Failed reading the checksum of {}: {}", remote_path, e),
+                    )
+                })?;
+                close(&self.sess, channel, &self.props.timeouts, |code| {
+                    format!("sha256sum exited with {}", code)
+                })?;
+                let remote = output.split_whitespace().next().unwrap_or("");
+                if format!("sha256:{}", remote) != checksum {
+                    return Err(HostError::new(
+                        ErrorKind::ChecksumMismatch,
+                        format!(
+                            "{} has sha256:{} on the host but {} locally",
+                            remote_path, remote, checksum
+                        ),
+                    ));
+                }
+                true
+            }
+            None => false,
+        };
+        let duration = start.elapsed();
+        Ok(DownloadReport {
+            remote_path: remote_path.to_string(),
+            local_path: local_path.to_path_buf(),
+            bytes: size,
+            strategy,
+            fallback,
+            duration,
+            throughput: (size as f64 / duration.as_secs_f64().max(1e-3)) as u64,
+            checksum,
+            verified,
+        })
+    }
+
+    fn prepare(&self, command: String) -> String {
+        prepare_command(command, self.shell, self.workdir.as_deref(), &self.props)
+    }
+
+    /// Reads the chunks in flight side by side, each into its range of `file`, until every
+    /// one of them reached EOF. Stderr is drained but not kept.
+    fn read_chunks(
+        &self,
+        in_flight: &mut [InFlight<'_>],
+        file: &mut File,
+        local_path: &Path,
+    ) -> Result<(), HostError> {
+        let idle_limit = self
+            .props
+            .timeouts
+            .read_idle
+            .unwrap_or(DEFAULT_PHASE_TIMEOUT);
+        self.sess.set_blocking(false);
+        let result = (|| {
+            let mut buffer = vec![0u8; 64 * 1024];
+            let mut last_data = Instant::now();
+            let mut pause = POLL_MIN;
+            while in_flight.iter().any(|part| part.open.iter().any(|o| *o)) {
+                let mut got_data = false;
+                for part in in_flight.iter_mut() {
+                    for id in 0..2 {
+                        while part.open[id as usize] {
+                            match part.channel.stream(id).read(&mut buffer) {
+                                Ok(0) => part.open[id as usize] = false,
+                                Ok(n) => {
+                                    got_data = true;
+                                    if id == 0 {
+                                        file.seek(SeekFrom::Start(
+                                            part.chunk.offset + part.received,
+                                        ))
+                                        .and_then(|_| file.write_all(&buffer[..n]))
+                                        .map_err(|e| local_error(local_path, e))?;
+                                        part.received += n as u64;
+                                    }
+                                }
+                                Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                                Err(e) => {
+                                    return Err(HostError::new(
+                                        ErrorKind::Read,
+                                        format!("Error reading download: {}", e),
+                                    ))
+                                }
+                            }
+                        }
+                    }
+                }
+                if got_data {
+                    last_data = Instant::now();
+                    pause = POLL_MIN;
+                    continue;
+                }
+                if last_data.elapsed() >= idle_limit {
+                    return Err(HostError::new(
+                        ErrorKind::ReadIdleTimeout,
+                        "Error reading download: timed out waiting for data",
+                    ));
+                }
+                thread::sleep(pause);
+                pause = (pause * 2).min(POLL_MAX);
+            }
+            Ok(())
+        })();
+        self.sess.set_blocking(true);
+        result
+    }
+}
+
+/// Waits for `channel` to close, failing with `failure(exit code)` unless it exited 0.
+fn close<F>(
+    sess: &Session,
+    mut channel: Channel,
+    timeouts: &Timeouts,
+    failure: F,
+) -> Result<(), HostError>
+where
+    F: FnOnce(i32) -> String,
+{
+    sess.set_timeout(Timeouts::session_ms(timeouts.read_idle));
+    let code = channel
+        .wait_close()
+        .and_then(|_| channel.exit_status())
+        .map_err(|e| {
+            HostError::new(
+                ssh_error_kind(&e, ErrorKind::Read, ErrorKind::ReadIdleTimeout),
+                format!("Failed closing channel: {}", e),
+            )
+        })?;
+    match code {
+        0 => Ok(()),
+        code => Err(HostError::new(ErrorKind::Read, failure(code))),
+    }
+}
+
+/// `sha256:<hex>` of the file at `path`.
+fn file_checksum(path: &Path) -> Result<String, HostError> {
+    let mut hasher = OutputHasher::new(OutputHashAlgorithm::Sha256);
+    let mut file = File::open(path).map_err(|e| local_error(path, e))?;
+    let mut buffer = vec![0u8; BLOCK_SIZE as usize];
+    loop {
+        match file.read(&mut buffer) {
+            Ok(0) => return Ok(hasher.finish()),
+            Ok(n) => hasher.update(&buffer[..n]),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(local_error(path, e)),
+        }
+    }
+}
+
+pub(crate) fn local_error(path: &Path, e: io::Error) -> HostError {
+    HostError::new(ErrorKind::LocalFile, format!("{}: {}", path.display(), e))
+}
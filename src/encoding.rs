@@ -0,0 +1,130 @@
+use encoding_rs::{Encoding, BIG5, EUC_KR, GBK, SHIFT_JIS, UTF_8, WINDOWS_1252};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
+
+/// How the output bytes of a command are turned into the textual `result`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OutputEncoding {
+    /// UTF-8, invalid sequences replaced by U+FFFD.
+    Utf8Lossy,
+    /// UTF-8, invalid output fails the host.
+    Strict,
+    /// Guess the encoding from the output itself.
+    Detect,
+    Fixed(&'static Encoding),
+}
+
+/// Parses `utf8_lossy`, `strict`, `detect` or any WHATWG encoding label, e.g. `gbk`.
+impl FromStr for OutputEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "utf8_lossy" => Ok(OutputEncoding::Utf8Lossy),
+            "strict" => Ok(OutputEncoding::Strict),
+            "detect" => Ok(OutputEncoding::Detect),
+            label => Encoding::for_label(label.as_bytes())
+                .map(OutputEncoding::Fixed)
+                .ok_or_else(|| format!("Unknown output encoding: {}", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for OutputEncoding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl Serialize for OutputEncoding {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(match self {
+            OutputEncoding::Utf8Lossy => "utf8_lossy",
+            OutputEncoding::Strict => "strict",
+            OutputEncoding::Detect => "detect",
+            OutputEncoding::Fixed(encoding) => encoding.name(),
+        })
+    }
+}
+
+impl OutputEncoding {
+    /// Decodes `bytes`, returning the text and the name of the encoding used.
+    pub fn decode(self, bytes: &[u8]) -> Result<(String, &'static str), String> {
+        match self {
+            OutputEncoding::Strict => std::str::from_utf8(bytes)
+                .map(|s| (s.to_string(), UTF_8.name()))
+                .map_err(|e| e.to_string()),
+            OutputEncoding::Utf8Lossy => Ok(decode_with(UTF_8, bytes)),
+            OutputEncoding::Fixed(encoding) => Ok(decode_with(encoding, bytes)),
+            OutputEncoding::Detect => Ok(decode_with(detect(bytes), bytes)),
+        }
+    }
+}
+
+fn decode_with(encoding: &'static Encoding, bytes: &[u8]) -> (String, &'static str) {
+    let (text, _had_errors) = encoding.decode_without_bom_handling(bytes);
+    (text.into_owned(), encoding.name())
+}
+
+/// Guesses the encoding of `bytes`.
+///
+/// Output which is mostly valid UTF-8 stays UTF-8 even with a few stray bytes, so one bad
+/// byte costs a replacement character rather than turning everything into mojibake.
+/// Otherwise the legacy multi-byte encoding decoding without errors into the largest
+/// share of CJK characters wins, and single-byte Latin text is the fallback.
+fn detect(bytes: &[u8]) -> &'static Encoding {
+    let (valid_multibyte, invalid) = utf8_stats(bytes);
+    if invalid == 0 || valid_multibyte >= invalid * 4 {
+        return UTF_8;
+    }
+    let mut best: Option<(&'static Encoding, f64)> = None;
+    for &encoding in [GBK, SHIFT_JIS, EUC_KR, BIG5].iter() {
+        let (text, had_errors) = encoding.decode_without_bom_handling(bytes);
+        if had_errors {
+            continue;
+        }
+        let non_ascii = text.chars().filter(|c| !c.is_ascii()).count();
+        if non_ascii == 0 {
+            continue;
+        }
+        let cjk = text.chars().filter(|c| is_cjk(*c)).count();
+        let score = cjk as f64 / non_ascii as f64;
+        if score > 0.5 && best.map_or(true, |(_, s)| score > s) {
+            best = Some((encoding, score));
+        }
+    }
+    best.map_or(WINDOWS_1252, |(e, _)| e)
+}
+
+/// Counts valid multi-byte UTF-8 sequences and invalid bytes.
+fn utf8_stats(mut bytes: &[u8]) -> (usize, usize) {
+    let mut valid_multibyte = 0;
+    let mut invalid = 0;
+    loop {
+        match std::str::from_utf8(bytes) {
+            Ok(s) => {
+                valid_multibyte += s.chars().filter(|c| !c.is_ascii()).count();
+                return (valid_multibyte, invalid);
+            }
+            Err(e) => {
+                let (valid, rest) = bytes.split_at(e.valid_up_to());
+                valid_multibyte += std::str::from_utf8(valid)
+                    .map(|s| s.chars().filter(|c| !c.is_ascii()).count())
+                    .unwrap_or(0);
+                let skip = e.error_len().unwrap_or_else(|| rest.len());
+                invalid += skip;
+                bytes = &rest[skip..];
+            }
+        }
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    match c as u32 {
+        0x3000..=0x30FF | 0x3400..=0x4DBF | 0x4E00..=0x9FFF | 0xAC00..=0xD7AF | 0xFF00..=0xFFEF => {
+            true
+        }
+        _ => false,
+    }
+}
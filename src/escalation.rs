@@ -0,0 +1,51 @@
+use crate::shell::shell_quote;
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+
+/// Tool `become` runs commands through, see `ParallelSshPropsBuilder::become_method`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BecomeMethod {
+    /// `sudo`, given the become password on stdin when the props have one.
+    Sudo,
+    /// `doas`, only without a password: it reads one from a terminal alone.
+    Doas,
+}
+
+impl Default for BecomeMethod {
+    fn default() -> Self {
+        BecomeMethod::Sudo
+    }
+}
+
+impl BecomeMethod {
+    /// `command` run by `sh -c` as `user`, reading the password from stdin with
+    /// `with_password`. Cached sudo credentials are ignored then, so the password line is
+    /// always consumed by sudo rather than left to the command.
+    pub(crate) fn wrap(self, command: &str, user: &str, with_password: bool) -> String {
+        let as_user = if user == "root" {
+            String::new()
+        } else {
+            format!("-u {} ", shell_quote(user))
+        };
+        let tool = match (self, with_password) {
+            (BecomeMethod::Sudo, false) => "sudo -n",
+            (BecomeMethod::Sudo, true) => "sudo -S -k -p ''",
+            (BecomeMethod::Doas, _) => "doas -n",
+        };
+        format!("{} {}-- sh -c {}", tool, as_user, shell_quote(command))
+    }
+}
+
+/// Become password of a host, given the host as named in its response, e.g. read from a
+/// vault. An error fails the host with `ErrorKind::Become` before connecting to it.
+pub type SecretProvider = Arc<dyn Fn(&str) -> Result<String, String> + Send + Sync>;
+
+/// What a command run with become needs when it is started.
+#[derive(Clone)]
+pub(crate) struct Escalation {
+    /// Written to the command's stdin, followed by a newline.
+    pub(crate) password: Option<String>,
+    /// Run the command on a pseudo terminal, echo off, for sudoers with `requiretty`.
+    pub(crate) pty: bool,
+}
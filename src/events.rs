@@ -0,0 +1,50 @@
+use crate::response::Response;
+use serde::Serialize;
+use std::time::Duration;
+
+/// Step of a host's run as seen on the event stream of
+/// `ParallelSshPropsBuilder::build_with_events`.
+///
+/// Events of one host come from the thread running it, so they arrive in order, and
+/// `Finished` is always the last one of the host, also when it timed out, was cancelled
+/// or never got connected. Hosts of a run interleave freely. A retried host goes through
+/// `Connected` and on again for every attempt.
+#[derive(Serialize, Debug, Clone)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum RunEvent {
+    /// TCP connection open, handshake next.
+    Connected { hostname: String },
+    /// Authenticated as `user`.
+    AuthOk { hostname: String, user: String },
+    /// The ssh-agent took `latency` to answer, above the `agent_latency_warning` limit of
+    /// the props. Sent after `AuthOk`.
+    AgentLatencyHigh {
+        hostname: String,
+        latency: Duration,
+        limit: Duration,
+    },
+    /// `command` started, the guard check as well as the command itself.
+    ExecStarted { hostname: String, command: String },
+    /// Output of the last started command as read, `stderr` or stdout. Chunks are not
+    /// aligned to lines nor to characters.
+    OutputChunk {
+        hostname: String,
+        stderr: bool,
+        data: Vec<u8>,
+    },
+    /// The response of the host, as sent on the response stream.
+    Finished(Response),
+}
+
+impl RunEvent {
+    pub fn hostname(&self) -> &str {
+        match self {
+            RunEvent::Connected { hostname }
+            | RunEvent::AuthOk { hostname, .. }
+            | RunEvent::AgentLatencyHigh { hostname, .. }
+            | RunEvent::ExecStarted { hostname, .. }
+            | RunEvent::OutputChunk { hostname, .. } => hostname,
+            RunEvent::Finished(response) => &response.hostname,
+        }
+    }
+}
@@ -0,0 +1,131 @@
+use crate::response::{ErrorKind, HostStatus};
+use serde::{Deserialize, Serialize};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+/// Failures after which a run is aborted, e.g.
+/// `failure_threshold = { max_fail_percent = 10.0, min_completed = 20 }`.
+///
+/// Only hosts which ran or could not be reached count; skipped and cancelled hosts do not.
+/// Once aborted, hosts not started yet fail with `ErrorKind::Cancelled` without being
+/// connected to, and hosts reading output stop, failing the same way.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct FailureThreshold {
+    /// Abort once more hosts than this failed.
+    #[serde(default)]
+    pub max_failures: Option<usize>,
+    /// Abort once more than this percentage of the completed hosts failed.
+    #[serde(default)]
+    pub max_fail_percent: Option<f64>,
+    /// Completed hosts needed before `max_fail_percent` applies, so the first failure
+    /// does not count as 100%.
+    #[serde(default = "default_min_completed")]
+    pub min_completed: usize,
+}
+
+fn default_min_completed() -> usize {
+    10
+}
+
+impl Default for FailureThreshold {
+    fn default() -> Self {
+        FailureThreshold {
+            max_failures: None,
+            max_fail_percent: None,
+            min_completed: default_min_completed(),
+        }
+    }
+}
+
+impl FailureThreshold {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_failures.is_none() && self.max_fail_percent.is_none() {
+            return Err("failure_threshold needs max_failures or max_fail_percent".to_string());
+        }
+        match self.max_fail_percent {
+            Some(p) if !(0.0..100.0).contains(&p) => Err(format!(
+                "failure_threshold.max_fail_percent must be at least 0 and below 100, got {}",
+                p
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Why `failed` failures out of `completed` hosts exceed the threshold, if they do.
+    fn exceeded(&self, completed: usize, failed: usize) -> Option<String> {
+        if let Some(max) = self.max_failures {
+            if failed > max {
+                return Some(format!(
+                    "aborted, {} hosts failed, more than max_failures {}",
+                    failed, max
+                ));
+            }
+        }
+        match self.max_fail_percent {
+            Some(max)
+                if completed >= self.min_completed.max(1)
+                    && failed as f64 * 100.0 > max * completed as f64 =>
+            {
+                Some(format!(
+                    "aborted, {} of {} completed hosts failed, more than max_fail_percent {}%",
+                    failed, completed, max
+                ))
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Counts of a run against its `FailureThreshold`, shared by the workers of the run.
+pub(crate) struct FailureBreaker {
+    threshold: FailureThreshold,
+    /// Completed and failed hosts, and why the run was aborted once it was.
+    state: Mutex<(usize, usize, Option<String>)>,
+    tripped: AtomicBool,
+}
+
+impl FailureBreaker {
+    pub(crate) fn new(threshold: FailureThreshold) -> Self {
+        FailureBreaker {
+            threshold,
+            state: Mutex::new((0, 0, None)),
+            tripped: AtomicBool::new(false),
+        }
+    }
+
+    /// Starts counting anew, for the next run of the props.
+    pub(crate) fn reset(&self) {
+        *self.state.lock().unwrap() = (0, 0, None);
+        self.tripped.store(false, Ordering::Relaxed);
+    }
+
+    /// Counts a host which ended with `error_kind`, or succeeded on `None`.
+    pub(crate) fn record(&self, error_kind: Option<ErrorKind>) {
+        let failed = match HostStatus::of(error_kind) {
+            HostStatus::Success => false,
+            HostStatus::Failed => true,
+            HostStatus::Skipped | HostStatus::Cancelled => return,
+        };
+        let mut state = self.state.lock().unwrap();
+        state.0 += 1;
+        if failed {
+            state.1 += 1;
+        }
+        if state.2.is_none() {
+            state.2 = self.threshold.exceeded(state.0, state.1);
+            if state.2.is_some() {
+                self.tripped.store(true, Ordering::Relaxed);
+            }
+        }
+    }
+
+    pub(crate) fn tripped(&self) -> bool {
+        self.tripped.load(Ordering::Relaxed)
+    }
+
+    /// Why the run was aborted, once it was.
+    pub(crate) fn reason(&self) -> Option<String> {
+        self.state.lock().unwrap().2.clone()
+    }
+}
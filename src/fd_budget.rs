@@ -0,0 +1,163 @@
+use serde::{Deserialize, Serialize};
+use std::io;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+/// Descriptors of the process itself: stdio, the incremental output and its index, the
+/// known hosts file, the resolver, with room to spare.
+pub const BASE_FDS: u64 = 64;
+/// Descriptors of a host in flight: its SSH socket, and the agent socket while it
+/// authenticates.
+pub const FDS_PER_HOST: u64 = 2;
+/// Descriptors of a run besides its hosts: the socket of the port probe.
+pub const FDS_PER_RUN: u64 = 1;
+
+/// What happens when the descriptor limit is below what the configured concurrency needs.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum FdShortage {
+    /// Lower the number of hosts run at once to what fits, with a warning.
+    Clamp,
+    /// Refuse to start, naming the limit to raise.
+    Fail,
+}
+
+impl Default for FdShortage {
+    fn default() -> Self {
+        FdShortage::Clamp
+    }
+}
+
+/// Open file descriptor limit of the process against what a run needs.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FdBudget {
+    /// Soft `RLIMIT_NOFILE`, `None` where it is unlimited or cannot be read.
+    pub limit: Option<u64>,
+    /// Descriptors needed to run `hosts` hosts at once over `runs` runs side by side.
+    pub needed: u64,
+    pub hosts: usize,
+    pub runs: usize,
+}
+
+impl FdBudget {
+    /// Budget of `runs` runs side by side, `hosts` hosts at once in total.
+    pub fn new(hosts: usize, runs: usize) -> Self {
+        FdBudget {
+            limit: nofile_limit().map(|(soft, _)| soft),
+            needed: BASE_FDS + FDS_PER_RUN * runs as u64 + FDS_PER_HOST * hosts as u64,
+            hosts,
+            runs,
+        }
+    }
+
+    pub fn fits(&self) -> bool {
+        self.limit.map_or(true, |limit| self.needed <= limit)
+    }
+
+    /// Hosts which fit the limit at once over the same runs; `None` without a limit.
+    pub fn max_hosts(&self) -> Option<usize> {
+        self.limit.map(|limit| {
+            let fixed = BASE_FDS + FDS_PER_RUN * self.runs as u64;
+            (limit.saturating_sub(fixed) / FDS_PER_HOST) as usize
+        })
+    }
+}
+
+/// Soft and hard `RLIMIT_NOFILE`, `None` where unlimited or not known.
+#[cfg(target_os = "linux")]
+pub fn nofile_limit() -> Option<(u64, u64)> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0
+        || limit.rlim_cur == libc::RLIM_INFINITY
+    {
+        return None;
+    }
+    Some((limit.rlim_cur as u64, limit.rlim_max as u64))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn nofile_limit() -> Option<(u64, u64)> {
+    None
+}
+
+/// Raises the soft `RLIMIT_NOFILE` to the hard one, returning the new soft limit.
+#[cfg(target_os = "linux")]
+pub fn raise_nofile_limit() -> io::Result<u64> {
+    let mut limit = libc::rlimit {
+        rlim_cur: 0,
+        rlim_max: 0,
+    };
+    if unsafe { libc::getrlimit(libc::RLIMIT_NOFILE, &mut limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    limit.rlim_cur = limit.rlim_max;
+    if unsafe { libc::setrlimit(libc::RLIMIT_NOFILE, &limit) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(limit.rlim_cur as u64)
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn raise_nofile_limit() -> io::Result<u64> {
+    Err(io::Error::new(
+        io::ErrorKind::Other,
+        "raising the open files limit is only supported on Linux",
+    ))
+}
+
+/// Descriptors the process has open, from `/proc/self/fd`.
+#[cfg(target_os = "linux")]
+pub fn open_fds() -> Option<u64> {
+    // The listing counts its own descriptor.
+    let entries = std::fs::read_dir("/proc/self/fd").ok()?.count() as u64;
+    Some(entries.saturating_sub(1))
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn open_fds() -> Option<u64> {
+    None
+}
+
+/// Samples `open_fds` from a background thread, keeping the peak. Sampling stops once the
+/// monitor is dropped.
+pub struct FdMonitor {
+    peak: Arc<AtomicU64>,
+    stop: Arc<AtomicBool>,
+}
+
+impl FdMonitor {
+    pub fn start(interval: Duration) -> Self {
+        let peak = Arc::new(AtomicU64::new(0));
+        let stop = Arc::new(AtomicBool::new(false));
+        if open_fds().is_some() {
+            let (peak, stop) = (peak.clone(), stop.clone());
+            thread::spawn(move || {
+                while !stop.load(Ordering::Relaxed) {
+                    if let Some(n) = open_fds() {
+                        peak.fetch_max(n, Ordering::Relaxed);
+                    }
+                    thread::sleep(interval);
+                }
+            });
+        }
+        FdMonitor { peak, stop }
+    }
+
+    /// Most descriptors seen open at once, now included; `None` where they cannot be
+    /// counted.
+    pub fn peak(&self) -> Option<u64> {
+        let now = open_fds()?;
+        Some(self.peak.fetch_max(now, Ordering::Relaxed).max(now))
+    }
+}
+
+impl Drop for FdMonitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::Relaxed);
+    }
+}
@@ -0,0 +1,92 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Check run on a host before the command, deciding whether the command runs at all.
+///
+/// The check runs in the same session and with the same shell, workdir and become
+/// settings as the command, and its time counts toward the host's `read_total` limit.
+/// In TOML:
+///
+/// ```toml
+/// guard = { skip_if_succeeds = "test -f /etc/app/installed" }
+/// guard = { skip_if_output_matches = { command = "app --version", pattern = "^2\\.4\\." } }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "GuardConfig", into = "GuardConfig")]
+pub enum Guard {
+    /// Skip the command when the check exits with 0, e.g. because the work is done.
+    SkipIfSucceeds(String),
+    /// Run the command only when the check exits with 0.
+    RunIfSucceeds(String),
+    /// Skip the command when the output of the check matches the pattern.
+    SkipIfOutputMatches(String, Regex),
+}
+
+/// Config form of `Guard`; TOML has no tuples of mixed types.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum GuardConfig {
+    SkipIfSucceeds(String),
+    RunIfSucceeds(String),
+    SkipIfOutputMatches { command: String, pattern: String },
+}
+
+impl TryFrom<GuardConfig> for Guard {
+    type Error = regex::Error;
+
+    fn try_from(config: GuardConfig) -> Result<Self, Self::Error> {
+        Ok(match config {
+            GuardConfig::SkipIfSucceeds(command) => Guard::SkipIfSucceeds(command),
+            GuardConfig::RunIfSucceeds(command) => Guard::RunIfSucceeds(command),
+            GuardConfig::SkipIfOutputMatches { command, pattern } => {
+                Guard::SkipIfOutputMatches(command, Regex::new(&pattern)?)
+            }
+        })
+    }
+}
+
+impl From<Guard> for GuardConfig {
+    fn from(guard: Guard) -> Self {
+        match guard {
+            Guard::SkipIfSucceeds(command) => GuardConfig::SkipIfSucceeds(command),
+            Guard::RunIfSucceeds(command) => GuardConfig::RunIfSucceeds(command),
+            Guard::SkipIfOutputMatches(command, pattern) => GuardConfig::SkipIfOutputMatches {
+                command,
+                pattern: pattern.as_str().to_string(),
+            },
+        }
+    }
+}
+
+impl Guard {
+    /// The check command.
+    pub fn command(&self) -> &str {
+        match self {
+            Guard::SkipIfSucceeds(command)
+            | Guard::RunIfSucceeds(command)
+            | Guard::SkipIfOutputMatches(command, _) => command,
+        }
+    }
+
+    /// Whether the command is skipped after the check exited with `exit_code`, printing
+    /// `output`.
+    pub fn skips(&self, exit_code: i32, output: &str) -> bool {
+        match self {
+            Guard::SkipIfSucceeds(_) => exit_code == 0,
+            Guard::RunIfSucceeds(_) => exit_code != 0,
+            Guard::SkipIfOutputMatches(_, pattern) => pattern.is_match(output),
+        }
+    }
+}
+
+/// What the guard check of a host did, recorded in its response.
+#[derive(Serialize, Debug, Clone)]
+pub struct GuardResult {
+    /// Check command as sent to the host.
+    pub command: String,
+    pub exit_code: i32,
+    pub output: String,
+    /// Whether the command was skipped because of the check.
+    pub skipped: bool,
+}
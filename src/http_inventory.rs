@@ -0,0 +1,182 @@
+use crate::inventory_source::{Inventory, InventorySource};
+use crate::misc::InventoryHost;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::time::Duration;
+
+/// Inventory served as JSON over HTTP, e.g. by a CMDB:
+///
+/// ```toml
+/// [inventory]
+/// type = "http"
+/// url = "https://cmdb.internal/api/hosts?env=prod"
+/// auth_header = "Authorization: Bearer 0123abcd"
+/// hosts_pointer = "/data/hosts"
+/// fields = { address = "/ip", group = "/role", vars = { remote_shell = "/os/shell" } }
+/// ```
+///
+/// HTTPS servers are verified against the webpki root certificates.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct HttpInventory {
+    pub url: String,
+    /// Header sent with the request as `Name: value`, usually the credentials.
+    #[serde(default)]
+    pub auth_header: Option<String>,
+    /// JSON pointer to the array of hosts in the response, the whole response when empty.
+    #[serde(default)]
+    pub hosts_pointer: String,
+    #[serde(default)]
+    pub fields: FieldMapping,
+    /// Limit of connecting, of waiting for the response and of reading its body.
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    30
+}
+
+/// JSON pointers, within each host object, of the values making an `InventoryHost`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FieldMapping {
+    /// Address or name of the host.
+    pub address: String,
+    /// Group name, or an array of them for a host in several groups.
+    pub group: Option<String>,
+    /// Host vars by name; strings, numbers and booleans are taken, missing values skipped.
+    pub vars: BTreeMap<String, String>,
+}
+
+impl Default for FieldMapping {
+    fn default() -> Self {
+        FieldMapping {
+            address: "/address".to_string(),
+            group: None,
+            vars: BTreeMap::new(),
+        }
+    }
+}
+
+impl InventorySource for HttpInventory {
+    async fn load(&self) -> Result<Inventory, String> {
+        let source = self.clone();
+        smol::unblock(move || {
+            let body = source.fetch()?;
+            let document: Value = serde_json::from_slice(&body)
+                .map_err(|e| format!("{}: invalid JSON: {}", source.url, e))?;
+            source.hosts(&document)
+        })
+        .await
+    }
+}
+
+impl HttpInventory {
+    /// Rejects URLs and headers which cannot be sent.
+    pub fn validate(&self) -> Result<(), String> {
+        check_url(&self.url)?;
+        if let Some(header) = &self.auth_header {
+            if !header.contains(':') || header.contains(['\r', '\n']) {
+                return Err("inventory.auth_header must be a single `Name: value`".to_string());
+            }
+        }
+        if self.timeout_secs == 0 {
+            return Err("inventory.timeout_secs must be above 0".to_string());
+        }
+        Ok(())
+    }
+
+    fn hosts(&self, document: &Value) -> Result<Inventory, String> {
+        let items = document
+            .pointer(&self.hosts_pointer)
+            .and_then(Value::as_array)
+            .ok_or_else(|| format!("{}: no array at {:?}", self.url, self.hosts_pointer))?;
+        let mut hosts = Vec::new();
+        for (i, item) in items.iter().enumerate() {
+            let addr = item
+                .pointer(&self.fields.address)
+                .and_then(Value::as_str)
+                .map(str::trim)
+                .filter(|a| !a.is_empty())
+                .ok_or_else(|| {
+                    format!(
+                        "{}: host {} has no address at {:?}",
+                        self.url, i, self.fields.address
+                    )
+                })?;
+            let vars: BTreeMap<String, String> = self
+                .fields
+                .vars
+                .iter()
+                .filter_map(|(name, pointer)| Some((name.clone(), scalar(item.pointer(pointer)?)?)))
+                .collect();
+            let groups = match self.fields.group.as_ref().and_then(|p| item.pointer(p)) {
+                Some(Value::Array(groups)) => groups.iter().filter_map(scalar).map(Some).collect(),
+                Some(group) => vec![scalar(group)],
+                None => vec![None],
+            };
+            for group in groups {
+                hosts.push(InventoryHost {
+                    addr: addr.to_string(),
+                    group,
+                    parents: Vec::new(),
+                    vars: vars.clone(),
+                });
+            }
+        }
+        Ok(Inventory {
+            hosts,
+            commands: BTreeMap::new(),
+        })
+    }
+
+    /// Body of a GET of `url`.
+    fn fetch(&self) -> Result<Vec<u8>, String> {
+        let timeout = Some(Duration::from_secs(self.timeout_secs));
+        let agent: ureq::Agent = ureq::Agent::config_builder()
+            .timeout_connect(timeout)
+            .timeout_recv_response(timeout)
+            .timeout_recv_body(timeout)
+            .build()
+            .into();
+        let mut request = agent.get(&self.url).header("Accept", "application/json");
+        if let Some(header) = &self.auth_header {
+            let mut kv = header.splitn(2, ':');
+            let (name, value) = (kv.next().unwrap_or(""), kv.next().unwrap_or(""));
+            request = request.header(name.trim(), value.trim());
+        }
+        let error = |e: ureq::Error| format!("{}: {}", self.url, e);
+        let response = request.call().map_err(error)?;
+        let mut body = Vec::new();
+        response
+            .into_body()
+            .into_reader()
+            .read_to_end(&mut body)
+            .map_err(|e| format!("{}: {}", self.url, e))?;
+        Ok(body)
+    }
+}
+
+/// Rejects URLs other than `http://` and `https://` ones with a host.
+fn check_url(url: &str) -> Result<(), String> {
+    let rest = url
+        .strip_prefix("http://")
+        .or_else(|| url.strip_prefix("https://"))
+        .ok_or_else(|| format!("{}: not an http:// or https:// URL", url))?;
+    if rest.split('/').next().unwrap_or("").is_empty() {
+        return Err(format!("{}: no host", url));
+    }
+    Ok(())
+}
+
+fn scalar(value: &Value) -> Option<String> {
+    match value {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
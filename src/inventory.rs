@@ -0,0 +1,673 @@
+use crate::args::HostInfo;
+use crate::auth::AuthMethod;
+use crate::command::RemoteCommand;
+use crate::dedup::DedupKey;
+use crate::dns::DnsCache;
+use crate::local::{local_process, local_target, Connection};
+use crate::post_condition::PostCondition;
+use crate::proxy::{ProxyConfig, Target};
+use crate::redact::REDACTED;
+use crate::response::{ErrorKind, HostError, HostTimings};
+use crate::scheduler::{host_command, ParallelSshProps};
+use crate::session_pool::SessionPool;
+use crate::shell::RemoteShell;
+use crate::target::{HostSpec, HostTarget, IntoTarget};
+use crate::timeouts::Timeouts;
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use smol::future::FutureExt;
+use smol::stream::{Stream, StreamExt};
+use smol::{io, Async, Timer};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::iter;
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+/// Per-host overrides of the settings in `ParallelSshProps`. Debug output shows
+/// `REDACTED` in place of the password.
+#[derive(Clone, Default)]
+pub struct HostOptions {
+    /// User to log in as, instead of the props' one.
+    pub user: Option<String>,
+    pub remote_shell: Option<RemoteShell>,
+    pub auth_chain: Option<Vec<AuthMethod>>,
+    /// Password of this host, tried after the other methods of its auth chain in place of
+    /// any password there, e.g. for an appliance which takes no keys.
+    pub password: Option<String>,
+    pub workdir: Option<String>,
+    /// Inventory vars of the host, as seen by an `ArgAssigner`.
+    pub vars: BTreeMap<String, String>,
+    /// Tags of the host, winning over run tags of the same key.
+    pub tags: BTreeMap<String, String>,
+    /// Class of the host, e.g. `appliance`, taking turns with other classes when the props
+    /// have class weights.
+    pub class: Option<String>,
+    /// Condition waited for after the command, instead of the props' one.
+    pub post_condition: Option<PostCondition>,
+    /// How the host is reached, instead of as `ParallelSshPropsBuilder::local_loopback`
+    /// says.
+    pub connection: Option<Connection>,
+    /// Container the command runs in with the docker and podman connections, instead of
+    /// the one named like the host.
+    pub container: Option<String>,
+}
+
+impl fmt::Debug for HostOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HostOptions")
+            .field("user", &self.user)
+            .field("remote_shell", &self.remote_shell)
+            .field("auth_chain", &self.auth_chain)
+            .field("password", &self.password.as_ref().map(|_| REDACTED))
+            .field("workdir", &self.workdir)
+            .field("vars", &self.vars)
+            .field("tags", &self.tags)
+            .field("class", &self.class)
+            .field("post_condition", &self.post_condition)
+            .field("connection", &self.connection)
+            .field("container", &self.container)
+            .finish()
+    }
+}
+
+/// Settings a host runs with, its `HostOptions` laid over the props' settings. Passwords
+/// and passphrases are replaced by `REDACTED`.
+///
+/// Plans list it for every host, and responses carry it with
+/// `ParallelSshPropsBuilder::verbose_config`; both come from
+/// `ParallelSshProps::effective_config`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct EffectiveHostConfig {
+    pub user: String,
+    /// Port of the host when given, 22 for a name without one.
+    pub port: u16,
+    /// Limit of the port probe before connecting.
+    pub probe_timeout: Duration,
+    pub timeouts: Timeouts,
+    pub auth_chain: Vec<AuthMethod>,
+    #[serde(rename = "become")]
+    pub become_root: bool,
+    pub remote_shell: RemoteShell,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<ProxyConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+}
+
+/// Settings of one host, borrowed from its `HostOptions` where set and from the props
+/// otherwise.
+pub(crate) struct HostSettings<'a> {
+    pub(crate) user: &'a str,
+    pub(crate) remote_shell: RemoteShell,
+    pub(crate) workdir: Option<&'a str>,
+    pub(crate) auth_chain: Cow<'a, [AuthMethod]>,
+}
+
+/// What a run would do on one host.
+#[derive(Serialize, Debug, Clone)]
+pub struct PlannedHost {
+    /// Host as given.
+    pub target: String,
+    /// Address connected to; `None` when it could not be resolved or is left to the proxy.
+    pub address: Option<SocketAddr>,
+    #[serde(flatten)]
+    pub config: EffectiveHostConfig,
+    /// Command as given for the host, rendered and with its assigned args, before the
+    /// workdir and shell are applied.
+    pub given: String,
+    /// Command line sent to the host, with workdir, become and shell applied.
+    pub command: String,
+    /// Host this one would share an execution with, when deduplication is on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deduplicated_with: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub assigned_args: Vec<String>,
+    /// Why the command could not be rendered for the host, which then fails without being
+    /// run; `given` is the command unrendered.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub render_error: Option<String>,
+}
+
+/// Hosts a run would process and the commands they would get, produced without connecting.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct RunPlan {
+    pub run_id: String,
+    pub hosts: Vec<PlannedHost>,
+}
+
+impl RunPlan {
+    /// Appends the hosts of `other`, e.g. the plan of another group of the same run.
+    pub fn extend(&mut self, other: RunPlan) {
+        if self.run_id.is_empty() {
+            self.run_id = other.run_id;
+        }
+        self.hosts.extend(other.hosts);
+    }
+}
+
+/// Fails targets of an address family without a bind address, when those are skipped.
+pub(crate) fn check_bind(target: Target, props: &ParallelSshProps) -> Result<Target, HostError> {
+    match &target {
+        Target::Resolved(addr) if props.proxy.is_none() && props.skip_bind_mismatch => {
+            match props.bind_addresses.for_target(addr) {
+                Ok(_) => Ok(target),
+                Err(e) => Err(HostError::new(ErrorKind::Skipped, e)),
+            }
+        }
+        _ => Ok(target),
+    }
+}
+
+/// Applies the workdir, become and shell settings to `command`.
+pub(crate) fn prepare_command(
+    command: String,
+    shell: RemoteShell,
+    workdir: Option<&str>,
+    props: &ParallelSshProps,
+) -> String {
+    let command = match workdir {
+        Some(dir) => shell.in_workdir(&command, dir, props.create_workdir),
+        None => command,
+    };
+    let command = if props.become_root {
+        props.become_method.wrap(
+            &command,
+            &props.become_user,
+            props.become_password.is_some(),
+        )
+    } else {
+        command
+    };
+    shell.wrap(&command)
+}
+
+/// Resolves `host` unless it is given by address, and probes its port for at most
+/// `probe_timeout`, timing both in `timings`.
+///
+/// Behind a proxy the target is usually not directly reachable, so the probe is skipped,
+/// and with `remote_dns` resolution is left to the proxy as well.
+pub(crate) async fn check_host(
+    host: &HostTarget,
+    proxy: Option<&ProxyConfig>,
+    dns: &Arc<DnsCache>,
+    pool: Option<&SessionPool>,
+    probe_timeout: Duration,
+    timings: &mut HostTimings,
+) -> Result<Target, HostError> {
+    let address = match host {
+        HostTarget::Address { address, .. } => *address,
+        HostTarget::Name(name) if proxy.map_or(false, |p| p.remote_dns) => {
+            return match name.parse() {
+                Ok(addr) => Ok(Target::Resolved(addr)),
+                Err(_) => Target::unresolved(name),
+            };
+        }
+        HostTarget::Name(name) => match name.parse() {
+            Ok(addr) => addr,
+            Err(_) => {
+                // A lookup can take seconds; keep it off the executor thread.
+                let (dns, name) = (Arc::clone(dns), name.clone());
+                let start = Instant::now();
+                let resolved = smol::unblock(move || dns.resolve(&name)).await;
+                timings.dns = Some(start.elapsed());
+                resolved?
+            }
+        },
+    };
+    // A pooled session to the host is as good a sign of life as a probe, and cheaper.
+    if proxy.is_some() || pool.map_or(false, |pool| pool.has_idle(&address.to_string())) {
+        return Ok(Target::Resolved(address));
+    }
+
+    let start = Instant::now();
+    let probe = Async::<TcpStream>::connect(address)
+        .or(async {
+            Timer::new(probe_timeout).await;
+            Err(io::ErrorKind::TimedOut.into())
+        })
+        .await;
+    timings.probe = Some(start.elapsed());
+    let _tcp = probe.map_err(|e| {
+        let kind = if e.kind() == io::ErrorKind::TimedOut {
+            ErrorKind::TcpTimeout
+        } else {
+            ErrorKind::TcpConnect
+        };
+        HostError::new(kind, e.to_string())
+    })?;
+    Ok(Target::Resolved(address))
+}
+
+/// Host as given, command as given, the args assigned to it, options, target and the
+/// timings of checking it.
+pub(crate) type CheckedHost = (
+    HostTarget,
+    String,
+    Vec<String>,
+    HostOptions,
+    Result<Target, HostError>,
+    HostTimings,
+);
+
+/// Resolves and probes `hosts`, handing them to the workers in their order.
+///
+/// As many hosts as the props' `tcp_connections_pool` are probed at a time. Hosts are pulled from `hosts`
+/// only as probes finish and the workers take them, so a huge inventory is never held in
+/// memory at once.
+///
+/// Once the run is cancelled or aborted, the remaining hosts are handed over failed without being
+/// looked at.
+pub(crate) fn check_hosts<A, C, S>(hosts: S, props: &ParallelSshProps, tx: Sender<CheckedHost>)
+where
+    A: IntoTarget,
+    C: Into<RemoteCommand>,
+    S: Stream<Item = (A, C, HostOptions)>,
+{
+    let mut index = 0;
+    let checks = hosts.map(|(host, command, options)| {
+        let host = host.into_target();
+        let command = command.into().to_string();
+        let args = props.assign_args(index, &host.to_string(), &options);
+        index += 1;
+        async move {
+            let mut timings = HostTimings::default();
+            let res = if let Some(e) = props.cancellation() {
+                Err(e)
+            } else if local_process(&host, &options, props).is_some() {
+                local_target(&host)
+            } else {
+                check_host(
+                    &host,
+                    props.proxy.as_ref(),
+                    &props.dns_cache,
+                    props.session_pool.as_deref(),
+                    props.timeout_socket,
+                    &mut timings,
+                )
+                .await
+            };
+            (host, command, args, options, res, timings)
+        }
+    });
+    let limit = props.tcp_threads_number.max(1) as usize;
+    smol::run(async {
+        let mut checked = Box::pin(futures::StreamExt::buffered(checks, limit));
+        while let Some(host) = checked.next().await {
+            if let Err(e) = tx.send(host) {
+                eprintln!("Error transmitting ip address between threads: {}", e)
+            }
+        }
+    })
+}
+
+impl ParallelSshProps {
+    /// Works out what `parallel_ssh_process_with_options` would do on `hosts`, without
+    /// connecting anywhere. Host names are resolved through the DNS cache.
+    pub fn plan<A, C, I>(&self, hosts: I) -> RunPlan
+    where
+        A: IntoTarget,
+        C: Into<RemoteCommand>,
+        I: IntoIterator<Item = (A, C, HostOptions)>,
+    {
+        let mut first_of: HashMap<DedupKey, String> = HashMap::new();
+        let hosts = hosts
+            .into_iter()
+            .enumerate()
+            .map(|(index, (host, command, options))| {
+                let host = host.into_target();
+                let target = host.to_string();
+                let address = match &host {
+                    HostTarget::Address { address, .. } => Some(*address),
+                    HostTarget::Name(name) => match name.parse() {
+                        Ok(addr) => Some(addr),
+                        Err(_) if self.proxy.as_ref().map_or(false, |p| p.remote_dns) => None,
+                        Err(_) => self.dns_cache.resolve(name).ok(),
+                    },
+                };
+                let assigned_args = self.assign_args(index, &target, &options);
+                let (command, render_error) = host_command(
+                    &host,
+                    &command.into().to_string(),
+                    &assigned_args,
+                    &options,
+                    self,
+                );
+                let config = self.effective_config(&host, &options);
+                let given = command.clone();
+                let command = prepare_command(
+                    command,
+                    config.remote_shell,
+                    config.workdir.as_deref(),
+                    self,
+                );
+                let deduplicated_with = match address {
+                    Some(addr) if self.deduplicate => first_of
+                        .entry((addr, config.user.clone(), command.clone()))
+                        .or_insert_with(|| target.clone())
+                        .clone(),
+                    _ => target.clone(),
+                };
+                PlannedHost {
+                    assigned_args,
+                    deduplicated_with: Some(deduplicated_with).filter(|d| *d != target),
+                    target,
+                    address,
+                    config,
+                    given,
+                    command,
+                    render_error: render_error.map(|e| e.to_string()),
+                }
+            })
+            .collect();
+        RunPlan {
+            run_id: self.run_id.clone(),
+            hosts,
+        }
+    }
+
+    /// Settings `host` runs with given `options`, as listed in plans and, with
+    /// `verbose_config`, in responses.
+    pub fn effective_config(
+        &self,
+        host: &HostTarget,
+        options: &HostOptions,
+    ) -> EffectiveHostConfig {
+        let settings = self.host_settings(options);
+        let port = match host {
+            HostTarget::Address { address, .. } => address.port(),
+            HostTarget::Name(name) => name
+                .rsplit(':')
+                .next()
+                .and_then(|port| port.parse().ok())
+                .unwrap_or(22),
+        };
+        EffectiveHostConfig {
+            user: settings.user.to_string(),
+            port,
+            probe_timeout: self.timeout_socket,
+            timeouts: self.timeouts,
+            auth_chain: settings.auth_chain.iter().map(redact_auth_method).collect(),
+            become_root: self.become_root,
+            remote_shell: settings.remote_shell,
+            workdir: settings.workdir.map(str::to_string),
+            proxy: self.proxy.clone().map(|proxy| ProxyConfig {
+                password: proxy.password.as_ref().map(|_| REDACTED.to_string()),
+                ..proxy
+            }),
+            group: self.group.clone(),
+            class: options.class.clone(),
+        }
+    }
+
+    /// Settings of a host with `options`, the one place host options are laid over the
+    /// props' settings.
+    pub(crate) fn host_settings<'a>(&'a self, options: &'a HostOptions) -> HostSettings<'a> {
+        HostSettings {
+            user: options.user.as_deref().unwrap_or(&self.user),
+            remote_shell: options.remote_shell.unwrap_or(self.remote_shell),
+            workdir: options.workdir.as_deref().or(self.workdir.as_deref()),
+            auth_chain: {
+                let chain = options.auth_chain.as_deref().unwrap_or(&self.auth_chain);
+                match &options.password {
+                    Some(password) => chain
+                        .iter()
+                        .filter(|method| !matches!(method, AuthMethod::Password { .. }))
+                        .cloned()
+                        .chain(iter::once(AuthMethod::Password {
+                            password: password.clone(),
+                        }))
+                        .collect(),
+                    None => Cow::Borrowed(chain),
+                }
+            },
+        }
+    }
+
+    /// Arguments the `ArgAssigner` gives the host at `index`, appended to its command once
+    /// rendered, see `host_command`.
+    pub(crate) fn assign_args(
+        &self,
+        index: usize,
+        hostname: &str,
+        options: &HostOptions,
+    ) -> Vec<String> {
+        match &self.arg_assigner {
+            None => Vec::new(),
+            Some(assigner) => {
+                let host = HostInfo {
+                    hostname,
+                    vars: &options.vars,
+                };
+                assigner.assign(index, &host)
+            }
+        }
+    }
+}
+
+fn redact_auth_method(method: &AuthMethod) -> AuthMethod {
+    match method {
+        AuthMethod::Agent => AuthMethod::Agent,
+        AuthMethod::KeyFile { path, passphrase } => AuthMethod::KeyFile {
+            path: path.clone(),
+            passphrase: passphrase.as_ref().map(|_| REDACTED.to_string()),
+        },
+        AuthMethod::Password { .. } => AuthMethod::Password {
+            password: REDACTED.to_string(),
+        },
+    }
+}
+
+/// Most hosts one inventory entry may expand to, so a typo like `10.0.0.0/8` is refused
+/// instead of queuing millions of hosts.
+pub const MAX_EXPANDED_HOSTS: usize = 65536;
+
+/// Expands an inventory entry into the hosts it stands for.
+///
+/// `10.0.0.0/24` stands for the addresses of the network, without its network and
+/// broadcast addresses unless the prefix is /31 or /32. `web[01:20].example.com` stands
+/// for `web01` to `web20`, keeping the zero padding of the start; `[a:f]` ranges over
+/// letters and `[1:9:2]` takes a step. Several ranges multiply, and ranges may make up
+/// the address of a network. A `user@` prefix and a `:port` suffix apply to every host.
+/// Any other entry is a single `HostSpec`.
+pub fn expand(entry: &str) -> Result<Vec<HostSpec>, String> {
+    let mut specs = Vec::new();
+    for entry in expand_ranges(entry)? {
+        if entry.contains('/') {
+            specs.extend(expand_network(&entry)?);
+        } else {
+            specs.push(entry.parse()?);
+        }
+        if specs.len() > MAX_EXPANDED_HOSTS {
+            return Err(too_many_hosts(&entry));
+        }
+    }
+    Ok(specs)
+}
+
+fn too_many_hosts(entry: &str) -> String {
+    format!(
+        "{}: expands to more than {} hosts",
+        entry, MAX_EXPANDED_HOSTS
+    )
+}
+
+/// Expands the `[start:end[:step]]` ranges of `entry`, leaving brackets which are no range,
+/// such as those of `[::1]:22`, as they are.
+fn expand_ranges(entry: &str) -> Result<Vec<String>, String> {
+    let mut searched = 0;
+    while let Some(open) = entry[searched..].find('[').map(|i| searched + i) {
+        let close = match entry[open..].find(']') {
+            Some(i) => open + i,
+            None => break,
+        };
+        let values = match range_values(&entry[open + 1..close]) {
+            Some(values) => values.map_err(|e| format!("{}: {}", entry, e))?,
+            None => {
+                searched = close;
+                continue;
+            }
+        };
+        let (head, tail) = (&entry[..open], &entry[close + 1..]);
+        let tails = expand_ranges(tail)?;
+        if values.len().saturating_mul(tails.len()) > MAX_EXPANDED_HOSTS {
+            return Err(too_many_hosts(entry));
+        }
+        return Ok(values
+            .iter()
+            .flat_map(|value| {
+                tails
+                    .iter()
+                    .map(move |tail| format!("{}{}{}", head, value, tail))
+            })
+            .collect());
+    }
+    Ok(vec![entry.to_string()])
+}
+
+/// Values of the range `spec`, `None` when it is no range at all.
+fn range_values(spec: &str) -> Option<Result<Vec<String>, String>> {
+    let parts: Vec<&str> = spec.split(':').collect();
+    if parts.len() < 2 || parts.len() > 3 {
+        return None;
+    }
+    let (start, end) = (parts[0], parts[1]);
+    let step = match parts.get(2) {
+        Some(step) => match step.parse::<usize>() {
+            Ok(step) if step > 0 => step,
+            _ => return Some(Err(format!("invalid step {}", step))),
+        },
+        None => 1,
+    };
+    let is_number = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let is_letter = |s: &str| s.len() == 1 && s.bytes().all(|b| b.is_ascii_alphabetic());
+    let values = if is_number(start) && is_number(end) {
+        let (first, last) = match (start.parse::<u64>(), end.parse::<u64>()) {
+            (Ok(first), Ok(last)) => (first, last),
+            _ => return Some(Err(format!("range [{}] is too large", spec))),
+        };
+        let width = if start.len() > 1 && start.starts_with('0') {
+            start.len()
+        } else {
+            0
+        };
+        if last < first {
+            return Some(Err(format!("range [{}] ends before it starts", spec)));
+        }
+        if (last - first) / step as u64 >= MAX_EXPANDED_HOSTS as u64 {
+            return Some(Err(format!(
+                "range [{}] has more than {} values",
+                spec, MAX_EXPANDED_HOSTS
+            )));
+        }
+        (first..=last)
+            .step_by(step)
+            .map(|n| format!("{:0width$}", n, width = width))
+            .collect()
+    } else if is_letter(start) && is_letter(end) {
+        let (first, last) = (start.as_bytes()[0], end.as_bytes()[0]);
+        if last < first || first.is_ascii_lowercase() != last.is_ascii_lowercase() {
+            return Some(Err(format!("range [{}] ends before it starts", spec)));
+        }
+        (first..=last)
+            .step_by(step)
+            .map(|c| (c as char).to_string())
+            .collect()
+    } else {
+        return None;
+    };
+    Some(Ok(values))
+}
+
+/// Hosts of `[user@]address/prefix[:port]`, IPv4 only.
+fn expand_network(entry: &str) -> Result<Vec<HostSpec>, String> {
+    let (user, network) = match entry.rfind('@') {
+        Some(at) if at > 0 => (Some(entry[..at].to_string()), &entry[at + 1..]),
+        Some(_) => return Err(format!("{}: empty user", entry)),
+        None => (None, entry),
+    };
+    let (address, prefix) = network.split_at(network.find('/').unwrap_or(network.len()));
+    let (prefix, port) = match prefix[1..].split_once(':') {
+        Some((prefix, port)) => (
+            prefix,
+            port.parse::<u16>()
+                .map_err(|_| format!("{}: invalid port {}", entry, port))?,
+        ),
+        None => (&prefix[1..], 22),
+    };
+    let address: Ipv4Addr = address
+        .parse()
+        .map_err(|_| format!("{}: {} is no IPv4 network", entry, address))?;
+    let prefix = match prefix.parse::<u32>() {
+        Ok(prefix) if prefix <= 32 => prefix,
+        _ => return Err(format!("{}: invalid prefix /{}", entry, prefix)),
+    };
+    let size = 1u64 << (32 - prefix);
+    let (first, count) = match prefix {
+        31 | 32 => (0, size),
+        _ => (1, size - 2),
+    };
+    if count > MAX_EXPANDED_HOSTS as u64 {
+        return Err(too_many_hosts(entry));
+    }
+    let network = u32::from(address) & !((size - 1) as u32);
+    Ok((first..first + count)
+        .map(|offset| HostSpec {
+            user: user.clone(),
+            target: SocketAddr::from((Ipv4Addr::from(network + offset as u32), port)).into_target(),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ranges_keep_padding_and_step() {
+        assert_eq!(
+            range_values("08:11").unwrap().unwrap(),
+            vec!["08", "09", "10", "11"]
+        );
+        assert_eq!(range_values("1:9:4").unwrap().unwrap(), vec!["1", "5", "9"]);
+        assert_eq!(range_values("a:c").unwrap().unwrap(), vec!["a", "b", "c"]);
+    }
+
+    #[test]
+    fn brackets_which_are_no_range_are_left_alone() {
+        assert!(range_values("::1").is_none());
+        assert!(range_values("web").is_none());
+        assert!(range_values("1:b").is_none());
+        assert_eq!(expand_ranges("[::1]:22").unwrap(), vec!["[::1]:22"]);
+        assert_eq!(
+            expand_ranges("[::1]:[22:23]").unwrap(),
+            vec!["[::1]:22", "[::1]:23"]
+        );
+    }
+
+    #[test]
+    fn invalid_ranges_are_errors() {
+        assert!(range_values("5:1").unwrap().is_err());
+        assert!(range_values("a:Z").unwrap().is_err());
+        assert!(range_values("1:5:0").unwrap().is_err());
+        assert!(range_values("0:99999999999999999999").unwrap().is_err());
+        assert!(expand_ranges("web[3:1]")
+            .unwrap_err()
+            .starts_with("web[3:1]"));
+    }
+
+    #[test]
+    fn several_ranges_multiply() {
+        assert_eq!(
+            expand_ranges("r[1:2]-n[a:b]").unwrap(),
+            vec!["r1-na", "r1-nb", "r2-na", "r2-nb"]
+        );
+        assert!(expand_ranges("[0:9999][0:9999]").is_err());
+    }
+}
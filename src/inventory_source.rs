@@ -0,0 +1,248 @@
+use crate::misc::{
+    generate_kv_hosts_from_csv, grouped_hosts_builder, yaml_hosts_builder, InventoryHost,
+};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime};
+
+/// Hosts of a run, as an `InventorySource` loads them.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+pub struct Inventory {
+    pub hosts: Vec<InventoryHost>,
+    /// Command of each host which has its own, run instead of the configured one.
+    #[serde(default)]
+    pub commands: BTreeMap<String, String>,
+}
+
+impl Inventory {
+    /// Keeps only the hosts `pattern` selects, ansible `--limit` style: names of groups or
+    /// host addresses separated by `,` or `:`, `all` for every host, `!name` excluding the
+    /// hosts of `name` from the others. A group selects the hosts of the groups belonging
+    /// to it as well, and a host is selected or excluded with all the groups it is listed
+    /// under.
+    ///
+    /// Returns the number of hosts the included names select, and of those left once the
+    /// excluded ones are taken out. A name which selects no host is an error, to catch a
+    /// misspelt group.
+    pub fn limit(&mut self, pattern: &str) -> Result<(usize, usize), String> {
+        let mut included: Option<BTreeSet<String>> = None;
+        let mut excluded = BTreeSet::new();
+        for name in pattern.split(|c| c == ',' || c == ':').map(str::trim) {
+            let (name, exclude) = match name.strip_prefix('!') {
+                Some(name) => (name, true),
+                None => (name, false),
+            };
+            if name.is_empty() {
+                continue;
+            }
+            let selected: BTreeSet<String> = self
+                .hosts
+                .iter()
+                .filter(|host| host.selected_by(name))
+                .map(|host| host.addr.clone())
+                .collect();
+            if selected.is_empty() {
+                return Err(format!("{}: no host matches", name));
+            }
+            if exclude {
+                excluded.extend(selected);
+            } else {
+                included.get_or_insert_with(BTreeSet::new).extend(selected);
+            }
+        }
+        self.hosts.retain(|host| {
+            included
+                .as_ref()
+                .map_or(true, |hosts| hosts.contains(&host.addr))
+        });
+        let matched = self.host_count();
+        self.hosts.retain(|host| !excluded.contains(&host.addr));
+        Ok((matched, self.host_count()))
+    }
+
+    /// Number of hosts, each counted once whatever the groups it is listed under.
+    pub fn host_count(&self) -> usize {
+        self.hosts
+            .iter()
+            .map(|host| &host.addr)
+            .collect::<BTreeSet<_>>()
+            .len()
+    }
+}
+
+impl InventoryHost {
+    fn selected_by(&self, name: &str) -> bool {
+        name == "all"
+            || self.group.as_deref() == Some(name)
+            || self.parents.iter().any(|group| group == name)
+            || self.addr == name
+    }
+}
+
+/// Where the hosts of a run come from.
+pub trait InventorySource {
+    fn load(&self) -> impl Future<Output = Result<Inventory, String>> + Send;
+}
+
+/// Host list with `[group]` headers and inline vars, see `grouped_hosts_builder`.
+pub struct HostsFile {
+    pub path: PathBuf,
+}
+
+impl InventorySource for HostsFile {
+    async fn load(&self) -> Result<Inventory, String> {
+        let path = self.path.clone();
+        if !path.is_file() {
+            return Err(format!("{}: not a file", path.display()));
+        }
+        let display = path.display().to_string();
+        let hosts = smol::unblock(move || grouped_hosts_builder(&path))
+            .await
+            .map_err(|e| format!("{}: {}", display, e))?;
+        Ok(Inventory {
+            hosts,
+            commands: BTreeMap::new(),
+        })
+    }
+}
+
+/// Ansible YAML inventory, see `yaml_hosts_builder`.
+pub struct YamlFile {
+    pub path: PathBuf,
+}
+
+impl InventorySource for YamlFile {
+    async fn load(&self) -> Result<Inventory, String> {
+        let path = self.path.clone();
+        let display = path.display().to_string();
+        let hosts = smol::unblock(move || yaml_hosts_builder(&path))
+            .await
+            .map_err(|e| format!("{}: {}", display, e))?;
+        Ok(Inventory {
+            hosts,
+            commands: BTreeMap::new(),
+        })
+    }
+}
+
+/// CSV of `address,command` rows, see `generate_kv_hosts_from_csv`.
+pub struct CsvFile {
+    pub path: PathBuf,
+}
+
+impl InventorySource for CsvFile {
+    async fn load(&self) -> Result<Inventory, String> {
+        let path = self.path.clone();
+        let commands: BTreeMap<String, String> = smol::unblock(move || {
+            generate_kv_hosts_from_csv(&path.to_string_lossy())
+                .map_err(|e| format!("{}: {}", path.display(), e))
+        })
+        .await?
+        .into_iter()
+        .map(|(addr, command)| (addr.to_string(), command))
+        .collect();
+        let hosts = commands
+            .keys()
+            .map(|addr| InventoryHost {
+                addr: addr.clone(),
+                group: None,
+                parents: Vec::new(),
+                vars: BTreeMap::new(),
+            })
+            .collect();
+        Ok(Inventory { hosts, commands })
+    }
+}
+
+/// Source of the CLI inventory, the `inventory` config table. `type = "file"`, the
+/// default, reads `--hosts`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum InventoryConfig {
+    File,
+    /// e.g. `inventory = { type = "http", url = "http://cmdb/api/hosts" }`.
+    #[cfg(feature = "http-inventory")]
+    Http(crate::http_inventory::HttpInventory),
+}
+
+impl Default for InventoryConfig {
+    fn default() -> Self {
+        InventoryConfig::File
+    }
+}
+
+/// Copy of the loaded inventory on disk, e.g.
+/// `inventory_cache = { path = "inventory.json", ttl_secs = 3600 }`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct InventoryCache {
+    pub path: PathBuf,
+    /// Age up to which the copy is used without asking the source.
+    pub ttl_secs: u64,
+}
+
+impl InventoryCache {
+    fn read(&self) -> Option<Inventory> {
+        let content = fs::read_to_string(&self.path).ok()?;
+        serde_json::from_str(&content).ok()
+    }
+
+    fn age(&self) -> Option<Duration> {
+        let modified = fs::metadata(&self.path).and_then(|m| m.modified()).ok()?;
+        SystemTime::now().duration_since(modified).ok()
+    }
+
+    fn write(&self, inventory: &Inventory) -> Result<(), String> {
+        let content = serde_json::to_string(inventory).map_err(|e| e.to_string())?;
+        // Renamed into place, so a reader never sees half a copy.
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, content)
+            .and_then(|_| fs::rename(&tmp, &self.path))
+            .map_err(|e| format!("{}: {}", self.path.display(), e))
+    }
+}
+
+/// Loads `source`, through `cache` when given: a copy younger than its TTL is used as is,
+/// and an older one stands in for the source when the source fails, so a run works
+/// offline with the inventory last fetched.
+pub async fn load_inventory<S>(
+    source: &S,
+    cache: Option<&InventoryCache>,
+) -> Result<Inventory, String>
+where
+    S: InventorySource + Sync,
+{
+    let cache = match cache {
+        Some(cache) => cache,
+        None => return source.load().await,
+    };
+    if let Some(age) = cache.age() {
+        if age < Duration::from_secs(cache.ttl_secs) {
+            if let Some(inventory) = cache.read() {
+                return Ok(inventory);
+            }
+        }
+    }
+    match source.load().await {
+        Ok(inventory) => {
+            if let Err(e) = cache.write(&inventory) {
+                eprintln!("Error caching the inventory: {}", e);
+            }
+            Ok(inventory)
+        }
+        Err(e) => match cache.read() {
+            Some(inventory) => {
+                eprintln!(
+                    "Error loading the inventory, using the copy in {}: {}",
+                    cache.path.display(),
+                    e
+                );
+                Ok(inventory)
+            }
+            None => Err(e),
+        },
+    }
+}
@@ -0,0 +1,211 @@
+use crate::transport::Transport;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::Mutex;
+
+/// What happens when a host presents a key other than the one first seen for it.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HostKeyPolicy {
+    /// Fail the host with `ErrorKind::HostKeyChanged`.
+    Fail,
+    /// Report the change in the response's `host_key`, and run anyway.
+    Warn,
+}
+
+impl Default for HostKeyPolicy {
+    fn default() -> Self {
+        HostKeyPolicy::Fail
+    }
+}
+
+/// Host key fingerprint recorded in a response.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct HostKeyInfo {
+    /// SHA256 fingerprint presented by the host, in OpenSSH notation.
+    pub fingerprint: String,
+    /// Fingerprint the store trusted for the host, when it differed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous: Option<String>,
+}
+
+/// Trust-on-first-use store of host key fingerprints, keyed by `host:port`.
+///
+/// The first fingerprint seen for a key is recorded and later connections are checked
+/// against it. A changed key is never recorded over the trusted one; `forget_host` drops
+/// it so the next connection records the new key.
+///
+/// The file holds one `host:port fingerprint` pair per line, other lines are ignored. Writes are serialized within
+/// the process, merge entries other processes added meanwhile, and replace the file
+/// through a rename so a reader never sees it half written.
+#[derive(Debug)]
+pub struct HostKeyStore {
+    path: PathBuf,
+    entries: Mutex<BTreeMap<String, String>>,
+}
+
+impl HostKeyStore {
+    /// Opens the store at `path`, empty when the file does not exist yet.
+    pub fn open<P: Into<PathBuf>>(path: P) -> io::Result<Self> {
+        let path = path.into();
+        let entries = read_entries(&path)?;
+        Ok(HostKeyStore {
+            path,
+            entries: Mutex::new(entries),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Fingerprint trusted for `key`.
+    pub fn get(&self, key: &str) -> Option<String> {
+        self.entries.lock().unwrap().get(key).cloned()
+    }
+
+    /// Checks `fingerprint` against the one trusted for `key`, recording it when there is
+    /// none. Returns the trusted fingerprint when it differs.
+    pub fn check(&self, key: &str, fingerprint: &str) -> io::Result<Option<String>> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(trusted) if trusted == fingerprint => Ok(None),
+            Some(trusted) => Ok(Some(trusted.clone())),
+            None => {
+                let mut on_disk = read_entries(&self.path)?;
+                if let Some(trusted) = on_disk.get(key) {
+                    // Recorded by another process since the store was opened.
+                    let trusted = trusted.clone();
+                    entries.insert(key.to_string(), trusted.clone());
+                    return Ok(Some(trusted).filter(|t| t != fingerprint));
+                }
+                on_disk.insert(key.to_string(), fingerprint.to_string());
+                write_entries(&self.path, &on_disk)?;
+                *entries = on_disk;
+                Ok(None)
+            }
+        }
+    }
+
+    /// Drops the fingerprints of `host`, either one `host:port` key or every port of a bare
+    /// host name. Returns the number of entries removed.
+    pub fn forget_host(&self, host: &str) -> io::Result<usize> {
+        let mut entries = self.entries.lock().unwrap();
+        let mut on_disk = read_entries(&self.path)?;
+        let before = on_disk.len();
+        on_disk.retain(|key, _| !matches_host(key, host));
+        let removed = before - on_disk.len();
+        if removed > 0 {
+            write_entries(&self.path, &on_disk)?;
+        }
+        *entries = on_disk;
+        Ok(removed)
+    }
+
+    /// Numbers of the lines of the file which are no `host:port SHA256:<base64>` entry.
+    /// They are ignored, so the hosts they were meant for are not trusted.
+    pub(crate) fn invalid_lines(&self) -> io::Result<Vec<usize>> {
+        let content = match fs::read_to_string(&self.path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e),
+        };
+        Ok(content
+            .lines()
+            .enumerate()
+            .filter(|(_, line)| !line.trim().is_empty() && !line.starts_with('#'))
+            .filter(|(_, line)| parse_entry(line).is_none())
+            .map(|(idx, _)| idx + 1)
+            .collect())
+    }
+
+    /// Checks new fingerprints can be recorded, by creating a file next to the store.
+    pub(crate) fn check_writable(&self) -> io::Result<()> {
+        let mut probe = self.path.as_os_str().to_owned();
+        probe.push(format!(".{}.probe", process::id()));
+        let probe = PathBuf::from(probe);
+        fs::OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .open(&probe)?;
+        fs::remove_file(&probe)
+    }
+}
+
+/// Splits a `host:port fingerprint` line, `None` unless the fingerprint is formatted like
+/// `fingerprint` does.
+fn parse_entry(line: &str) -> Option<(String, String)> {
+    let mut fields = line.split_whitespace();
+    let (key, fingerprint) = (fields.next()?, fields.next()?);
+    let hash = fingerprint.strip_prefix("SHA256:")?;
+    let valid = fields.next().is_none()
+        && key.contains(':')
+        && hash.len() == 43
+        && hash
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'+' || c == b'/');
+    if valid {
+        Some((key.to_string(), fingerprint.to_string()))
+    } else {
+        None
+    }
+}
+
+/// Whether store key `key` belongs to `host`, given as `host:port` or as a bare host.
+fn matches_host(key: &str, host: &str) -> bool {
+    key == host
+        || key.rfind(':').map_or(false, |idx| {
+            key[..idx].trim_matches(|c| c == '[' || c == ']') == host
+        })
+}
+
+fn read_entries(path: &Path) -> io::Result<BTreeMap<String, String>> {
+    let content = match fs::read_to_string(path) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(BTreeMap::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .filter(|line| !line.trim().is_empty() && !line.starts_with('#'))
+        .filter_map(parse_entry)
+        .collect())
+}
+
+fn write_entries(path: &Path, entries: &BTreeMap<String, String>) -> io::Result<()> {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(format!(".{}.tmp", process::id()));
+    let tmp = PathBuf::from(tmp);
+    let mut file = fs::File::create(&tmp)?;
+    for (key, fingerprint) in entries {
+        writeln!(file, "{} {}", key, fingerprint)?;
+    }
+    file.sync_all()?;
+    fs::rename(&tmp, path)
+}
+
+/// SHA256 fingerprint of the key the server presented, as `SHA256:` followed by unpadded
+/// base64 like `ssh-keygen -l` prints it.
+pub(crate) fn fingerprint(sess: &dyn Transport) -> Option<String> {
+    sess.host_key_sha256()
+        .map(|hash| format!("SHA256:{}", base64(&hash)))
+}
+
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((bytes.len() * 4 + 2) / 3);
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | u32::from(*b) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[(n >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    out
+}
@@ -0,0 +1,284 @@
+use crate::inventory::RunPlan;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+
+/// Pattern which makes a command dangerous to run across a fleet.
+pub struct LintRule {
+    /// Id the rule is reported and disabled by.
+    pub id: &'static str,
+    pub summary: &'static str,
+    check: fn(&LintedCommand) -> bool,
+}
+
+/// Command of one host as the rules see it.
+struct LintedCommand<'a> {
+    command: &'a str,
+    assigned_args: &'a [String],
+}
+
+/// Every rule `CommandLint` applies unless disabled.
+pub const LINT_RULES: [LintRule; 7] = [
+    LintRule {
+        id: "rm-variable-path",
+        summary: "recursive rm of a path made of a variable alone",
+        check: rm_variable_path,
+    },
+    LintRule {
+        id: "rm-root",
+        summary: "recursive rm of /",
+        check: rm_root,
+    },
+    LintRule {
+        id: "mkfs",
+        summary: "creates a filesystem",
+        check: mkfs,
+    },
+    LintRule {
+        id: "dd-to-disk",
+        summary: "dd writing to a disk device",
+        check: dd_to_disk,
+    },
+    LintRule {
+        id: "fork-bomb",
+        summary: "fork bomb",
+        check: fork_bomb,
+    },
+    LintRule {
+        id: "empty-value",
+        summary: "an assigned argument rendered empty",
+        check: empty_value,
+    },
+    LintRule {
+        id: "unquoted-variable-glob",
+        summary: "unquoted variable next to a glob",
+        check: unquoted_variable_glob,
+    },
+];
+
+/// Checks the command of every host of a plan against `LINT_RULES`, e.g.
+///
+/// ```toml
+/// [lint]
+/// strict = true
+/// disabled = ["unquoted-variable-glob"]
+/// ```
+///
+/// The rules look for a few well known disasters, not for every unsafe command.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandLint {
+    /// Refuse to run when a rule matched, instead of warning.
+    pub strict: bool,
+    /// Ids of rules not applied.
+    pub disabled: Vec<String>,
+}
+
+impl CommandLint {
+    /// Rejects unknown rule ids.
+    pub fn validate(&self) -> Result<(), String> {
+        match self
+            .disabled
+            .iter()
+            .find(|id| !LINT_RULES.iter().any(|rule| rule.id == id.as_str()))
+        {
+            Some(id) => Err(format!("lint.disabled: unknown rule {}", id)),
+            None => Ok(()),
+        }
+    }
+
+    /// Ids of the rules `command` matches; `assigned_args` are the arguments appended to it
+    /// for the host.
+    pub fn check(&self, command: &str, assigned_args: &[String]) -> Vec<&'static str> {
+        let linted = LintedCommand {
+            command,
+            assigned_args,
+        };
+        LINT_RULES
+            .iter()
+            .filter(|rule| !self.disabled.iter().any(|id| id == rule.id))
+            .filter(|rule| (rule.check)(&linted))
+            .map(|rule| rule.id)
+            .collect()
+    }
+
+    /// Hosts of `plan` each rule matched.
+    pub fn check_plan(&self, plan: &RunPlan) -> LintReport {
+        let mut findings: BTreeMap<&'static str, Vec<String>> = BTreeMap::new();
+        for host in &plan.hosts {
+            for id in self.check(&host.given, &host.assigned_args) {
+                findings.entry(id).or_default().push(host.target.clone());
+            }
+        }
+        LintReport { findings }
+    }
+}
+
+/// Hosts each rule matched on, by rule id.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct LintReport {
+    pub findings: BTreeMap<&'static str, Vec<String>>,
+}
+
+impl LintReport {
+    pub fn is_clean(&self) -> bool {
+        self.findings.is_empty()
+    }
+}
+
+/// Hosts listed per rule before the rest are only counted.
+const HOSTS_SHOWN: usize = 5;
+
+impl Display for LintReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (id, hosts) in &self.findings {
+            let summary = LINT_RULES
+                .iter()
+                .find(|rule| rule.id == *id)
+                .map_or("", |rule| rule.summary);
+            write!(
+                f,
+                "lint {} ({}) on {} host(s): {}",
+                id,
+                summary,
+                hosts.len(),
+                hosts[..hosts.len().min(HOSTS_SHOWN)].join(", ")
+            )?;
+            if hosts.len() > HOSTS_SHOWN {
+                write!(f, " and {} more", hosts.len() - HOSTS_SHOWN)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// Words of each simple command of `command`, split on `;`, `&`, `|` and newlines, with
+/// `sudo` and its options dropped. Quoting is not parsed.
+fn simple_commands(command: &str) -> Vec<Vec<&str>> {
+    command
+        .split(|c| c == ';' || c == '&' || c == '|' || c == '\n')
+        .map(|part| {
+            let mut words: Vec<&str> = part.split_whitespace().collect();
+            if words.first() == Some(&"sudo") {
+                let options = words[1..].iter().take_while(|w| w.starts_with('-')).count();
+                words.drain(..1 + options);
+            }
+            words
+        })
+        .filter(|words| !words.is_empty())
+        .collect()
+}
+
+/// Arguments of the `rm` commands removing recursively.
+fn recursive_rm_args(command: &str) -> Vec<&str> {
+    simple_commands(command)
+        .into_iter()
+        .filter(|words| words[0] == "rm" || words[0].ends_with("/rm"))
+        .filter(|words| {
+            words[1..].iter().any(|w| {
+                *w == "--recursive"
+                    || (w.starts_with('-')
+                        && !w.starts_with("--")
+                        && w.contains(|c| c == 'r' || c == 'R'))
+            })
+        })
+        .flat_map(|words| words.into_iter().skip(1).filter(|w| !w.starts_with('-')))
+        .collect()
+}
+
+fn unquote(word: &str) -> &str {
+    word.trim_matches(|c| c == '"' || c == '\'')
+}
+
+/// Whether `word` is a variable alone, e.g. `$DIR`, `"${DIR}"` or `$DIR/*`.
+fn is_variable_path(word: &str) -> bool {
+    let word = word.replace(|c| c == '"' || c == '\'', "");
+    let word = word.trim_end_matches('*').trim_end_matches('/');
+    let name = match word.strip_prefix("${") {
+        Some(rest) => match rest.strip_suffix('}') {
+            Some(name) => name,
+            None => return false,
+        },
+        None => match word.strip_prefix('$') {
+            Some(name) => name,
+            None => return false,
+        },
+    };
+    !name.is_empty() && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+fn rm_variable_path(c: &LintedCommand) -> bool {
+    recursive_rm_args(c.command)
+        .into_iter()
+        .any(is_variable_path)
+}
+
+fn rm_root(c: &LintedCommand) -> bool {
+    recursive_rm_args(c.command)
+        .into_iter()
+        .any(|w| matches!(unquote(w), "/" | "/*" | "/."))
+}
+
+fn mkfs(c: &LintedCommand) -> bool {
+    simple_commands(c.command)
+        .iter()
+        .any(|words| words[0].starts_with("mkfs") || words[0] == "mke2fs")
+}
+
+/// Device names of disks and their partitions.
+const DISK_DEVICES: &[&str] = &["sd", "hd", "vd", "xvd", "nvme", "mmcblk", "disk", "mapper/"];
+
+fn dd_to_disk(c: &LintedCommand) -> bool {
+    simple_commands(c.command)
+        .iter()
+        .filter(|words| words[0] == "dd")
+        .flat_map(|words| words[1..].iter())
+        .filter_map(|w| unquote(w).strip_prefix("of=/dev/"))
+        .any(|device| DISK_DEVICES.iter().any(|disk| device.starts_with(disk)))
+}
+
+/// Matches `name(){ name|name& };name` whatever the name and spacing.
+fn fork_bomb(c: &LintedCommand) -> bool {
+    let compact: String = c.command.chars().filter(|c| !c.is_whitespace()).collect();
+    compact.match_indices("(){").any(|(i, _)| {
+        let name = compact[..i]
+            .rsplit(|c: char| c == ';' || c == '&' || c == '|')
+            .next()
+            .unwrap_or("");
+        !name.is_empty() && compact[i..].starts_with(&format!("(){{{0}|{0}&}};{0}", name))
+    })
+}
+
+fn empty_value(c: &LintedCommand) -> bool {
+    c.assigned_args.iter().any(|arg| arg.trim().is_empty())
+}
+
+/// `command` with the text inside quotes blanked out, quotes kept.
+fn blank_quoted(command: &str) -> String {
+    let mut quote = None;
+    command
+        .chars()
+        .map(|c| match quote {
+            Some(q) if c == q => {
+                quote = None;
+                c
+            }
+            Some(_) => '_',
+            None => {
+                if c == '"' || c == '\'' {
+                    quote = Some(c);
+                }
+                c
+            }
+        })
+        .collect()
+}
+
+fn unquoted_variable_glob(c: &LintedCommand) -> bool {
+    blank_quoted(c.command)
+        .split(|c: char| c.is_whitespace() || c == ';' || c == '&' || c == '|')
+        // `$?` is the exit code, not a glob.
+        .map(|word| word.replace("$?", ""))
+        .any(|word| word.contains('$') && word.contains(|c| c == '*' || c == '?'))
+}
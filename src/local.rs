@@ -0,0 +1,442 @@
+use crate::escalation::Escalation;
+use crate::inventory::HostOptions;
+use crate::output::OutputCollector;
+use crate::output_hash::OutputHasher;
+use crate::progress::{HostProgress, Phase};
+use crate::proxy::Target;
+use crate::pty::strip_ansi;
+use crate::response::{CommandOutput, ConnectionInfo, ErrorKind, HostError, HostTimings};
+use crate::scheduler::ParallelSshProps;
+use crate::session::{FollowupOutput, HostCommands, HostFacts, HostOutput};
+use crate::shell::{check_no_nul, RemoteShell};
+use crate::target::HostTarget;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, SocketAddr};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::str::FromStr;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+/// How a host is reached, ansible's `ansible_connection`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Connection {
+    Ssh,
+    /// The command runs on the controller through `sh -c`, as the user running the
+    /// crate; no port is probed and nothing is connected to.
+    Local,
+    /// The command runs in a container through `docker exec`, as the container's user.
+    /// The container is the host's `HostOptions::container`, its name when unset.
+    Docker,
+    /// Like `Docker`, through `podman exec`.
+    Podman,
+}
+
+impl FromStr for Connection {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "ssh" => Ok(Connection::Ssh),
+            "local" => Ok(Connection::Local),
+            "docker" => Ok(Connection::Docker),
+            "podman" => Ok(Connection::Podman),
+            _ => Err(format!("Unknown connection: {}", s)),
+        }
+    }
+}
+
+/// How often a local command is checked for having exited.
+const POLL: Duration = Duration::from_millis(10);
+
+/// Process the commands of a host run in, instead of an SSH channel.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum LocalProcess {
+    /// `sh -c` on the controller.
+    Shell,
+    /// `sh -c` in `container`, through `<runtime> exec`.
+    Container {
+        runtime: &'static str,
+        container: String,
+    },
+    /// `sh -c` in a pod for a `k8s://` host, through `kubectl exec`, or through the exec
+    /// API of the cluster's API server with the `kube` feature.
+    Pod(String),
+}
+
+impl LocalProcess {
+    fn command(&self, command: &str) -> Result<Command, HostError> {
+        let mut process = match self {
+            LocalProcess::Shell => Command::new("sh"),
+            LocalProcess::Container { runtime, container } => {
+                let mut process = Command::new(runtime);
+                // -i keeps stdin open, for become passwords.
+                process.arg("exec").arg("-i").arg(container).arg("sh");
+                process
+            }
+            LocalProcess::Pod(address) => {
+                let pod = PodAddress::parse(address)?;
+                let mut process = Command::new("kubectl");
+                process
+                    .arg("exec")
+                    .arg("-i")
+                    .arg("--namespace")
+                    .arg(pod.namespace)
+                    .arg(pod.pod);
+                if let Some(container) = pod.container {
+                    process.arg("--container").arg(container);
+                }
+                process.arg("--").arg("sh");
+                process
+            }
+        };
+        process.arg("-c").arg(command);
+        Ok(process)
+    }
+
+    /// Name of the process in errors, and auth method of its responses.
+    fn name(&self) -> &'static str {
+        match self {
+            LocalProcess::Shell => "local",
+            LocalProcess::Container { runtime, .. } => runtime,
+            #[cfg(not(feature = "kube"))]
+            LocalProcess::Pod(_) => "kubectl",
+            #[cfg(feature = "kube")]
+            LocalProcess::Pod(_) => "kube",
+        }
+    }
+}
+
+/// Scheme of hosts which are pods, e.g. `k8s://default/web-0/nginx`.
+const POD_SCHEME: &str = "k8s://";
+
+/// Parts of a `k8s://namespace/pod[/container]` host, the container being the pod's
+/// default one when left out.
+pub(crate) struct PodAddress<'a> {
+    pub(crate) namespace: &'a str,
+    pub(crate) pod: &'a str,
+    pub(crate) container: Option<&'a str>,
+}
+
+impl<'a> PodAddress<'a> {
+    fn parse(address: &'a str) -> Result<Self, HostError> {
+        let invalid = || {
+            HostError::new(
+                ErrorKind::Dns,
+                format!(
+                    "Invalid pod address: {}, expected k8s://namespace/pod[/container]",
+                    address
+                ),
+            )
+        };
+        let path = address.strip_prefix(POD_SCHEME).ok_or_else(invalid)?;
+        let parts: Vec<&str> = path.split('/').collect();
+        if parts.iter().any(|part| part.is_empty()) {
+            return Err(invalid());
+        }
+        match parts[..] {
+            [namespace, pod] => Ok(PodAddress {
+                namespace,
+                pod,
+                container: None,
+            }),
+            [namespace, pod, container] => Ok(PodAddress {
+                namespace,
+                pod,
+                container: Some(container),
+            }),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+/// How `host` runs when not over SSH: in its pod for a `k8s://` host, as its options say,
+/// or on the controller when it is a loopback address or `localhost` and the props run
+/// those locally.
+pub(crate) fn local_process(
+    host: &HostTarget,
+    options: &HostOptions,
+    props: &ParallelSshProps,
+) -> Option<LocalProcess> {
+    let container = |runtime| LocalProcess::Container {
+        runtime,
+        container: options
+            .container
+            .clone()
+            .unwrap_or_else(|| container_name(host)),
+    };
+    if let HostTarget::Name(name) = host {
+        if name.starts_with(POD_SCHEME) {
+            return Some(LocalProcess::Pod(name.clone()));
+        }
+    }
+    match options.connection {
+        Some(Connection::Ssh) => None,
+        Some(Connection::Local) => Some(LocalProcess::Shell),
+        Some(Connection::Docker) => Some(container("docker")),
+        Some(Connection::Podman) => Some(container("podman")),
+        None if props.local_loopback && is_loopback(host) => Some(LocalProcess::Shell),
+        None => None,
+    }
+}
+
+/// Container named by `host`: its name without any port.
+fn container_name(host: &HostTarget) -> String {
+    match host {
+        HostTarget::Address {
+            name: Some(name), ..
+        } => name.clone(),
+        HostTarget::Address { address, .. } => address.ip().to_string(),
+        HostTarget::Name(name) => match name.rsplitn(2, ':').collect::<Vec<_>>()[..] {
+            [port, host] if port.parse::<u16>().is_ok() => host.to_string(),
+            _ => name.clone(),
+        },
+    }
+}
+
+/// Whether `host` is a loopback address or `localhost`.
+fn is_loopback(host: &HostTarget) -> bool {
+    match host {
+        HostTarget::Address { address, .. } => address.ip().is_loopback(),
+        HostTarget::Name(name) => {
+            if let Ok(address) = name.parse::<SocketAddr>() {
+                return address.ip().is_loopback();
+            }
+            if let Ok(ip) = name.parse::<IpAddr>() {
+                return ip.is_loopback();
+            }
+            let host = name.rsplitn(2, ':').last().unwrap_or(name);
+            host.eq_ignore_ascii_case("localhost")
+                || host.parse::<IpAddr>().map_or(false, |ip| ip.is_loopback())
+        }
+    }
+}
+
+/// Target of a local host, standing for it in its response. A pod has no port, and
+/// stands for itself.
+pub(crate) fn local_target(host: &HostTarget) -> Result<Target, HostError> {
+    match host {
+        HostTarget::Address { address, .. } => Ok(Target::Resolved(*address)),
+        HostTarget::Name(name) if name.starts_with(POD_SCHEME) => {
+            PodAddress::parse(name)?;
+            Ok(Target::Unresolved {
+                host: name.clone(),
+                port: 0,
+            })
+        }
+        HostTarget::Name(name) => Target::unresolved(name),
+    }
+}
+
+/// Runs `commands` in `process` instead of over SSH, with the output, exit code and
+/// timeouts handled as for a remote host.
+pub(crate) fn run_local(
+    process: &LocalProcess,
+    commands: &HostCommands,
+    shell: RemoteShell,
+    props: &ParallelSshProps,
+    host_deadline: Option<Instant>,
+    facts: &mut HostFacts,
+    progress: &HostProgress,
+) -> Result<HostOutput, HostError> {
+    facts.followups.clear();
+    if props.skip_if.is_some()
+        || props.guard.is_some()
+        || commands.post_condition.is_some()
+        || props.transfer.is_some()
+    {
+        return Err(HostError::new(
+            ErrorKind::Exec,
+            "Skip checks, guards, post conditions and file transfers need an SSH connection, \
+             and the host runs without one"
+                .to_string(),
+        ));
+    }
+    progress.set_phase(Phase::Running);
+    let escalation = commands.escalation.as_ref();
+    let out = HostTimings::time(&mut facts.timings.exec, || {
+        run_command(
+            process,
+            &commands.command,
+            shell,
+            props,
+            host_deadline,
+            escalation,
+        )
+    })?;
+    for (_, command) in &commands.followups {
+        let start = Instant::now();
+        let result = run_command(process, command, shell, props, host_deadline, escalation);
+        let failed = result.is_err();
+        facts.followups.push(FollowupOutput {
+            duration: start.elapsed(),
+            result,
+        });
+        if failed {
+            break;
+        }
+    }
+    Ok(HostOutput {
+        output: out.output,
+        stderr: out.stderr,
+        encoding: Some(out.encoding),
+        discarded: out.discarded,
+        exit_code: Some(out.exit_code),
+        connection: Some(ConnectionInfo {
+            auth_method: process.name().to_string(),
+            output_bytes: out.output_bytes,
+            ..ConnectionInfo::default()
+        }),
+        passed_through: None,
+        output_hash: out.output_hash,
+        upload: None,
+        fetch: None,
+    })
+}
+
+/// Runs `command` in `process`, failing with `ErrorKind::ReadTotalTimeout` once the
+/// props' `read_total` limit or `host_deadline` passes.
+fn run_command(
+    process: &LocalProcess,
+    command: &str,
+    shell: RemoteShell,
+    props: &ParallelSshProps,
+    host_deadline: Option<Instant>,
+    escalation: Option<&Escalation>,
+) -> Result<CommandOutput, HostError> {
+    check_no_nul(command)?;
+    let timeouts = props.timeouts.within(host_deadline);
+    let deadline = timeouts.read_total.map(|t| Instant::now() + t);
+    let input = escalation
+        .and_then(|e| e.password.as_deref())
+        .map(|password| format!("{}\n", password));
+    let (stdout, stderr, exit_code) = match process {
+        #[cfg(feature = "kube")]
+        LocalProcess::Pod(address) => {
+            crate::pod_exec::exec(&PodAddress::parse(address)?, command, input, deadline)?
+        }
+        _ => spawn_command(process, command, input, deadline)?,
+    };
+
+    let mut collector = OutputCollector::new(props.keep_output);
+    collector.feed(&stdout);
+    let (kept, discarded) = collector.finish();
+    let (output, encoding) = shell
+        .decode_output(kept, props.output_encoding)
+        .map_err(|e| {
+            HostError::new(
+                ErrorKind::Read,
+                format!("Error reading result of work: {}", e),
+            )
+        })?;
+    let mut collector = OutputCollector::new(props.keep_output);
+    collector.feed(&stderr);
+    let (kept, _) = collector.finish();
+    let stderr = match shell.decode_output(kept.clone(), props.output_encoding) {
+        Ok((stderr, _)) => stderr,
+        Err(_) => String::from_utf8_lossy(&kept).into_owned(),
+    };
+    let (output, stderr) = if props.strip_ansi {
+        (strip_ansi(&output), strip_ansi(&stderr))
+    } else {
+        (output, stderr)
+    };
+    let output_hash = props.output_hash.map(|algorithm| {
+        let mut hasher = OutputHasher::new(algorithm);
+        hasher.update(&stdout);
+        hasher.finish()
+    });
+    Ok(CommandOutput {
+        output,
+        stderr,
+        encoding,
+        discarded,
+        exit_code,
+        output_bytes: stdout.len() as u64,
+        output_hash,
+    })
+}
+
+/// Runs `command` in a child process of `process`, writing `input` to its stdin, and
+/// returns its stdout, stderr and exit code.
+fn spawn_command(
+    process: &LocalProcess,
+    command: &str,
+    input: Option<String>,
+    deadline: Option<Instant>,
+) -> Result<(Vec<u8>, Vec<u8>, i32), HostError> {
+    let mut child = process
+        .command(command)?
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| {
+            HostError::new(
+                ErrorKind::Exec,
+                format!("Failed starting {} command: {}", process.name(), e),
+            )
+        })?;
+    let mut stdin = child.stdin.take().expect("piped stdin");
+    if let Some(input) = input {
+        // A command which exits without reading its stdin is no error.
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    drop(stdin);
+    let stdout = read_all(child.stdout.take().expect("piped stdout"));
+    let stderr = read_all(child.stderr.take().expect("piped stderr"));
+    let status = wait(&mut child, deadline)?;
+    let read = |reader: JoinHandle<io::Result<Vec<u8>>>| {
+        reader
+            .join()
+            .unwrap_or_else(|_| Err(io::Error::new(io::ErrorKind::Other, "reader panicked")))
+            .map_err(|e| HostError::new(ErrorKind::Read, format!("Error reading output: {}", e)))
+    };
+    Ok((read(stdout)?, read(stderr)?, exit_code(status)))
+}
+
+/// Reads `source` to its end on a thread of its own, so a full stderr pipe does not stall
+/// a command writing to stdout.
+fn read_all<R: Read + Send + 'static>(mut source: R) -> JoinHandle<io::Result<Vec<u8>>> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        source.read_to_end(&mut buf).map(|_| buf)
+    })
+}
+
+/// Waits for `child` to exit, killing it at `deadline`.
+fn wait(child: &mut Child, deadline: Option<Instant>) -> Result<ExitStatus, HostError> {
+    loop {
+        match child.try_wait() {
+            Ok(Some(status)) => return Ok(status),
+            Ok(None) if deadline.map_or(false, |d| Instant::now() >= d) => {
+                let _ = child.kill();
+                let _ = child.wait();
+                return Err(HostError::new(
+                    ErrorKind::ReadTotalTimeout,
+                    "Command did not finish in time and was killed".to_string(),
+                ));
+            }
+            Ok(None) => thread::sleep(POLL),
+            Err(e) => {
+                return Err(HostError::new(
+                    ErrorKind::Exec,
+                    format!("Failed waiting for command: {}", e),
+                ))
+            }
+        }
+    }
+}
+
+/// Exit code of `status`, 128 plus the signal for a command killed by one, like a shell.
+fn exit_code(status: ExitStatus) -> i32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::ExitStatusExt;
+        if let Some(signal) = status.signal() {
+            return 128 + signal;
+        }
+    }
+    status.code().unwrap_or(-1)
+}
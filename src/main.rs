@@ -1,29 +1,42 @@
-use ansible_rs::{ParallelSshProps, ParallelSshPropsBuilder, Response};
-use chrono::Utc;
+use ansible_rs::compare::{load_results, DiffReport};
+use ansible_rs::inventory_source::{
+    load_inventory, CsvFile, HostsFile, Inventory, InventoryConfig, YamlFile,
+};
+use ansible_rs::misc::{
+    check_output_path, fit_fd_budget, group_builder, incremental_save, interrupted, load_config,
+    load_host_passwords, load_run_context, print_detailed, print_plan, print_summary,
+    save_diff_report, save_plan, save_run_context, save_to_console, save_to_file,
+    warn_host_key_change, watch_interrupt, watch_limit_signals, Config, EffectiveSettings,
+    ProgressMode, DEFAULT_INTERRUPT_GRACE,
+};
+use ansible_rs::prelude::{
+    FdMonitor, HostKeyStore, HostOptions, HostStatus, HostTarget, ParallelSshProps,
+    ParallelSshPropsBuilder, RunHandle, RunPlan,
+};
+use ansible_rs::tags::validate_tags;
 use clap::crate_version;
-use clap::{App, Arg};
-use color_backtrace;
-use crossbeam_channel::Receiver;
-use indicatif::{ProgressBar, ProgressStyle};
-use rayon::prelude::*;
-use rayon::ThreadPoolBuilder;
-use std::collections::BTreeMap;
-use std::fs::File;
-use std::io::prelude::*;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use std::net::IpAddr;
 use std::path::{Path, PathBuf};
+use std::sync::Arc;
 
 use std::thread::spawn;
-use std::time::{Duration};
+use std::time::Duration;
 
-mod misc;
-use misc::{generate_kv_hosts_from_csv, get_config, hosts_builder, Config};
 use std::net::SocketAddr;
 
+/// Group name, its settings and its hosts with their commands.
+type PlannedRun = (
+    Option<String>,
+    EffectiveSettings,
+    Vec<(HostTarget, String, HostOptions)>,
+);
+
 fn main() {
     color_backtrace::install();
     let args = App::new("ansible-rs")
         .version(crate_version!())
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(
             Arg::with_name("config")
                 .short("c")
@@ -36,8 +49,7 @@ fn main() {
         .arg(
             Arg::with_name("hosts")
                 .long("hosts")
-                .help("Path to file with hosts")
-                .required(true)
+                .help("Path to file with hosts, needed unless the config sets another inventory")
                 .takes_value(true),
         )
         .arg(
@@ -46,115 +58,508 @@ fn main() {
                 .long("format")
                 .takes_value(true)
                 .help("Hosts format")
-                .long_help("Hosts format: csv for key value and empty(default) for list")
+                .long_help(
+                    "Hosts format: csv for key value, yaml for an ansible YAML inventory and \
+                     empty(default) for list, yaml for a .yml or .yaml file",
+                )
                 .default_value(""),
         )
+        .arg(
+            Arg::with_name("output")
+                .short("o")
+                .long("output")
+                .takes_value(true)
+                .possible_values(&["json", "table"])
+                .help("Console output format, overrides the config"),
+        )
+        .arg(
+            Arg::with_name("sort")
+                .long("sort")
+                .takes_value(true)
+                .possible_values(&["none", "hostname", "duration_desc", "failures_first"])
+                .help("Row order of the table output, overrides the config"),
+        )
+        .arg(
+            Arg::with_name("expand_failed")
+                .long("expand-failed")
+                .help("Print the full output of failed hosts below the table"),
+        )
+        .arg(
+            Arg::with_name("run_id")
+                .long("run-id")
+                .takes_value(true)
+                .help("Id recorded on every result, generated when not given"),
+        )
+        .arg(
+            Arg::with_name("dry_run")
+                .long("dry-run")
+                .help("Print the hosts and commands of the run without connecting anywhere"),
+        )
+        .arg(
+            Arg::with_name("plan_output")
+                .long("plan-output")
+                .takes_value(true)
+                .requires("dry_run")
+                .help("Also save the dry-run plan as JSON to this file"),
+        )
+        .arg(
+            Arg::with_name("banners")
+                .long("banners")
+                .help("Only collect each host's SSH banner, without running the command"),
+        )
+        .arg(
+            Arg::with_name("strict_config")
+                .long("strict-config")
+                .help("Refuse to run with unknown config keys instead of warning about them"),
+        )
+        .arg(
+            Arg::with_name("legacy_format")
+                .long("legacy-format")
+                .help("List failed hosts by name only, as before records with their commands"),
+        )
+        .arg(
+            Arg::with_name("yes_i_mean_it")
+                .long("yes-i-mean-it")
+                .help("Run although the command lint flagged commands in strict mode"),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .short("l")
+                .long("limit")
+                .takes_value(true)
+                .help("Run only on these groups or hosts, e.g. webservers:!staging"),
+        )
+        .arg(
+            Arg::with_name("skip_preflight")
+                .long("skip-preflight")
+                .help("Start the run without checking agent, inventory, DNS and output first"),
+        )
+        .subcommand(
+            SubCommand::with_name("debug-host")
+                .about("Run the command on one host alone and show each step it went through")
+                .arg(
+                    Arg::with_name("host")
+                        .required(true)
+                        .help("Host address or name, with :port when not 22"),
+                )
+                .arg(
+                    Arg::with_name("command")
+                        .help("Command to run, the config's command when not given"),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("replay")
+                .about("Run the hosts of a failed hosts file again, with their recorded commands")
+                .arg(
+                    Arg::with_name("file")
+                        .required(true)
+                        .help("Failed hosts file of a previous run"),
+                )
+                .arg(
+                    Arg::with_name("force")
+                        .long("force")
+                        .help("Also run hosts whose command may already have run"),
+                ),
+        )
         .get_matches();
-    let config: Config = confy::load_path(args.value_of("config").unwrap()).unwrap();
-    let command = &config.command;
-
-    let hosts = if args.value_of("hosts_format").unwrap() == "csv" {
-        let hosts = generate_kv_hosts_from_csv(&args.value_of("hosts").unwrap()).unwrap();
-        hosts
-            .into_iter()
-            .map(|(ad, com)| (SocketAddr::new(IpAddr::from(ad), 22), com))
-            .collect()
-    } else {
-        let mut map = BTreeMap::new();
-        for h in hosts_builder(Path::new(&args.value_of("hosts").unwrap())) {
-            map.insert(SocketAddr::new(IpAddr::from(h), 22), command.clone());
+    let (mut config, unknown_keys) = load_config(Path::new(args.value_of("config").unwrap()))
+        .unwrap_or_else(|e| {
+            eprintln!("Invalid config: {}", e);
+            std::process::exit(1)
+        });
+    for key in &unknown_keys {
+        eprintln!("Config: {}", key);
+    }
+    if !unknown_keys.is_empty() && (config.strict_config || args.is_present("strict_config")) {
+        eprintln!("Unknown config keys, not starting the run in strict mode");
+        std::process::exit(1)
+    }
+    if let Err(e) = config
+        .retry
+        .validate()
+        .and_then(|_| validate_tags(&config.tags))
+        .and_then(|_| config.check_pass_through())
+        .and_then(|_| config.check_shared_append())
+        .and_then(|_| config.check_timeouts())
+        .and_then(|_| config.check_batches())
+        .and_then(|_| config.become_password().map(|_| ()))
+        .and_then(|_| {
+            config
+                .failure_threshold
+                .as_ref()
+                .map_or(Ok(()), |threshold| threshold.validate())
+        })
+        .and_then(|_| {
+            config
+                .request_pty
+                .as_ref()
+                .map_or(Ok(()), |pty| pty.validate())
+        })
+        .and_then(|_| config.check_inventory())
+        .and_then(|_| config.lint.as_ref().map_or(Ok(()), |lint| lint.validate()))
+    {
+        eprintln!("Invalid config: {}", e);
+        std::process::exit(1)
+    }
+    if let Some(format) = args.value_of("output") {
+        config.output.console_format = format.parse().unwrap();
+    }
+    if let Some(sort) = args.value_of("sort") {
+        config.output.sort = sort.parse().unwrap();
+    }
+    if args.is_present("expand_failed") {
+        config.output.expand_failed = true;
+    }
+    if args.is_present("legacy_format") {
+        config.output.legacy_failed_hosts = true;
+    }
+    let host_key_store = config.known_hosts.as_ref().map(|path| {
+        Arc::new(HostKeyStore::open(path).unwrap_or_else(|e| {
+            eprintln!("Error reading known hosts {}: {}", path.display(), e);
+            std::process::exit(1)
+        }))
+    });
+    if let Some(debug) = args.subcommand_matches("debug-host") {
+        debug_host(&config, &host_key_store, debug);
+        return;
+    }
+    if let Some(replay) = args.subcommand_matches("replay") {
+        replay_failed(&config, &host_key_store, replay);
+        return;
+    }
+    let mut inventory = smol::run(load_cli_inventory(&config, &args)).unwrap_or_else(|e| {
+        eprintln!("Error loading the inventory: {}", e);
+        std::process::exit(1)
+    });
+    // Checked against the whole inventory, before --limit narrows it.
+    let passwords = config.host_passwords.as_ref().map(|path| {
+        load_host_passwords(path, &inventory.hosts).unwrap_or_else(|e| {
+            eprintln!("Error reading host passwords: {}", e);
+            std::process::exit(1)
+        })
+    });
+    let mut plans = config
+        .select_hosts(&mut inventory, args.value_of("limit"))
+        .unwrap_or_else(|e| {
+            eprintln!("Not starting the run, {}", e);
+            std::process::exit(1)
+        });
+    for (host, options) in plans.iter_mut().flat_map(|plan| plan.hosts.iter_mut()) {
+        if let Some(password) = passwords.as_ref().and_then(|p| p.get(host)) {
+            options.password = Some(password.clone());
         }
-        map
-    };
-    dbg!(&config);
-    ThreadPoolBuilder::new()
-        .num_threads(config.threads)
-        .build_global()
-        .expect("failed creating pool");
-    let (channel, ssh_processor): (_, ParallelSshProps) = ParallelSshPropsBuilder::default()
-        .agent_connections_pool(config.agent_parallelism)
-        .tcp_connections_pool(config.threads as isize)
-        .timeout_socket(Duration::from_millis(config.timeout as u64))
-        .timeout_ssh(Duration::from_secs(60))
+    }
+    let mut plans: Vec<PlannedRun> = plans
+        .into_iter()
+        .map(|plan| {
+            let hosts = plan.targets(&inventory.commands);
+            (plan.name, plan.settings, hosts)
+        })
+        .collect();
+    let fd_budget = fit_fd_budget(
+        &config,
+        plans
+            .iter_mut()
+            .map(|(_, settings, _)| &mut settings.threads)
+            .collect(),
+    )
+    .unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        std::process::exit(1)
+    });
+    let mut base = ParallelSshPropsBuilder::default();
+    base.agent_connections_pool(config.agent_parallelism);
+    if let Some(run_id) = args.value_of("run_id") {
+        base.run_id(run_id.to_string());
+    }
+    if let Some(chain) = &config.auth_chain {
+        base.auth_chain(chain.clone());
+    }
+    if let Some(proxy) = &config.proxy {
+        base.proxy(proxy.clone());
+    }
+    if let Some(ttl) = config.dns_cache_ttl {
+        base.dns_cache_ttl(Duration::from_secs(ttl));
+    }
+    let (channel, ssh_processor): (_, ParallelSshProps) = base
         .build()
         .expect("Failed building ssh_processor instance");
-    let len = hosts.len();
-    let handler = spawn(move || incremental_save(channel, len));
-    ssh_processor.parallel_ssh_process(hosts);
-    handler.join().unwrap();
-}
-
-fn progress_bar_creator(queue_len: u64) -> ProgressBar {
-    let total_hosts_processed = ProgressBar::new(queue_len);
-    let total_style = ProgressStyle::default_bar()
-        .template("{eta_precise} {wide_bar} Hosts processed: {pos}/{len} Speed: {per_sec} {msg}")
-        .progress_chars("##-");
-    total_hosts_processed.set_style(total_style);
-
-    total_hosts_processed
-}
-
-fn config_incremental_folders() -> File {
-    let datetime = Utc::now().format("%H_%M_%S").to_string();
-    let filename = &datetime;
-    let store_dir_date = Utc::today().format("%d_%B_%Y").to_string();
-    if !Path::new(&store_dir_date).exists() {
-        std::fs::create_dir(Path::new(&store_dir_date))
-            .expect("Failed creating dir for temporary save");
-    }
-    let incremental_name = PathBuf::from(store_dir_date + "/incremental_" + &filename + ".json");
-    File::create(incremental_name).expect("incremental salving failed.")
-}
-enum Stat {
-    Ok,
-    Fail,
-    TokenFail,
-}
-fn progress_bar_display(queue_len: u64, rx: std::sync::mpsc::Receiver<Stat>) {
-    let mut ok = 0;
-    let mut ko = 0;
-    let mut token = 0;
-    let total = progress_bar_creator(queue_len);
-    for _ in 0..queue_len {
-        let stat = match rx.recv() {
-            Ok(a) => a,
-            Err(e) => {
-                eprintln!("Error receiving stats: {}", e);
-                return;
+    if !args.is_present("skip_preflight") && !args.is_present("dry_run") {
+        let hosts: Vec<HostTarget> = plans
+            .iter()
+            .flat_map(|(_, _, hosts)| hosts.iter().map(|(host, _, _)| host.clone()))
+            .collect();
+        let mut report = ssh_processor.preflight(&hosts);
+        check_output_path(&config, &mut report);
+        eprint!("{}", report);
+        if !report.passed() {
+            eprintln!("Preflight failed, not starting the run (--skip-preflight to override)");
+            std::process::exit(1)
+        }
+    }
+    let run_context = config
+        .facts
+        .as_ref()
+        .map(|facts| Arc::new(load_run_context(facts)));
+    let runs: Vec<_> = plans
+        .into_iter()
+        .map(|(group, settings, hosts)| {
+            let mut builder = group_builder(
+                &config,
+                &settings,
+                args.is_present("banners"),
+                &host_key_store,
+            );
+            if let Some(group) = group {
+                builder.group(group);
             }
+            if let Some(context) = &run_context {
+                builder.run_context(context.clone());
+            }
+            if let Some(prefix) = config.facts.as_ref().and_then(|f| f.gather_as.clone()) {
+                builder.gather_facts(prefix);
+            }
+            let props = builder
+                .build_sharing_stream(&ssh_processor)
+                .expect("Failed building ssh_processor instance");
+            (props, hosts)
+        })
+        .collect();
+    if let Some(lint) = &config.lint {
+        let mut plan = RunPlan::default();
+        for (props, hosts) in &runs {
+            plan.extend(props.plan(hosts.iter().cloned()));
+        }
+        let report = lint.check_plan(&plan);
+        eprint!("{}", report);
+        if !report.is_clean()
+            && lint.strict
+            && !args.is_present("dry_run")
+            && !args.is_present("yes_i_mean_it")
+        {
+            eprintln!("Command lint failed, not starting the run (--yes-i-mean-it to override)");
+            std::process::exit(1)
+        }
+    }
+    if args.is_present("dry_run") {
+        let mut plan = RunPlan::default();
+        for (props, hosts) in &runs {
+            plan.extend(props.plan(hosts.iter().cloned()));
+        }
+        if plan.hosts.is_empty() {
+            eprintln!("No hosts selected");
+            std::process::exit(1)
+        }
+        if let Some(path) = args.value_of("plan_output") {
+            save_plan(Path::new(path), &plan);
+        }
+        print_plan(&config, &plan);
+        return;
+    }
+    if let Some(dir) = &config.output.pass_through_dir {
+        if let Err(e) = std::fs::create_dir_all(dir) {
+            eprintln!("Error creating output directory {}: {}", dir.display(), e);
+            std::process::exit(1)
+        }
+    }
+    let previous = config.compare_to.as_ref().map(|path| {
+        let results = load_results(path).unwrap_or_else(|e| {
+            eprintln!("Error reading results to compare to: {}", e);
+            std::process::exit(1)
+        });
+        (path, results)
+    });
+    let len = runs.iter().map(|(_, hosts)| hosts.len()).sum();
+    let verbose_attempts = config.output.verbose_attempts;
+    let progress = ssh_processor.progress();
+    let run_id = ssh_processor.run_id().to_string();
+    eprintln!("Run id: {}", run_id);
+    let incremental_run_id = run_id.clone();
+    let rotation = config.output.rotate;
+    let flush = config.output.flush;
+    let shared_append = config.output.shared_append;
+    let legacy_failed_hosts = config.output.legacy_failed_hosts;
+    let memory_budget = config.output.memory_budget_bytes;
+    let progress_mode = ProgressMode::of(&config.output);
+    let limits: Vec<RunHandle> = runs.iter().map(|(props, _)| props.handle()).collect();
+    watch_limit_signals(limits.clone());
+    let interrupt_grace = config.interrupt_grace.unwrap_or(DEFAULT_INTERRUPT_GRACE);
+    watch_interrupt(
+        runs.iter().map(|(props, _)| props.clone()).collect(),
+        interrupt_grace,
+    );
+    let gauge_limits = limits.clone();
+    let fd_monitor = FdMonitor::start(Duration::from_millis(500));
+    let handler = spawn(move || {
+        incremental_save(
+            channel,
+            len,
+            verbose_attempts,
+            rotation,
+            flush,
+            shared_append,
+            legacy_failed_hosts,
+            progress,
+            gauge_limits,
+            progress_mode,
+            memory_budget,
+            &incremental_run_id,
+            interrupt_grace,
+        )
+    });
+    let runs: Vec<_> = runs
+        .into_iter()
+        .map(|(props, hosts)| spawn(move || props.parallel_ssh_process_with_options(hosts)))
+        .collect();
+    // Done waiting once the results are in, or the grace period after Ctrl-C is over.
+    let (mut results, flushes) = handler.join().unwrap();
+    for run in runs {
+        if interrupted() && !run.is_finished() {
+            continue;
+        }
+        if let Err(e) = run.join().unwrap() {
+            eprintln!("Run aborted: {}", e);
+        }
+    }
+    let mut limit_changes: Vec<_> = limits.iter().flat_map(RunHandle::changes).collect();
+    limit_changes.sort_by_key(|change| change.at);
+    if let (Some(facts), Some(context)) = (&config.facts, &run_context) {
+        if let Err(e) = save_run_context(facts, context) {
+            eprintln!("Error saving facts: {}", e);
+        }
+    }
+    print_summary(
+        results.responses(),
+        flushes,
+        ssh_processor.dns_cache_stats(),
+        ssh_processor.progress().agent_latency(),
+        config.agent_latency_warning,
+        fd_budget,
+        fd_monitor.peak(),
+        results.stats(),
+        &limit_changes,
+    );
+    if let Some((path, previous)) = &previous {
+        let restored;
+        let current = if results.stats().spilled_outputs == 0 {
+            results.responses()
+        } else {
+            restored = results.to_vec().unwrap_or_else(|e| {
+                eprintln!("Error reading spilled outputs back: {}", e);
+                std::process::exit(1)
+            });
+            &restored
         };
-        match stat {
-            Stat::Ok => ok += 1,
-            Stat::Fail => ko += 1,
-            Stat::TokenFail => token += 1,
-        };
-        total.inc(1);
-        total.set_message(&format!("OK: {}, Failed: {}, Token: {}", ok, ko, token));
+        let report = DiffReport::new(path, previous, current);
+        save_diff_report(&config, &report, &run_id);
+        if config.output.changed_only {
+            results.retain(|r| report.is_changed(&r.hostname));
+        }
+    }
+    // Outputs spilled to disk are read back one at a time as they are written.
+    let results = results.drain().filter_map(|response| {
+        response
+            .map_err(|e| eprintln!("Error reading a spilled output back: {}", e))
+            .ok()
+    });
+    if config.output.save_to_file && config.output.filename.is_some() {
+        match save_to_file(&config, results, args.is_present("banners")) {
+            Ok(_) => println!("Saved successfully"),
+            Err(e) => {
+                eprintln!("Error saving results: {}", e);
+                std::process::exit(1)
+            }
+        }
+    } else {
+        if config.output.save_to_file {
+            eprintln!("Filename to save is not given. Printing to stdout.");
+        }
+        if let Err(e) = save_to_console(&config, results, args.is_present("banners")) {
+            eprintln!("Error printing results: {}", e);
+            std::process::exit(1)
+        }
+    }
+    if interrupted() {
+        std::process::exit(130)
     }
 }
 
-fn incremental_save(rx: Receiver<Response>, stream_len: usize) {
-    let mut file = config_incremental_folders();
-    let len = stream_len;
-    let (sender, reciever) = std::sync::mpsc::channel();
-    std::thread::spawn(move || progress_bar_display(len as u64, reciever));
-    for _ in 0..len {
-        if let Ok(received) = rx.recv() {
-            let stat = if received.status {
-                Stat::Ok
-            } else if received.result.contains("[-19]") {
-                Stat::TokenFail
-            } else {
-                Stat::Fail
-            };
-            if let Err(e) = sender.send(stat) {
-                eprintln!("Error sending stats: {}", e)
+/// Loads the inventory the config selects, `--hosts` for a file one.
+async fn load_cli_inventory(config: &Config, args: &ArgMatches<'_>) -> Result<Inventory, String> {
+    let cache = config.inventory_cache.as_ref();
+    match &config.inventory {
+        InventoryConfig::File => {
+            let path = PathBuf::from(
+                args.value_of("hosts")
+                    .ok_or("--hosts is needed with a file inventory")?,
+            );
+            let yaml_extension = matches!(
+                path.extension().and_then(|e| e.to_str()),
+                Some("yml") | Some("yaml")
+            );
+            match args.value_of("hosts_format") {
+                Some("csv") => load_inventory(&CsvFile { path }, cache).await,
+                Some("yaml") => load_inventory(&YamlFile { path }, cache).await,
+                Some("") if yaml_extension => load_inventory(&YamlFile { path }, cache).await,
+                _ => load_inventory(&HostsFile { path }, cache).await,
             }
-            let mut data = serde_json::to_string_pretty(&received).unwrap();
-            data += "\n";
-            file.write_all(data.as_bytes())
-                .expect("Writing for incremental saving failed");
         }
+        #[cfg(feature = "http-inventory")]
+        InventoryConfig::Http(source) => load_inventory(source, cache).await,
+    }
+}
+
+/// Runs the `replay` subcommand with the config's settings, exiting with 1 when it is
+/// refused or a host failed again.
+fn replay_failed(config: &Config, host_key_store: &Option<Arc<HostKeyStore>>, args: &ArgMatches) {
+    let settings = config.default_settings();
+    let mut builder = group_builder(config, &settings, false, host_key_store);
+    if let Some(ttl) = config.dns_cache_ttl {
+        builder.dns_cache_ttl(Duration::from_secs(ttl));
+    }
+    let (rx, props) = builder.build().unwrap_or_else(|e| {
+        eprintln!("Invalid config: {}", e);
+        std::process::exit(1)
+    });
+    let path = Path::new(args.value_of("file").unwrap());
+    if let Err(e) = props.replay_failed(path, args.is_present("force")) {
+        eprintln!("Not replaying, {} (--force to run it again)", e);
+        std::process::exit(1)
+    }
+    let results: Vec<_> = rx.try_iter().collect();
+    results.iter().for_each(warn_host_key_change);
+    if let Err(e) = save_to_console(config, results.iter().cloned(), false) {
+        eprintln!("Error printing results: {}", e);
+        std::process::exit(1)
+    }
+    if results.iter().any(|r| r.outcome != HostStatus::Success) {
+        std::process::exit(1)
+    }
+}
+
+/// Runs the `debug-host` subcommand, exiting with 1 when the host failed.
+fn debug_host(config: &Config, host_key_store: &Option<Arc<HostKeyStore>>, args: &ArgMatches) {
+    let settings = config.default_settings();
+    let mut builder = group_builder(config, &settings, false, host_key_store);
+    if let Some(ttl) = config.dns_cache_ttl {
+        builder.dns_cache_ttl(Duration::from_secs(ttl));
+    }
+    let (_, props) = builder.build().unwrap_or_else(|e| {
+        eprintln!("Invalid config: {}", e);
+        std::process::exit(1)
+    });
+    let host = args.value_of("host").unwrap();
+    let host = match host.parse::<IpAddr>() {
+        Ok(ip) => SocketAddr::new(ip, 22).to_string(),
+        Err(_) if host.contains(':') => host.to_string(),
+        Err(_) => format!("{}:22", host),
+    };
+    let command = args
+        .value_of("command")
+        .map_or(settings.command, str::to_string);
+    let detail = props.run_single_blocking(host, command);
+    print_detailed(&detail);
+    if detail.response.outcome == HostStatus::Failed {
+        std::process::exit(1)
     }
-    file.flush().expect("Failed flushing");
 }
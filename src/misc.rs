@@ -1,28 +1,687 @@
-use crate::Response;
+use crate::auth::parse_auth_chain;
+use crate::coalesce::{Coalescer, FlushPolicy, FlushStats};
+use crate::compare::DiffReport;
+use crate::fd_budget::raise_nofile_limit;
+use crate::inventory::expand;
+use crate::inventory_source::{Inventory, InventoryCache, InventoryConfig};
+use crate::lint::CommandLint;
+use crate::prelude::{
+    AgentLatencyStats, AuthMethod, BannerReport, BatchReport, BecomeMethod, CheckStatus,
+    DetailedResponse, DnsCacheStats, ErrorKind, ExitCodeClasses, FactsConfig, FailureThreshold,
+    FdBudget, FdShortage, Guard, HostKeyInfo, HostKeyPolicy, HostKeyStore, HostOptions,
+    HostSelection, HostStatus, HostTarget, LimitChange, OutputEncoding, OutputHashAlgorithm,
+    OutputKeep, OutputPassThrough, ParallelSshProps, ParallelSshPropsBuilder, PendingResponses,
+    Permit, PostCondition, PreflightReport, ProgressTracker, ProxyConfig, PtyRequest, Redactor,
+    RemoteShell, Response, RetryPolicy, RunContext, RunError, RunHandle, RunPlan, RunSummary,
+    Serial, SkipCheck, SpillStats, TcpKeepaliveConfig, Timeouts, TypedResponse,
+};
+use crate::replay::FailedHost;
+use crate::rotation::{RotatingWriter, Rotation};
+use crate::shared_file::SharedFile;
+use crate::table::{colors_wanted, write_plan_table, write_table};
+use crate::tags::parse_tags;
+use chrono::Utc;
+use crossbeam_channel::Receiver;
+use indicatif::{ProgressBar, ProgressStyle};
 use serde::{Deserialize, Serialize};
+use smol::stream::{self, Stream, StreamExt};
 use std::collections::BTreeMap;
+use std::fmt::{self, Display};
 use std::fs;
-use std::fs::File;
-use std::io::{BufRead, BufReader};
-use std::net::Ipv4Addr;
-use std::path::Path;
+use std::fs::{File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{self, BufRead, BufReader, BufWriter, IsTerminal};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::RecvTimeoutError;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+/// How results are rendered when printed to stdout.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OutputFormat {
+    Json,
+    Table,
+}
+
+impl Default for OutputFormat {
+    fn default() -> Self {
+        OutputFormat::Json
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(OutputFormat::Json),
+            "table" => Ok(OutputFormat::Table),
+            _ => Err(format!("Unknown output format: {}", s)),
+        }
+    }
+}
+
+/// Order of rows in the table output. `None` keeps the completion order.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    None,
+    Hostname,
+    DurationDesc,
+    FailuresFirst,
+}
+
+impl Default for SortOrder {
+    fn default() -> Self {
+        SortOrder::None
+    }
+}
+
+impl FromStr for SortOrder {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(SortOrder::None),
+            "hostname" => Ok(SortOrder::Hostname),
+            "duration_desc" => Ok(SortOrder::DurationDesc),
+            "failures_first" => Ok(SortOrder::FailuresFirst),
+            _ => Err(format!("Unknown sort order: {}", s)),
+        }
+    }
+}
 
 #[derive(Deserialize, Debug, Clone, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct OutputProps {
     pub save_to_file: bool,
     pub filename: Option<String>,
     pub pretty_format: bool,
-    pub show_progress: bool,
+    /// Progress bar when `true`, plain progress lines when `false`; when not set, the bar
+    /// is shown if stderr is a terminal and the lines otherwise, e.g. under cron.
+    #[serde(default)]
+    pub show_progress: Option<bool>,
+    /// Seconds between plain progress lines, 30 when not set.
+    #[serde(default)]
+    pub progress_interval: Option<u64>,
     pub keep_incremental_data: Option<bool>,
+    #[serde(default)]
+    pub console_format: OutputFormat,
+    #[serde(default)]
+    pub sort: SortOrder,
+    /// Print the full output of failed hosts below the table.
+    #[serde(default)]
+    pub expand_failed: bool,
+    /// Keep the per-attempt history in saved results; otherwise only the attempt count is kept.
+    #[serde(default)]
+    pub verbose_attempts: bool,
+    /// Split incremental results into NDJSON parts with an index, instead of one file.
+    #[serde(default)]
+    pub rotate: Option<Rotation>,
+    /// How often incremental results are written out, see `FlushPolicy`.
+    #[serde(default)]
+    pub flush: FlushPolicy,
+    /// With `compare_to`, only output hosts which differ from the previous run.
+    #[serde(default)]
+    pub changed_only: bool,
+    /// File the JSON diff report of `compare_to` is saved to, `diff_<run id>.json` when
+    /// not set.
+    #[serde(default)]
+    pub diff_report: Option<PathBuf>,
+    /// Append incremental results as NDJSON to `incremental.ndjson`, and failed hosts to
+    /// `failed_hosts.ndjson`, shared with other runs writing to the same directory. Records
+    /// are appended under a file lock; otherwise every run writes its own files, named
+    /// after its run id.
+    #[serde(default)]
+    pub shared_append: bool,
+    /// Write each host's output to `<dir>/<host>.out` as it is read instead of keeping it
+    /// in the results, which then only carry its size and hash.
+    #[serde(default)]
+    pub pass_through_dir: Option<PathBuf>,
+    /// With `pass_through_dir`, keep outputs of up to this many bytes in the results as
+    /// usual and only write larger ones to the directory.
+    #[serde(default)]
+    pub pass_through_threshold: Option<usize>,
+    /// List failed hosts by name only, one per line, instead of as NDJSON records with the
+    /// port, user and command a replay needs.
+    #[serde(default)]
+    pub legacy_failed_hosts: bool,
+    /// Bytes of output the results waiting for the outputs keep in memory; past it, the
+    /// largest outputs are moved to a temporary directory until written. All are kept in
+    /// memory when not set.
+    #[serde(default)]
+    pub memory_budget_bytes: Option<usize>,
+    /// Record on every result the settings its host ran with, as the plan lists them.
+    #[serde(default)]
+    pub verbose_config: bool,
+}
+
+/// Overrides for the hosts of one inventory group. Unset values fall back to the global ones.
+#[derive(Deserialize, Debug, Clone, Default, Serialize)]
+#[serde(deny_unknown_fields)]
+pub struct GroupProps {
+    pub command: Option<String>,
+    pub threads: Option<usize>,
+    #[serde(default, with = "probe_timeout::option")]
+    pub timeout: Option<Duration>,
+    pub user: Option<String>,
+    #[serde(rename = "become")]
+    pub become_root: Option<bool>,
 }
 
 #[derive(Deserialize, Debug, Clone, Serialize)]
+#[serde(deny_unknown_fields)]
 pub struct Config {
     pub threads: usize,
     pub agent_parallelism: isize,
+    /// Warn about hosts whose ssh-agent round trip took longer, e.g. `"500ms"`.
+    #[serde(default, with = "probe_timeout::option")]
+    pub agent_latency_warning: Option<Duration>,
+    /// Hosts of a group reading command output at once, capped at the group's threads;
+    /// unlimited when unset.
+    #[serde(default)]
+    pub max_concurrent_reads: Option<usize>,
+    /// Hosts of a group started per second; unlimited when unset. SIGUSR1 halves and
+    /// SIGUSR2 doubles the hosts run at once during the run, up to `threads`.
+    #[serde(default)]
+    pub connect_rate: Option<f64>,
     pub command: String,
-    pub timeout: u32,
+    /// Limit of the port probe before connecting, in milliseconds or as a duration such as
+    /// `"250ms"`.
+    #[serde(with = "probe_timeout")]
+    pub timeout: Duration,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default, rename = "become")]
+    pub become_root: bool,
+    /// User `become` runs commands as, root when not set.
+    #[serde(default)]
+    pub become_user: Option<String>,
+    #[serde(default)]
+    pub become_method: BecomeMethod,
+    /// Environment variable holding the sudo password of `become`, e.g.
+    /// `become_password_env = "SUDO_PASSWORD"`; passwordless sudo when not set.
+    #[serde(default)]
+    pub become_password_env: Option<String>,
+    /// Run commands with `become` on a pseudo terminal, for sudoers with `requiretty`.
+    #[serde(default)]
+    pub become_pty: bool,
+    /// Run commands on a pseudo terminal, e.g. `request_pty = { term = "xterm" }`.
+    #[serde(default)]
+    pub request_pty: Option<PtyRequest>,
+    /// Remove ANSI escape sequences, e.g. colors, from the captured output.
+    #[serde(default)]
+    pub strip_ansi: bool,
+    /// Run loopback hosts and `localhost` on this machine instead of over SSH; a
+    /// `connection=local` inventory var does so for any host.
+    #[serde(default)]
+    pub local_loopback: bool,
+    /// Shell for hosts without a `remote_shell` inventory var.
+    #[serde(default)]
+    pub remote_shell: RemoteShell,
+    /// Auth methods tried in order, for hosts without an `auth_chain` inventory var.
+    #[serde(default)]
+    pub auth_chain: Option<Vec<AuthMethod>>,
+    /// CSV of `host,password` rows, see `load_host_passwords`.
+    #[serde(default)]
+    pub host_passwords: Option<PathBuf>,
+    /// SOCKS5 proxy every connection is tunnelled through.
+    #[serde(default)]
+    pub proxy: Option<ProxyConfig>,
+    /// Seconds a resolved host name is reused.
+    #[serde(default)]
+    pub dns_cache_ttl: Option<u64>,
+    /// Local addresses connections are made from, at most one IPv4 and one IPv6.
+    #[serde(default)]
+    pub bind_addresses: Vec<IpAddr>,
+    /// Skip hosts whose address family has no bind address instead of failing them.
+    #[serde(default)]
+    pub skip_bind_mismatch: bool,
+    /// Time limits of the connect, handshake, auth, exec and read phases.
+    #[serde(default)]
+    pub timeouts: Timeouts,
+    /// Time a host may take in total, retries included, e.g. `"10m"`; unlimited when not
+    /// set.
+    #[serde(default, with = "probe_timeout::option")]
+    pub host_timeout: Option<Duration>,
+    /// Run `canary_hosts` hosts first and stop when all of them fail on auth the same way.
+    #[serde(default)]
+    pub canary: bool,
+    #[serde(default = "default_canary_hosts")]
+    pub canary_hosts: usize,
+    /// Run the hosts of each group in batches of this many hosts, or this share of them,
+    /// one batch after the other, e.g. `serial = 10` or `serial = "25%"`.
+    #[serde(default)]
+    pub serial: Option<Serial>,
+    /// Pause between batches, e.g. `"30s"`.
+    #[serde(default, with = "probe_timeout::option")]
+    pub batch_pause: Option<Duration>,
+    /// Ask on the terminal before each batch but the first.
+    #[serde(default)]
+    pub confirm_batches: bool,
+    /// Abort the run of a group once too many of its hosts failed, e.g.
+    /// `failure_threshold = { max_failures = 5 }`.
+    #[serde(default)]
+    pub failure_threshold: Option<FailureThreshold>,
+    /// How long hosts in flight are waited for after Ctrl-C, e.g. `"30s"`; 10 seconds
+    /// when not set.
+    #[serde(default, with = "probe_timeout::option")]
+    pub interrupt_grace: Option<Duration>,
+    /// Run once per machine when several host names resolve to the same address.
+    #[serde(default)]
+    pub deduplicate: bool,
+    /// Render the command of each host against its inventory vars, e.g.
+    /// `command = "systemctl restart {{ service }}"` with `service=nginx` on the host line.
+    #[serde(default)]
+    pub templated: bool,
+    /// Maintenance check run first on every host, as the login user, e.g.
+    /// `skip_if = { succeeds = "test -e /etc/nomaint" }`.
+    #[serde(default)]
+    pub skip_if: Option<SkipCheck>,
+    /// Check deciding per host whether `command` runs, e.g.
+    /// `guard = { skip_if_succeeds = "test -f /etc/app/installed" }`.
+    #[serde(default)]
+    pub guard: Option<Guard>,
+    /// Condition waited for after the command succeeded, e.g.
+    /// `post_condition = { tcp_port_open = { port = 8080, timeout = "2m" } }`.
+    #[serde(default)]
+    pub post_condition: Option<PostCondition>,
+    /// File host key fingerprints are recorded in on first use and checked against later.
+    #[serde(default)]
+    pub known_hosts: Option<PathBuf>,
+    /// `fail` or `warn` when a host key differs from the recorded one.
+    #[serde(default)]
+    pub host_key_mismatch: HostKeyPolicy,
+    /// Directory commands are run in, for hosts without a `workdir` inventory var.
+    #[serde(default)]
+    pub workdir: Option<String>,
+    /// Create a missing workdir instead of failing the host's command.
+    #[serde(default)]
+    pub create_workdir: bool,
+    /// `utf8_lossy`, `strict`, `detect` or an encoding label such as `gbk`.
+    #[serde(default)]
+    pub output_encoding: Option<OutputEncoding>,
+    /// Secrets replaced in every result written, e.g.
+    /// `redact = { patterns = ['DB_PASS=(?P<secret>\S+)'] }`; common token formats are
+    /// redacted unless `builtins = false`.
+    #[serde(default)]
+    pub redact: Option<Redactor>,
+    /// e.g. `keep_output = { tail = 50 }` or `keep_output = { head_tail = [10, 50] }`.
+    #[serde(default)]
+    pub keep_output: OutputKeep,
+    /// `sha256` or `fnv1a`; records a hash of each host's whole output, which `compare_to`
+    /// then compares instead of the kept output.
+    #[serde(default)]
+    pub output_hash: Option<OutputHashAlgorithm>,
+    /// e.g. `exit_code_classes = { ok = [0], warning = [1], error = ["2.."] }`; codes are
+    /// single codes or ranges such as `"2.."` or `"3..=5"`.
+    #[serde(default)]
+    pub exit_code_classes: ExitCodeClasses,
+    #[serde(default)]
+    pub retry: RetryPolicy,
+    /// e.g. `tcp_keepalive = { idle_secs = 60, interval_secs = 10, retries = 5 }`.
+    #[serde(default)]
+    pub tcp_keepalive: Option<TcpKeepaliveConfig>,
+    /// Request SSH compression, worth it for large outputs over slow links.
+    #[serde(default)]
+    pub compression: bool,
+    /// Tags recorded on every result, e.g. `tags = { team = "infra" }`. Hosts add theirs
+    /// with a `tags=key=value,...` inventory var, overriding these on the same key.
+    #[serde(default)]
+    pub tags: BTreeMap<String, String>,
+    /// Start hosts with their classes, from a `class` inventory var, taking turns instead
+    /// of in inventory order, e.g. `class_weights = { container = 4, appliance = 1 }`.
+    /// Classes not listed weigh 1.
+    #[serde(default)]
+    pub class_weights: Option<BTreeMap<String, u32>>,
+    /// Run, writing empty results, when no host is selected, instead of failing.
+    #[serde(default)]
+    pub allow_empty: bool,
+    /// Refuse to run with unknown config keys instead of warning about them.
+    #[serde(default)]
+    pub strict_config: bool,
+    /// Results of a previous run, as a JSON array or NDJSON, to report the hosts whose
+    /// output changed since.
+    #[serde(default)]
+    pub compare_to: Option<PathBuf>,
+    /// Raise the soft open files limit to the hard one before the run.
+    #[serde(default)]
+    pub raise_nofile_limit: bool,
+    /// `clamp` or `fail` when the open files limit is too low for the configured threads.
+    #[serde(default)]
+    pub fd_shortage: FdShortage,
+    /// Where the hosts come from, `--hosts` unless e.g.
+    /// `inventory = { type = "http", url = "http://cmdb/api/hosts" }`.
+    #[serde(default)]
+    pub inventory: InventoryConfig,
+    /// Copy of the loaded inventory, used while younger than its TTL and whenever the
+    /// source fails.
+    #[serde(default)]
+    pub inventory_cache: Option<InventoryCache>,
+    /// Check the command of every host for dangerous patterns before the run, e.g.
+    /// `lint = { strict = true, disabled = ["unquoted-variable-glob"] }`.
+    #[serde(default)]
+    pub lint: Option<CommandLint>,
+    /// Facts gathered from the output of a run and used by commands as
+    /// `{{facts.NAME}}`, e.g. `facts = { gather_as = "os_release", cache = "facts.json" }`.
+    #[serde(default)]
+    pub facts: Option<FactsConfig>,
     pub output: OutputProps,
+    #[serde(default)]
+    pub groups: BTreeMap<String, GroupProps>,
+}
+
+fn default_canary_hosts() -> usize {
+    5
+}
+
+/// Settings a group of hosts is run with once group overrides are applied.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveSettings {
+    pub command: String,
+    pub threads: usize,
+    pub timeout: Duration,
+    pub user: Option<String>,
+    pub become_root: bool,
+}
+
+/// Hosts which run with the same settings. `name` is `None` for hosts outside configured groups.
+#[derive(Debug, Clone)]
+pub struct GroupPlan {
+    pub name: Option<String>,
+    pub settings: EffectiveSettings,
+    pub hosts: Vec<(String, HostOptions)>,
+}
+
+impl GroupPlan {
+    /// Hosts of the plan as a run takes them, each with its own command in `commands` or
+    /// the group's.
+    pub fn targets(
+        &self,
+        commands: &BTreeMap<String, String>,
+    ) -> Vec<(HostTarget, String, HostOptions)> {
+        self.hosts
+            .iter()
+            .map(|(host, options)| {
+                let port = host_port(&options.vars).unwrap_or(22);
+                let command = commands.get(host).unwrap_or(&self.settings.command);
+                (
+                    inventory_target(host, port),
+                    command.clone(),
+                    options.clone(),
+                )
+            })
+            .collect()
+    }
+}
+
+/// An inventory host at `port`: its address, or its name to resolve when connecting.
+fn inventory_target(host: &str, port: u16) -> HostTarget {
+    match host.parse::<IpAddr>() {
+        Ok(ip) => HostTarget::Address {
+            address: SocketAddr::new(ip, port),
+            name: None,
+        },
+        Err(_) => HostTarget::Name(format!("{}:{}", host, port)),
+    }
+}
+
+/// A host line of the inventory with its enclosing group and inline vars.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct InventoryHost {
+    /// Address or name of the host; its port is the `port` var.
+    pub addr: String,
+    pub group: Option<String>,
+    /// Groups holding `group` through `[name:children]` sections, nearest first.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parents: Vec<String>,
+    pub vars: BTreeMap<String, String>,
+}
+
+/// Port probe limits, in milliseconds as integers or as humantime strings such as
+/// `"250ms"`. Saved as strings.
+mod probe_timeout {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Raw {
+        Millis(u64),
+        Text(String),
+    }
+
+    fn parse(raw: Raw) -> Result<Duration, String> {
+        match raw {
+            Raw::Millis(ms) => Ok(Duration::from_millis(ms)),
+            Raw::Text(s) => humantime::parse_duration(&s).map_err(|e| format!("{}: {}", s, e)),
+        }
+    }
+
+    pub fn serialize<S: Serializer>(d: &Duration, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&humantime::format_duration(*d).to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Duration, D::Error> {
+        parse(Raw::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+    }
+
+    pub mod option {
+        use super::Raw;
+        use serde::{Deserialize, Deserializer, Serializer};
+        use std::time::Duration;
+
+        pub fn serialize<S: Serializer>(
+            d: &Option<Duration>,
+            serializer: S,
+        ) -> Result<S::Ok, S::Error> {
+            match d {
+                Some(d) => super::serialize(d, serializer),
+                None => serializer.serialize_none(),
+            }
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Option<Duration>, D::Error> {
+            match Option::<Raw>::deserialize(deserializer)? {
+                Some(raw) => super::parse(raw)
+                    .map(Some)
+                    .map_err(serde::de::Error::custom),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+impl Config {
+    /// The become password from `become_password_env`, rejecting an unset variable.
+    pub fn become_password(&self) -> Result<Option<String>, String> {
+        match &self.become_password_env {
+            Some(name) => std::env::var(name)
+                .map(Some)
+                .map_err(|e| format!("become_password_env {}: {}", name, e)),
+            None => Ok(None),
+        }
+    }
+
+    /// Rejects confirming batches without a terminal to ask on, or without batches.
+    pub fn check_batches(&self) -> Result<(), String> {
+        if self.confirm_batches && self.serial.is_none() {
+            return Err("confirm_batches needs serial".to_string());
+        }
+        if self.confirm_batches && !io::stdin().is_terminal() {
+            return Err("confirm_batches needs a terminal on stdin".to_string());
+        }
+        Ok(())
+    }
+
+    /// Rejects probe and phase limits of 0.
+    pub fn check_timeouts(&self) -> Result<(), String> {
+        let zero = Duration::from_secs(0);
+        if self.timeout == zero {
+            return Err("timeout must be above 0".to_string());
+        }
+        for (name, group) in &self.groups {
+            if group.timeout == Some(zero) {
+                return Err(format!("groups.{}.timeout must be above 0", name));
+            }
+        }
+        self.timeouts.validate()
+    }
+
+    /// Rejects inventory sources which cannot be loaded as configured.
+    pub fn check_inventory(&self) -> Result<(), String> {
+        match &self.inventory {
+            InventoryConfig::File => Ok(()),
+            #[cfg(feature = "http-inventory")]
+            InventoryConfig::Http(source) => source.validate(),
+        }
+    }
+
+    /// Rejects rotating results which are appended to a shared file.
+    pub fn check_shared_append(&self) -> Result<(), String> {
+        if self.output.shared_append && self.output.rotate.is_some() {
+            return Err("output.rotate cannot be set with output.shared_append".to_string());
+        }
+        self.output.flush.validate()
+    }
+
+    /// Rejects settings which need the output kept when it is passed through to files.
+    pub fn check_pass_through(&self) -> Result<(), String> {
+        let conflict = if self.output.pass_through_dir.is_none() {
+            None
+        } else if self.compare_to.is_some() {
+            Some("compare_to")
+        } else if self.keep_output != OutputKeep::All {
+            Some("keep_output")
+        } else if self.output_encoding.is_some() {
+            Some("output_encoding")
+        } else {
+            None
+        };
+        match conflict {
+            Some(key) => Err(format!(
+                "output.pass_through_dir keeps no output, {} cannot be set with it",
+                key
+            )),
+            None => Ok(()),
+        }
+    }
+
+    pub fn default_settings(&self) -> EffectiveSettings {
+        EffectiveSettings {
+            command: self.command.clone(),
+            threads: self.threads,
+            timeout: self.timeout,
+            user: self.user.clone(),
+            become_root: self.become_root,
+        }
+    }
+
+    pub fn group_settings(&self, group: &str) -> Option<EffectiveSettings> {
+        let g = self.groups.get(group)?;
+        Some(EffectiveSettings {
+            command: g.command.clone().unwrap_or_else(|| self.command.clone()),
+            threads: g.threads.unwrap_or(self.threads),
+            timeout: g.timeout.unwrap_or(self.timeout),
+            user: g.user.clone().or_else(|| self.user.clone()),
+            become_root: g.become_root.unwrap_or(self.become_root),
+        })
+    }
+
+    /// Splits hosts into per-group runs.
+    ///
+    /// A host which belongs to several configured groups is an error unless all of them resolve
+    /// to the same settings.
+    ///
+    /// Vars of a host listed several times are merged, later lines winning.
+    pub fn plan_groups(&self, hosts: &[InventoryHost]) -> Result<Vec<GroupPlan>, String> {
+        let mut membership: BTreeMap<&str, (Vec<&str>, BTreeMap<String, String>)> = BTreeMap::new();
+        for host in hosts {
+            let (groups, vars) = membership
+                .entry(&host.addr)
+                .or_insert_with(|| (Vec::new(), BTreeMap::new()));
+            if let Some(g) = &host.group {
+                if self.groups.contains_key(g) && !groups.contains(&g.as_str()) {
+                    groups.push(g.as_str());
+                }
+            }
+            vars.extend(host.vars.clone());
+        }
+
+        let mut plans: BTreeMap<Option<String>, GroupPlan> = BTreeMap::new();
+        for (host, (groups, vars)) in membership {
+            let options = host_options(&vars).map_err(|e| format!("Host {}: {}", host, e))?;
+            let name = match groups.split_first() {
+                None => None,
+                Some((first, rest)) => {
+                    let settings = self.group_settings(first);
+                    for other in rest {
+                        if self.group_settings(other) != settings {
+                            return Err(format!(
+                                "Host {} is in groups {} and {} with conflicting settings",
+                                host, first, other
+                            ));
+                        }
+                    }
+                    Some(first.to_string())
+                }
+            };
+            let settings = match &name {
+                Some(g) => self
+                    .group_settings(g)
+                    .unwrap_or_else(|| self.default_settings()),
+                None => self.default_settings(),
+            };
+            plans
+                .entry(name.clone())
+                .or_insert_with(|| GroupPlan {
+                    name,
+                    settings,
+                    hosts: Vec::new(),
+                })
+                .hosts
+                .push((host.to_string(), options));
+        }
+        Ok(plans.into_iter().map(|(_, plan)| plan).collect())
+    }
+
+    /// Plans the runs of the hosts of `inventory` `--limit`'s `pattern` selects.
+    ///
+    /// No host left is an error unless `allow_empty` is set, telling with the hosts left
+    /// after each step which one emptied the selection.
+    pub fn select_hosts(
+        &self,
+        inventory: &mut Inventory,
+        limit: Option<&str>,
+    ) -> Result<Vec<GroupPlan>, String> {
+        let loaded = inventory.host_count();
+        let (after_limit, after_exclude) = match limit {
+            Some(pattern) => inventory
+                .limit(pattern)
+                .map_err(|e| format!("invalid --limit: {}", e))?,
+            None => (loaded, loaded),
+        };
+        let plans = self
+            .plan_groups(&inventory.hosts)
+            .map_err(|e| format!("cannot plan the run: {}", e))?;
+        let selection = HostSelection {
+            loaded,
+            after_groups: plans.iter().map(|plan| plan.hosts.len()).sum(),
+            after_limit,
+            after_exclude,
+        };
+        if selection.after_groups == 0 && !self.allow_empty {
+            let error = RunError::from(selection);
+            return Err(format!("{} (allow_empty to run anyway)", error));
+        }
+        Ok(plans)
+    }
 }
 
 impl Default for OutputProps {
@@ -31,8 +690,23 @@ impl Default for OutputProps {
             save_to_file: false,
             filename: None,
             pretty_format: false,
-            show_progress: false,
+            show_progress: None,
+            progress_interval: None,
             keep_incremental_data: Some(false),
+            console_format: OutputFormat::default(),
+            sort: SortOrder::default(),
+            expand_failed: false,
+            verbose_attempts: false,
+            rotate: None,
+            flush: FlushPolicy::default(),
+            changed_only: false,
+            diff_report: None,
+            shared_append: false,
+            pass_through_dir: None,
+            pass_through_threshold: None,
+            legacy_failed_hosts: false,
+            memory_budget_bytes: None,
+            verbose_config: false,
         }
     }
 }
@@ -42,26 +716,403 @@ impl Default for Config {
         Config {
             threads: 10,
             agent_parallelism: 1,
+            agent_latency_warning: None,
+            max_concurrent_reads: None,
+            connect_rate: None,
             command: "uptime".to_string(),
             output: OutputProps::default(),
-            timeout: 60,
+            timeout: Duration::from_millis(60),
+            user: None,
+            become_root: false,
+            become_user: None,
+            become_method: BecomeMethod::Sudo,
+            become_password_env: None,
+            become_pty: false,
+            request_pty: None,
+            strip_ansi: false,
+            local_loopback: false,
+            remote_shell: RemoteShell::default(),
+            auth_chain: None,
+            host_passwords: None,
+            proxy: None,
+            dns_cache_ttl: None,
+            bind_addresses: Vec::new(),
+            skip_bind_mismatch: false,
+            timeouts: Timeouts::default(),
+            host_timeout: None,
+            canary: false,
+            canary_hosts: default_canary_hosts(),
+            serial: None,
+            batch_pause: None,
+            confirm_batches: false,
+            failure_threshold: None,
+            interrupt_grace: None,
+            deduplicate: false,
+            templated: false,
+            skip_if: None,
+            guard: None,
+            post_condition: None,
+            known_hosts: None,
+            host_key_mismatch: HostKeyPolicy::default(),
+            workdir: None,
+            create_workdir: false,
+            output_encoding: None,
+            keep_output: OutputKeep::default(),
+            output_hash: None,
+            exit_code_classes: ExitCodeClasses::default(),
+            retry: RetryPolicy::default(),
+            tcp_keepalive: None,
+            compression: false,
+            tags: BTreeMap::new(),
+            class_weights: None,
+            redact: None,
+            strict_config: false,
+            compare_to: None,
+            raise_nofile_limit: false,
+            allow_empty: false,
+            fd_shortage: FdShortage::default(),
+            inventory: InventoryConfig::default(),
+            inventory_cache: None,
+            lint: None,
+            facts: None,
+            groups: BTreeMap::new(),
+        }
+    }
+}
+
+/// Reads a list of addresses, one per line; lines which are no address are left out.
+pub fn hosts_builder(path: &Path) -> io::Result<Vec<Ipv4Addr>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut hosts = Vec::new();
+    for line in reader.lines() {
+        if let Ok(addr) = line?.replace("\"", "").replace("'", "").parse() {
+            hosts.push(addr);
         }
     }
+    Ok(hosts)
 }
 
-pub fn hosts_builder(path: &Path) -> Vec<Ipv4Addr> {
-    let file = File::open(path).expect("Unable to open the file");
-    let reader = BufReader::new(file);
-    reader
-        .lines()
-        .map(|l| l.unwrap_or("Error reading line".to_string()))
-        .map(|l| l.replace("\"", ""))
-        .map(|l| l.replace("'", ""))
-        .map(|l| l.parse())
-        .filter_map(Result::ok)
+/// Reads a host list where `[name]` lines start a group, ansible inventory style.
+///
+/// Hosts listed before the first header belong to no group. A host may be listed under
+/// several groups.
+/// Host vars may follow the address as `key=value` pairs, e.g. `10.0.0.5 remote_shell=powershell`.
+/// `deploy@10.0.0.5` is short for `10.0.0.5 user=deploy`, `web1:2222` for `web1 port=2222`,
+/// and a name with an `ansible_host` var, e.g. `db1 ansible_host=10.0.0.7`, is the host at
+/// that address. Networks like `10.0.0.0/24` and ranges like `web[01:20].example.com` stand
+/// for all their hosts, see `inventory::expand`; the vars apply to each of them.
+///
+/// `[name:children]` sections list groups belonging to `name`, and `[name:vars]` sections
+/// hold `key=value` lines applying to every host of `name`, `all` for every host. Vars of
+/// a group win over those of the groups it belongs to, and host vars win over both.
+pub fn grouped_hosts_builder(path: &Path) -> io::Result<Vec<InventoryHost>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut section = InventorySection::Hosts(None);
+    let mut hosts = Vec::new();
+    let mut children: BTreeMap<String, Vec<String>> = BTreeMap::new();
+    let mut group_vars: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    for line in reader.lines() {
+        let line = line?;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            let header = line[1..line.len() - 1].trim();
+            section = match header.rsplit_once(':') {
+                Some((name, "children")) => InventorySection::Children(name.trim().to_string()),
+                Some((name, "vars")) => InventorySection::Vars(name.trim().to_string()),
+                _ => InventorySection::Hosts(Some(header.to_string())),
+            };
+            continue;
+        }
+        let group = match &section {
+            InventorySection::Hosts(group) => group,
+            InventorySection::Children(parent) => {
+                children
+                    .entry(line.to_string())
+                    .or_default()
+                    .push(parent.clone());
+                continue;
+            }
+            InventorySection::Vars(group) => {
+                if let Some((key, value)) = line.split_once('=') {
+                    group_vars
+                        .entry(group.clone())
+                        .or_default()
+                        .insert(key.trim().to_string(), value.trim().to_string());
+                }
+                continue;
+            }
+        };
+        let mut tokens = line.split_whitespace();
+        let addr = match tokens.next() {
+            Some(a) => a.replace("\"", "").replace("'", ""),
+            None => continue,
+        };
+        let mut vars: BTreeMap<String, String> = tokens
+            .filter_map(|t| {
+                let mut kv = t.splitn(2, '=');
+                Some((kv.next()?.to_string(), kv.next()?.to_string()))
+            })
+            .collect();
+        let (user, addr) = match addr.rfind('@') {
+            Some(at) => (Some(addr[..at].to_string()), &addr[at + 1..]),
+            None => (None, addr.as_str()),
+        };
+        if let Some(user) = user.filter(|u| !u.is_empty()) {
+            vars.entry("user".to_string()).or_insert(user);
+        }
+        push_hosts(&mut hosts, addr, group, &vars);
+    }
+    inherit_group_vars(&mut hosts, &children, &group_vars);
+    Ok(hosts)
+}
+
+/// Adds the hosts `entry` stands for, or its `ansible_host` var, to `hosts`.
+fn push_hosts(
+    hosts: &mut Vec<InventoryHost>,
+    entry: &str,
+    group: &Option<String>,
+    vars: &BTreeMap<String, String>,
+) {
+    let addrs = match vars.get("ansible_host") {
+        Some(address) => expanded_hosts(address),
+        None => expanded_hosts(entry),
+    };
+    for (addr, port) in addrs {
+        let mut vars = vars.clone();
+        if port != 22 && !vars.contains_key("ansible_port") {
+            vars.entry("port".to_string())
+                .or_insert_with(|| port.to_string());
+        }
+        hosts.push(InventoryHost {
+            addr,
+            group: group.clone(),
+            parents: Vec::new(),
+            vars,
+        });
+    }
+}
+
+/// Sets the parents of `hosts` from `children`, and gives them the vars of `all` and of
+/// their groups, nearest winning, under their own.
+fn inherit_group_vars(
+    hosts: &mut [InventoryHost],
+    children: &BTreeMap<String, Vec<String>>,
+    group_vars: &BTreeMap<String, BTreeMap<String, String>>,
+) {
+    for host in hosts {
+        host.parents = host
+            .group
+            .as_deref()
+            .map_or_else(Vec::new, |group| ancestors(group, children));
+        let mut vars = group_vars.get("all").cloned().unwrap_or_default();
+        for group in host.parents.iter().rev().chain(&host.group) {
+            vars.extend(group_vars.get(group).cloned().unwrap_or_default());
+        }
+        vars.extend(std::mem::take(&mut host.vars));
+        host.vars = vars;
+    }
+}
+
+/// Reads an ansible YAML inventory: groups under `all` or at the top, each with `hosts`
+/// mapping names to their vars, `vars` applying to all its hosts and `children` groups
+/// belonging to it. Hosts right under `all` belong to no group.
+///
+/// Hosts take `ansible_host`, `ansible_port` and `ansible_user` as in the hosts file, see
+/// `grouped_hosts_builder`, and ranges such as `web[01:20].example.com` stand for all their
+/// hosts. Vars which are lists or mappings are left out.
+pub fn yaml_hosts_builder(path: &Path) -> io::Result<Vec<InventoryHost>> {
+    let groups: BTreeMap<String, Option<YamlGroup>> = serde_yaml::from_reader(File::open(path)?)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let mut inventory = YamlInventory::default();
+    for (name, group) in groups {
+        inventory.add_group(&name, None, group.unwrap_or_default());
+    }
+    inherit_group_vars(
+        &mut inventory.hosts,
+        &inventory.children,
+        &inventory.group_vars,
+    );
+    Ok(inventory.hosts)
+}
+
+#[derive(Deserialize, Default)]
+#[serde(default, deny_unknown_fields)]
+struct YamlGroup {
+    hosts: BTreeMap<String, Option<BTreeMap<String, serde_yaml::Value>>>,
+    vars: BTreeMap<String, serde_yaml::Value>,
+    children: BTreeMap<String, Option<YamlGroup>>,
+}
+
+/// What the groups of a YAML inventory hold, as the hosts file parser collects it.
+#[derive(Default)]
+struct YamlInventory {
+    hosts: Vec<InventoryHost>,
+    children: BTreeMap<String, Vec<String>>,
+    group_vars: BTreeMap<String, BTreeMap<String, String>>,
+}
+
+impl YamlInventory {
+    fn add_group(&mut self, name: &str, parent: Option<&str>, group: YamlGroup) {
+        if let Some(parent) = parent {
+            let parents = self.children.entry(name.to_string()).or_default();
+            if !parents.iter().any(|p| p == parent) {
+                parents.push(parent.to_string());
+            }
+        }
+        self.group_vars
+            .entry(name.to_string())
+            .or_default()
+            .extend(yaml_vars(group.vars));
+        let host_group = Some(name.to_string()).filter(|name| name != "all");
+        for (entry, vars) in group.hosts {
+            let vars = yaml_vars(vars.unwrap_or_default());
+            push_hosts(&mut self.hosts, &entry, &host_group, &vars);
+        }
+        // Groups under `all` are top level ones.
+        let parent = host_group.as_deref();
+        for (child, group) in group.children {
+            self.add_group(&child, parent, group.unwrap_or_default());
+        }
+    }
+}
+
+/// Scalar vars of a YAML mapping as strings.
+fn yaml_vars(vars: BTreeMap<String, serde_yaml::Value>) -> BTreeMap<String, String> {
+    vars.into_iter()
+        .filter_map(|(name, value)| {
+            let value = match value {
+                serde_yaml::Value::String(s) => s,
+                serde_yaml::Value::Number(n) => n.to_string(),
+                serde_yaml::Value::Bool(b) => b.to_string(),
+                _ => return None,
+            };
+            Some((name, value))
+        })
         .collect()
 }
 
+enum InventorySection {
+    /// Hosts of the group, of none before the first header.
+    Hosts(Option<String>),
+    /// Groups belonging to the group.
+    Children(String),
+    /// Vars of the group.
+    Vars(String),
+}
+
+/// Groups `group` belongs to through `children`, nearest first.
+fn ancestors(group: &str, children: &BTreeMap<String, Vec<String>>) -> Vec<String> {
+    let mut found: Vec<String> = Vec::new();
+    let mut current = group.to_string();
+    let mut next = 0;
+    loop {
+        for parent in children.get(&current).into_iter().flatten() {
+            if parent != group && !found.contains(parent) {
+                found.push(parent.clone());
+            }
+        }
+        match found.get(next) {
+            Some(parent) => current = parent.clone(),
+            None => return found,
+        }
+        next += 1;
+    }
+}
+
+/// Hosts of an entry with their ports, several for a network or a range.
+fn expanded_hosts(entry: &str) -> Vec<(String, u16)> {
+    match expand(entry) {
+        Ok(specs) => specs
+            .into_iter()
+            .filter_map(|spec| match spec.target {
+                HostTarget::Address { address, .. } => {
+                    Some((address.ip().to_string(), address.port()))
+                }
+                HostTarget::Name(name) => {
+                    let (host, port) = name.rsplit_once(':')?;
+                    Some((host.to_string(), port.parse().ok()?))
+                }
+            })
+            .collect(),
+        Err(e) => {
+            eprintln!("Skipping hosts entry {}", e);
+            Vec::new()
+        }
+    }
+}
+
+/// Asks on the terminal whether to go on with the next batch of a serial run. Batches of
+/// groups run at once are asked about one at a time.
+pub fn confirm_batch(report: &BatchReport) -> bool {
+    static PROMPT: Mutex<()> = Mutex::new(());
+    let _prompt = PROMPT.lock().unwrap_or_else(|e| e.into_inner());
+    let of = report
+        .batches
+        .map_or_else(String::new, |batches| format!("/{}", batches));
+    eprint!(
+        "Batch {}{} done, {} of {} hosts failed. Start the next batch? [y/N] ",
+        report.batch, of, report.failed, report.hosts
+    );
+    let mut answer = String::new();
+    if io::stdin().read_line(&mut answer).is_err() {
+        return false;
+    }
+    matches!(answer.trim().to_lowercase().as_str(), "y" | "yes")
+}
+
+/// SSH port of a host, from its `port` or `ansible_port` var; 22 without either.
+pub fn host_port(vars: &BTreeMap<String, String>) -> Result<u16, String> {
+    match vars.get("port").or_else(|| vars.get("ansible_port")) {
+        Some(port) => port.parse().map_err(|_| format!("invalid port {}", port)),
+        None => Ok(22),
+    }
+}
+
+/// Turns inventory host vars into engine overrides. All vars are kept as `vars`.
+pub fn host_options(vars: &BTreeMap<String, String>) -> Result<HostOptions, String> {
+    let mut options = HostOptions {
+        vars: vars.clone(),
+        ..HostOptions::default()
+    };
+    if let Some(shell) = vars.get("remote_shell") {
+        options.remote_shell = Some(shell.parse()?);
+    }
+    if let Some(chain) = vars.get("auth_chain") {
+        options.auth_chain = Some(parse_auth_chain(chain)?);
+    }
+    if let Some(user) = vars.get("user").or_else(|| vars.get("ansible_user")) {
+        options.user = Some(user.clone());
+    }
+    host_port(vars)?;
+    if let Some(dir) = vars.get("workdir") {
+        options.workdir = Some(dir.clone());
+    }
+    if let Some(tags) = vars.get("tags") {
+        options.tags = parse_tags(tags)?;
+    }
+    if let Some(class) = vars.get("class") {
+        options.class = Some(class.clone());
+    }
+    if let Some(connection) = vars
+        .get("connection")
+        .or_else(|| vars.get("ansible_connection"))
+    {
+        options.connection = Some(connection.parse()?);
+    }
+    if let Some(container) = vars.get("container") {
+        options.container = Some(container.clone());
+    }
+    // Kept out of the vars, which debug output and templates show.
+    let password = options.vars.remove("password");
+    if let Some(password) = options.vars.remove("ansible_password").or(password) {
+        options.password = Some(password);
+    }
+    Ok(options)
+}
+
 pub fn generate_kv_hosts_from_csv(
     path: &str,
 ) -> Result<BTreeMap<Ipv4Addr, String>, std::io::Error> {
@@ -83,6 +1134,69 @@ pub fn generate_kv_hosts_from_csv(
     Ok(map)
 }
 
+/// Reads a CSV of `host,password` rows without a header, the passwords of hosts which
+/// take no keys kept apart from the inventory, keyed by the host as `hosts` name it: an
+/// address or a host name. Each listed host gets its password as `HostOptions::password`,
+/// in place of any `ansible_password` var. A row naming none of `hosts` is an error, as
+/// the host it meant would go without its password.
+///
+/// Like ssh with private keys, a file readable by group or others is refused on Unix.
+/// Errors name the row, never its content.
+pub fn load_host_passwords(
+    path: &Path,
+    hosts: &[InventoryHost],
+) -> Result<BTreeMap<String, String>, String> {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        let mode = fs::metadata(path)
+            .map_err(|e| format!("{}: {}", path.display(), e))?
+            .permissions()
+            .mode();
+        if mode & 0o077 != 0 {
+            return Err(format!(
+                "{}: readable by others (mode {:o}), chmod 600 it",
+                path.display(),
+                mode & 0o777
+            ));
+        }
+    }
+    let mut rd = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_path(path)
+        .map_err(|e| format!("{}: {}", path.display(), e))?;
+    let mut passwords = BTreeMap::new();
+    for (row, record) in rd.records().enumerate() {
+        let row = row + 1;
+        let record =
+            record.map_err(|_| format!("{}: row {} is not valid CSV", path.display(), row))?;
+        let host = match record.get(0).map(str::trim) {
+            Some(host) if hosts.iter().any(|h| h.addr == host) => host,
+            Some(host) if !host.is_empty() => {
+                return Err(format!(
+                    "{}: row {} names no host of the inventory",
+                    path.display(),
+                    row
+                ))
+            }
+            _ => return Err(format!("{}: row {} has no host", path.display(), row)),
+        };
+        let password = match (record.get(1), record.len()) {
+            (Some(password), 2) => password,
+            _ => {
+                return Err(format!(
+                    "{}: row {} is not address,password",
+                    path.display(),
+                    row
+                ))
+            }
+        };
+        passwords.insert(host.to_string(), password.to_string());
+    }
+    Ok(passwords)
+}
+
 pub fn get_config(path: &Path) -> Config {
     let f = match fs::read_to_string(path) {
         Ok(a) => a,
@@ -100,41 +1214,1303 @@ pub fn get_config(path: &Path) -> Config {
     }
 }
 
-pub fn save_to_file(conf: &Config, data: Vec<Response>) {
-    let filename = match &conf.output.filename {
-        None => {
-            eprintln!("Filename to save is not given. Printing to stdout.");
-            save_to_console(&conf, &data);
-            return;
+/// Config key ansible-rs does not know, with the known key closest to it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownKey {
+    /// Dotted path of the key, e.g. `output.pretty_fromat`.
+    pub key: String,
+    pub suggestion: Option<String>,
+}
+
+impl Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown key '{}'", self.key)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, ", did you mean '{}'?", suggestion)?;
         }
-        Some(a) => Path::new(a.as_str()),
+        Ok(())
+    }
+}
+
+/// Reads the config at `path`, writing the default config there first when it is missing.
+///
+/// Unknown keys, typos mostly, would otherwise be ignored with the default used in their
+/// place. They are dropped from the config and returned instead, for the caller to warn
+/// about or refuse.
+pub fn load_config(path: &Path) -> Result<(Config, Vec<UnknownKey>), String> {
+    if !path.exists() {
+        let config = confy::load_path(path).map_err(|e| e.to_string())?;
+        return Ok((config, Vec::new()));
+    }
+    let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+    let mut value: toml::Value = toml::from_str(&text).map_err(|e| e.to_string())?;
+    let mut unknown = Vec::new();
+    let mut current = text;
+    loop {
+        let error = match toml::from_str::<Config>(&current) {
+            Ok(config) => return Ok((config, unknown)),
+            Err(e) => e.to_string(),
+        };
+        let (table, field, expected) = match parse_unknown_field(&error) {
+            Some(parsed) => parsed,
+            None if unknown.is_empty() => return Err(error),
+            // Line numbers would refer to the rewritten config, and a missing key may well
+            // be one of the unknown ones misspelt.
+            None => {
+                let notes: Vec<String> = unknown.iter().map(UnknownKey::to_string).collect();
+                let error = error.split(" at line ").next().unwrap_or("");
+                return Err(format!("{} ({})", error, notes.join("; ")));
+            }
+        };
+        let path: Vec<&str> = table.split('.').filter(|s| !s.is_empty()).collect();
+        if !remove_key(&mut value, &path, &field) {
+            return Err(error);
+        }
+        let mut key = path.join(".");
+        if !key.is_empty() {
+            key.push('.');
+        }
+        key.push_str(&field);
+        unknown.push(UnknownKey {
+            key,
+            suggestion: closest(&field, &expected),
+        });
+        current = toml::to_string(&value).map_err(|e| e.to_string())?;
+    }
+}
+
+/// Table path, field and expected fields of a serde "unknown field" error from toml.
+fn parse_unknown_field(error: &str) -> Option<(String, String, Vec<String>)> {
+    let rest = error.strip_prefix("unknown field `")?;
+    let end = rest.find('`')?;
+    let field = rest[..end].to_string();
+    let rest = &rest[end + 1..];
+    let (expected, table) = match rest.find(" for key `") {
+        Some(i) => {
+            let table = &rest[i + " for key `".len()..];
+            (&rest[..i], &table[..table.find('`')?])
+        }
+        None => (rest.split(" at line ").next()?, ""),
     };
+    let expected = expected
+        .split('`')
+        .skip(1)
+        .step_by(2)
+        .map(str::to_string)
+        .collect();
+    Some((table.to_string(), field, expected))
+}
 
-    let file = match File::create(filename) {
-        Ok(a) => a,
-        Err(e) => {
-            eprintln!("Erorr saving content to file:{}", e);
-            save_to_console(&conf, &data);
-            return;
+/// Removes `field` from the table at `path`, from every table of it for arrays of tables.
+fn remove_key(value: &mut toml::Value, path: &[&str], field: &str) -> bool {
+    match value {
+        toml::Value::Array(items) => items
+            .iter_mut()
+            .fold(false, |found, item| remove_key(item, path, field) || found),
+        toml::Value::Table(table) => match path.split_first() {
+            None => table.remove(field).is_some(),
+            Some((first, rest)) => table
+                .get_mut(*first)
+                .map_or(false, |v| remove_key(v, rest, field)),
+        },
+        _ => false,
+    }
+}
+
+/// Known key within a few edits of `key`, the closest one first.
+fn closest(key: &str, known: &[String]) -> Option<String> {
+    let limit = (key.chars().count() / 3).max(2);
+    known
+        .iter()
+        .map(|k| (edit_distance(key, k), k))
+        .filter(|(d, _)| *d <= limit)
+        .min_by_key(|(d, _)| *d)
+        .map(|(_, k)| k.clone())
+}
+
+/// Levenshtein distance between `a` and `b`.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut row: Vec<usize> = (0..=b.len()).collect();
+    for (i, ca) in a.chars().enumerate() {
+        let mut diagonal = row[0];
+        row[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = diagonal + if ca == *cb { 0 } else { 1 };
+            diagonal = row[j + 1];
+            row[j + 1] = substitution.min(row[j] + 1).min(row[j + 1] + 1);
+        }
+    }
+    row[b.len()]
+}
+
+/// Run context of the `facts` config, with the hosts of its cache younger than its TTL.
+/// A missing or unreadable cache starts an empty context.
+pub fn load_run_context(facts: &FactsConfig) -> RunContext {
+    let snapshot = facts
+        .cache
+        .as_ref()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|content| serde_json::from_str(&content).ok())
+        .unwrap_or_default();
+    RunContext::from_snapshot(
+        snapshot,
+        facts.max_bytes,
+        Duration::from_secs(facts.ttl_secs),
+    )
+}
+
+/// Writes `context` to the cache of the `facts` config, when it has one.
+pub fn save_run_context(facts: &FactsConfig, context: &RunContext) -> Result<(), String> {
+    let path = match &facts.cache {
+        Some(path) => path,
+        None => return Ok(()),
+    };
+    let content = serde_json::to_string(&context.snapshot()).map_err(|e| e.to_string())?;
+    // Renamed into place, so a concurrent invocation never reads half a cache.
+    let tmp = path.with_extension("tmp");
+    fs::write(&tmp, content)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| format!("{}: {}", path.display(), e))
+}
+
+/// Checks the file results are saved to can be written, without truncating it.
+pub fn check_output_path(conf: &Config, report: &mut PreflightReport) {
+    let filename = match (&conf.output.filename, conf.output.save_to_file) {
+        (Some(f), true) => Path::new(f.as_str()),
+        _ => return,
+    };
+    let existed = filename.exists();
+    match OpenOptions::new().append(true).create(true).open(filename) {
+        Ok(_) => {
+            if !existed {
+                let _ = std::fs::remove_file(filename);
+            }
+            report.push("output", CheckStatus::Pass, "Writable")
+        }
+        Err(e) => report.push(
+            "output",
+            CheckStatus::Fail,
+            format!("{}: {}", filename.display(), e),
+        ),
+    }
+}
+
+/// Saves the results to the output file as a JSON array. `banners` writes the results
+/// of a banner run as `TypedResponse<BannerReport>`s.
+pub fn save_to_file<I>(conf: &Config, data: I, banners: bool) -> io::Result<u64>
+where
+    I: IntoIterator<Item = Response>,
+    I::IntoIter: Unpin,
+{
+    save_stream_to_file(conf, stream::iter(data), banners)
+}
+
+/// Writes the responses of `stream` to the output file as a JSON array while they arrive,
+/// flushing at least every `FILE_FLUSH_INTERVAL`, and closes the array once the stream
+/// ends. Returns the number of responses written.
+///
+/// On an error, e.g. a full disk, the file is left as far as it got.
+pub fn save_stream_to_file<S>(conf: &Config, mut stream: S, banners: bool) -> io::Result<u64>
+where
+    S: Stream<Item = Response> + Unpin,
+{
+    let filename = conf.output.filename.as_ref().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::InvalidInput, "Filename to save is not given")
+    })?;
+    let file = BufWriter::new(File::create(filename)?);
+    let mut writer = JsonArrayWriter::new(file, conf.output.pretty_format);
+    smol::run(async {
+        let mut flushed = Instant::now();
+        while let Some(response) = stream.next().await {
+            writer.push_response(response, banners)?;
+            if flushed.elapsed() >= FILE_FLUSH_INTERVAL {
+                writer.out.flush()?;
+                flushed = Instant::now();
+            }
+        }
+        Ok::<_, io::Error>(())
+    })?;
+    writer.finish()
+}
+
+/// Longest time a record written by `save_stream_to_file` stays buffered.
+const FILE_FLUSH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Writes records into a JSON array one at a time, laid out as `serde_json` lays out a
+/// whole array.
+struct JsonArrayWriter<W: Write> {
+    out: W,
+    pretty: bool,
+    records: u64,
+}
+
+impl<W: Write> JsonArrayWriter<W> {
+    fn new(out: W, pretty: bool) -> Self {
+        JsonArrayWriter {
+            out,
+            pretty,
+            records: 0,
+        }
+    }
+
+    /// `record` laid out as an element of the array, for `push_element`.
+    fn element<T: Serialize>(&self, record: &T) -> serde_json::Result<String> {
+        if self.pretty {
+            // JSON strings escape newlines, so every newline is between tokens.
+            serde_json::to_string_pretty(record).map(|json| json.replace('\n', "\n  "))
+        } else {
+            serde_json::to_string(record)
+        }
+    }
+
+    /// Writes `record`; one which fails to serialize is reported on stderr, naming
+    /// `hostname`, and left out instead of ending the array.
+    fn push<T: Serialize>(&mut self, record: &T, hostname: &str) -> io::Result<()> {
+        match self.element(record) {
+            Ok(json) => self.push_element(json.as_bytes()),
+            Err(e) => {
+                eprintln!("Error serializing the result of {}: {}", hostname, e);
+                Ok(())
+            }
+        }
+    }
+
+    /// Writes an element serialized by `element`.
+    fn push_element(&mut self, json: &[u8]) -> io::Result<()> {
+        let separator = match (self.records, self.pretty) {
+            (0, false) => "[",
+            (0, true) => "[\n  ",
+            (_, false) => ",",
+            (_, true) => ",\n  ",
+        };
+        self.out.write_all(separator.as_bytes())?;
+        self.out.write_all(json)?;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Writes `response`, for a banner run with its banner data nested in `payload`.
+    fn push_response(&mut self, response: Response, banners: bool) -> io::Result<()> {
+        let hostname = response.hostname.clone();
+        if banners {
+            self.push(&TypedResponse::<BannerReport>::from(response), &hostname)
+        } else {
+            self.push(&response, &hostname)
+        }
+    }
+
+    /// Closes the array, returning the number of records.
+    fn finish(mut self) -> io::Result<u64> {
+        let end = match (self.records, self.pretty) {
+            (0, _) => "[]",
+            (_, false) => "]",
+            (_, true) => "\n]",
+        };
+        self.out.write_all(end.as_bytes())?;
+        self.out.flush()?;
+        Ok(self.records)
+    }
+}
+
+/// Builder of the props of a group, with everything but the group name set from the
+/// config and `settings`.
+pub fn group_builder(
+    config: &Config,
+    settings: &EffectiveSettings,
+    banners: bool,
+    host_key_store: &Option<Arc<HostKeyStore>>,
+) -> ParallelSshPropsBuilder {
+    let mut builder = ParallelSshPropsBuilder::default();
+    builder
+        .agent_connections_pool(config.agent_parallelism)
+        .tcp_connections_pool(settings.threads as isize)
+        .timeout_socket(settings.timeout)
+        .timeouts(config.timeouts)
+        .compression(config.compression)
+        .banner_only(banners)
+        .become_root(settings.become_root)
+        .become_method(config.become_method)
+        .become_pty(config.become_pty)
+        .strip_ansi(config.strip_ansi)
+        .local_loopback(config.local_loopback)
+        .remote_shell(config.remote_shell)
+        .skip_bind_mismatch(config.skip_bind_mismatch)
+        .deduplicate(config.deduplicate)
+        .templated(config.templated)
+        .canary_hosts(if config.canary {
+            config.canary_hosts
+        } else {
+            0
+        })
+        .host_key_policy(config.host_key_mismatch)
+        .create_workdir(config.create_workdir)
+        .keep_output(config.keep_output)
+        .exit_code_classes(config.exit_code_classes.clone())
+        .allow_empty(config.allow_empty)
+        .retry_policy(config.retry.clone())
+        .tags(config.tags.clone())
+        .verbose_config(config.output.verbose_config);
+    if let Some(limit) = config.agent_latency_warning {
+        builder.agent_latency_warning(limit);
+    }
+    if let Some(rate) = config.connect_rate {
+        builder.connect_rate(rate);
+    }
+    if let Some(serial) = config.serial {
+        builder.serial(serial);
+    }
+    if let Some(pause) = config.batch_pause {
+        builder.batch_pause(pause);
+    }
+    if config.confirm_batches {
+        builder.batch_hook(Arc::new(confirm_batch));
+    }
+    if let Some(threshold) = config.failure_threshold {
+        builder.failure_threshold(threshold);
+    }
+    if let Some(limit) = config.host_timeout {
+        builder.timeout_ssh(limit);
+    }
+    if let Some(user) = &config.become_user {
+        builder.become_user(user.clone());
+    }
+    if let Ok(Some(password)) = config.become_password() {
+        builder.become_password(Arc::new(move |_: &str| Ok(password.clone())));
+    }
+    if let Some(pty) = &config.request_pty {
+        builder.request_pty(pty.clone());
+    }
+    if let Some(reads) = config.max_concurrent_reads {
+        builder.max_concurrent_reads(reads.min(settings.threads));
+    }
+    if let Some(weights) = &config.class_weights {
+        builder.class_weights(weights.clone());
+    }
+    if let Some(algorithm) = config.output_hash {
+        builder.output_hash(algorithm);
+    }
+    if let Some(redactor) = &config.redact {
+        builder.redact(redactor.clone());
+    }
+    if let Some(skip_if) = &config.skip_if {
+        builder.skip_if(skip_if.clone());
+    }
+    if let Some(guard) = &config.guard {
+        builder.guard(guard.clone());
+    }
+    if let Some(condition) = &config.post_condition {
+        builder.post_condition(condition.clone());
+    }
+    if let Some(store) = &host_key_store {
+        builder.host_key_store(store.clone());
+    }
+    if let Some(keepalive) = config.tcp_keepalive {
+        builder.tcp_keepalive(keepalive);
+    }
+    if let Some(encoding) = config.output_encoding {
+        builder.output_encoding(encoding);
+    }
+    if let Some(dir) = &config.output.pass_through_dir {
+        builder.pass_through_output(match config.output.pass_through_threshold {
+            Some(threshold) => OutputPassThrough::Spill {
+                dir: dir.clone(),
+                threshold,
+            },
+            None => OutputPassThrough::Dir(dir.clone()),
+        });
+    }
+    if let Some(dir) = &config.workdir {
+        builder.workdir(dir.clone());
+    }
+    for addr in &config.bind_addresses {
+        builder.bind_address(*addr);
+    }
+    if let Some(chain) = &config.auth_chain {
+        builder.auth_chain(chain.clone());
+    }
+    if let Some(proxy) = &config.proxy {
+        builder.proxy(proxy.clone());
+    }
+    if let Some(user) = &settings.user {
+        builder.user(user.clone());
+    }
+    builder
+}
+
+/// Prints the results to stdout in the configured console format, JSON one result at a
+/// time.
+///
+/// A stdout closed by its reader, e.g. `head`, ends the output quietly as other Unix
+/// tools do; other write errors are returned.
+pub fn save_to_console<I>(conf: &Config, data: I, banners: bool) -> io::Result<()>
+where
+    I: IntoIterator<Item = Response>,
+{
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    let result = match conf.output.console_format {
+        OutputFormat::Table => {
+            let data: Vec<Response> = data.into_iter().collect();
+            let colors = colors_wanted(&stdout);
+            write_table(
+                &mut out,
+                &data,
+                conf.output.sort,
+                conf.output.expand_failed,
+                colors,
+            )
+        }
+        OutputFormat::Json => {
+            let mut writer = JsonArrayWriter::new(&mut out, conf.output.pretty_format);
+            data.into_iter()
+                .try_for_each(|response| writer.push_response(response, banners))
+                .and_then(|_| writer.finish())
+                .and_then(|_| writeln!(out))
         }
     };
-    if conf.output.pretty_format {
-        match serde_json::to_writer_pretty(file, &data) {
-            Ok(_) => println!("Saved successfully"),
-            Err(e) => eprintln!("Error saving: {}", e),
+    match result.and_then(|_| out.flush()) {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
+    }
+}
+
+/// Prints a dry-run plan in the configured console format.
+pub fn print_plan(conf: &Config, plan: &RunPlan) {
+    match conf.output.console_format {
+        OutputFormat::Table => {
+            let stdout = std::io::stdout();
+            let mut out = stdout.lock();
+            if let Err(e) = write_plan_table(&mut out, plan) {
+                eprintln!("Error printing plan: {}", e)
+            }
+        }
+        OutputFormat::Json if conf.output.pretty_format => {
+            println!("{}", serde_json::to_string_pretty(plan).unwrap())
+        }
+        OutputFormat::Json => println!("{}", serde_json::to_string(plan).unwrap()),
+    }
+}
+
+/// Saves a dry-run plan as pretty JSON, for review before the real run.
+pub fn save_plan(path: &Path, plan: &RunPlan) {
+    let result = File::create(path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_json::to_writer_pretty(file, plan).map_err(|e| e.to_string()));
+    match result {
+        Ok(_) => eprintln!("Plan saved to {}", path.display()),
+        Err(e) => eprintln!("Error saving plan: {}", e),
+    }
+}
+
+/// Prints the result of `debug-host`: the steps and algorithms to stderr, the response
+/// as pretty JSON to stdout and the error chain, if any, to stderr.
+pub fn print_detailed(detail: &DetailedResponse) {
+    warn_host_key_change(&detail.response);
+    for step in &detail.steps {
+        eprintln!(
+            "[attempt {}] +{}ms {}: {}ms{}{}",
+            step.attempt,
+            step.offset.as_millis(),
+            step.step,
+            step.duration.as_millis(),
+            step.limit.map_or(String::new(), |limit| format!(
+                " (limit {}ms)",
+                limit.as_millis()
+            )),
+            step.error_kind
+                .map_or(String::new(), |kind| format!(", failed with {}", kind))
+        );
+    }
+    if let Some(algorithms) = &detail.algorithms {
+        let name = |value: &Option<String>| value.clone().unwrap_or_else(|| "-".to_string());
+        eprintln!("kex: {}", name(&algorithms.kex));
+        eprintln!("host key: {}", name(&algorithms.host_key));
+        // Client to server, then server to client.
+        eprintln!(
+            "cipher: {} / {}",
+            name(&algorithms.cipher_cs),
+            name(&algorithms.cipher_sc)
+        );
+        eprintln!(
+            "mac: {} / {}",
+            name(&algorithms.mac_cs),
+            name(&algorithms.mac_sc)
+        );
+        eprintln!(
+            "compression: {} / {}",
+            name(&algorithms.compression_cs),
+            name(&algorithms.compression_sc)
+        );
+    }
+    println!(
+        "{}",
+        serde_json::to_string_pretty(&detail.response).unwrap()
+    );
+    if let Some(error) = &detail.error {
+        eprintln!("Error: {}", error);
+        for cause in error.chain().skip(1) {
+            eprintln!("Caused by: {}", cause);
+        }
+    }
+}
+
+/// Checks the open files limit against the hosts the groups run at once, after raising the
+/// soft limit to the hard one when `raise_nofile_limit` is set.
+///
+/// Short of descriptors, the threads of every group are scaled down to fit with a warning,
+/// or an error naming the limit to raise is returned, as `fd_shortage` says.
+pub fn fit_fd_budget(config: &Config, mut threads: Vec<&mut usize>) -> Result<FdBudget, String> {
+    if config.raise_nofile_limit {
+        match raise_nofile_limit() {
+            Ok(limit) => eprintln!("Open files limit raised to {}", limit),
+            Err(e) => eprintln!("Warning: failed raising the open files limit: {}", e),
+        }
+    }
+    let total: usize = threads.iter().map(|t| **t).sum();
+    let budget = FdBudget::new(total, threads.len());
+    let (limit, max) = match (budget.limit, budget.max_hosts()) {
+        (Some(limit), Some(max)) if !budget.fits() => (limit, max),
+        _ => return Ok(budget),
+    };
+    if config.fd_shortage == FdShortage::Fail || max == 0 {
+        return Err(format!(
+            "{} hosts at once need {} open files but the limit is {}: raise it to {} \
+             (ulimit -n), set raise_nofile_limit or lower threads",
+            total, budget.needed, limit, budget.needed
+        ));
+    }
+    for t in threads.iter_mut() {
+        **t = (**t * max / total).max(1);
+    }
+    let clamped = FdBudget::new(threads.iter().map(|t| **t).sum(), threads.len());
+    eprintln!(
+        "Warning: the open files limit of {} fits {} hosts at once, running {} instead of {}",
+        limit, max, clamped.hosts, total
+    );
+    Ok(clamped)
+}
+
+/// Prints the diff report to stderr and saves it as JSON to `diff_report`, or to
+/// `diff_<run id>.json`.
+pub fn save_diff_report(conf: &Config, report: &DiffReport, run_id: &str) {
+    eprint!("{}", report);
+    let path = conf
+        .output
+        .diff_report
+        .clone()
+        .unwrap_or_else(|| PathBuf::from(sanitize_file_name(&format!("diff_{}.json", run_id))));
+    let result = File::create(&path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| serde_json::to_writer_pretty(file, report).map_err(|e| e.to_string()));
+    match result {
+        Ok(_) => eprintln!("Diff report saved to {}", path.display()),
+        Err(e) => eprintln!("Error saving diff report to {}: {}", path.display(), e),
+    }
+}
+
+/// Prints run totals to stderr, keeping stdout for the results.
+pub fn print_summary(
+    data: &[Response],
+    flushes: FlushStats,
+    dns: DnsCacheStats,
+    agent: AgentLatencyStats,
+    agent_limit: Option<Duration>,
+    fds: FdBudget,
+    peak_fds: Option<u64>,
+    spill: SpillStats,
+    limit_changes: &[LimitChange],
+) {
+    let summary = RunSummary::of(data);
+    eprintln!(
+        "Hosts: {}, OK: {}, Failed: {} (skipped: {}, cancelled: {})",
+        summary.total, summary.succeeded, summary.failed, summary.skipped, summary.cancelled
+    );
+    if summary.warnings > 0 {
+        let (yellow, reset) = if io::stderr().is_terminal() {
+            ("\x1b[33m", "\x1b[0m")
+        } else {
+            ("", "")
         };
+        eprintln!("{}Warnings: {}{}", yellow, summary.warnings, reset);
+    }
+    if summary.maintenance > 0 {
+        eprintln!("Skipped in maintenance: {}", summary.maintenance);
+    }
+    eprintln!(
+        "Hosts needing more than one attempt: {}",
+        data.iter().filter(|r| r.attempts > 1).count()
+    );
+    print_class_times(data);
+    eprintln!(
+        "Incremental output: {} results in {} writes, {:.1} per write on average",
+        flushes.records,
+        flushes.flushes,
+        flushes.average_batch()
+    );
+    eprintln!("DNS cache: {} hits, {} misses", dns.hits, dns.misses);
+    if let (Some(average), Some(p95)) = (agent.average, agent.p95) {
+        eprintln!(
+            "ssh-agent round trips: {}, avg {}ms, p95 {}ms",
+            agent.round_trips,
+            average.as_millis(),
+            p95.as_millis()
+        );
+    }
+    if let Some(limit) = agent_limit {
+        let slow = data
+            .iter()
+            .filter(|r| {
+                let latency = r.connection.as_ref().and_then(|c| c.agent_latency);
+                latency.map_or(false, |latency| latency > limit)
+            })
+            .count();
+        if slow > 0 {
+            eprintln!(
+                "ssh-agent slower than {}ms on {} hosts",
+                limit.as_millis(),
+                slow
+            );
+        }
+    }
+    let show = |n: Option<u64>| n.map_or("unknown".to_string(), |n| n.to_string());
+    eprintln!(
+        "Open files: peak {}, budget {} for {} hosts, limit {}",
+        show(peak_fds),
+        fds.needed,
+        fds.hosts,
+        show(fds.limit)
+    );
+    if spill.budget_bytes.is_some() {
+        eprintln!(
+            "Output memory: peak {} bytes, {} bytes spilled in {} outputs",
+            spill.peak_memory_bytes, spill.spilled_bytes, spill.spilled_outputs
+        );
+    }
+    for change in limit_changes {
+        let rate = change
+            .connect_rate
+            .map_or("unlimited".to_string(), |rate| format!("{}/s", rate));
+        eprintln!(
+            "Limits changed at {}: max connections {}, connect rate {}",
+            chrono::DateTime::<Utc>::from(change.at).to_rfc3339(),
+            change.max_connections,
+            rate
+        );
+    }
+}
+
+/// Prints percentiles of the processing time of each host class, when hosts have classes.
+fn print_class_times(data: &[Response]) {
+    if data.iter().all(|r| r.class.is_none()) {
+        return;
+    }
+    let mut times: BTreeMap<&str, Vec<Duration>> = BTreeMap::new();
+    for response in data {
+        times
+            .entry(response.class.as_deref().unwrap_or("(none)"))
+            .or_default()
+            .push(response.process_time);
+    }
+    for (class, mut times) in times {
+        times.sort();
+        let percentile = |p: usize| times[(times.len() * p + 99) / 100 - 1].as_secs_f64();
+        eprintln!(
+            "Class {}: {} hosts, p50 {:.1}s, p90 {:.1}s, p99 {:.1}s",
+            class,
+            times.len(),
+            percentile(50),
+            percentile(90),
+            percentile(99)
+        );
+    }
+}
+
+/// Names Windows reserves for devices, whatever the extension.
+const RESERVED_FILE_NAMES: &[&str] = &[
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Turns `name`, e.g. a host name or run id, into a file name valid on Windows and Unix
+/// alike: separators, characters Windows reserves and control characters become `_`,
+/// trailing dots and spaces are dropped and device names such as `NUL` get a `_` prefix.
+pub fn sanitize_file_name(name: &str) -> String {
+    let mut out: String = name
+        .chars()
+        .map(|c| {
+            if c.is_control() || "<>:\"/\\|?*".contains(c) {
+                '_'
+            } else {
+                c
+            }
+        })
+        .collect();
+    out.truncate(out.trim_end_matches(|c| c == '.' || c == ' ').len());
+    let stem = out.split('.').next().unwrap_or("");
+    if out.is_empty()
+        || RESERVED_FILE_NAMES
+            .iter()
+            .any(|r| r.eq_ignore_ascii_case(stem))
+    {
+        out.insert(0, '_');
+    }
+    out
+}
+
+/// Where incremental results go: one file, or rotated parts with an index.
+enum IncrementalOutput {
+    /// A JSON array, closed once the run ends or is interrupted.
+    Single(JsonArrayWriter<BufWriter<File>>),
+    Rotating(RotatingWriter),
+    /// NDJSON appended to a file shared with other runs.
+    Shared(SharedFile),
+}
+
+impl IncrementalOutput {
+    /// `response` serialized as this output writes it.
+    fn record(&self, response: &Response) -> io::Result<Vec<u8>> {
+        let mut data = match self {
+            IncrementalOutput::Single(writer) => return Ok(writer.element(response)?.into_bytes()),
+            IncrementalOutput::Rotating(_) | IncrementalOutput::Shared(_) => {
+                serde_json::to_vec(response)?
+            }
+        };
+        data.push(b'\n');
+        Ok(data)
+    }
+
+    /// Writes out a batch of records, taking the file lock of a shared file once for all.
+    fn write_batch(&mut self, records: Vec<Vec<u8>>) -> io::Result<()> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        match self {
+            IncrementalOutput::Single(writer) => {
+                for record in &records {
+                    writer.push_element(record)?;
+                }
+                writer.out.flush()
+            }
+            IncrementalOutput::Rotating(writer) => {
+                for record in &records {
+                    writer.write_line(record)?;
+                }
+                writer.flush()
+            }
+            IncrementalOutput::Shared(file) => file.append(&records.concat()),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            IncrementalOutput::Single(writer) => writer.finish().map(|_| ()),
+            IncrementalOutput::Rotating(writer) => {
+                let index = writer.finish()?;
+                eprintln!("Incremental results indexed in {}", index.display());
+                Ok(())
+            }
+            IncrementalOutput::Shared(_) => Ok(()),
+        }
+    }
+}
+
+/// Creates the incremental output of the run and returns it with the path of the failed
+/// hosts list next to it.
+fn config_incremental_folders(
+    run_id: &str,
+    rotation: Option<Rotation>,
+    shared: bool,
+    legacy_failed_hosts: bool,
+) -> (IncrementalOutput, PathBuf) {
+    let datetime = Utc::now().format("%H_%M_%S").to_string();
+    let stem = sanitize_file_name(&format!("incremental_{}_{}", datetime, run_id));
+    let extension = if legacy_failed_hosts { "txt" } else { "ndjson" };
+    let failed_name = format!("failed_hosts_{}_{}.{}", datetime, run_id, extension);
+    let store_dir_date = PathBuf::from(Utc::today().format("%d_%B_%Y").to_string());
+    // Other runs may be creating it at the same time.
+    std::fs::create_dir_all(&store_dir_date).expect("Failed creating dir for temporary save");
+    if shared {
+        let path = store_dir_date.join("incremental.ndjson");
+        let file = SharedFile::open(&path)
+            .unwrap_or_else(|e| panic!("Failed opening {}: {}", path.display(), e));
+        return (
+            IncrementalOutput::Shared(file),
+            store_dir_date.join(format!("failed_hosts.{}", extension)),
+        );
+    }
+    let output = match rotation {
+        Some(limits) => {
+            IncrementalOutput::Rotating(RotatingWriter::new(&store_dir_date, &stem, limits))
+        }
+        None => IncrementalOutput::Single(JsonArrayWriter::new(
+            BufWriter::new(
+                File::create(store_dir_date.join(format!("{}.json", stem)))
+                    .expect("incremental salving failed."),
+            ),
+            true,
+        )),
+    };
+    (
+        output,
+        store_dir_date.join(sanitize_file_name(&failed_name)),
+    )
+}
+
+/// Writes the responses which did not succeed, one `FailedHost` record per line for
+/// `replay_failed`, or only their host names with `legacy`. Derived from the results, so
+/// it never disagrees with them.
+///
+/// A `shared` list is appended to under a file lock, one record at a time.
+fn save_failed_hosts(path: &Path, results: &[Response], shared: bool, legacy: bool) {
+    let lines: Vec<String> = results
+        .iter()
+        .filter_map(FailedHost::of)
+        .map(|failed| {
+            if legacy {
+                format!("{}\n", failed.name)
+            } else {
+                let mut line = serde_json::to_string(&failed).expect("records serialize");
+                line.push('\n');
+                line
+            }
+        })
+        .collect();
+    if lines.is_empty() {
+        return;
+    }
+    let result = if shared {
+        SharedFile::open(path).and_then(|mut file| {
+            lines
+                .iter()
+                .try_for_each(|line| file.append(line.as_bytes()))
+        })
     } else {
-        match serde_json::to_writer(file, &data) {
-            Ok(_) => println!("Saved successfully"),
-            Err(e) => eprintln!("Error saving: {}", e),
+        File::create(path).and_then(|mut file| file.write_all(lines.concat().as_bytes()))
+    };
+    if let Err(e) = result {
+        eprintln!("Error saving failed hosts to {}: {}", path.display(), e)
+    }
+}
+/// Hosts in flight listed in the status line, longest running first.
+const OLDEST_SHOWN: usize = 3;
+/// Seconds between plain progress lines when the config does not say.
+const DEFAULT_PROGRESS_INTERVAL: u64 = 30;
+
+/// How the progress of a run is shown on stderr; stdout only ever gets results.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ProgressMode {
+    Bar,
+    /// A plain line every interval, for logs where the bar's escape codes would be noise.
+    Lines(Duration),
+}
+
+impl ProgressMode {
+    /// Mode `output` asks for, the bar only when stderr is a terminal unless set.
+    pub fn of(output: &OutputProps) -> Self {
+        let bar = output
+            .show_progress
+            .unwrap_or_else(|| io::stderr().is_terminal());
+        if bar {
+            ProgressMode::Bar
+        } else {
+            ProgressMode::Lines(Duration::from_secs(
+                output
+                    .progress_interval
+                    .unwrap_or(DEFAULT_PROGRESS_INTERVAL),
+            ))
+        }
+    }
+}
+
+enum ProgressDisplay {
+    Bar(ProgressBar),
+    Lines { interval: Duration, last: Instant },
+}
+
+impl ProgressDisplay {
+    fn new(mode: ProgressMode, queue_len: u64) -> Self {
+        match mode {
+            ProgressMode::Bar => {
+                let bar = ProgressBar::new(queue_len);
+                bar.set_style(
+                    ProgressStyle::default_bar()
+                        .template(
+                            "{eta_precise} {wide_bar} Hosts processed: {pos}/{len} \
+                             Speed: {per_sec} {msg}",
+                        )
+                        .progress_chars("##-"),
+                );
+                ProgressDisplay::Bar(bar)
+            }
+            ProgressMode::Lines(interval) => ProgressDisplay::Lines {
+                interval,
+                last: Instant::now(),
+            },
+        }
+    }
+}
+
+/// Connection limits over all `limits`, e.g. `limit 25/50 (31 running) 5/s`; `None` while
+/// they are as the props were built, without a connect rate.
+fn limits_gauge(limits: &[RunHandle]) -> Option<String> {
+    let max: usize = limits.iter().map(RunHandle::max_connections).sum();
+    let ceiling: usize = limits.iter().map(RunHandle::ceiling).sum();
+    let rate: f64 = limits.iter().filter_map(RunHandle::connect_rate).sum();
+    if max == ceiling && rate == 0.0 {
+        return None;
+    }
+    let running: usize = limits.iter().map(RunHandle::running).sum();
+    let mut gauge = format!("limit {}/{} ({} running)", max, ceiling, running);
+    if rate > 0.0 {
+        gauge += &format!(" {}/s", rate);
+    }
+    Some(gauge)
+}
+
+/// Plain progress line, e.g. `processed 1200/5000, ok 1100, failed 100, elapsed 3m12s`.
+fn progress_line(done: u64, queue_len: u64, summary: &RunSummary, elapsed: Duration) -> String {
+    let mut line = format!(
+        "processed {}/{}, ok {}, failed {}",
+        done, queue_len, summary.succeeded, summary.failed
+    );
+    if summary.skipped > 0 {
+        line += &format!(", skipped {}", summary.skipped);
+    }
+    if summary.cancelled > 0 {
+        line += &format!(", cancelled {}", summary.cancelled);
+    }
+    let secs = elapsed.as_secs();
+    line += &match (secs / 3600, secs / 60 % 60, secs % 60) {
+        (0, 0, s) => format!(", elapsed {}s", s),
+        (0, m, s) => format!(", elapsed {}m{:02}s", m, s),
+        (h, m, s) => format!(", elapsed {}h{:02}m{:02}s", h, m, s),
+    };
+    line
+}
+
+/// Shows the progress of the run as results arrive on `rx`, in either mode, and a final
+/// progress line once they are all in or `rx` is closed.
+fn progress_display(
+    queue_len: u64,
+    rx: std::sync::mpsc::Receiver<HostStatus>,
+    progress: Arc<ProgressTracker>,
+    limits: Vec<RunHandle>,
+    mode: ProgressMode,
+) {
+    let started = Instant::now();
+    let mut summary = RunSummary::default();
+    let mut done = 0;
+    let mut display = ProgressDisplay::new(mode, queue_len);
+    while done < queue_len {
+        // Refresh at least every second, so a stuck host shows even without results.
+        match rx.recv_timeout(Duration::from_secs(1)) {
+            Ok(status) => {
+                match status {
+                    HostStatus::Success => summary.succeeded += 1,
+                    HostStatus::Failed => summary.failed += 1,
+                    HostStatus::Skipped => summary.skipped += 1,
+                    HostStatus::Cancelled => summary.cancelled += 1,
+                }
+                done += 1;
+                if let ProgressDisplay::Bar(bar) = &display {
+                    bar.inc(1);
+                }
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            // Saving is over, with some hosts missing.
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+        let bar = match &mut display {
+            ProgressDisplay::Bar(bar) => bar,
+            ProgressDisplay::Lines { interval, last } => {
+                if last.elapsed() >= *interval {
+                    *last = Instant::now();
+                    eprintln!(
+                        "{}{}",
+                        progress_line(done, queue_len, &summary, started.elapsed()),
+                        limits_gauge(&limits).map_or(String::new(), |g| format!(", {}", g))
+                    );
+                }
+                continue;
+            }
+        };
+        let oldest: Vec<String> = progress
+            .in_flight()
+            .iter()
+            .take(OLDEST_SHOWN)
+            .map(|e| format!("{} {:?} {}s", e.hostname, e.phase, e.elapsed.as_secs()))
+            .collect();
+        let mut message = format!(
+            "OK: {}, Failed: {}, Skipped: {}, Cancelled: {}",
+            summary.succeeded, summary.failed, summary.skipped, summary.cancelled
+        );
+        if let Some(p95) = progress.agent_latency().p95 {
+            message += &format!(" agent p95 {}ms", p95.as_millis());
+        }
+        for (name, permit) in [("agent", Permit::Agent), ("read", Permit::Read)].iter() {
+            let stats = progress.permit_stats(*permit);
+            if let Some(average) = stats.average_wait() {
+                message += &format!(
+                    " avg {} wait {:.1}s ({} waiting)",
+                    name,
+                    average.as_secs_f64(),
+                    stats.waiting
+                );
+            }
+        }
+        if let Some(gauge) = limits_gauge(&limits) {
+            message += &format!(" {}", gauge);
+        }
+        if !oldest.is_empty() {
+            message += &format!(" Oldest: {}", oldest.join(", "));
         }
+        bar.set_message(&message);
     }
+    if let ProgressDisplay::Bar(bar) = &display {
+        bar.finish();
+    }
+    eprintln!(
+        "{}",
+        progress_line(done, queue_len, &summary, started.elapsed())
+    );
 }
 
-pub fn save_to_console(conf: &Config, data: &Vec<Response>) {
-    if conf.output.pretty_format {
-        println!("{}", serde_json::to_string_pretty(&data).unwrap())
+/// Writes responses to the incremental file as they arrive and returns them all, once
+/// `stream_len` arrived or every sender is gone. Hosts which did not succeed are listed in
+/// a failed hosts file next to it.
+///
+/// Attempt histories are dropped unless `verbose_attempts` is set. With `rotation`, results
+/// are written as NDJSON parts listed in an index file. Results are written out in batches
+/// as `flush` says, the last one once the stream ends; what was written is returned along.
+/// Progress goes to stderr as `progress_mode` says, with the connection limits of `limits`
+/// once they differ from those the props were built with. With `memory_budget`, the outputs of
+/// the returned results are kept within it, see `PendingResponses`.
+///
+/// After Ctrl-C, see `watch_interrupt`, hosts in flight are waited for up to
+/// `interrupt_grace`; the results so far are then written out and returned, the
+/// incremental file closed so it stays valid JSON.
+pub fn incremental_save(
+    rx: Receiver<Response>,
+    stream_len: usize,
+    verbose_attempts: bool,
+    rotation: Option<Rotation>,
+    flush: FlushPolicy,
+    shared: bool,
+    legacy_failed_hosts: bool,
+    progress: Arc<ProgressTracker>,
+    limits: Vec<RunHandle>,
+    progress_mode: ProgressMode,
+    memory_budget: Option<usize>,
+    run_id: &str,
+    interrupt_grace: Duration,
+) -> (PendingResponses, FlushStats) {
+    let (mut output, failed_hosts) =
+        config_incremental_folders(run_id, rotation, shared, legacy_failed_hosts);
+    let mut results = PendingResponses::new(memory_budget, run_id);
+    let len = stream_len;
+    let (sender, reciever) = std::sync::mpsc::channel();
+    let display = std::thread::spawn(move || {
+        progress_display(len as u64, reciever, progress, limits, progress_mode)
+    });
+    let mut coalescer = Coalescer::new(flush);
+    let mut give_up_at = None;
+    while results.len() < len {
+        if give_up_at.is_none() && interrupted() {
+            give_up_at = Some(Instant::now() + interrupt_grace);
+        }
+        if give_up_at.map_or(false, |at| Instant::now() >= at) {
+            break;
+        }
+        let wait = coalescer
+            .time_left()
+            .map_or(SIGNAL_POLL, |left| left.min(SIGNAL_POLL));
+        let mut received = match rx.recv_timeout(wait) {
+            Ok(received) => received,
+            Err(crossbeam_channel::RecvTimeoutError::Timeout) => {
+                if coalescer.due() {
+                    output
+                        .write_batch(coalescer.take())
+                        .expect("Writing for incremental saving failed");
+                }
+                continue;
+            }
+            Err(crossbeam_channel::RecvTimeoutError::Disconnected) => break,
+        };
+        if !verbose_attempts {
+            received.attempt_history.clear();
+        }
+        warn_host_key_change(&received);
+        if let Err(e) = sender.send(received.outcome) {
+            eprintln!("Error sending stats: {}", e)
+        }
+        coalescer.push(
+            output
+                .record(&received)
+                .expect("Serializing for incremental saving failed"),
+        );
+        if coalescer.due() {
+            output
+                .write_batch(coalescer.take())
+                .expect("Writing for incremental saving failed");
+        }
+        results
+            .push(received)
+            .expect("Spilling output to disk failed");
+    }
+    drop(sender);
+    let _ = display.join();
+    output
+        .write_batch(coalescer.take())
+        .and_then(|_| output.finish())
+        .expect("Failed flushing");
+    if results.len() < len && interrupted() {
+        eprintln!(
+            "Interrupted: saved the results of {} of {} hosts, {} still in flight were not \
+             waited for",
+            results.len(),
+            len,
+            len - results.len()
+        );
+    } else if results.len() < len {
+        eprintln!(
+            "Warning: {} of {} hosts produced no result",
+            len - results.len(),
+            len
+        );
+    }
+    save_failed_hosts(
+        &failed_hosts,
+        results.responses(),
+        shared,
+        legacy_failed_hosts,
+    );
+    (results, coalescer.stats())
+}
+
+/// Warns of a host key other than the trusted one, which `HostKeyPolicy::Warn` let
+/// through.
+pub fn warn_host_key_change(response: &Response) {
+    if response.error_kind == Some(ErrorKind::HostKeyChanged) {
+        return;
+    }
+    if let Some(HostKeyInfo {
+        fingerprint,
+        previous: Some(previous),
+    }) = &response.host_key
+    {
+        eprintln!(
+            "Warning: Host key of {} changed from {} to {}",
+            response.address, previous, fingerprint
+        );
+    }
+}
+
+/// How often signals are looked for by the threads acting on them.
+const SIGNAL_POLL: Duration = Duration::from_millis(200);
+
+/// Hosts in flight are waited for this long after Ctrl-C when the config does not say.
+pub const DEFAULT_INTERRUPT_GRACE: Duration = Duration::from_secs(10);
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::Relaxed) {
+        unsafe { libc::_exit(130) }
+    }
+}
+
+#[cfg(unix)]
+fn catch_interrupt() {
+    let handler = on_interrupt as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+#[cfg(windows)]
+fn catch_interrupt() {
+    // The console control handler runs on a thread of its own, where exiting is safe.
+    let handler = || {
+        if INTERRUPTED.swap(true, Ordering::Relaxed) {
+            std::process::exit(130)
+        }
+    };
+    if let Err(e) = ctrlc::set_handler(handler) {
+        eprintln!("Warning: Ctrl-C will not be caught: {}", e);
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn catch_interrupt() {}
+
+/// Whether Ctrl-C was pressed since `watch_interrupt`.
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// Cancels the runs of `props` on the first SIGINT: hosts not started yet fail with
+/// `ErrorKind::Cancelled`, and hosts in flight are waited for as long as `grace` says,
+/// see `incremental_save`. A second SIGINT exits right away with status 130. On Windows
+/// the console's Ctrl-C and Ctrl-Break are caught instead.
+pub fn watch_interrupt(props: Vec<ParallelSshProps>, grace: Duration) {
+    catch_interrupt();
+    std::thread::spawn(move || {
+        while !interrupted() {
+            std::thread::sleep(SIGNAL_POLL);
+        }
+        eprintln!(
+            "Interrupted, starting no more hosts and waiting up to {} for those in flight; \
+             Ctrl-C again to quit right away",
+            humantime::format_duration(grace)
+        );
+        for props in &props {
+            props.cancel();
+        }
+    });
+}
+
+#[cfg(unix)]
+static HALVE_LIMITS: AtomicBool = AtomicBool::new(false);
+#[cfg(unix)]
+static DOUBLE_LIMITS: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_limit_signal(signal: libc::c_int) {
+    if signal == libc::SIGUSR1 {
+        HALVE_LIMITS.store(true, Ordering::Relaxed);
     } else {
-        println!("{}", serde_json::to_string(&data).unwrap())
+        DOUBLE_LIMITS.store(true, Ordering::Relaxed);
+    }
+}
+
+/// Halves the connection limits of `limits` on SIGUSR1 and doubles them on SIGUSR2, until
+/// the process exits. Hosts in flight are left to finish; see `RunHandle`.
+#[cfg(unix)]
+pub fn watch_limit_signals(limits: Vec<RunHandle>) {
+    let handler = on_limit_signal as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGUSR1, handler as libc::sighandler_t);
+        libc::signal(libc::SIGUSR2, handler as libc::sighandler_t);
     }
+    std::thread::spawn(move || loop {
+        std::thread::sleep(SIGNAL_POLL);
+        let scale: fn(usize) -> usize = if HALVE_LIMITS.swap(false, Ordering::Relaxed) {
+            |n| n / 2
+        } else if DOUBLE_LIMITS.swap(false, Ordering::Relaxed) {
+            |n| n * 2
+        } else {
+            continue;
+        };
+        let max: usize = limits
+            .iter()
+            .map(|handle| handle.set_max_connections(scale(handle.max_connections())))
+            .sum();
+        eprintln!("Max connections now {}", max);
+    });
 }
+
+/// There are no SIGUSR1 and SIGUSR2 outside Unix; the limits stay as configured.
+#[cfg(not(unix))]
+pub fn watch_limit_signals(_limits: Vec<RunHandle>) {}
@@ -0,0 +1,367 @@
+use crate::redact::Redactor;
+use fnv::FnvHasher;
+use serde::{Deserialize, Serialize};
+use std::collections::VecDeque;
+use std::fs::File;
+use std::hash::Hasher;
+use std::io::{self, BufWriter, Write};
+use std::mem;
+use std::path::PathBuf;
+
+/// Which lines of a command's output are kept.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputKeep {
+    All,
+    /// First `n` lines.
+    Head(usize),
+    /// Last `n` lines.
+    Tail(usize),
+    /// First `n` and last `m` lines.
+    HeadTail(usize, usize),
+}
+
+impl Default for OutputKeep {
+    fn default() -> Self {
+        OutputKeep::All
+    }
+}
+
+/// Amount of output dropped by `OutputKeep`.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DiscardedOutput {
+    pub lines: u64,
+    pub bytes: u64,
+}
+
+/// Applies an `OutputKeep` to output arriving in arbitrary chunks.
+///
+/// Lines are split on `\n` bytes. Memory stays bounded by the kept lines plus the line
+/// currently being read: the tail is a ring of at most `m` complete lines, so a line
+/// split across two reads is only placed once its end has arrived.
+pub(crate) struct OutputCollector {
+    head_limit: usize,
+    tail_limit: usize,
+    head: Vec<u8>,
+    head_lines: usize,
+    tail: VecDeque<Vec<u8>>,
+    partial: Vec<u8>,
+    discarded: DiscardedOutput,
+}
+
+impl OutputCollector {
+    pub(crate) fn new(keep: OutputKeep) -> Self {
+        let (head_limit, tail_limit) = match keep {
+            OutputKeep::All => (usize::MAX, 0),
+            OutputKeep::Head(n) => (n, 0),
+            OutputKeep::Tail(m) => (0, m),
+            OutputKeep::HeadTail(n, m) => (n, m),
+        };
+        OutputCollector {
+            head_limit,
+            tail_limit,
+            head: Vec::new(),
+            head_lines: 0,
+            tail: VecDeque::with_capacity(tail_limit.min(1024) + 1),
+            partial: Vec::new(),
+            discarded: DiscardedOutput::default(),
+        }
+    }
+
+    pub(crate) fn feed(&mut self, mut chunk: &[u8]) {
+        if self.head_limit == usize::MAX {
+            self.head.extend_from_slice(chunk);
+            return;
+        }
+        while let Some(pos) = chunk.iter().position(|&b| b == b'\n') {
+            self.partial.extend_from_slice(&chunk[..=pos]);
+            let line = mem::replace(&mut self.partial, Vec::new());
+            self.push_line(line);
+            chunk = &chunk[pos + 1..];
+        }
+        self.partial.extend_from_slice(chunk);
+    }
+
+    fn push_line(&mut self, line: Vec<u8>) {
+        if self.head_lines < self.head_limit {
+            self.head.extend_from_slice(&line);
+            self.head_lines += 1;
+            return;
+        }
+        let dropped = if self.tail_limit == 0 {
+            Some(line)
+        } else {
+            self.tail.push_back(line);
+            if self.tail.len() > self.tail_limit {
+                self.tail.pop_front()
+            } else {
+                None
+            }
+        };
+        if let Some(dropped) = dropped {
+            self.discarded.lines += 1;
+            self.discarded.bytes += dropped.len() as u64;
+        }
+    }
+
+    /// Kept output, with a final line lacking `\n` counted as a line.
+    pub(crate) fn finish(mut self) -> (Vec<u8>, DiscardedOutput) {
+        if !self.partial.is_empty() {
+            let line = mem::replace(&mut self.partial, Vec::new());
+            self.push_line(line);
+        }
+        let mut out = self.head;
+        for line in self.tail {
+            out.extend_from_slice(&line);
+        }
+        (out, self.discarded)
+    }
+}
+
+/// Where the output of the command goes instead of into the response, so memory stays
+/// flat whatever the size of the outputs and the number of hosts.
+///
+/// The response then only carries the size and a hash of the output. The output of the
+/// guard and the skip check is still kept.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputPassThrough {
+    /// As `RunEvent::OutputChunk`s on the event stream of `build_with_events`.
+    Events,
+    /// Into `<dir>/<host>.out`, one file per host, rewritten on every attempt.
+    Dir(PathBuf),
+    /// Kept in the response up to `threshold` bytes, as without pass-through; a larger
+    /// output is written whole into `<dir>/<host>.out` as with `Dir` from the moment it
+    /// grows past `threshold`, so at most `threshold` bytes per host are buffered.
+    Spill { dir: PathBuf, threshold: usize },
+}
+
+/// Output of a command passed through instead of kept.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct PassedThrough {
+    pub bytes: u64,
+    /// FNV-1a 64 of the output in hex, the same for the same output in any run.
+    pub fnv1a: String,
+    /// File the output was written to, with `OutputPassThrough::Dir`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub file: Option<PathBuf>,
+    /// Secrets redacted from the file, when the props have a redactor.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redactions: Option<u64>,
+}
+
+/// `hostname` with anything but letters, digits, dots and dashes replaced by `_`, for
+/// naming a file or directory after the host.
+pub(crate) fn host_file_name(hostname: &str) -> String {
+    hostname
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '.' | '-' => c,
+            _ => '_',
+        })
+        .collect()
+}
+
+/// How a `PassThroughWriter` ended.
+pub(crate) enum PassThroughEnd {
+    /// Output below the spill threshold, to keep in the response.
+    Kept(Vec<u8>),
+    Passed(PassedThrough),
+}
+
+/// Counts and hashes output as it arrives, writing it to the host's file if any. With a
+/// redactor, the file is written a line at a time, redacted.
+pub(crate) struct PassThroughWriter {
+    file: Option<(PathBuf, BufWriter<File>)>,
+    /// File and threshold of `OutputPassThrough::Spill` while below the threshold, with
+    /// the output so far.
+    spill: Option<(PathBuf, usize)>,
+    buffer: Vec<u8>,
+    bytes: u64,
+    hash: FnvHasher,
+    error: Option<io::Error>,
+    redactor: Option<Redactor>,
+    /// Start of a line not written yet, with a redactor.
+    partial: Vec<u8>,
+    redactions: u64,
+}
+
+impl PassThroughWriter {
+    pub(crate) fn new(
+        pass_through: &OutputPassThrough,
+        hostname: &str,
+        redactor: Option<&Redactor>,
+    ) -> io::Result<Self> {
+        let path = |dir: &PathBuf| dir.join(format!("{}.out", host_file_name(hostname)));
+        let (file, spill) = match pass_through {
+            OutputPassThrough::Events => (None, None),
+            OutputPassThrough::Dir(dir) => {
+                let path = path(dir);
+                let file = BufWriter::new(File::create(&path)?);
+                (Some((path, file)), None)
+            }
+            OutputPassThrough::Spill { dir, threshold } => (None, Some((path(dir), *threshold))),
+        };
+        Ok(PassThroughWriter {
+            file,
+            spill,
+            buffer: Vec::new(),
+            bytes: 0,
+            hash: FnvHasher::default(),
+            error: None,
+            redactor: redactor.cloned(),
+            partial: Vec::new(),
+            redactions: 0,
+        })
+    }
+
+    pub(crate) fn feed(&mut self, chunk: &[u8]) {
+        self.bytes += chunk.len() as u64;
+        self.hash.write(chunk);
+        if let Some((_, threshold)) = &self.spill {
+            self.buffer.extend_from_slice(chunk);
+            if self.buffer.len() <= *threshold {
+                return;
+            }
+            let (path, _) = self.spill.take().unwrap();
+            match File::create(&path) {
+                Ok(file) => self.file = Some((path, BufWriter::new(file))),
+                Err(e) => self.error = Some(e),
+            }
+            let buffered = mem::replace(&mut self.buffer, Vec::new());
+            self.pass(&buffered);
+            return;
+        }
+        self.pass(chunk);
+    }
+
+    fn pass(&mut self, chunk: &[u8]) {
+        if self.redactor.is_none() || self.file.is_none() {
+            self.write(chunk);
+            return;
+        }
+        self.partial.extend_from_slice(chunk);
+        while let Some(end) = self.partial.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.partial.drain(..=end).collect();
+            self.write_redacted(&line);
+        }
+    }
+
+    fn write_redacted(&mut self, data: &[u8]) {
+        if let Some(redactor) = &self.redactor {
+            let (redacted, count) = redactor.redact_bytes(data);
+            self.redactions += count;
+            self.write(&redacted);
+        }
+    }
+
+    fn write(&mut self, data: &[u8]) {
+        if let (Some((_, file)), None) = (&mut self.file, &self.error) {
+            if let Err(e) = file.write_all(data) {
+                self.error = Some(e);
+            }
+        }
+    }
+
+    /// Flushes the file, failing with the first write error.
+    pub(crate) fn finish(mut self) -> io::Result<PassThroughEnd> {
+        if self.spill.is_some() {
+            return Ok(PassThroughEnd::Kept(self.buffer));
+        }
+        if !self.partial.is_empty() {
+            let line = mem::replace(&mut self.partial, Vec::new());
+            self.write_redacted(&line);
+        }
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+        let file = match self.file {
+            Some((path, mut file)) => {
+                file.flush()?;
+                Some(path)
+            }
+            None => None,
+        };
+        Ok(PassThroughEnd::Passed(PassedThrough {
+            bytes: self.bytes,
+            fnv1a: format!("{:016x}", self.hash.finish()),
+            redactions: match (&self.redactor, &file) {
+                (Some(_), Some(_)) => Some(self.redactions),
+                _ => None,
+            },
+            file,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const INPUT: &[u8] = b"one\ntwo\nthree\nfour\nfive";
+
+    fn collect(keep: OutputKeep, chunks: &[&[u8]]) -> (Vec<u8>, DiscardedOutput) {
+        let mut collector = OutputCollector::new(keep);
+        for chunk in chunks {
+            collector.feed(chunk);
+        }
+        collector.finish()
+    }
+
+    #[test]
+    fn tail_is_the_same_at_every_chunk_boundary() {
+        for input in &[INPUT, b"one\ntwo\nthree\nfour\nfive\n" as &[u8]] {
+            let whole = collect(OutputKeep::Tail(2), &[input]);
+            assert!(whole.0.starts_with(b"four\nfive"));
+            assert_eq!(
+                whole.1,
+                DiscardedOutput {
+                    lines: 3,
+                    bytes: 14
+                }
+            );
+            for split in 0..=input.len() {
+                let (a, b) = input.split_at(split);
+                assert_eq!(collect(OutputKeep::Tail(2), &[a, b]), whole, "{}", split);
+            }
+            let bytes: Vec<&[u8]> = input.chunks(1).collect();
+            assert_eq!(collect(OutputKeep::Tail(2), &bytes), whole);
+        }
+    }
+
+    #[test]
+    fn head_and_tail_split_mid_line() {
+        let chunks: [&[u8]; 3] = [b"one\ntw", b"o\nthree\nfo", b"ur\nfive"];
+        assert_eq!(
+            collect(OutputKeep::HeadTail(1, 1), &chunks),
+            (
+                b"one\nfive".to_vec(),
+                DiscardedOutput {
+                    lines: 3,
+                    bytes: 15
+                }
+            )
+        );
+        assert_eq!(
+            collect(OutputKeep::Head(2), &chunks).0,
+            b"one\ntwo\n".to_vec()
+        );
+    }
+
+    #[test]
+    fn tail_longer_than_output_keeps_everything() {
+        let (out, discarded) = collect(OutputKeep::Tail(10), &[INPUT]);
+        assert_eq!(out, INPUT);
+        assert_eq!(discarded, DiscardedOutput::default());
+        assert_eq!(collect(OutputKeep::Tail(3), &[]).0, b"");
+    }
+
+    #[test]
+    fn host_file_names_hold_no_separator() {
+        assert_eq!(host_file_name("web-1.example.com"), "web-1.example.com");
+        assert_eq!(host_file_name("[::1]:22"), "___1__22");
+        assert_eq!(host_file_name("k8s://ns/pod"), "k8s___ns_pod");
+        assert_eq!(host_file_name("a\\b:c"), "a_b_c");
+        assert_eq!(host_file_name("web-ü"), "web-_");
+    }
+}
@@ -0,0 +1,130 @@
+use fnv::FnvHasher;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::hash::Hasher;
+#[cfg(feature = "xxh3")]
+use xxhash_rust::xxh3::Xxh3;
+
+/// Hash of command output recorded in `Response::output_hash`, prefixed with the name of
+/// the algorithm, e.g. `sha256:9f86...`.
+///
+/// The hash is computed while the output is read, so it covers the whole output even
+/// when `OutputKeep` drops part of it or the output is passed through.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum OutputHashAlgorithm {
+    Sha256,
+    /// FNV-1a 64, much faster but not collision resistant.
+    Fnv1a,
+    /// XXH3 64, faster still on long outputs and not collision resistant either.
+    #[cfg(feature = "xxh3")]
+    Xxh3,
+}
+
+impl Default for OutputHashAlgorithm {
+    fn default() -> Self {
+        OutputHashAlgorithm::Sha256
+    }
+}
+
+/// Incremental hash of one output.
+pub(crate) enum OutputHasher {
+    Sha256(Sha256),
+    Fnv1a(FnvHasher),
+    #[cfg(feature = "xxh3")]
+    Xxh3(Box<Xxh3>),
+}
+
+impl OutputHasher {
+    pub(crate) fn new(algorithm: OutputHashAlgorithm) -> Self {
+        match algorithm {
+            OutputHashAlgorithm::Sha256 => OutputHasher::Sha256(Sha256::new()),
+            OutputHashAlgorithm::Fnv1a => OutputHasher::Fnv1a(FnvHasher::default()),
+            #[cfg(feature = "xxh3")]
+            OutputHashAlgorithm::Xxh3 => OutputHasher::Xxh3(Box::new(Xxh3::new())),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            OutputHasher::Sha256(hasher) => hasher.update(data),
+            OutputHasher::Fnv1a(hasher) => hasher.write(data),
+            #[cfg(feature = "xxh3")]
+            OutputHasher::Xxh3(hasher) => hasher.update(data),
+        }
+    }
+
+    pub(crate) fn finish(self) -> String {
+        match self {
+            OutputHasher::Sha256(hasher) => format!("sha256:{}", hex(&hasher.finalize())),
+            OutputHasher::Fnv1a(hasher) => format!("fnv1a:{:016x}", hasher.finish()),
+            #[cfg(feature = "xxh3")]
+            OutputHasher::Xxh3(hasher) => format!("xxh3:{:016x}", hasher.digest()),
+        }
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash(algorithm: OutputHashAlgorithm, chunks: &[&[u8]]) -> String {
+        let mut hasher = OutputHasher::new(algorithm);
+        for chunk in chunks {
+            hasher.update(chunk);
+        }
+        hasher.finish()
+    }
+
+    #[test]
+    fn sha256_matches_the_nist_vectors() {
+        let sha256 = |data: &[u8]| hash(OutputHashAlgorithm::Sha256, &[data]);
+        assert_eq!(
+            sha256(b""),
+            "sha256:e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855"
+        );
+        assert_eq!(
+            sha256(b"abc"),
+            "sha256:ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+        assert_eq!(
+            sha256(b"abcdbcdecdefdefgefghfghighijhijkijkljklmklmnlmnomnopnopq"),
+            "sha256:248d6a61d20638b8e5c026930c3e6039a33ce45964ff2167f6ecedd419db06c1"
+        );
+    }
+
+    #[test]
+    fn sha256_of_a_million_a_in_uneven_chunks() {
+        let data = vec![b'a'; 1_000_000];
+        let chunks: Vec<&[u8]> = data.chunks(4093).collect();
+        assert_eq!(
+            hash(OutputHashAlgorithm::Sha256, &chunks),
+            "sha256:cdc76e5c9914fb9281a1c7e284d73e67f1809a48a497200e046d39ccc7112cd0"
+        );
+    }
+
+    #[test]
+    fn fnv1a_matches_its_reference_values() {
+        let fnv1a = |data: &[u8]| hash(OutputHashAlgorithm::Fnv1a, &[data]);
+        assert_eq!(fnv1a(b""), "fnv1a:cbf29ce484222325");
+        assert_eq!(fnv1a(b"a"), "fnv1a:af63dc4c8601ec8c");
+        assert_eq!(fnv1a(b"foobar"), "fnv1a:85944171f73967e8");
+        assert_eq!(
+            hash(OutputHashAlgorithm::Fnv1a, &[b"foo", b"", b"bar"]),
+            fnv1a(b"foobar")
+        );
+    }
+
+    #[cfg(feature = "xxh3")]
+    #[test]
+    fn xxh3_of_nothing() {
+        assert_eq!(
+            hash(OutputHashAlgorithm::Xxh3, &[]),
+            "xxh3:2d06800538d394c2"
+        );
+    }
+}
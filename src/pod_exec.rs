@@ -0,0 +1,164 @@
+use crate::local::PodAddress;
+use crate::response::{ErrorKind, HostError};
+use futures::future::try_join;
+use k8s_openapi::api::core::v1::Pod;
+use k8s_openapi::apimachinery::pkg::apis::meta::v1::Status;
+use kube::api::{Api, AttachParams};
+use kube::Client;
+use std::sync::OnceLock;
+use std::time::Instant;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::runtime::Runtime;
+
+/// Runtime and client of the cluster the kubeconfig or the pod's service account points
+/// at, made on the first pod host and shared by all of them.
+struct Cluster {
+    runtime: Runtime,
+    client: Client,
+}
+
+fn cluster() -> Result<&'static Cluster, HostError> {
+    static CLUSTER: OnceLock<Result<Cluster, String>> = OnceLock::new();
+    CLUSTER
+        .get_or_init(|| {
+            let runtime = tokio::runtime::Builder::new_multi_thread()
+                .worker_threads(2)
+                .enable_all()
+                .build()
+                .map_err(|e| e.to_string())?;
+            let client = runtime
+                .block_on(Client::try_default())
+                .map_err(|e| e.to_string())?;
+            Ok(Cluster { runtime, client })
+        })
+        .as_ref()
+        .map_err(|e| HostError::new(ErrorKind::Session, format!("No Kubernetes client: {}", e)))
+}
+
+/// Runs `sh -c command` in `pod` through the exec API, writing `input` to its stdin, and
+/// returns its stdout, stderr and exit code. At `deadline` the exec is aborted and fails
+/// with `ErrorKind::ReadTotalTimeout`.
+pub(crate) fn exec(
+    pod: &PodAddress,
+    command: &str,
+    input: Option<String>,
+    deadline: Option<Instant>,
+) -> Result<(Vec<u8>, Vec<u8>, i32), HostError> {
+    let cluster = cluster()?;
+    let pods: Api<Pod> = Api::namespaced(cluster.client.clone(), pod.namespace);
+    let mut params = AttachParams::default()
+        .stdin(true)
+        .stdout(true)
+        .stderr(true);
+    if let Some(container) = pod.container {
+        params = params.container(container);
+    }
+    let exec_error = |e: String| {
+        HostError::new(
+            ErrorKind::Exec,
+            format!("Failed executing in pod {}: {}", pod.pod, e),
+        )
+    };
+    cluster.runtime.block_on(async {
+        let mut attached = pods
+            .exec(pod.pod, vec!["sh", "-c", command], &params)
+            .await
+            .map_err(|e| exec_error(e.to_string()))?;
+        let mut stdin = attached.stdin().expect("stdin attached");
+        let mut stdout = attached.stdout().expect("stdout attached");
+        let mut stderr = attached.stderr().expect("stderr attached");
+        let status = attached.take_status().expect("status not taken");
+        let run = async {
+            if let Some(input) = input {
+                // A command which exits without reading its stdin is no error.
+                let _ = stdin.write_all(input.as_bytes()).await;
+            }
+            drop(stdin);
+            let (mut out, mut err) = (Vec::new(), Vec::new());
+            try_join(stdout.read_to_end(&mut out), stderr.read_to_end(&mut err))
+                .await
+                .map_err(|e| {
+                    HostError::new(ErrorKind::Read, format!("Error reading output: {}", e))
+                })?;
+            Ok((out, err, status.await))
+        };
+        let (out, err, status) = match deadline {
+            Some(deadline) => tokio::time::timeout_at(deadline.into(), run)
+                .await
+                .map_err(|_| {
+                    attached.abort();
+                    HostError::new(
+                        ErrorKind::ReadTotalTimeout,
+                        "Command did not finish in time and was aborted".to_string(),
+                    )
+                })??,
+            None => run.await?,
+        };
+        let _ = attached.join().await;
+        Ok((out, err, exit_code(status).map_err(exec_error)?))
+    })
+}
+
+/// Exit code the API server reports in the status of an exec.
+fn exit_code(status: Option<Status>) -> Result<i32, String> {
+    let status = status.ok_or("the exec ended without a status")?;
+    if status.status.as_deref() == Some("Success") {
+        return Ok(0);
+    }
+    let code = status
+        .details
+        .as_ref()
+        .and_then(|details| details.causes.as_ref())
+        .into_iter()
+        .flatten()
+        .find(|cause| cause.reason.as_deref() == Some("ExitCode"))
+        .and_then(|cause| cause.message.as_ref()?.parse().ok());
+    match code {
+        Some(code) if status.reason.as_deref() == Some("NonZeroExitCode") => Ok(code),
+        _ => Err(status
+            .message
+            .unwrap_or_else(|| "the exec failed without a message".to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use k8s_openapi::apimachinery::pkg::apis::meta::v1::{StatusCause, StatusDetails};
+
+    fn failure(reason: &str, causes: Vec<(&str, &str)>) -> Status {
+        Status {
+            status: Some("Failure".to_string()),
+            reason: Some(reason.to_string()),
+            message: Some("command terminated with non-zero exit code".to_string()),
+            details: Some(StatusDetails {
+                causes: Some(
+                    causes
+                        .into_iter()
+                        .map(|(reason, message)| StatusCause {
+                            reason: Some(reason.to_string()),
+                            message: Some(message.to_string()),
+                            ..StatusCause::default()
+                        })
+                        .collect(),
+                ),
+                ..StatusDetails::default()
+            }),
+            ..Status::default()
+        }
+    }
+
+    #[test]
+    fn exit_code_comes_from_the_status() {
+        let success = Status {
+            status: Some("Success".to_string()),
+            ..Status::default()
+        };
+        assert_eq!(exit_code(Some(success)), Ok(0));
+        let exited = failure("NonZeroExitCode", vec![("ExitCode", "7")]);
+        assert_eq!(exit_code(Some(exited)), Ok(7));
+        let other = failure("InternalError", vec![]);
+        assert!(exit_code(Some(other)).is_err());
+        assert!(exit_code(None).is_err());
+    }
+}
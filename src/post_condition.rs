@@ -0,0 +1,211 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::convert::TryFrom;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Poll interval when the config gives none.
+const DEFAULT_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Condition waited for after the command succeeded, so a host only counts as done once
+/// what the command started is up, e.g. a restarted service accepting connections.
+///
+/// The condition is polled until it holds or `timeout` passes, which fails the host with
+/// `ErrorKind::PostConditionTimeout`. Checks run in the host's session, with the same
+/// shell, workdir and become settings as the command; the port is probed with a new
+/// connection to the host. Each wait between polls is `interval` give or take a quarter,
+/// so hosts which finished together do not poll in lockstep. In TOML:
+///
+/// ```toml
+/// post_condition = { tcp_port_open = { port = 8080, timeout = "2m", interval = "2s" } }
+/// post_condition = { command_succeeds = { command = "systemctl is-active myapp", timeout = "1m" } }
+/// post_condition = { output_matches = { command = "curl -s localhost:8080/health", pattern = "ok", timeout = "1m" } }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "PostConditionConfig", into = "PostConditionConfig")]
+pub enum PostCondition {
+    TcpPortOpen {
+        port: u16,
+        timeout: Duration,
+        interval: Duration,
+    },
+    CommandSucceeds {
+        command: String,
+        timeout: Duration,
+        interval: Duration,
+    },
+    OutputMatches {
+        command: String,
+        pattern: Regex,
+        timeout: Duration,
+        interval: Duration,
+    },
+}
+
+/// Config form of `PostCondition`, with durations as humantime strings.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum PostConditionConfig {
+    TcpPortOpen {
+        port: u16,
+        timeout: String,
+        #[serde(default)]
+        interval: Option<String>,
+    },
+    CommandSucceeds {
+        command: String,
+        timeout: String,
+        #[serde(default)]
+        interval: Option<String>,
+    },
+    OutputMatches {
+        command: String,
+        pattern: String,
+        timeout: String,
+        #[serde(default)]
+        interval: Option<String>,
+    },
+}
+
+fn parse_duration(s: &str) -> Result<Duration, String> {
+    humantime::parse_duration(s).map_err(|e| format!("{}: {}", s, e))
+}
+
+fn parse_interval(s: Option<String>) -> Result<Duration, String> {
+    s.map_or(Ok(DEFAULT_INTERVAL), |s| parse_duration(&s))
+}
+
+fn format_duration(d: Duration) -> String {
+    humantime::format_duration(d).to_string()
+}
+
+impl TryFrom<PostConditionConfig> for PostCondition {
+    type Error = String;
+
+    fn try_from(config: PostConditionConfig) -> Result<Self, Self::Error> {
+        Ok(match config {
+            PostConditionConfig::TcpPortOpen {
+                port,
+                timeout,
+                interval,
+            } => PostCondition::TcpPortOpen {
+                port,
+                timeout: parse_duration(&timeout)?,
+                interval: parse_interval(interval)?,
+            },
+            PostConditionConfig::CommandSucceeds {
+                command,
+                timeout,
+                interval,
+            } => PostCondition::CommandSucceeds {
+                command,
+                timeout: parse_duration(&timeout)?,
+                interval: parse_interval(interval)?,
+            },
+            PostConditionConfig::OutputMatches {
+                command,
+                pattern,
+                timeout,
+                interval,
+            } => PostCondition::OutputMatches {
+                command,
+                pattern: Regex::new(&pattern).map_err(|e| e.to_string())?,
+                timeout: parse_duration(&timeout)?,
+                interval: parse_interval(interval)?,
+            },
+        })
+    }
+}
+
+impl From<PostCondition> for PostConditionConfig {
+    fn from(condition: PostCondition) -> Self {
+        match condition {
+            PostCondition::TcpPortOpen {
+                port,
+                timeout,
+                interval,
+            } => PostConditionConfig::TcpPortOpen {
+                port,
+                timeout: format_duration(timeout),
+                interval: Some(format_duration(interval)),
+            },
+            PostCondition::CommandSucceeds {
+                command,
+                timeout,
+                interval,
+            } => PostConditionConfig::CommandSucceeds {
+                command,
+                timeout: format_duration(timeout),
+                interval: Some(format_duration(interval)),
+            },
+            PostCondition::OutputMatches {
+                command,
+                pattern,
+                timeout,
+                interval,
+            } => PostConditionConfig::OutputMatches {
+                command,
+                pattern: pattern.as_str().to_string(),
+                timeout: format_duration(timeout),
+                interval: Some(format_duration(interval)),
+            },
+        }
+    }
+}
+
+impl PostCondition {
+    /// The check command, `None` for a port probe.
+    pub fn command(&self) -> Option<&str> {
+        match self {
+            PostCondition::TcpPortOpen { .. } => None,
+            PostCondition::CommandSucceeds { command, .. }
+            | PostCondition::OutputMatches { command, .. } => Some(command),
+        }
+    }
+
+    pub fn timeout(&self) -> Duration {
+        match self {
+            PostCondition::TcpPortOpen { timeout, .. }
+            | PostCondition::CommandSucceeds { timeout, .. }
+            | PostCondition::OutputMatches { timeout, .. } => *timeout,
+        }
+    }
+
+    pub fn interval(&self) -> Duration {
+        match self {
+            PostCondition::TcpPortOpen { interval, .. }
+            | PostCondition::CommandSucceeds { interval, .. }
+            | PostCondition::OutputMatches { interval, .. } => *interval,
+        }
+    }
+
+    /// Whether a check which exited with `exit_code`, printing `output`, meets the
+    /// condition.
+    pub fn met_by(&self, exit_code: i32, output: &str) -> bool {
+        match self {
+            PostCondition::TcpPortOpen { .. } => false,
+            PostCondition::CommandSucceeds { .. } => exit_code == 0,
+            PostCondition::OutputMatches { pattern, .. } => pattern.is_match(output),
+        }
+    }
+
+    /// The interval, between 0.75 and 1.25 times it.
+    pub(crate) fn jittered_interval(&self) -> Duration {
+        let mut hasher = RandomState::new().build_hasher();
+        hasher.write_u64(0);
+        let fraction = (hasher.finish() % 1000) as f64 / 1000.0;
+        self.interval().mul_f64(0.75 + fraction / 2.0)
+    }
+}
+
+/// How the post condition of a host went, recorded in its response.
+#[derive(Serialize, Debug, Clone)]
+pub struct PostConditionResult {
+    pub met: bool,
+    /// From the end of the command until the condition held or timed out.
+    pub waited: Duration,
+    pub polls: u32,
+    /// What the last poll saw: the output of the check, or why the port was not open.
+    pub last_result: String,
+}
@@ -0,0 +1,182 @@
+use crate::auth::{self, AuthMethod};
+use crate::known_hosts::HostKeyStore;
+use crate::response::{ErrorKind, HostError};
+use serde::Serialize;
+use ssh2::Session;
+use std::fmt::{self, Display};
+use std::fs;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CheckStatus {
+    Pass,
+    /// Not fatal on its own, but worth a look.
+    Warn,
+    /// The run would fail for most hosts.
+    Fail,
+}
+
+/// Outcome of one preflight check.
+#[derive(Serialize, Debug, Clone)]
+pub struct PreflightCheck {
+    pub name: String,
+    pub status: CheckStatus,
+    pub details: String,
+}
+
+/// Checks run before a run, to catch problems every host would otherwise fail on.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct PreflightReport {
+    pub checks: Vec<PreflightCheck>,
+}
+
+impl PreflightReport {
+    pub fn push<N: Into<String>, D: Into<String>>(
+        &mut self,
+        name: N,
+        status: CheckStatus,
+        details: D,
+    ) {
+        self.checks.push(PreflightCheck {
+            name: name.into(),
+            status,
+            details: details.into(),
+        });
+    }
+
+    /// Whether no check failed; warnings do not count.
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|c| c.status != CheckStatus::Fail)
+    }
+}
+
+impl Display for PreflightReport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for check in &self.checks {
+            let status = match check.status {
+                CheckStatus::Pass => "PASS",
+                CheckStatus::Warn => "WARN",
+                CheckStatus::Fail => "FAIL",
+            };
+            writeln!(f, "[{}] {}: {}", status, check.name, check.details)?;
+        }
+        Ok(())
+    }
+}
+
+/// Comments of the identities the running ssh-agent offers.
+pub fn list_agent_identities() -> Result<Vec<String>, HostError> {
+    let agent_error = |e: ssh2::Error| HostError::new(ErrorKind::Agent, e.to_string());
+    let sess = Session::new()
+        .map_err(|_e| HostError::new(ErrorKind::Session, "Error initializing session"))?;
+    let mut agent = sess.agent().map_err(agent_error)?;
+    agent.connect().map_err(|e| {
+        HostError::new(
+            ErrorKind::Agent,
+            format!("{}: {}", e, auth::agent_unreachable_hint()),
+        )
+    })?;
+    agent.list_identities().map_err(agent_error)?;
+    let identities = agent
+        .identities()
+        .map_err(agent_error)?
+        .iter()
+        .map(|key| key.comment().to_string())
+        .collect();
+    let _ = agent.disconnect();
+    Ok(identities)
+}
+
+/// Checks each method of `chain` can be used at all.
+///
+/// A broken method is only a failure when it is the whole chain, as the others may still
+/// authenticate.
+pub(crate) fn check_auth_chain(report: &mut PreflightReport, chain: &[AuthMethod]) {
+    if chain.is_empty() {
+        report.push("auth", CheckStatus::Fail, "The auth chain is empty");
+        return;
+    }
+    let broken = if chain.len() == 1 {
+        CheckStatus::Fail
+    } else {
+        CheckStatus::Warn
+    };
+    for method in chain {
+        match method {
+            AuthMethod::Agent => match list_agent_identities() {
+                Ok(ids) if ids.is_empty() => {
+                    report.push("agent", broken, "The agent holds no identities")
+                }
+                Ok(ids) => report.push(
+                    "agent",
+                    CheckStatus::Pass,
+                    format!("{} identities: {}", ids.len(), ids.join(", ")),
+                ),
+                Err(e) => report.push("agent", broken, format!("Agent unreachable: {}", e)),
+            },
+            AuthMethod::KeyFile { path, passphrase } => {
+                let name = format!("key_file {}", path.display());
+                match fs::read_to_string(path) {
+                    Err(e) => report.push(name, broken, format!("Unreadable: {}", e)),
+                    Ok(key) if key.contains("ENCRYPTED") && passphrase.is_none() => report.push(
+                        name,
+                        broken,
+                        "The key is encrypted, but no passphrase is configured",
+                    ),
+                    Ok(_) => report.push(name, CheckStatus::Pass, "Readable"),
+                }
+            }
+            AuthMethod::Password { .. } => report.push("password", CheckStatus::Pass, "Configured"),
+        }
+    }
+}
+
+/// Checks the host key store can be read and updated, and how many of the hosts with the
+/// store keys `keys` it already trusts.
+///
+/// Hosts given by name are only known after resolving them, so they are left out of
+/// `keys`.
+pub(crate) fn check_host_keys(report: &mut PreflightReport, store: &HostKeyStore, keys: &[String]) {
+    let name = format!("known_hosts {}", store.path().display());
+    match store.invalid_lines() {
+        Err(e) => {
+            report.push(name, CheckStatus::Fail, format!("Unreadable: {}", e));
+            return;
+        }
+        Ok(lines) if !lines.is_empty() => {
+            let lines: Vec<String> = lines.iter().map(ToString::to_string).collect();
+            report.push(
+                name.clone(),
+                CheckStatus::Warn,
+                format!(
+                    "Lines {} are no host key entry and are ignored",
+                    lines.join(", ")
+                ),
+            );
+        }
+        Ok(_) => {}
+    }
+    let unknown = keys.iter().filter(|key| store.get(key).is_none()).count();
+    if unknown > 0 {
+        if let Err(e) = store.check_writable() {
+            report.push(
+                name,
+                CheckStatus::Fail,
+                format!(
+                    "{} hosts have no key yet, which cannot be recorded: {}",
+                    unknown, e
+                ),
+            );
+            return;
+        }
+    }
+    report.push(
+        name,
+        CheckStatus::Pass,
+        format!(
+            "{} of {} hosts given by address have a trusted key",
+            keys.len() - unknown,
+            keys.len()
+        ),
+    );
+}
@@ -0,0 +1,49 @@
+pub use crate::args::{ArgAssigner, HostInfo};
+pub use crate::auth::AuthMethod;
+pub use crate::coalesce::{Coalescer, FlushPolicy, FlushStats};
+pub use crate::command::RemoteCommand;
+pub use crate::context::{ContextSnapshot, FactsConfig, HostContext, RunContext};
+pub use crate::control::{LimitChange, RunHandle};
+pub use crate::diagnostics::{Algorithms, DetailedResponse, Step, StepTiming};
+pub use crate::dns::DnsCacheStats;
+pub use crate::download::{DownloadOptions, DownloadReport, DownloadStrategy};
+pub use crate::encoding::OutputEncoding;
+pub use crate::escalation::{BecomeMethod, SecretProvider};
+pub use crate::events::RunEvent;
+pub use crate::failure_threshold::FailureThreshold;
+pub use crate::fd_budget::{FdBudget, FdMonitor, FdShortage};
+pub use crate::guard::{Guard, GuardResult};
+pub use crate::inventory::{EffectiveHostConfig, HostOptions, PlannedHost, RunPlan};
+pub use crate::known_hosts::{HostKeyInfo, HostKeyPolicy, HostKeyStore};
+pub use crate::lint::{CommandLint, LintReport, LintRule, LINT_RULES};
+pub use crate::local::Connection;
+pub use crate::output::{DiscardedOutput, OutputKeep, OutputPassThrough, PassedThrough};
+pub use crate::output_hash::OutputHashAlgorithm;
+pub use crate::post_condition::{PostCondition, PostConditionResult};
+pub use crate::preflight::{CheckStatus, PreflightReport};
+pub use crate::preset::{Preset, PresetValues};
+pub use crate::progress::{
+    AgentLatencyStats, Permit, PermitStats, PermitWait, Phase, ProgressEvent, ProgressHook,
+    ProgressTracker, AGENT_LATENCY_WINDOW,
+};
+pub use crate::proxy::ProxyConfig;
+pub use crate::pty::PtyRequest;
+pub use crate::redact::{Redactor, REDACTED};
+pub use crate::response::{
+    AttemptRecord, CommandOutput, ConnectionInfo, ErrorKind, HostError, HostSelection, HostStatus,
+    HostTimings, Response, RunError, RunSummary,
+};
+pub use crate::result_class::{ExitCodeClasses, ExitCodeRange, ResultClass};
+pub use crate::retry::{KindRetry, RetryPolicy};
+pub use crate::scheduler::{ParallelSshProps, ParallelSshPropsBuilder};
+pub use crate::serial::{BatchHook, BatchReport, Serial};
+pub use crate::session::HostSession;
+pub use crate::session_pool::{SessionPool, SessionPoolStats};
+pub use crate::sftp::{FetchReport, UploadReport};
+pub use crate::shell::RemoteShell;
+pub use crate::skip_check::{SkipCheck, SkipCheckResult};
+pub use crate::socket::TcpKeepaliveConfig;
+pub use crate::spill::{PendingResponses, SpillStats};
+pub use crate::target::{HostSpec, HostTarget, IntoTarget};
+pub use crate::timeouts::Timeouts;
+pub use crate::typed_response::{BannerReport, ResponsePayload, TypedResponse};
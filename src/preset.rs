@@ -0,0 +1,119 @@
+use crate::retry::RetryPolicy;
+use crate::timeouts::Timeouts;
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+/// Coherent set of concurrency, timeout and retry settings for a kind of workload, set
+/// with `ParallelSshPropsBuilder::preset`.
+///
+/// The exact values are in `PRESETS`, one row per preset.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum Preset {
+    /// 20 connections, 1 agent user; probe 1s, connect 10s, other phases 30s, idle
+    /// read 120s; 3 attempts 5s apart.
+    Careful,
+    /// 100 connections, 3 agent users; probe 500ms, connect 5s, other phases 20s, idle
+    /// read 60s; 2 attempts 2s apart.
+    Balanced,
+    /// 500 connections, 8 agent users; probe 200ms, connect 2s, other phases 10s, idle
+    /// read 30s; a single attempt.
+    Aggressive,
+    /// 50 connections, 3 agent users; probe 3s, connect 15s, other phases 60s, idle read
+    /// 300s; 3 attempts 10s apart.
+    WanHighLatency,
+}
+
+/// Settings of a preset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PresetValues {
+    /// Hosts run at once, as `tcp_connections_pool`.
+    pub connections: isize,
+    /// As `agent_connections_pool`.
+    pub agent_parallelism: isize,
+    /// Port probe before connecting, as `timeout_socket`.
+    pub probe_timeout: Duration,
+    pub timeouts: Timeouts,
+    /// Attempts per host on the failures `RetryPolicy` retries by default.
+    pub max_attempts: u32,
+    pub backoff_ms: u64,
+}
+
+impl PresetValues {
+    pub fn retry_policy(&self) -> RetryPolicy {
+        RetryPolicy {
+            max_attempts: self.max_attempts,
+            backoff_ms: self.backoff_ms,
+            ..RetryPolicy::default()
+        }
+    }
+}
+
+const fn phases(connect: u64, phase: u64, read_idle: u64) -> Timeouts {
+    Timeouts {
+        connect: Some(Duration::from_secs(connect)),
+        handshake: Some(Duration::from_secs(phase)),
+        auth: Some(Duration::from_secs(phase)),
+        exec: Some(Duration::from_secs(phase)),
+        read_total: None,
+        read_idle: Some(Duration::from_secs(read_idle)),
+    }
+}
+
+/// Values of every preset; the docs of `Preset` mirror them.
+pub const PRESETS: [(Preset, PresetValues); 4] = [
+    (
+        Preset::Careful,
+        PresetValues {
+            connections: 20,
+            agent_parallelism: 1,
+            probe_timeout: Duration::from_millis(1000),
+            timeouts: phases(10, 30, 120),
+            max_attempts: 3,
+            backoff_ms: 5000,
+        },
+    ),
+    (
+        Preset::Balanced,
+        PresetValues {
+            connections: 100,
+            agent_parallelism: 3,
+            probe_timeout: Duration::from_millis(500),
+            timeouts: phases(5, 20, 60),
+            max_attempts: 2,
+            backoff_ms: 2000,
+        },
+    ),
+    (
+        Preset::Aggressive,
+        PresetValues {
+            connections: 500,
+            agent_parallelism: 8,
+            probe_timeout: Duration::from_millis(200),
+            timeouts: phases(2, 10, 30),
+            max_attempts: 1,
+            backoff_ms: 0,
+        },
+    ),
+    (
+        Preset::WanHighLatency,
+        PresetValues {
+            connections: 50,
+            agent_parallelism: 3,
+            probe_timeout: Duration::from_millis(3000),
+            timeouts: phases(15, 60, 300),
+            max_attempts: 3,
+            backoff_ms: 10000,
+        },
+    ),
+];
+
+impl Preset {
+    pub fn values(self) -> PresetValues {
+        PRESETS
+            .iter()
+            .find(|(preset, _)| *preset == self)
+            .map(|(_, values)| *values)
+            .expect("every preset is in PRESETS")
+    }
+}
@@ -0,0 +1,362 @@
+use crate::events::RunEvent;
+use crossbeam_channel::Sender;
+use serde::Serialize;
+use std::collections::{HashMap, VecDeque};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Step a host's run is at.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Phase {
+    Connecting,
+    Handshake,
+    Authenticating,
+    /// Command started, output being read.
+    Running,
+    /// Command started, waiting for a read permit of `max_concurrent_reads`.
+    WaitingToRead,
+    /// Command done, waiting for its post condition.
+    WaitingForCondition,
+}
+
+/// Permit a host can wait for.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Permit {
+    /// The ssh-agent, used by one host at a time.
+    Agent,
+    /// A read permit of `max_concurrent_reads`.
+    Read,
+}
+
+/// Wait of an in-flight host for a permit.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PermitWait {
+    pub permit: Permit,
+    /// Time waited so far.
+    pub waited: Duration,
+    /// Hosts waiting for the same permit, this one included.
+    pub queue_depth: u64,
+}
+
+/// Waits for one kind of permit over all hosts of a tracker.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PermitStats {
+    /// Hosts waiting right now.
+    pub waiting: u64,
+    /// Waits finished so far.
+    pub acquired: u64,
+    /// Time the finished waits took together.
+    pub total_wait: Duration,
+}
+
+impl PermitStats {
+    /// Average of the finished waits, `None` before the first one.
+    pub fn average_wait(&self) -> Option<Duration> {
+        match self.acquired {
+            0 => None,
+            n => Some(self.total_wait / n as u32),
+        }
+    }
+}
+
+/// Counters behind `PermitStats`, atomics so waiting hosts do not contend on a lock.
+#[derive(Default)]
+struct PermitGauge {
+    waiting: AtomicU64,
+    acquired: AtomicU64,
+    wait_nanos: AtomicU64,
+}
+
+/// Round trips to the ssh-agent over all hosts of a tracker, from connecting to the agent
+/// to its last answer, without the wait for the agent permit.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct AgentLatencyStats {
+    /// Round trips so far, failed ones included.
+    pub round_trips: u64,
+    /// Average over the whole run, `None` before the first round trip.
+    pub average: Option<Duration>,
+    /// 95th percentile of the last `AGENT_LATENCY_WINDOW` round trips.
+    pub p95: Option<Duration>,
+}
+
+/// Round trips the agent latency percentile is taken over, so it follows the agent as it
+/// slows down or recovers during a long run.
+pub const AGENT_LATENCY_WINDOW: usize = 200;
+
+#[derive(Default)]
+struct AgentLatency {
+    round_trips: u64,
+    total: Duration,
+    recent: VecDeque<Duration>,
+}
+
+/// State of one in-flight host, sent on every phase change and every heartbeat.
+#[derive(Serialize, Debug, Clone)]
+pub struct ProgressEvent {
+    pub hostname: String,
+    /// Class of the host, see `HostOptions::class`.
+    pub class: Option<String>,
+    pub phase: Phase,
+    pub elapsed: Duration,
+    pub bytes_read: u64,
+    /// Permit the host is waiting for, if any.
+    pub waiting: Option<PermitWait>,
+}
+
+pub type ProgressHook = Arc<dyn Fn(&ProgressEvent) + Send + Sync>;
+
+struct HostState {
+    hostname: String,
+    class: Option<String>,
+    phase: Phase,
+    started: Instant,
+    bytes_read: Arc<AtomicU64>,
+    waiting: Option<(Permit, Instant)>,
+}
+
+impl HostState {
+    fn event(&self, tracker: &ProgressTracker) -> ProgressEvent {
+        ProgressEvent {
+            hostname: self.hostname.clone(),
+            class: self.class.clone(),
+            phase: self.phase,
+            elapsed: self.started.elapsed(),
+            bytes_read: self.bytes_read.load(Ordering::Relaxed),
+            waiting: self.waiting.map(|(permit, since)| PermitWait {
+                permit,
+                waited: since.elapsed(),
+                queue_depth: tracker.gauge(permit).waiting.load(Ordering::Relaxed),
+            }),
+        }
+    }
+}
+
+/// Registry of the hosts in flight, shared by all props of a stream.
+///
+/// Heartbeats for all hosts come from one ticker thread per tracker, which exits once the
+/// tracker is dropped.
+pub struct ProgressTracker {
+    hook: Option<ProgressHook>,
+    hosts: Mutex<HashMap<u64, HostState>>,
+    next_id: AtomicU64,
+    agent: PermitGauge,
+    read: PermitGauge,
+    agent_latency: Mutex<AgentLatency>,
+}
+
+impl ProgressTracker {
+    pub(crate) fn new(hook: Option<ProgressHook>, interval: Duration) -> Arc<Self> {
+        let tracker = Arc::new(ProgressTracker {
+            hook,
+            hosts: Mutex::new(HashMap::new()),
+            next_id: AtomicU64::new(0),
+            agent: PermitGauge::default(),
+            read: PermitGauge::default(),
+            agent_latency: Mutex::new(AgentLatency::default()),
+        });
+        if tracker.hook.is_some() {
+            let weak: Weak<ProgressTracker> = Arc::downgrade(&tracker);
+            thread::spawn(move || loop {
+                thread::sleep(interval);
+                match weak.upgrade() {
+                    Some(tracker) => tracker.beat(),
+                    None => break,
+                }
+            });
+        }
+        tracker
+    }
+
+    fn emit(&self, event: &ProgressEvent) {
+        if let Some(hook) = &self.hook {
+            hook(event)
+        }
+    }
+
+    fn beat(&self) {
+        let events: Vec<ProgressEvent> = self
+            .hosts
+            .lock()
+            .unwrap()
+            .values()
+            .map(|state| state.event(self))
+            .collect();
+        for event in &events {
+            self.emit(event);
+        }
+    }
+
+    /// Starts tracking `hostname` in the `Connecting` phase until the handle is dropped.
+    pub(crate) fn start(&self, hostname: String, class: Option<String>) -> HostProgress<'_> {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let bytes_read = Arc::new(AtomicU64::new(0));
+        let state = HostState {
+            hostname,
+            class,
+            phase: Phase::Connecting,
+            started: Instant::now(),
+            bytes_read: bytes_read.clone(),
+            waiting: None,
+        };
+        let event = state.event(self);
+        self.hosts.lock().unwrap().insert(id, state);
+        self.emit(&event);
+        HostProgress {
+            tracker: self,
+            id,
+            bytes_read,
+            events: None,
+        }
+    }
+
+    /// Hosts started so far, finished ones included.
+    pub fn started(&self) -> u64 {
+        self.next_id.load(Ordering::Relaxed)
+    }
+
+    /// Hosts in flight, longest running first.
+    pub fn in_flight(&self) -> Vec<ProgressEvent> {
+        let mut events: Vec<ProgressEvent> = self
+            .hosts
+            .lock()
+            .unwrap()
+            .values()
+            .map(|state| state.event(self))
+            .collect();
+        events.sort_by(|a, b| b.elapsed.cmp(&a.elapsed));
+        events
+    }
+
+    /// Waits for `permit` so far, over all hosts.
+    pub fn permit_stats(&self, permit: Permit) -> PermitStats {
+        let gauge = self.gauge(permit);
+        PermitStats {
+            waiting: gauge.waiting.load(Ordering::Relaxed),
+            acquired: gauge.acquired.load(Ordering::Relaxed),
+            total_wait: Duration::from_nanos(gauge.wait_nanos.load(Ordering::Relaxed)),
+        }
+    }
+
+    /// Round trips to the ssh-agent so far, over all hosts.
+    pub fn agent_latency(&self) -> AgentLatencyStats {
+        let latency = self.agent_latency.lock().unwrap();
+        let mut recent: Vec<Duration> = latency.recent.iter().copied().collect();
+        recent.sort();
+        AgentLatencyStats {
+            round_trips: latency.round_trips,
+            average: match latency.round_trips {
+                0 => None,
+                n => Some(latency.total / n as u32),
+            },
+            p95: match recent.len() {
+                0 => None,
+                n => Some(recent[(n * 95 + 99) / 100 - 1]),
+            },
+        }
+    }
+
+    pub(crate) fn agent_round_trip(&self, latency: Duration) {
+        let mut stats = self.agent_latency.lock().unwrap();
+        stats.round_trips += 1;
+        stats.total += latency;
+        if stats.recent.len() == AGENT_LATENCY_WINDOW {
+            stats.recent.pop_front();
+        }
+        stats.recent.push_back(latency);
+    }
+
+    fn gauge(&self, permit: Permit) -> &PermitGauge {
+        match permit {
+            Permit::Agent => &self.agent,
+            Permit::Read => &self.read,
+        }
+    }
+}
+
+/// Handle of one tracked host.
+pub(crate) struct HostProgress<'a> {
+    tracker: &'a ProgressTracker,
+    id: u64,
+    bytes_read: Arc<AtomicU64>,
+    /// Event stream of the run and the host name its events carry.
+    events: Option<(Sender<RunEvent>, String)>,
+}
+
+impl HostProgress<'_> {
+    pub(crate) fn set_phase(&self, phase: Phase) {
+        let event = match self.tracker.hosts.lock().unwrap().get_mut(&self.id) {
+            Some(state) if state.phase != phase => {
+                state.phase = phase;
+                state.event(self.tracker)
+            }
+            _ => return,
+        };
+        self.tracker.emit(&event);
+    }
+
+    /// Runs `acquire`, a wait for `permit`, showing the wait in the host's events and
+    /// adding it to the tracker's permit stats.
+    pub(crate) fn wait_for<T, F: FnOnce() -> T>(&self, permit: Permit, acquire: F) -> T {
+        let gauge = self.tracker.gauge(permit);
+        let start = Instant::now();
+        gauge.waiting.fetch_add(1, Ordering::Relaxed);
+        let event = match self.tracker.hosts.lock().unwrap().get_mut(&self.id) {
+            Some(state) => {
+                state.waiting = Some((permit, start));
+                Some(state.event(self.tracker))
+            }
+            None => None,
+        };
+        if let Some(event) = event {
+            self.tracker.emit(&event);
+        }
+        let acquired = acquire();
+        gauge.waiting.fetch_sub(1, Ordering::Relaxed);
+        gauge.acquired.fetch_add(1, Ordering::Relaxed);
+        gauge
+            .wait_nanos
+            .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+        if let Some(state) = self.tracker.hosts.lock().unwrap().get_mut(&self.id) {
+            state.waiting = None;
+        }
+        acquired
+    }
+
+    /// Also sends the host's events into `events`, if any.
+    pub(crate) fn with_events(
+        mut self,
+        events: Option<Sender<RunEvent>>,
+        hostname: String,
+    ) -> Self {
+        self.events = events.map(|tx| (tx, hostname));
+        self
+    }
+
+    /// Sends the event `f` builds from the host name.
+    pub(crate) fn event<F: FnOnce(String) -> RunEvent>(&self, f: F) {
+        if let Some((tx, hostname)) = &self.events {
+            let _ = tx.send(f(hostname.clone()));
+        }
+    }
+
+    /// Counts output read from stream `id`, 0 for stdout, and passes it on as an event.
+    pub(crate) fn add_output(&self, id: i32, data: &[u8]) {
+        self.bytes_read
+            .fetch_add(data.len() as u64, Ordering::Relaxed);
+        self.event(|hostname| RunEvent::OutputChunk {
+            hostname,
+            stderr: id != 0,
+            data: data.to_vec(),
+        });
+    }
+}
+
+impl Drop for HostProgress<'_> {
+    fn drop(&mut self) {
+        self.tracker.hosts.lock().unwrap().remove(&self.id);
+    }
+}
@@ -0,0 +1,239 @@
+use crate::redact::REDACTED;
+use crate::response::{ErrorKind, HostError};
+use crate::socket::{self, BindAddresses, TcpKeepaliveConfig};
+use serde::{Deserialize, Serialize};
+use std::fmt::{self, Display};
+use std::io::{Read, Write};
+use std::net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs};
+use std::time::Duration;
+
+/// SOCKS5 proxy the SSH connections are tunnelled through. Debug output shows `REDACTED`
+/// in place of the password.
+#[derive(Serialize, Deserialize, Clone, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ProxyConfig {
+    pub host: String,
+    pub port: u16,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Let the proxy resolve host names (socks5h) instead of resolving them locally.
+    #[serde(default)]
+    pub remote_dns: bool,
+}
+
+impl fmt::Debug for ProxyConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ProxyConfig")
+            .field("host", &self.host)
+            .field("port", &self.port)
+            .field("username", &self.username)
+            .field("password", &self.password.as_ref().map(|_| REDACTED))
+            .field("remote_dns", &self.remote_dns)
+            .finish()
+    }
+}
+
+/// Address a host connection goes to.
+#[derive(Debug, Clone)]
+pub enum Target {
+    Resolved(SocketAddr),
+    /// Host name left for the proxy to resolve.
+    Unresolved {
+        host: String,
+        port: u16,
+    },
+}
+
+impl Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Target::Resolved(addr) => write!(f, "{}", addr),
+            // Port 0 is no SSH port, but a host reached without one, e.g. a pod.
+            Target::Unresolved { host, port: 0 } => f.write_str(host),
+            Target::Unresolved { host, port } => write!(f, "{}:{}", host, port),
+        }
+    }
+}
+
+impl Target {
+    /// Splits a `host:port` string, keeping the name unresolved.
+    pub fn unresolved(s: &str) -> Result<Target, HostError> {
+        let invalid = || HostError::new(ErrorKind::Dns, format!("Invalid address: {}", s));
+        let idx = s.rfind(':').ok_or_else(invalid)?;
+        let port = s[idx + 1..].parse().map_err(|_| invalid())?;
+        let host = s[..idx].trim_start_matches('[').trim_end_matches(']');
+        Ok(Target::Unresolved {
+            host: host.to_string(),
+            port,
+        })
+    }
+}
+
+const SOCKS_VERSION: u8 = 5;
+const NO_AUTH: u8 = 0;
+const USER_PASS_AUTH: u8 = 2;
+const NO_ACCEPTABLE_METHOD: u8 = 0xFF;
+const CMD_CONNECT: u8 = 1;
+const ATYP_IPV4: u8 = 1;
+const ATYP_DOMAIN: u8 = 3;
+const ATYP_IPV6: u8 = 4;
+
+fn proxy_error<E: Display>(e: E) -> HostError {
+    HostError::new(ErrorKind::Proxy, format!("Proxy error: {}", e))
+}
+
+/// Opens a TCP connection to `target` through the SOCKS5 proxy.
+///
+/// Failures of the proxy itself are reported as `ErrorKind::Proxy`; a target the proxy
+/// could not reach is reported like a direct connection failure. The connection to the
+/// proxy is made from the bind address matching the proxy's address family.
+pub fn connect(
+    proxy: &ProxyConfig,
+    target: &Target,
+    bind: &BindAddresses,
+    keepalive: Option<&TcpKeepaliveConfig>,
+    timeout: Duration,
+) -> Result<TcpStream, HostError> {
+    let proxy_addr = (proxy.host.as_str(), proxy.port)
+        .to_socket_addrs()
+        .map_err(proxy_error)?
+        .next()
+        .ok_or_else(|| proxy_error(format!("Failed resolving {}", proxy.host)))?;
+    let local = bind
+        .for_target(&proxy_addr)
+        .map_err(|e| HostError::new(ErrorKind::Bind, e))?;
+    let mut stream =
+        socket::connect(&proxy_addr, local, Some(timeout), keepalive).map_err(proxy_error)?;
+    stream
+        .set_read_timeout(Some(timeout))
+        .map_err(proxy_error)?;
+    stream
+        .set_write_timeout(Some(timeout))
+        .map_err(proxy_error)?;
+
+    let credentials = match (&proxy.username, &proxy.password) {
+        (Some(u), Some(p)) => Some((u.as_str(), p.as_str())),
+        (Some(u), None) => Some((u.as_str(), "")),
+        _ => None,
+    };
+    let method = if credentials.is_some() {
+        USER_PASS_AUTH
+    } else {
+        NO_AUTH
+    };
+    stream
+        .write_all(&[SOCKS_VERSION, 1, method])
+        .map_err(proxy_error)?;
+    let mut reply = [0u8; 2];
+    stream.read_exact(&mut reply).map_err(proxy_error)?;
+    if reply[0] != SOCKS_VERSION {
+        return Err(proxy_error("not a SOCKS5 proxy"));
+    }
+    match reply[1] {
+        NO_AUTH => {}
+        USER_PASS_AUTH => {
+            let (user, pass) =
+                credentials.ok_or_else(|| proxy_error("proxy requires credentials"))?;
+            if user.len() > 255 || pass.len() > 255 {
+                return Err(proxy_error("proxy credentials longer than 255 bytes"));
+            }
+            let mut req = vec![1, user.len() as u8];
+            req.extend_from_slice(user.as_bytes());
+            req.push(pass.len() as u8);
+            req.extend_from_slice(pass.as_bytes());
+            stream.write_all(&req).map_err(proxy_error)?;
+            stream.read_exact(&mut reply).map_err(proxy_error)?;
+            if reply[1] != 0 {
+                return Err(proxy_error("proxy rejected the credentials"));
+            }
+        }
+        NO_ACCEPTABLE_METHOD => return Err(proxy_error("no acceptable auth method")),
+        m => return Err(proxy_error(format!("unsupported auth method {}", m))),
+    }
+
+    let mut req = vec![SOCKS_VERSION, CMD_CONNECT, 0];
+    let port = match target {
+        Target::Resolved(addr) => {
+            match addr.ip() {
+                IpAddr::V4(ip) => {
+                    req.push(ATYP_IPV4);
+                    req.extend_from_slice(&ip.octets());
+                }
+                IpAddr::V6(ip) => {
+                    req.push(ATYP_IPV6);
+                    req.extend_from_slice(&ip.octets());
+                }
+            }
+            addr.port()
+        }
+        Target::Unresolved { host, port } => {
+            if host.len() > 255 {
+                return Err(proxy_error("host name longer than 255 bytes"));
+            }
+            req.push(ATYP_DOMAIN);
+            req.push(host.len() as u8);
+            req.extend_from_slice(host.as_bytes());
+            *port
+        }
+    };
+    req.extend_from_slice(&port.to_be_bytes());
+    stream.write_all(&req).map_err(proxy_error)?;
+
+    let mut head = [0u8; 4];
+    stream.read_exact(&mut head).map_err(proxy_error)?;
+    match head[1] {
+        0 => {}
+        3 | 4 | 5 => {
+            return Err(HostError::new(
+                ErrorKind::TcpConnect,
+                format!(
+                    "Proxy could not reach {}: {}",
+                    target,
+                    reply_message(head[1])
+                ),
+            ))
+        }
+        6 => {
+            return Err(HostError::new(
+                ErrorKind::TcpTimeout,
+                format!(
+                    "Proxy could not reach {}: {}",
+                    target,
+                    reply_message(head[1])
+                ),
+            ))
+        }
+        code => return Err(proxy_error(reply_message(code))),
+    }
+    // The bound address is of no use to us, but has to be drained from the stream.
+    let bound_len = match head[3] {
+        ATYP_IPV4 => 4,
+        ATYP_IPV6 => 16,
+        ATYP_DOMAIN => {
+            let mut len = [0u8; 1];
+            stream.read_exact(&mut len).map_err(proxy_error)?;
+            len[0] as usize
+        }
+        a => return Err(proxy_error(format!("unknown address type {}", a))),
+    };
+    let mut bound = vec![0u8; bound_len + 2];
+    stream.read_exact(&mut bound).map_err(proxy_error)?;
+
+    stream.set_read_timeout(None).map_err(proxy_error)?;
+    stream.set_write_timeout(None).map_err(proxy_error)?;
+    Ok(stream)
+}
+
+fn reply_message(code: u8) -> String {
+    match code {
+        1 => "general SOCKS server failure".to_string(),
+        2 => "connection not allowed by ruleset".to_string(),
+        3 => "network unreachable".to_string(),
+        4 => "host unreachable".to_string(),
+        5 => "connection refused".to_string(),
+        6 => "TTL expired".to_string(),
+        7 => "command not supported".to_string(),
+        8 => "address type not supported".to_string(),
+        c => format!("unknown reply {}", c),
+    }
+}
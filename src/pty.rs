@@ -0,0 +1,101 @@
+use serde::{Deserialize, Serialize};
+
+/// Pseudo terminal commands are run on, for those which behave differently or refuse to
+/// run without one, e.g. `request_pty = { term = "xterm", width = 200 }`.
+///
+/// Stderr arrives mixed into the output on a pty, and lines end with `\r\n`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct PtyRequest {
+    /// Value of `TERM` on the host.
+    #[serde(default = "default_term")]
+    pub term: String,
+    /// Columns.
+    #[serde(default = "default_width")]
+    pub width: u32,
+    /// Rows.
+    #[serde(default = "default_height")]
+    pub height: u32,
+}
+
+fn default_term() -> String {
+    "xterm".to_string()
+}
+
+fn default_width() -> u32 {
+    80
+}
+
+fn default_height() -> u32 {
+    24
+}
+
+impl Default for PtyRequest {
+    fn default() -> Self {
+        PtyRequest {
+            term: default_term(),
+            width: default_width(),
+            height: default_height(),
+        }
+    }
+}
+
+impl PtyRequest {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.term.is_empty() {
+            return Err("request_pty.term must not be empty".to_string());
+        }
+        if self.width == 0 || self.height == 0 {
+            return Err(format!(
+                "request_pty needs a width and height above 0, got {}x{}",
+                self.width, self.height
+            ));
+        }
+        Ok(())
+    }
+}
+
+const ESC: char = '\u{1b}';
+const BEL: char = '\u{7}';
+
+/// `text` without its ANSI escape sequences: colors, cursor movement, window titles and
+/// the like. Other control characters, `\r` included, are kept.
+pub fn strip_ansi(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c != ESC {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            // CSI: parameters and intermediates up to a final byte in @..~.
+            Some('[') => {
+                for c in chars.by_ref() {
+                    if ('@'..='~').contains(&c) {
+                        break;
+                    }
+                }
+            }
+            // OSC, DCS and the other strings, ended by BEL or ESC \.
+            Some(']') | Some('P') | Some('X') | Some('^') | Some('_') => {
+                while let Some(c) = chars.next() {
+                    if c == BEL {
+                        break;
+                    }
+                    if c == ESC && chars.peek() == Some(&'\\') {
+                        chars.next();
+                        break;
+                    }
+                }
+            }
+            // Character set selection takes one more character, e.g. ESC ( B.
+            Some('(') | Some(')') | Some('*') | Some('+') => {
+                chars.next();
+            }
+            // Any other escape is two characters long, e.g. ESC = or ESC 7.
+            Some(_) | None => {}
+        }
+    }
+    out
+}
@@ -0,0 +1,135 @@
+use regex::bytes::{Captures, Regex};
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Text secrets are replaced with.
+pub const REDACTED: &str = "***REDACTED***";
+
+/// Common token formats, used unless turned off with `builtins = false`.
+const BUILTIN_PATTERNS: &[&str] = &[
+    // AWS access key ids.
+    r"\b(?:AKIA|ASIA)[0-9A-Z]{16}\b",
+    // GitHub tokens.
+    r"\bgh[pousr]_[A-Za-z0-9]{36,}\b",
+    // Slack tokens.
+    r"\bxox[abposr]-[A-Za-z0-9-]{10,}",
+    // JSON web tokens.
+    r"\beyJ[A-Za-z0-9_-]+\.eyJ[A-Za-z0-9_-]+\.[A-Za-z0-9_-]+",
+    r"(?i)\bbearer\s+(?P<secret>[A-Za-z0-9._~+/-]+=*)",
+    r"(?i)\b(?:password|passwd|pwd|secret|token|api[_-]?key)\b\s*[:=]\s*(?P<secret>[^\s'\x22]+)",
+    r"-----BEGIN [A-Z ]*PRIVATE KEY-----(?s:.*?)-----END [A-Z ]*PRIVATE KEY-----",
+];
+
+/// Replaces secrets in output and error messages with `REDACTED` before results are
+/// written anywhere.
+///
+/// A pattern with a group named `secret` only has that group replaced, so
+/// `password=(?P<secret>\S+)` keeps the `password=` in the output. Files of
+/// `OutputPassThrough::Dir` are redacted line by line, so patterns spanning lines only
+/// apply to responses. In TOML:
+///
+/// ```toml
+/// [redact]
+/// patterns = ['DB_PASS=(?P<secret>\S+)']
+/// builtins = true
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "RedactorConfig", into = "RedactorConfig")]
+pub struct Redactor {
+    patterns: Vec<Regex>,
+    builtins: bool,
+}
+
+/// Config form of `Redactor`.
+#[derive(Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct RedactorConfig {
+    #[serde(default)]
+    patterns: Vec<String>,
+    #[serde(default = "default_builtins")]
+    builtins: bool,
+}
+
+fn default_builtins() -> bool {
+    true
+}
+
+impl TryFrom<RedactorConfig> for Redactor {
+    type Error = regex::Error;
+
+    fn try_from(config: RedactorConfig) -> Result<Self, Self::Error> {
+        Redactor::new(&config.patterns, config.builtins)
+    }
+}
+
+impl From<Redactor> for RedactorConfig {
+    fn from(redactor: Redactor) -> Self {
+        let skip = if redactor.builtins {
+            BUILTIN_PATTERNS.len()
+        } else {
+            0
+        };
+        RedactorConfig {
+            patterns: redactor.patterns[skip..]
+                .iter()
+                .map(|p| p.as_str().to_string())
+                .collect(),
+            builtins: redactor.builtins,
+        }
+    }
+}
+
+impl Redactor {
+    /// Redactor of `patterns`, and of the built-in token formats with `builtins`.
+    pub fn new<S: AsRef<str>>(patterns: &[S], builtins: bool) -> Result<Self, regex::Error> {
+        let builtin: &[&str] = if builtins { BUILTIN_PATTERNS } else { &[] };
+        let patterns = builtin
+            .iter()
+            .map(|p| Regex::new(p))
+            .chain(patterns.iter().map(|p| Regex::new(p.as_ref())))
+            .collect::<Result<_, _>>()?;
+        Ok(Redactor { patterns, builtins })
+    }
+
+    /// Redacts `text` in place, returning the number of secrets replaced.
+    pub fn redact(&self, text: &mut String) -> u64 {
+        let (redacted, count) = self.redact_bytes(text.as_bytes());
+        if count > 0 {
+            *text = match String::from_utf8(redacted) {
+                Ok(redacted) => redacted,
+                Err(e) => String::from_utf8_lossy(e.as_bytes()).into_owned(),
+            };
+        }
+        count
+    }
+
+    /// `data` redacted, and the number of secrets replaced.
+    pub fn redact_bytes(&self, data: &[u8]) -> (Vec<u8>, u64) {
+        let mut data = data.to_vec();
+        let mut count = 0;
+        for pattern in &self.patterns {
+            if !pattern.is_match(&data) {
+                continue;
+            }
+            let redacted = pattern.replace_all(&data, |caps: &Captures| {
+                let whole = caps.get(0).expect("group 0 always matches");
+                let secret = caps.name("secret").unwrap_or(whole);
+                let (start, end) = (secret.start() - whole.start(), secret.end() - whole.start());
+                // Not counted again when an earlier pattern redacted it already.
+                if !contains(secret.as_bytes(), REDACTED.as_bytes()) {
+                    count += 1;
+                }
+                let mut replaced = whole.as_bytes()[..start].to_vec();
+                replaced.extend_from_slice(REDACTED.as_bytes());
+                replaced.extend_from_slice(&whole.as_bytes()[end..]);
+                replaced
+            });
+            data = redacted.into_owned();
+        }
+        (data, count)
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    haystack.windows(needle.len()).any(|w| w == needle)
+}
@@ -0,0 +1,132 @@
+use crate::inventory::HostOptions;
+use crate::response::{ErrorKind, HostStatus, Response};
+use crate::scheduler::ParallelSshProps;
+use crate::target::HostTarget;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::time::SystemTime;
+
+/// Port assumed for a host recorded without one.
+const DEFAULT_PORT: u16 = 22;
+
+/// Host which did not succeed, with what it takes to run it again. Written one per line
+/// to the failed hosts file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FailedHost {
+    /// Address or name connected to, without the port.
+    pub host: String,
+    pub port: u16,
+    /// Name of the host in its response, kept by a replay.
+    pub name: String,
+    pub user: String,
+    /// Command as given, before the workdir and shell are applied.
+    pub command: String,
+    /// `None` for a host which failed without an error, e.g. one its canary cancelled.
+    pub error_code: Option<ErrorKind>,
+    pub run_id: String,
+    #[serde(default)]
+    pub group: Option<String>,
+    /// When the failure was recorded, in RFC 3339.
+    pub timestamp: String,
+}
+
+impl FailedHost {
+    /// Record of `response`, `None` when it succeeded.
+    pub fn of(response: &Response) -> Option<Self> {
+        if response.outcome == HostStatus::Success {
+            return None;
+        }
+        let (host, port) = split_address(&response.address);
+        Some(FailedHost {
+            host,
+            port,
+            name: response.hostname.clone(),
+            user: response.user.clone(),
+            command: response.command.clone(),
+            error_code: response.error_kind,
+            run_id: response.run_id.clone(),
+            group: response.group.clone(),
+            timestamp: humantime::format_rfc3339_seconds(SystemTime::now()).to_string(),
+        })
+    }
+
+    /// Whether the command may have started before the host failed, see
+    /// `ErrorKind::command_may_have_run`.
+    pub fn may_have_run(&self) -> bool {
+        self.error_code
+            .map_or(false, ErrorKind::command_may_have_run)
+    }
+
+    /// Target of the host, named as in its response when it was an address.
+    pub fn target(&self) -> HostTarget {
+        match self.host.parse::<IpAddr>() {
+            Ok(ip) => HostTarget::named(SocketAddr::new(ip, self.port), self.name.clone()),
+            Err(_) => HostTarget::Name(format!("{}:{}", self.host, self.port)),
+        }
+    }
+
+    pub fn options(&self) -> HostOptions {
+        HostOptions {
+            user: Some(self.user.clone()),
+            ..HostOptions::default()
+        }
+    }
+}
+
+/// Splits `host:port`, with the brackets of an IPv6 address removed.
+fn split_address(address: &str) -> (String, u16) {
+    if let Ok(addr) = address.parse::<SocketAddr>() {
+        return (addr.ip().to_string(), addr.port());
+    }
+    match address
+        .rfind(':')
+        .map(|i| (&address[..i], address[i + 1..].parse()))
+    {
+        Some((host, Ok(port))) => (host.to_string(), port),
+        _ => (address.to_string(), DEFAULT_PORT),
+    }
+}
+
+/// Loads a failed hosts file, one JSON record per line.
+pub fn load_failed_hosts(path: &Path) -> Result<Vec<FailedHost>, String> {
+    let content = fs::read_to_string(path).map_err(|e| format!("{}: {}", path.display(), e))?;
+    content
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(i, line)| {
+            serde_json::from_str(line)
+                .map_err(|e| format!("{}: line {}: {}", path.display(), i + 1, e))
+        })
+        .collect()
+}
+
+impl ParallelSshProps {
+    /// Runs the hosts of a failed hosts file again, each with its recorded port, user and
+    /// command; everything else comes from these props.
+    ///
+    /// Refuses to run anything when a host failed after its command may have started, as
+    /// running it again could apply its changes twice, unless `force` is set.
+    pub fn replay_failed(&self, path: &Path, force: bool) -> Result<(), String> {
+        let hosts = load_failed_hosts(path)?;
+        let started: Vec<&str> = hosts
+            .iter()
+            .filter(|host| host.may_have_run())
+            .map(|host| host.name.as_str())
+            .collect();
+        if !started.is_empty() && !force {
+            return Err(format!(
+                "the command may already have run on {}",
+                started.join(", ")
+            ));
+        }
+        self.parallel_ssh_process_with_options(
+            hosts
+                .into_iter()
+                .map(|host| (host.target(), host.command.clone(), host.options())),
+        )
+        .map_err(|e| e.to_string())
+    }
+}
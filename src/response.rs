@@ -0,0 +1,689 @@
+use crate::guard::GuardResult;
+use crate::inventory::EffectiveHostConfig;
+use crate::known_hosts::HostKeyInfo;
+use crate::output::{DiscardedOutput, PassedThrough};
+use crate::post_condition::PostConditionResult;
+use crate::result_class::ResultClass;
+use crate::sftp::{FetchReport, UploadReport};
+use crate::skip_check::SkipCheckResult;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::collections::BTreeMap;
+use std::fmt::{self, Display};
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+/// Classification of a host failure.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum ErrorKind {
+    Dns,
+    TcpConnect,
+    TcpTimeout,
+    Session,
+    Handshake,
+    Auth,
+    Agent,
+    Channel,
+    Exec,
+    Read,
+    /// The host ran out of its overall time, see `ParallelSshPropsBuilder::timeout_ssh`.
+    Timeout,
+    Cancelled,
+    Proxy,
+    Bind,
+    Skipped,
+    HandshakeTimeout,
+    AuthTimeout,
+    ExecTimeout,
+    ReadIdleTimeout,
+    ReadTotalTimeout,
+    HostKeyChanged,
+    /// Not run because the guard check said so.
+    GuardSatisfied,
+    /// Not run because the skip check found the host in maintenance.
+    MaintenanceMode,
+    /// The command succeeded, but its post condition did not hold in time.
+    PostConditionTimeout,
+    /// A downloaded file differs from the host's one.
+    ChecksumMismatch,
+    /// A local file could not be written, e.g. the target of a download.
+    LocalFile,
+    /// The command refers to a fact the run context does not have for the host.
+    MissingFact,
+    /// A file could not be written on the host.
+    Upload,
+    /// The command template of the host could not be rendered, e.g. for a missing var.
+    Template,
+    /// The become password of the host could not be had from its provider.
+    Become,
+}
+
+impl ErrorKind {
+    /// Stable machine-readable code emitted as `error_code` in every output.
+    ///
+    /// Codes are append-only: an existing code is never renamed, and a code of a removed
+    /// kind is never reused for a different failure.
+    pub fn code(self) -> &'static str {
+        match self {
+            ErrorKind::Dns => "E_DNS",
+            ErrorKind::TcpConnect => "E_TCP_CONNECT",
+            ErrorKind::TcpTimeout => "E_TCP_TIMEOUT",
+            ErrorKind::Session => "E_SESSION",
+            ErrorKind::Handshake => "E_HANDSHAKE",
+            ErrorKind::Auth => "E_AUTH",
+            ErrorKind::Agent => "E_AGENT",
+            ErrorKind::Channel => "E_CHANNEL",
+            ErrorKind::Exec => "E_EXEC",
+            ErrorKind::Read => "E_READ",
+            ErrorKind::Timeout => "E_TIMEOUT",
+            ErrorKind::Cancelled => "E_CANCELLED",
+            ErrorKind::Proxy => "E_PROXY",
+            ErrorKind::Bind => "E_BIND",
+            ErrorKind::Skipped => "E_SKIPPED",
+            ErrorKind::HandshakeTimeout => "E_HANDSHAKE_TIMEOUT",
+            ErrorKind::AuthTimeout => "E_AUTH_TIMEOUT",
+            ErrorKind::ExecTimeout => "E_EXEC_TIMEOUT",
+            ErrorKind::ReadIdleTimeout => "E_READ_IDLE_TIMEOUT",
+            ErrorKind::ReadTotalTimeout => "E_READ_TOTAL_TIMEOUT",
+            ErrorKind::HostKeyChanged => "E_HOST_KEY_CHANGED",
+            ErrorKind::GuardSatisfied => "E_GUARD_SATISFIED",
+            ErrorKind::MaintenanceMode => "E_MAINTENANCE_MODE",
+            ErrorKind::PostConditionTimeout => "E_POST_CONDITION_TIMEOUT",
+            ErrorKind::ChecksumMismatch => "E_CHECKSUM_MISMATCH",
+            ErrorKind::LocalFile => "E_LOCAL_FILE",
+            ErrorKind::MissingFact => "E_MISSING_FACT",
+            ErrorKind::Upload => "E_UPLOAD",
+            ErrorKind::Template => "E_TEMPLATE",
+            ErrorKind::Become => "E_BECOME",
+        }
+    }
+
+    /// Whether the command may have started on the host before the failure, so running it
+    /// again could apply its changes twice. Kinds whose timing is unclear count as started.
+    pub fn command_may_have_run(self) -> bool {
+        match self {
+            ErrorKind::Exec
+            | ErrorKind::Read
+            | ErrorKind::Timeout
+            | ErrorKind::Cancelled
+            | ErrorKind::ExecTimeout
+            | ErrorKind::ReadIdleTimeout
+            | ErrorKind::ReadTotalTimeout
+            | ErrorKind::PostConditionTimeout
+            | ErrorKind::ChecksumMismatch
+            | ErrorKind::LocalFile
+            | ErrorKind::Upload => true,
+            ErrorKind::Dns
+            | ErrorKind::TcpConnect
+            | ErrorKind::TcpTimeout
+            | ErrorKind::Session
+            | ErrorKind::Handshake
+            | ErrorKind::Auth
+            | ErrorKind::Agent
+            | ErrorKind::Channel
+            | ErrorKind::Proxy
+            | ErrorKind::Bind
+            | ErrorKind::Skipped
+            | ErrorKind::HandshakeTimeout
+            | ErrorKind::AuthTimeout
+            | ErrorKind::HostKeyChanged
+            | ErrorKind::GuardSatisfied
+            | ErrorKind::MaintenanceMode
+            | ErrorKind::MissingFact
+            | ErrorKind::Template
+            | ErrorKind::Become => false,
+        }
+    }
+}
+
+impl Display for ErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+impl Serialize for ErrorKind {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.code())
+    }
+}
+
+/// Parses the code returned by `ErrorKind::code`.
+impl FromStr for ErrorKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "E_DNS" => Ok(ErrorKind::Dns),
+            "E_TCP_CONNECT" => Ok(ErrorKind::TcpConnect),
+            "E_TCP_TIMEOUT" => Ok(ErrorKind::TcpTimeout),
+            "E_SESSION" => Ok(ErrorKind::Session),
+            "E_HANDSHAKE" => Ok(ErrorKind::Handshake),
+            "E_AUTH" => Ok(ErrorKind::Auth),
+            "E_AGENT" => Ok(ErrorKind::Agent),
+            "E_CHANNEL" => Ok(ErrorKind::Channel),
+            "E_EXEC" => Ok(ErrorKind::Exec),
+            "E_READ" => Ok(ErrorKind::Read),
+            "E_TIMEOUT" => Ok(ErrorKind::Timeout),
+            "E_CANCELLED" => Ok(ErrorKind::Cancelled),
+            "E_PROXY" => Ok(ErrorKind::Proxy),
+            "E_BIND" => Ok(ErrorKind::Bind),
+            "E_SKIPPED" => Ok(ErrorKind::Skipped),
+            "E_HANDSHAKE_TIMEOUT" => Ok(ErrorKind::HandshakeTimeout),
+            "E_AUTH_TIMEOUT" => Ok(ErrorKind::AuthTimeout),
+            "E_EXEC_TIMEOUT" => Ok(ErrorKind::ExecTimeout),
+            "E_READ_IDLE_TIMEOUT" => Ok(ErrorKind::ReadIdleTimeout),
+            "E_READ_TOTAL_TIMEOUT" => Ok(ErrorKind::ReadTotalTimeout),
+            "E_HOST_KEY_CHANGED" => Ok(ErrorKind::HostKeyChanged),
+            "E_GUARD_SATISFIED" => Ok(ErrorKind::GuardSatisfied),
+            "E_MAINTENANCE_MODE" => Ok(ErrorKind::MaintenanceMode),
+            "E_POST_CONDITION_TIMEOUT" => Ok(ErrorKind::PostConditionTimeout),
+            "E_CHECKSUM_MISMATCH" => Ok(ErrorKind::ChecksumMismatch),
+            "E_LOCAL_FILE" => Ok(ErrorKind::LocalFile),
+            "E_MISSING_FACT" => Ok(ErrorKind::MissingFact),
+            "E_UPLOAD" => Ok(ErrorKind::Upload),
+            "E_TEMPLATE" => Ok(ErrorKind::Template),
+            "E_BECOME" => Ok(ErrorKind::Become),
+            _ => Err(format!("Unknown error code: {}", s)),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ErrorKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+/// Failure of a single host together with its classification.
+#[derive(Debug, Clone)]
+pub struct HostError {
+    pub kind: ErrorKind,
+    pub message: String,
+}
+
+impl HostError {
+    pub fn new<M: Into<String>>(kind: ErrorKind, message: M) -> Self {
+        HostError {
+            kind,
+            message: message.into(),
+        }
+    }
+}
+
+impl Display for HostError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl std::error::Error for HostError {}
+
+/// Hosts left after each step of selecting the hosts of a run, for
+/// `RunError::NoHostsSelected`; see `ParallelSshPropsBuilder::host_selection`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostSelection {
+    /// Hosts of the inventory.
+    pub loaded: usize,
+    /// Hosts in a group run.
+    pub after_groups: usize,
+    /// Hosts with a name `--limit` includes.
+    pub after_limit: usize,
+    /// Hosts left once the names `--limit` excludes are taken out.
+    pub after_exclude: usize,
+}
+
+impl From<HostSelection> for RunError {
+    fn from(selection: HostSelection) -> Self {
+        RunError::NoHostsSelected {
+            loaded: selection.loaded,
+            after_groups: selection.after_groups,
+            after_limit: selection.after_limit,
+            after_exclude: selection.after_exclude,
+        }
+    }
+}
+
+/// Why a run as a whole did not go through.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RunError {
+    /// No host was given to run on, and the props do not allow an empty run, see
+    /// `ParallelSshPropsBuilder::allow_empty`. The counts are the hosts left after each
+    /// step of selecting them: loading the inventory, planning the group runs, the names
+    /// `--limit` includes and the ones it excludes. They are the props' `HostSelection`,
+    /// all 0 when they have none.
+    NoHostsSelected {
+        loaded: usize,
+        after_groups: usize,
+        after_limit: usize,
+        after_exclude: usize,
+    },
+    /// The canary hosts all failed the same way, so the other hosts were skipped.
+    CanaryFailed(String),
+    /// The batch hook of a serial run declined the next batch, so the remaining hosts were
+    /// skipped.
+    Stopped(String),
+    /// More hosts failed than the failure threshold allows, so the run was aborted.
+    TooManyFailures(String),
+}
+
+impl Display for RunError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RunError::NoHostsSelected {
+                loaded,
+                after_groups,
+                after_limit,
+                after_exclude,
+            } => {
+                f.write_str("no hosts selected")?;
+                if *loaded == 0 {
+                    f.write_str(", none were loaded")
+                } else if *after_limit == 0 {
+                    write!(f, ", --limit matched none of the {} loaded", loaded)
+                } else if *after_exclude == 0 {
+                    write!(f, ", --limit excluded all {} it matched", after_limit)
+                } else if *after_groups == 0 {
+                    write!(f, ", no group run has any of the {} left", after_exclude)
+                } else {
+                    Ok(())
+                }
+            }
+            RunError::CanaryFailed(reason)
+            | RunError::Stopped(reason)
+            | RunError::TooManyFailures(reason) => f.write_str(reason),
+        }
+    }
+}
+
+impl std::error::Error for RunError {}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct Response {
+    /// Stdout of the command, or what went wrong when the host failed.
+    pub result: String,
+    /// Stderr of the command, kept as `OutputKeep` says like its stdout.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+    pub hostname: String,
+    /// Address connected to as `host:port`, or the host as given when it did not resolve.
+    pub address: String,
+    pub user: String,
+    /// Command as given for the host, before the workdir and shell are applied.
+    pub command: String,
+    pub process_time: Duration,
+    /// `process_time` split by phase, for the last attempt.
+    #[serde(skip_serializing_if = "HostTimings::is_empty")]
+    pub timings: HostTimings,
+    pub status: bool,
+    /// `status` refined: failures split into skipped, cancelled and other failures.
+    pub outcome: HostStatus,
+    /// Ok, warning or error by the exit code, see `ExitCodeClasses`; an error whenever the
+    /// host failed. Not to be confused with `class`, the class of the host.
+    pub result_class: ResultClass,
+    #[serde(rename = "error_code", skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<ErrorKind>,
+    /// Id of the run the response belongs to.
+    pub run_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    /// Class of the host, see `HostOptions::class`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exit_code: Option<i32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub connection: Option<ConnectionInfo>,
+    /// Directory the command was run in.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub workdir: Option<String>,
+    /// Encoding `result` was decoded from.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub encoding: Option<&'static str>,
+    /// Hash of the whole output, see `OutputHashAlgorithm`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_hash: Option<String>,
+    /// Version string the server sent in the handshake, when it got that far.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub server_banner: Option<String>,
+    /// Banner the server sent during authentication, e.g. a legal notice, when it sent
+    /// one.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_banner: Option<String>,
+    /// Host key fingerprint, when a host key store is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_key: Option<HostKeyInfo>,
+    /// What the skip check found, when one ran.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub skip_check: Option<SkipCheckResult>,
+    /// What the guard check did, when one ran.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub guard: Option<GuardResult>,
+    /// How waiting for the post condition went, when the command got that far.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub post_condition: Option<PostConditionResult>,
+    /// Output dropped by `OutputKeep`, when any was.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub discarded: Option<DiscardedOutput>,
+    /// Size and hash of the output, when it was passed through instead of kept in
+    /// `result`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub passed_through: Option<PassedThrough>,
+    /// What the upload did, for a response of `ParallelSshProps::parallel_upload`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub upload: Option<UploadReport>,
+    /// What the fetch did, for a response of `ParallelSshProps::parallel_fetch`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fetch: Option<FetchReport>,
+    /// Secrets replaced in the output, error and check outputs, when the props have a
+    /// redactor and responses are redacted.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub redactions: Option<u64>,
+    /// Host whose execution this response repeats, when the host was an alias of it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub deduplicated_with: Option<String>,
+    /// Arguments the `ArgAssigner` appended to the command.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub assigned_args: Vec<String>,
+    /// Run tags merged with the host's tags.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// Settings the host ran with, when the props have `verbose_config`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effective_config: Option<EffectiveHostConfig>,
+    /// Number of attempts made on the host.
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub attempt_history: Vec<AttemptRecord>,
+}
+
+/// How a host ended. Every host of a run gets exactly one response with one of these.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum HostStatus {
+    Success,
+    /// Ran and failed, or could not be reached; `error_code` tells which.
+    Failed,
+    /// Not run on purpose, e.g. after failed canaries; `result` gives the reason.
+    Skipped,
+    /// Not run because the run was cancelled.
+    Cancelled,
+}
+
+impl HostStatus {
+    /// Status of a host which failed with `error_kind`, or succeeded on `None`.
+    pub fn of(error_kind: Option<ErrorKind>) -> Self {
+        match error_kind {
+            None => HostStatus::Success,
+            Some(ErrorKind::Skipped)
+            | Some(ErrorKind::GuardSatisfied)
+            | Some(ErrorKind::MaintenanceMode) => HostStatus::Skipped,
+            Some(ErrorKind::Cancelled) => HostStatus::Cancelled,
+            Some(_) => HostStatus::Failed,
+        }
+    }
+}
+
+/// Totals of a run.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    pub total: usize,
+    pub succeeded: usize,
+    pub failed: usize,
+    /// Hosts skipped on purpose, counted in `failed` as well.
+    pub skipped: usize,
+    /// Hosts the skip check found in maintenance, counted in `skipped` as well.
+    pub maintenance: usize,
+    /// Hosts not run on because the run was cancelled, counted in `failed` as well.
+    pub cancelled: usize,
+    /// Hosts whose exit code is a warning, counted in `succeeded` as well.
+    pub warnings: usize,
+}
+
+impl RunSummary {
+    pub fn record(&mut self, response: &Response) {
+        self.total += 1;
+        if response.status {
+            self.succeeded += 1;
+        } else {
+            self.failed += 1;
+        }
+        match response.outcome {
+            HostStatus::Skipped => self.skipped += 1,
+            HostStatus::Cancelled => self.cancelled += 1,
+            HostStatus::Success | HostStatus::Failed => {}
+        }
+        if response.error_kind == Some(ErrorKind::MaintenanceMode) {
+            self.maintenance += 1;
+        }
+        if response.result_class == ResultClass::Warning {
+            self.warnings += 1;
+        }
+    }
+
+    /// Totals of `responses`.
+    pub fn of(responses: &[Response]) -> Self {
+        let mut summary = RunSummary::default();
+        for response in responses {
+            summary.record(response);
+        }
+        summary
+    }
+}
+
+/// Outcome of one attempt on a host.
+#[derive(Serialize, Debug, Clone)]
+pub struct AttemptRecord {
+    /// 1-based attempt number.
+    pub attempt: u32,
+    /// `None` when the attempt succeeded.
+    #[serde(rename = "error_code")]
+    pub error_kind: Option<ErrorKind>,
+    pub duration: Duration,
+    pub timestamp: SystemTime,
+    /// Attempt limit of the retry policy applied to this attempt's failure.
+    pub max_attempts: u32,
+    /// Delay before the next attempt, when one followed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub backoff: Option<Duration>,
+}
+
+/// How long each phase of the last attempt on a host took. Phases it did not get to, or
+/// which did not apply, are `None`; a phase which failed is timed up to its failure.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct HostTimings {
+    /// Resolving the host name, `None` for an address or with remote DNS. Cache hits count.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dns: Option<Duration>,
+    /// Probing the port before the host was queued, `None` behind a proxy.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub probe: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tcp_connect: Option<Duration>,
+    /// SSH handshake, up to the banner and key exchange; the host key check is not in it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub handshake: Option<Duration>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth: Option<Duration>,
+    /// Opening the channel of the command and starting it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub exec: Option<Duration>,
+    /// Reading the output of the command until it exited.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub read: Option<Duration>,
+}
+
+impl HostTimings {
+    pub fn is_empty(&self) -> bool {
+        *self == HostTimings::default()
+    }
+
+    /// Runs `f`, storing how long it took in `phase`.
+    pub(crate) fn time<T, F: FnOnce() -> T>(phase: &mut Option<Duration>, f: F) -> T {
+        let start = Instant::now();
+        let result = f();
+        *phase = Some(start.elapsed());
+        result
+    }
+
+    /// Timings kept across attempts: the host is resolved and probed only once.
+    pub(crate) fn precheck(&self) -> Self {
+        HostTimings {
+            dns: self.dns,
+            probe: self.probe,
+            ..HostTimings::default()
+        }
+    }
+}
+
+/// Details of how the SSH connection to a host was established.
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct ConnectionInfo {
+    /// Name of the auth chain method which succeeded.
+    pub auth_method: String,
+    /// Round trip to the ssh-agent, from connecting to it to its answer, when the agent
+    /// was tried.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub agent_latency: Option<Duration>,
+    /// Local end of the TCP connection.
+    pub local_addr: Option<SocketAddr>,
+    /// Compression method the server agreed to for its output, when compression was
+    /// requested; `"none"` when it declined.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub compression: Option<String>,
+    /// Uncompressed output bytes received, before `OutputKeep` is applied. libssh2 does not
+    /// expose the compressed size on the wire.
+    pub output_bytes: u64,
+    /// Whether the session came from the props' session pool rather than being opened
+    /// for the host.
+    pub reused: bool,
+}
+
+/// Output and exit code of one command.
+#[derive(Serialize, Debug, Clone)]
+pub struct CommandOutput {
+    pub output: String,
+    /// Stderr, kept as `OutputKeep` says and decoded like `output`.
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub stderr: String,
+    /// Encoding `output` was decoded from.
+    pub encoding: &'static str,
+    pub discarded: DiscardedOutput,
+    pub exit_code: i32,
+    /// Output bytes received, before `OutputKeep` was applied.
+    pub output_bytes: u64,
+    /// Hash of the whole output, when the props have an output hash algorithm.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output_hash: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn response(error_kind: Option<ErrorKind>, result_class: ResultClass) -> Response {
+        Response {
+            result: String::new(),
+            stderr: String::new(),
+            hostname: "web-1".to_string(),
+            address: "10.0.0.1:22".to_string(),
+            user: "root".to_string(),
+            command: "true".to_string(),
+            process_time: Duration::from_millis(5),
+            timings: HostTimings::default(),
+            status: error_kind.is_none(),
+            outcome: HostStatus::of(error_kind),
+            result_class,
+            error_kind,
+            run_id: "run".to_string(),
+            group: None,
+            class: None,
+            exit_code: None,
+            connection: None,
+            workdir: None,
+            encoding: None,
+            output_hash: None,
+            server_banner: None,
+            auth_banner: None,
+            host_key: None,
+            skip_check: None,
+            guard: None,
+            post_condition: None,
+            discarded: None,
+            passed_through: None,
+            upload: None,
+            fetch: None,
+            redactions: None,
+            deduplicated_with: None,
+            assigned_args: Vec::new(),
+            tags: BTreeMap::new(),
+            effective_config: None,
+            attempts: 1,
+            attempt_history: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn status_of_each_kind() {
+        assert_eq!(HostStatus::of(None), HostStatus::Success);
+        for kind in &[
+            ErrorKind::Skipped,
+            ErrorKind::GuardSatisfied,
+            ErrorKind::MaintenanceMode,
+        ] {
+            assert_eq!(HostStatus::of(Some(*kind)), HostStatus::Skipped);
+        }
+        assert_eq!(
+            HostStatus::of(Some(ErrorKind::Cancelled)),
+            HostStatus::Cancelled
+        );
+        assert_eq!(HostStatus::of(Some(ErrorKind::Auth)), HostStatus::Failed);
+    }
+
+    #[test]
+    fn summary_counts_each_host_once_per_total() {
+        let summary = RunSummary::of(&[
+            response(None, ResultClass::Ok),
+            response(None, ResultClass::Warning),
+            response(Some(ErrorKind::Auth), ResultClass::Error),
+            response(Some(ErrorKind::MaintenanceMode), ResultClass::Error),
+            response(Some(ErrorKind::Cancelled), ResultClass::Error),
+        ]);
+        assert_eq!(
+            summary,
+            RunSummary {
+                total: 5,
+                succeeded: 2,
+                failed: 3,
+                skipped: 1,
+                maintenance: 1,
+                cancelled: 1,
+                warnings: 1,
+            }
+        );
+        assert_eq!(summary.succeeded + summary.failed, summary.total);
+    }
+
+    #[test]
+    fn precheck_keeps_only_resolution_and_probe() {
+        let mut timings = HostTimings::default();
+        assert_eq!(HostTimings::time(&mut timings.dns, || 7), 7);
+        timings.probe = Some(Duration::from_millis(1));
+        timings.auth = Some(Duration::from_millis(2));
+        let kept = timings.precheck();
+        assert!(kept.dns.is_some() && kept.probe.is_some());
+        assert_eq!(kept.auth, None);
+        assert!(HostTimings::default().is_empty());
+    }
+
+    #[test]
+    fn host_error_displays_its_message() {
+        let error = HostError::new(ErrorKind::TcpConnect, "refused");
+        assert_eq!(error.to_string(), "refused");
+        assert_eq!(
+            error.kind.code().parse::<ErrorKind>().ok(),
+            Some(ErrorKind::TcpConnect)
+        );
+    }
+}
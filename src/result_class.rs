@@ -0,0 +1,191 @@
+use crate::response::ErrorKind;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+
+/// How a host's result counts, from its exit code: Nagios-style, a command may succeed
+/// with a warning.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum ResultClass {
+    Ok,
+    Warning,
+    Error,
+}
+
+impl Display for ResultClass {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ResultClass::Ok => "ok",
+            ResultClass::Warning => "warning",
+            ResultClass::Error => "error",
+        })
+    }
+}
+
+/// Exit codes from `start` to `end`, both included. Written as a single code such as `1`,
+/// or as a range in Rust syntax: `"2.."`, `"..=-1"`, `"3..=5"` or `"3..6"`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ExitCodeRange {
+    pub start: i32,
+    pub end: i32,
+}
+
+impl ExitCodeRange {
+    pub fn single(code: i32) -> Self {
+        ExitCodeRange {
+            start: code,
+            end: code,
+        }
+    }
+
+    pub fn contains(&self, code: i32) -> bool {
+        self.start <= code && code <= self.end
+    }
+
+    fn overlaps(&self, other: &ExitCodeRange) -> bool {
+        self.start <= other.end && other.start <= self.end
+    }
+}
+
+impl Display for ExitCodeRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.start, self.end) {
+            (start, end) if start == end => write!(f, "{}", start),
+            (start, i32::MAX) => write!(f, "{}..", start),
+            (i32::MIN, end) => write!(f, "..={}", end),
+            (start, end) => write!(f, "{}..={}", start, end),
+        }
+    }
+}
+
+impl FromStr for ExitCodeRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let code = |part: &str| {
+            part.trim()
+                .parse::<i32>()
+                .map_err(|_| format!("invalid exit code range {:?}", s))
+        };
+        let (start, end) = match s.find("..") {
+            None => {
+                let code = code(s)?;
+                (code, code)
+            }
+            Some(i) => {
+                let (start, rest) = (&s[..i], &s[i + 2..]);
+                let start = match start.trim() {
+                    "" => i32::MIN,
+                    start => code(start)?,
+                };
+                let end = if rest.starts_with('=') {
+                    code(&rest[1..])?
+                } else if rest.trim().is_empty() {
+                    i32::MAX
+                } else {
+                    code(rest)?
+                        .checked_sub(1)
+                        .ok_or_else(|| format!("empty exit code range {:?}", s))?
+                };
+                (start, end)
+            }
+        };
+        if start > end {
+            return Err(format!("empty exit code range {:?}", s));
+        }
+        Ok(ExitCodeRange { start, end })
+    }
+}
+
+impl Serialize for ExitCodeRange {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        if self.start == self.end {
+            serializer.serialize_i32(self.start)
+        } else {
+            serializer.collect_str(self)
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for ExitCodeRange {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Code(i32),
+            Range(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Code(code) => Ok(ExitCodeRange::single(code)),
+            Raw::Range(s) => s.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+/// Exit codes of each `ResultClass`, e.g.
+/// `exit_code_classes = { ok = [0], warning = [1], error = ["2.."] }`. Codes in none of
+/// the lists are errors; by default only 0 is ok.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExitCodeClasses {
+    pub ok: Vec<ExitCodeRange>,
+    pub warning: Vec<ExitCodeRange>,
+    pub error: Vec<ExitCodeRange>,
+}
+
+impl Default for ExitCodeClasses {
+    fn default() -> Self {
+        ExitCodeClasses {
+            ok: vec![ExitCodeRange::single(0)],
+            warning: Vec::new(),
+            error: Vec::new(),
+        }
+    }
+}
+
+impl ExitCodeClasses {
+    fn lists(&self) -> [(ResultClass, &[ExitCodeRange]); 3] {
+        [
+            (ResultClass::Ok, &self.ok),
+            (ResultClass::Warning, &self.warning),
+            (ResultClass::Error, &self.error),
+        ]
+    }
+
+    /// Rejects a code listed in two classes.
+    pub fn validate(&self) -> Result<(), String> {
+        let lists = self.lists();
+        for (i, (class, ranges)) in lists.iter().enumerate() {
+            for (other_class, other_ranges) in &lists[i + 1..] {
+                for range in ranges.iter() {
+                    if let Some(other) = other_ranges.iter().find(|o| range.overlaps(o)) {
+                        return Err(format!(
+                            "exit codes {} of {} overlap {} of {}",
+                            range, class, other, other_class
+                        ));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Class of an exit code.
+    pub fn of_code(&self, code: i32) -> ResultClass {
+        self.lists()
+            .iter()
+            .find(|(_, ranges)| ranges.iter().any(|range| range.contains(code)))
+            .map_or(ResultClass::Error, |(class, _)| *class)
+    }
+
+    /// Class of a host result: an error when the host failed, whatever its exit code, and
+    /// ok when the command ran but its exit status could not be read.
+    pub fn classify(&self, error_kind: Option<ErrorKind>, exit_code: Option<i32>) -> ResultClass {
+        match (error_kind, exit_code) {
+            (Some(_), _) => ResultClass::Error,
+            (None, Some(code)) => self.of_code(code),
+            (None, None) => ResultClass::Ok,
+        }
+    }
+}
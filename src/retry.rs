@@ -0,0 +1,139 @@
+use crate::response::ErrorKind;
+use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::BTreeMap;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+/// Attempt limit and delay for one class of failures.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct KindRetry {
+    /// Attempts in total, the first one included.
+    pub max_attempts: u32,
+    /// Delay before the first retry, in milliseconds; later ones grow by the policy's
+    /// `backoff_multiplier`.
+    pub backoff_ms: u64,
+}
+
+/// Which failures are retried, how often and how long apart.
+///
+/// Only kinds in `retryable` are retried; `per_kind` overrides the limits for some of them.
+/// The delay is `backoff_ms` before the first retry and `backoff_multiplier` times the
+/// previous one after that, up to `max_backoff_ms`; `jitter` then takes a random part of
+/// up to that fraction off each delay, so hosts failing together do not retry together.
+/// In TOML:
+///
+/// ```toml
+/// [retry]
+/// max_attempts = 4
+/// backoff_ms = 1000
+/// backoff_multiplier = 2.0
+/// max_backoff_ms = 30000
+/// jitter = 0.5
+/// [retry.per_kind.E_AGENT]
+/// max_attempts = 5
+/// backoff_ms = 100
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RetryPolicy {
+    pub retryable: Vec<ErrorKind>,
+    pub max_attempts: u32,
+    pub backoff_ms: u64,
+    /// Growth of the delay from one retry to the next, 1 for a constant delay.
+    pub backoff_multiplier: f64,
+    pub max_backoff_ms: Option<u64>,
+    /// Fraction of each delay which may randomly be taken off, between 0 and 1.
+    pub jitter: f64,
+    pub per_kind: BTreeMap<ErrorKind, KindRetry>,
+}
+
+impl Default for RetryPolicy {
+    /// A single attempt; raising `max_attempts` retries the transient failures.
+    fn default() -> Self {
+        RetryPolicy {
+            retryable: vec![
+                ErrorKind::Dns,
+                ErrorKind::TcpTimeout,
+                ErrorKind::Handshake,
+                ErrorKind::Agent,
+                ErrorKind::Timeout,
+                ErrorKind::HandshakeTimeout,
+                ErrorKind::AuthTimeout,
+                ErrorKind::ExecTimeout,
+                ErrorKind::ReadIdleTimeout,
+                ErrorKind::ReadTotalTimeout,
+            ],
+            max_attempts: 1,
+            backoff_ms: 1000,
+            backoff_multiplier: 1.0,
+            max_backoff_ms: None,
+            jitter: 0.0,
+            per_kind: BTreeMap::new(),
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn validate(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("retry.max_attempts must be at least 1".to_string());
+        }
+        if !self.backoff_multiplier.is_finite() || self.backoff_multiplier < 1.0 {
+            return Err(format!(
+                "retry.backoff_multiplier must be at least 1, got {}",
+                self.backoff_multiplier
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err(format!(
+                "retry.jitter must be between 0 and 1, got {}",
+                self.jitter
+            ));
+        }
+        for (kind, retry) in &self.per_kind {
+            if !self.retryable.contains(kind) {
+                return Err(format!(
+                    "retry.per_kind.{} is set, but {} is not retryable",
+                    kind, kind
+                ));
+            }
+            if retry.max_attempts == 0 {
+                return Err(format!(
+                    "retry.per_kind.{}.max_attempts must be at least 1",
+                    kind
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Limits applying to a failure of `kind`, `None` when it is not retried.
+    pub fn for_kind(&self, kind: ErrorKind) -> Option<KindRetry> {
+        if !self.retryable.contains(&kind) {
+            return None;
+        }
+        Some(self.per_kind.get(&kind).copied().unwrap_or(KindRetry {
+            max_attempts: self.max_attempts,
+            backoff_ms: self.backoff_ms,
+        }))
+    }
+
+    /// Delay before retrying after failed attempt number `attempt`, `None` to give up.
+    pub fn next_delay(&self, kind: ErrorKind, attempt: u32) -> Option<Duration> {
+        let retry = self.for_kind(kind).filter(|r| attempt < r.max_attempts)?;
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let mut delay = retry.backoff_ms as f64 * self.backoff_multiplier.powi(exponent);
+        if let Some(max) = self.max_backoff_ms {
+            delay = delay.min(max as f64);
+        }
+        if self.jitter > 0.0 {
+            let mut hasher = RandomState::new().build_hasher();
+            hasher.write_u32(attempt);
+            let fraction = (hasher.finish() % 1000) as f64 / 1000.0;
+            delay *= 1.0 - self.jitter * fraction;
+        }
+        Some(Duration::from_millis(delay.min(u64::MAX as f64) as u64))
+    }
+}
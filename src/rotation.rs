@@ -0,0 +1,157 @@
+use chrono::Utc;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+/// When the incremental results of a run move on to a new part file. A part is closed
+/// before the record which would exceed a limit, so it always holds at least one record
+/// and never a partial one.
+///
+/// ```toml
+/// [output.rotate]
+/// max_bytes = 1_000_000_000
+/// max_records = 100_000
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Rotation {
+    pub max_bytes: Option<u64>,
+    pub max_records: Option<usize>,
+}
+
+/// One part file as listed in the index.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PartInfo {
+    /// File name, relative to the index.
+    pub file: String,
+    /// Position of the first and last record of the part in the whole run, from 0.
+    pub first_record: usize,
+    pub last_record: usize,
+    /// RFC 3339 times the first and last record were written.
+    pub first_time: String,
+    pub last_time: String,
+}
+
+/// Writes records as NDJSON into numbered parts, `<stem>.0001.ndjson` and on, listed with
+/// their record ranges in `<stem>.index.json`.
+///
+/// Records are buffered, `flush` writes them out. Each part is flushed and synced before
+/// the next one is opened, so closed parts can be shipped while the run goes on. The index is rewritten through a rename on every part
+/// change and at the end, listing the parts closed so far.
+pub struct RotatingWriter {
+    dir: PathBuf,
+    stem: String,
+    limits: Rotation,
+    file: Option<BufWriter<File>>,
+    part_bytes: u64,
+    part_records: usize,
+    records: usize,
+    parts: Vec<PartInfo>,
+}
+
+impl RotatingWriter {
+    pub fn new(dir: &Path, stem: &str, limits: Rotation) -> Self {
+        RotatingWriter {
+            dir: dir.to_path_buf(),
+            stem: stem.to_string(),
+            limits,
+            file: None,
+            part_bytes: 0,
+            part_records: 0,
+            records: 0,
+            parts: Vec::new(),
+        }
+    }
+
+    /// Path of the index file.
+    pub fn index_path(&self) -> PathBuf {
+        self.dir.join(format!("{}.index.json", self.stem))
+    }
+
+    /// Appends one record, opening a new part first when it would not fit the current one.
+    pub fn write<T: Serialize>(&mut self, record: &T) -> io::Result<()> {
+        let mut line = serde_json::to_vec(record)?;
+        line.push(b'\n');
+        self.write_line(&line)
+    }
+
+    /// Appends one record already serialized as a newline terminated JSON line.
+    pub fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        let full = self.part_records > 0
+            && (self
+                .limits
+                .max_bytes
+                .map_or(false, |max| self.part_bytes + line.len() as u64 > max)
+                || self
+                    .limits
+                    .max_records
+                    .map_or(false, |max| self.part_records >= max));
+        if self.file.is_none() || full {
+            self.open_part()?;
+        }
+        self.file.as_mut().expect("part opened").write_all(line)?;
+        let now = Utc::now().to_rfc3339();
+        let part = self.parts.last_mut().expect("part opened");
+        if self.part_records == 0 {
+            part.first_time = now.clone();
+        }
+        part.last_record = self.records;
+        part.last_time = now;
+        self.part_bytes += line.len() as u64;
+        self.part_records += 1;
+        self.records += 1;
+        Ok(())
+    }
+
+    /// Writes the buffered records of the current part out.
+    pub fn flush(&mut self) -> io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+
+    /// Closes the current part and writes the final index.
+    pub fn finish(mut self) -> io::Result<PathBuf> {
+        self.close_part()?;
+        self.write_index()?;
+        Ok(self.index_path())
+    }
+
+    fn open_part(&mut self) -> io::Result<()> {
+        self.close_part()?;
+        let name = format!("{}.{:04}.ndjson", self.stem, self.parts.len() + 1);
+        self.file = Some(BufWriter::new(File::create(self.dir.join(&name))?));
+        self.parts.push(PartInfo {
+            file: name,
+            first_record: self.records,
+            last_record: self.records,
+            first_time: String::new(),
+            last_time: String::new(),
+        });
+        self.part_bytes = 0;
+        self.part_records = 0;
+        self.write_index()
+    }
+
+    fn close_part(&mut self) -> io::Result<()> {
+        if let Some(mut file) = self.file.take() {
+            file.flush()?;
+            file.get_ref().sync_all()?;
+        }
+        Ok(())
+    }
+
+    fn write_index(&self) -> io::Result<()> {
+        let path = self.index_path();
+        let tmp = self.dir.join(format!("{}.index.json.tmp", self.stem));
+        let parts: Vec<&PartInfo> = self
+            .parts
+            .iter()
+            .filter(|p| !p.first_time.is_empty())
+            .collect();
+        fs::write(&tmp, serde_json::to_vec_pretty(&parts)?)?;
+        fs::rename(&tmp, path)
+    }
+}
@@ -0,0 +1,32 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::process;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+const CROCKFORD: &[u8; 32] = b"0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+
+/// Generates a ULID: 48 bits of milliseconds since the epoch followed by 80 random bits,
+/// as 26 Crockford base32 characters. Ids sort by creation time.
+///
+/// The random part comes from the per-process random keys of `RandomState`, mixed with
+/// the process id, which is enough to keep concurrent runs apart.
+pub fn generate() -> String {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u32(process::id());
+    hasher.write_u64(millis);
+    let high = hasher.finish();
+    hasher.write_u64(high);
+    let low = hasher.finish();
+
+    let value: u128 = (u128::from(millis & 0xFFFF_FFFF_FFFF) << 80)
+        | (u128::from(high & 0xFFFF) << 64)
+        | u128::from(low);
+    (0..26)
+        .rev()
+        .map(|i| CROCKFORD[((value >> (i * 5)) & 31) as usize] as char)
+        .collect()
+}
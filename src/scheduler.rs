@@ -0,0 +1,2288 @@
+use crate::args::ArgAssigner;
+use crate::auth::AuthMethod;
+use crate::classes;
+use crate::command::RemoteCommand;
+use crate::context::RunContext;
+use crate::control::RunHandle;
+use crate::dedup::{DedupKey, Deduplicator};
+use crate::diagnostics::{timed, Step};
+use crate::dns::{DnsCache, DnsCacheStats};
+use crate::encoding::OutputEncoding;
+use crate::escalation::{BecomeMethod, Escalation, SecretProvider};
+use crate::events::RunEvent;
+use crate::failure_threshold::{FailureBreaker, FailureThreshold};
+use crate::guard::Guard;
+use crate::inventory::{
+    check_bind, check_host, check_hosts, prepare_command, CheckedHost, HostOptions,
+};
+use crate::known_hosts::{HostKeyPolicy, HostKeyStore};
+use crate::local::local_process;
+use crate::output::{OutputKeep, OutputPassThrough};
+use crate::output_hash::OutputHashAlgorithm;
+use crate::post_condition::PostCondition;
+use crate::preflight::{self, CheckStatus, PreflightReport};
+use crate::preset::Preset;
+use crate::progress::{Phase, ProgressEvent, ProgressHook, ProgressTracker};
+use crate::proxy::{ProxyConfig, Target};
+use crate::pty::PtyRequest;
+use crate::redact::Redactor;
+use crate::response::{
+    AttemptRecord, ErrorKind, HostError, HostSelection, HostStatus, HostTimings, Response,
+    RunError, RunSummary,
+};
+use crate::result_class::{ExitCodeClasses, ResultClass};
+use crate::retry::RetryPolicy;
+use crate::run_id;
+use crate::semaphore::Semaphore;
+use crate::serial::{BatchHook, BatchReport, Serial};
+use crate::session::{process_host_inner, FollowupOutput, HostCommands, HostFacts, HostOutput};
+use crate::session_pool::SessionPool;
+use crate::sftp::Transfer;
+use crate::shell::RemoteShell;
+use crate::skip_check::SkipCheck;
+use crate::socket::{BindAddresses, TcpKeepaliveConfig};
+use crate::tags;
+use crate::target::{HostTarget, IntoTarget};
+use crate::template::render_template;
+use crate::timeouts::Timeouts;
+use crossbeam_channel::{bounded, unbounded, Receiver, Sender};
+use futures::sink::{Sink, SinkExt};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use smol::stream::{self, Stream, StreamExt};
+use std::collections::{BTreeMap, HashMap};
+use std::net::{IpAddr, SocketAddr};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, spawn};
+use std::time::{Duration, Instant, SystemTime};
+
+#[derive(Clone)]
+pub struct ParallelSshProps {
+    pub(crate) agent_connections_pool: Arc<Semaphore>,
+    pub(crate) read_permits: Option<Arc<Semaphore>>,
+    pub(crate) timeout_socket: Duration,
+    pub(crate) tcp_keepalive: Option<TcpKeepaliveConfig>,
+    pub(crate) timeout_ssh: Option<Duration>,
+    pub(crate) timeouts: Timeouts,
+    pub(crate) compression: bool,
+    pub(crate) banner_only: bool,
+    pub(crate) channel_parallelism: usize,
+    pub(crate) sender: Sender<Response>,
+    pub(crate) events: Option<Sender<RunEvent>>,
+    pub(crate) tcp_threads_number: isize,
+    /// Threads the hosts run on, kept for every run of these props.
+    pub(crate) workers: Arc<ThreadPool>,
+    pub(crate) user: String,
+    pub(crate) become_root: bool,
+    pub(crate) become_user: String,
+    pub(crate) become_method: BecomeMethod,
+    pub(crate) become_password: Option<SecretProvider>,
+    pub(crate) become_pty: bool,
+    pub(crate) request_pty: Option<PtyRequest>,
+    pub(crate) session_pool: Option<Arc<SessionPool>>,
+    pub(crate) local_loopback: bool,
+    pub(crate) strip_ansi: bool,
+    pub(crate) group: Option<String>,
+    pub(crate) remote_shell: RemoteShell,
+    pub(crate) auth_chain: Vec<AuthMethod>,
+    pub(crate) proxy: Option<ProxyConfig>,
+    pub(crate) bind_addresses: BindAddresses,
+    pub(crate) skip_bind_mismatch: bool,
+    pub(crate) deduplicate: bool,
+    pub(crate) templated: bool,
+    pub(crate) canary_hosts: usize,
+    pub(crate) serial: Option<Serial>,
+    pub(crate) batch_pause: Option<Duration>,
+    pub(crate) batch_hook: Option<BatchHook>,
+    pub(crate) failure_breaker: Option<Arc<FailureBreaker>>,
+    pub(crate) allow_empty: bool,
+    pub(crate) host_selection: HostSelection,
+    pub(crate) host_key_store: Option<Arc<HostKeyStore>>,
+    pub(crate) run_context: Option<Arc<RunContext>>,
+    pub(crate) gather_facts: Option<String>,
+    pub(crate) host_key_policy: HostKeyPolicy,
+    pub(crate) skip_if: Option<SkipCheck>,
+    pub(crate) guard: Option<Guard>,
+    pub(crate) post_condition: Option<PostCondition>,
+    pub(crate) workdir: Option<String>,
+    pub(crate) create_workdir: bool,
+    pub(crate) output_encoding: Option<OutputEncoding>,
+    pub(crate) keep_output: OutputKeep,
+    pub(crate) output_hash: Option<OutputHashAlgorithm>,
+    pub(crate) exit_code_classes: ExitCodeClasses,
+    pub(crate) pass_through: Option<OutputPassThrough>,
+    /// File transfer done instead of running the command, set by `parallel_upload` and
+    /// `parallel_fetch`.
+    pub(crate) transfer: Option<Arc<Transfer>>,
+    /// Commands run after the host's command over the same session, set by
+    /// `run_commands`.
+    pub(crate) followups: Option<Arc<Vec<String>>>,
+    pub(crate) redactor: Option<Redactor>,
+    pub(crate) unredacted_responses: bool,
+    pub(crate) verbose_config: bool,
+    pub(crate) retry_policy: RetryPolicy,
+    pub(crate) arg_assigner: Option<ArgAssigner>,
+    pub(crate) tags: BTreeMap<String, String>,
+    pub(crate) class_weights: Option<BTreeMap<String, u32>>,
+    pub(crate) agent_latency_warning: Option<Duration>,
+    pub(crate) limits: RunHandle,
+    pub(crate) dns_cache: Arc<DnsCache>,
+    pub(crate) progress: Arc<ProgressTracker>,
+    pub(crate) run_id: String,
+    pub(crate) preset: Option<Preset>,
+    pub(crate) cancelled: Arc<AtomicBool>,
+}
+
+impl Default for ParallelSshPropsBuilder {
+    fn default() -> Self {
+        Self {
+            agent_parallelism: Some(3),
+            max_concurrent_reads: None,
+            read_permits: None,
+            timeout_socket: Some(Duration::from_millis(200)),
+            tcp_keepalive: None,
+            timeout_ssh: None,
+            timeouts: Some(Timeouts::default()),
+            compression: Some(false),
+            banner_only: Some(false),
+            channel_parallelism: Some(4),
+            progress_hook: None,
+            run_id: None,
+            preset: None,
+            heartbeat_interval: Some(Duration::from_secs(10)),
+            tcp_threads_number: Some(10),
+            user: Some("scan".to_string()),
+            become_root: Some(false),
+            become_user: Some("root".to_string()),
+            become_method: Some(BecomeMethod::Sudo),
+            become_password: None,
+            become_pty: Some(false),
+            request_pty: None,
+            session_pool: None,
+            local_loopback: Some(false),
+            strip_ansi: Some(false),
+            group: None,
+            remote_shell: Some(RemoteShell::default()),
+            auth_chain: Some(vec![AuthMethod::Agent]),
+            proxy: None,
+            bind_addresses: Some(BindAddresses::default()),
+            skip_bind_mismatch: Some(false),
+            deduplicate: Some(false),
+            templated: Some(false),
+            canary_hosts: Some(0),
+            serial: None,
+            batch_pause: None,
+            batch_hook: None,
+            failure_threshold: None,
+            allow_empty: Some(false),
+            host_selection: None,
+            host_key_store: None,
+            run_context: None,
+            gather_facts: None,
+            host_key_policy: Some(HostKeyPolicy::Fail),
+            skip_if: None,
+            guard: None,
+            post_condition: None,
+            workdir: None,
+            create_workdir: Some(false),
+            output_encoding: None,
+            keep_output: Some(OutputKeep::All),
+            output_hash: None,
+            exit_code_classes: Some(ExitCodeClasses::default()),
+            pass_through: None,
+            redactor: None,
+            unredacted_responses: Some(false),
+            verbose_config: Some(false),
+            retry_policy: Some(RetryPolicy::default()),
+            arg_assigner: None,
+            tags: Some(BTreeMap::new()),
+            class_weights: None,
+            agent_latency_warning: None,
+            connect_rate: None,
+            dns_cache_ttl: Some(Duration::from_secs(300)),
+            dns_negative_ttl: Some(Duration::from_secs(10)),
+        }
+    }
+}
+
+impl ParallelSshPropsBuilder {
+    /// Sets connections, agent parallelism, probe and phase timeouts and the retry policy
+    /// as `preset` says. Setters called afterwards override single values.
+    pub fn preset(&mut self, a: Preset) -> &mut Self {
+        let values = a.values();
+        self.tcp_connections_pool(values.connections)
+            .agent_connections_pool(values.agent_parallelism)
+            .timeout_socket(values.probe_timeout)
+            .timeouts(values.timeouts)
+            .retry_policy(values.retry_policy());
+        let new = self;
+        new.preset = Some(a);
+        new
+    }
+    /// Hosts run at once, at least 1, each on a worker thread of its own; see
+    /// `RunHandle::set_max_connections` to change it during the run.
+    pub fn tcp_connections_pool(&mut self, a: isize) -> &mut Self {
+        let new = self;
+        new.tcp_threads_number = Some(a);
+        new
+    }
+    /// Hosts authenticating through the ssh-agent at once, at least 1.
+    pub fn agent_connections_pool(&mut self, a: isize) -> &mut Self {
+        let new = self;
+        new.agent_parallelism = Some(a);
+        new
+    }
+    /// Limit of the port probe made before connecting, sub-second values included.
+    pub fn timeout_socket(&mut self, a: Duration) -> &mut Self {
+        let new = self;
+        new.timeout_socket = Some(a);
+        new
+    }
+    /// Hosts reading command output at once, out of the open connections. Hosts whose
+    /// command has started wait in `Phase::WaitingToRead` for a permit. Unlimited by
+    /// default; must not exceed `tcp_connections_pool`.
+    ///
+    /// Props built from clones of this builder share the permits.
+    pub fn max_concurrent_reads(&mut self, a: usize) -> &mut Self {
+        let new = self;
+        new.max_concurrent_reads = Some(a);
+        new.read_permits = Some(Arc::new(Semaphore::new(a)));
+        new
+    }
+    /// Enable OS-level TCP keepalives on the SSH connections.
+    pub fn tcp_keepalive(&mut self, a: TcpKeepaliveConfig) -> &mut Self {
+        let new = self;
+        new.tcp_keepalive = Some(a);
+        new
+    }
+    /// Fail a host with `ErrorKind::Timeout` once it ran for `a`, from connecting to its
+    /// command's exit, retries included, so a hung host cannot stall the run. Phase limits
+    /// are cut to fit in it. Unlimited by default.
+    pub fn timeout_ssh(&mut self, a: Duration) -> &mut Self {
+        let new = self;
+        new.timeout_ssh = Some(a);
+        new
+    }
+    /// Time limits of the connect, handshake, auth, exec and read phases.
+    pub fn timeouts(&mut self, a: Timeouts) -> &mut Self {
+        let new = self;
+        new.timeouts = Some(a);
+        new
+    }
+    /// Request zlib compression of the SSH sessions. Costs CPU on both ends.
+    pub fn compression(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.compression = Some(a);
+        new
+    }
+    /// Only connect and collect each server's banners, without authenticating or running
+    /// the command; the auth methods of the user are asked for, so the server sends its
+    /// auth banner. `TypedResponse::<BannerReport>::from` gives the banners and host key
+    /// of a response as typed data.
+    pub fn banner_only(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.banner_only = Some(a);
+        new
+    }
+    /// Commands a `HostSession` starts at once, each on its own channel.
+    pub fn channel_parallelism(&mut self, a: usize) -> &mut Self {
+        let new = self;
+        new.channel_parallelism = Some(a);
+        new
+    }
+    /// Called on every phase change of a host and, for hosts in flight, every
+    /// `heartbeat_interval`.
+    pub fn progress_hook(&mut self, a: ProgressHook) -> &mut Self {
+        let new = self;
+        new.progress_hook = Some(a);
+        new
+    }
+    pub fn heartbeat_interval(&mut self, a: Duration) -> &mut Self {
+        let new = self;
+        new.heartbeat_interval = Some(a);
+        new
+    }
+    /// Id recorded on every response, to correlate the run with external systems.
+    /// Generated as a ULID when not given.
+    pub fn run_id(&mut self, a: String) -> &mut Self {
+        let new = self;
+        new.run_id = Some(a);
+        new
+    }
+    pub fn user(&mut self, a: String) -> &mut Self {
+        let new = self;
+        new.user = Some(a);
+        new
+    }
+    /// Run commands through `sudo -n`, i.e. as root without a password prompt, or as
+    /// `become_user`, `become_method` and `become_password` say.
+    pub fn become_root(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.become_root = Some(a);
+        new
+    }
+    /// User commands run as with `become_root`, root by default.
+    pub fn become_user(&mut self, a: String) -> &mut Self {
+        let new = self;
+        new.become_user = Some(a);
+        new
+    }
+    /// Tool commands run through with `become_root`, sudo by default.
+    pub fn become_method(&mut self, a: BecomeMethod) -> &mut Self {
+        let new = self;
+        new.become_method = Some(a);
+        new
+    }
+    /// Feed sudo the password `provider` gives for each host over stdin, instead of
+    /// relying on passwordless sudo.
+    pub fn become_password(&mut self, provider: SecretProvider) -> &mut Self {
+        let new = self;
+        new.become_password = Some(provider);
+        new
+    }
+    /// Run commands with become on a pseudo terminal, with echo off, for sudoers with
+    /// `requiretty`. Stderr then arrives mixed into the output.
+    pub fn become_pty(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.become_pty = Some(a);
+        new
+    }
+    /// Run commands on the pseudo terminal `pty` asks for, set up before they start.
+    /// Stderr then arrives mixed into the output; guards and post condition checks run
+    /// without one.
+    pub fn request_pty(&mut self, pty: PtyRequest) -> &mut Self {
+        let new = self;
+        new.request_pty = Some(pty);
+        new
+    }
+    /// Remove ANSI escape sequences, e.g. colors, from the output and stderr of commands.
+    pub fn strip_ansi(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.strip_ansi = Some(a);
+        new
+    }
+    /// Run hosts on the authenticated sessions `pool` keeps from earlier runs, and keep
+    /// theirs in it. The pool can be shared by several props.
+    pub fn session_pool(&mut self, pool: Arc<SessionPool>) -> &mut Self {
+        let new = self;
+        new.session_pool = Some(pool);
+        new
+    }
+    /// Run hosts on loopback addresses and `localhost` on the controller through `sh -c`,
+    /// ansible's local connection, instead of over SSH. `HostOptions::connection` decides
+    /// for the hosts it is set for.
+    pub fn local_loopback(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.local_loopback = Some(a);
+        new
+    }
+    /// Name recorded on every response produced by the built props.
+    pub fn group(&mut self, a: String) -> &mut Self {
+        let new = self;
+        new.group = Some(a);
+        new
+    }
+    /// Shell used for hosts without their own `HostOptions::remote_shell`.
+    pub fn remote_shell(&mut self, a: RemoteShell) -> &mut Self {
+        let new = self;
+        new.remote_shell = Some(a);
+        new
+    }
+    /// Auth methods tried in order for hosts without their own `HostOptions::auth_chain`.
+    pub fn auth_chain(&mut self, a: Vec<AuthMethod>) -> &mut Self {
+        let new = self;
+        new.auth_chain = Some(a);
+        new
+    }
+    /// Directory commands are run in, for hosts without their own `HostOptions::workdir`.
+    pub fn workdir(&mut self, a: String) -> &mut Self {
+        let new = self;
+        new.workdir = Some(a);
+        new
+    }
+    /// Create a missing workdir instead of failing the command.
+    pub fn create_workdir(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.create_workdir = Some(a);
+        new
+    }
+    /// How command output is decoded; by default as the remote shell expects.
+    pub fn output_encoding(&mut self, a: OutputEncoding) -> &mut Self {
+        let new = self;
+        new.output_encoding = Some(a);
+        new
+    }
+    /// Keep only the first and/or last lines of each host's output.
+    pub fn keep_output(&mut self, a: OutputKeep) -> &mut Self {
+        let new = self;
+        new.keep_output = Some(a);
+        new
+    }
+    /// Record a hash of each host's whole output in `Response::output_hash`, for telling
+    /// outputs apart without keeping them. Off by default.
+    pub fn output_hash(&mut self, a: OutputHashAlgorithm) -> &mut Self {
+        let new = self;
+        new.output_hash = Some(a);
+        new
+    }
+    /// Exit codes counted as ok, warning and error in `Response::result_class`. By default
+    /// only 0 is ok.
+    pub fn exit_code_classes(&mut self, a: ExitCodeClasses) -> &mut Self {
+        let new = self;
+        new.exit_code_classes = Some(a);
+        new
+    }
+    /// Pass each host's command output through instead of keeping it in the response,
+    /// for outputs too big to hold, or only those past a size with
+    /// `OutputPassThrough::Spill`. Cannot be combined with `keep_output` nor
+    /// `output_encoding`, and `OutputPassThrough::Events` needs `build_with_events`.
+    pub fn pass_through_output(&mut self, a: OutputPassThrough) -> &mut Self {
+        let new = self;
+        new.pass_through = Some(a);
+        new
+    }
+    /// Replace secrets in responses and pass-through files with `REDACTED`, before the
+    /// response is sent anywhere. Output chunks on the event stream stay as read.
+    pub fn redact(&mut self, a: Redactor) -> &mut Self {
+        let new = self;
+        new.redactor = Some(a);
+        new
+    }
+    /// Leave responses unredacted despite the redactor, for callers which only keep them
+    /// in memory. Pass-through files are still redacted.
+    pub fn unredacted_responses(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.unredacted_responses = Some(a);
+        new
+    }
+    /// Record on every response the settings its host ran with, see
+    /// `ParallelSshProps::effective_config`.
+    pub fn verbose_config(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.verbose_config = Some(a);
+        new
+    }
+    /// Which failures are retried; by default nothing is.
+    pub fn retry_policy(&mut self, a: RetryPolicy) -> &mut Self {
+        let new = self;
+        new.retry_policy = Some(a);
+        new
+    }
+    /// Append per-host arguments to the command, recorded in `Response::assigned_args`.
+    pub fn arg_assigner(&mut self, a: ArgAssigner) -> &mut Self {
+        let new = self;
+        new.arg_assigner = Some(a);
+        new
+    }
+    /// Tags recorded on every response, e.g. `team=infra`. Host tags override them.
+    pub fn tags(&mut self, a: BTreeMap<String, String>) -> &mut Self {
+        let new = self;
+        new.tags = Some(a);
+        new
+    }
+    pub fn tag<K: Into<String>, V: Into<String>>(&mut self, key: K, value: V) -> &mut Self {
+        let new = self;
+        new.tags
+            .get_or_insert_with(BTreeMap::new)
+            .insert(key.into(), value.into());
+        new
+    }
+    /// Tunnel every connection through a SOCKS5 proxy.
+    pub fn proxy(&mut self, a: ProxyConfig) -> &mut Self {
+        let new = self;
+        new.proxy = Some(a);
+        new
+    }
+    /// Local address outgoing connections are made from.
+    ///
+    /// Can be called once with an IPv4 and once with an IPv6 address; each target uses the
+    /// one matching its family.
+    pub fn bind_address(&mut self, a: IpAddr) -> &mut Self {
+        let new = self;
+        new.bind_addresses
+            .get_or_insert_with(BindAddresses::default)
+            .set(a);
+        new
+    }
+    /// Skip targets of a family without a bind address instead of failing them.
+    pub fn skip_bind_mismatch(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.skip_bind_mismatch = Some(a);
+        new
+    }
+    /// Run the command once per address and command line within a call, answering for the
+    /// other host names resolving to that address with a copy of the response.
+    pub fn deduplicate(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.deduplicate = Some(a);
+        new
+    }
+    /// Render commands as templates against the vars of their host, see
+    /// `template::render_template`; a host the template fails for fails with
+    /// `ErrorKind::Template`. `parallel_ssh_templated` renders its template either way.
+    pub fn templated(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.templated = Some(a);
+        new
+    }
+    /// Start the hosts of `parallel_ssh_process_with_options` with their classes taking
+    /// turns instead of in input order, so slow hosts listed first do not hold every worker
+    /// while fast ones wait. Each turn, a class starts as many hosts as its weight; classes
+    /// not in `a`, and hosts without a class, weigh 1. The `ArgAssigner` sees hosts in the
+    /// order they start.
+    pub fn class_weights(&mut self, a: BTreeMap<String, u32>) -> &mut Self {
+        let new = self;
+        new.class_weights = Some(a);
+        new
+    }
+    /// Run the first `n` hosts to completion before starting the others, and skip those
+    /// when all `n` failed with the same auth or agent error. 0, the default, disables it.
+    pub fn canary_hosts(&mut self, n: usize) -> &mut Self {
+        let new = self;
+        new.canary_hosts = Some(n);
+        new
+    }
+    /// Start the hosts in batches of `serial`, in input order, each batch only once the
+    /// previous one is done, e.g. for a rolling restart. Canary hosts run before the first
+    /// batch.
+    pub fn serial(&mut self, serial: Serial) -> &mut Self {
+        let new = self;
+        new.serial = Some(serial);
+        new
+    }
+    /// Wait `pause` between the batches of a serial run.
+    pub fn batch_pause(&mut self, pause: Duration) -> &mut Self {
+        let new = self;
+        new.batch_pause = Some(pause);
+        new
+    }
+    /// Ask `hook` after each batch of a serial run whether to start the next one, e.g. to
+    /// have an operator confirm. Declined, the remaining hosts are skipped and the run
+    /// fails with `RunError::Stopped`.
+    pub fn batch_hook(&mut self, hook: BatchHook) -> &mut Self {
+        let new = self;
+        new.batch_hook = Some(hook);
+        new
+    }
+    /// Abort the run once more hosts failed than `threshold` allows: hosts not started yet
+    /// fail with `ErrorKind::Cancelled` without being connected to, hosts reading output
+    /// stop, and the run fails with `RunError::TooManyFailures`.
+    pub fn failure_threshold(&mut self, threshold: FailureThreshold) -> &mut Self {
+        let new = self;
+        new.failure_threshold = Some(threshold);
+        new
+    }
+    /// Succeed without doing anything when a run is given no hosts, instead of failing it
+    /// with `RunError::NoHostsSelected`.
+    pub fn allow_empty(&mut self, a: bool) -> &mut Self {
+        let new = self;
+        new.allow_empty = Some(a);
+        new
+    }
+    /// How the hosts given to runs of the props were selected, e.g. from an inventory
+    /// filtered by group and name, so that `RunError::NoHostsSelected` tells which step
+    /// left none.
+    pub fn host_selection(&mut self, a: HostSelection) -> &mut Self {
+        let new = self;
+        new.host_selection = Some(a);
+        new
+    }
+    /// Record what each host's run found in `context`, and render `{{facts.NAME}}` in
+    /// commands from the facts it has for the host. Props built with the same context,
+    /// e.g. of successive runs, share what it holds.
+    pub fn run_context(&mut self, context: Arc<RunContext>) -> &mut Self {
+        let new = self;
+        new.run_context = Some(context);
+        new
+    }
+    /// Keep the `KEY=VALUE` lines of each successful output, e.g. of `cat /etc/os-release`,
+    /// as the facts `<prefix>.<key>` of the host in the run context.
+    pub fn gather_facts(&mut self, prefix: String) -> &mut Self {
+        let new = self;
+        new.gather_facts = Some(prefix);
+        new
+    }
+    /// Check host keys against `store`, recording the key of hosts seen for the first time.
+    ///
+    /// Share one store between all props of a run, so writes to its file are serialized.
+    pub fn host_key_store(&mut self, store: Arc<HostKeyStore>) -> &mut Self {
+        let new = self;
+        new.host_key_store = Some(store);
+        new
+    }
+    /// Maintenance check run first on every host, as the login user. Hosts it skips fail
+    /// with `ErrorKind::MaintenanceMode`, the marker it read kept in their response.
+    pub fn skip_if(&mut self, a: SkipCheck) -> &mut Self {
+        let new = self;
+        new.skip_if = Some(a);
+        new
+    }
+    /// Check run before the command on every host, deciding whether the command runs.
+    /// Hosts it skips fail with `ErrorKind::GuardSatisfied`.
+    pub fn guard(&mut self, a: Guard) -> &mut Self {
+        let new = self;
+        new.guard = Some(a);
+        new
+    }
+    /// Condition waited for after the command succeeded on a host, e.g. its service port
+    /// accepting connections. Hosts where it does not hold in time fail with
+    /// `ErrorKind::PostConditionTimeout`. `HostOptions::post_condition` overrides it.
+    pub fn post_condition(&mut self, a: PostCondition) -> &mut Self {
+        let new = self;
+        new.post_condition = Some(a);
+        new
+    }
+    /// What to do when a host key differs from the stored one, failing the host by default.
+    pub fn host_key_policy(&mut self, a: HostKeyPolicy) -> &mut Self {
+        let new = self;
+        new.host_key_policy = Some(a);
+        new
+    }
+    /// Send a `RunEvent::AgentLatencyHigh` for every host whose ssh-agent round trip took
+    /// longer than `a`. Round trips are measured and summed up in
+    /// `ProgressTracker::agent_latency` either way.
+    pub fn agent_latency_warning(&mut self, a: Duration) -> &mut Self {
+        let new = self;
+        new.agent_latency_warning = Some(a);
+        new
+    }
+    /// Start at most `a` hosts per second; see `RunHandle::set_connect_rate` to change
+    /// it during the run.
+    pub fn connect_rate(&mut self, a: f64) -> &mut Self {
+        let new = self;
+        new.connect_rate = Some(a);
+        new
+    }
+    /// How long resolved host names are reused.
+    pub fn dns_cache_ttl(&mut self, a: Duration) -> &mut Self {
+        let new = self;
+        new.dns_cache_ttl = Some(a);
+        new
+    }
+    /// How long a failed resolution is reused before the name is queried again.
+    pub fn dns_negative_ttl(&mut self, a: Duration) -> &mut Self {
+        let new = self;
+        new.dns_negative_ttl = Some(a);
+        new
+    }
+    pub fn build(&self) -> Result<(Receiver<Response>, ParallelSshProps), String> {
+        self.build_new(None)
+    }
+    /// Like `build`, also returning a stream of `RunEvent`s: each host's steps as they
+    /// happen, output as it is read and, last, its response.
+    ///
+    /// Meant for UIs tracking hosts live; the event stream is unbounded, so it has to be
+    /// drained alongside the run. Props built with `build_sharing_stream` from the
+    /// returned ones report into the same event stream.
+    pub fn build_with_events(
+        &self,
+    ) -> Result<(Receiver<Response>, Receiver<RunEvent>, ParallelSshProps), String> {
+        let (events_tx, events_rx) = unbounded();
+        let (rx, props) = self.build_new(Some(events_tx))?;
+        Ok((rx, events_rx, props))
+    }
+    fn build_new(
+        &self,
+        events: Option<Sender<RunEvent>>,
+    ) -> Result<(Receiver<Response>, ParallelSshProps), String> {
+        let (tx, rx) = unbounded();
+        let dns_cache = DnsCache::new(
+            self.dns_cache_ttl
+                .ok_or("dns_cache_ttl must be initialized")?,
+            self.dns_negative_ttl
+                .ok_or("dns_negative_ttl must be initialized")?,
+        );
+        let progress = ProgressTracker::new(
+            self.progress_hook.clone(),
+            self.heartbeat_interval
+                .ok_or("heartbeat_interval must be initialized")?,
+        );
+        Ok((
+            rx,
+            self.build_with_sender(
+                tx,
+                events,
+                None,
+                Arc::new(dns_cache),
+                progress,
+                self.run_id.clone().unwrap_or_else(run_id::generate),
+            )?,
+        ))
+    }
+    /// Builds props which report into the same result stream as `props`.
+    ///
+    /// Used to run subsets of hosts with their own settings while collecting one stream of
+    /// responses. The agent connections pool, the DNS cache and the progress tracking of
+    /// `props`, including its progress hook, are shared. Responses carry
+    /// the run id of `props`.
+    pub fn build_sharing_stream(
+        &self,
+        props: &ParallelSshProps,
+    ) -> Result<ParallelSshProps, String> {
+        self.build_with_sender(
+            props.sender.clone(),
+            props.events.clone(),
+            Some(props.agent_connections_pool.clone()),
+            props.dns_cache.clone(),
+            props.progress.clone(),
+            props.run_id.clone(),
+        )
+    }
+    fn build_with_sender(
+        &self,
+        tx: Sender<Response>,
+        events: Option<Sender<RunEvent>>,
+        agent_permits: Option<Arc<Semaphore>>,
+        dns_cache: Arc<DnsCache>,
+        progress: Arc<ProgressTracker>,
+        run_id: String,
+    ) -> Result<ParallelSshProps, String> {
+        let connections = match self.tcp_threads_number {
+            Some(n) if n < 1 => return Err("maximum_connections must be at least 1".to_string()),
+            Some(n) => n,
+            None => return Err("maximum_connections must be initialized".to_string()),
+        };
+        Ok(ParallelSshProps {
+            timeout_ssh: match self.timeout_ssh {
+                Some(t) if t == Duration::from_secs(0) => {
+                    return Err("timeout_ssh must be above 0".to_string())
+                }
+                t => t,
+            },
+            timeouts: match self.timeouts {
+                Some(timeouts) => timeouts.validate().map(|_| timeouts)?,
+                None => return Err("timeouts must be initialized".to_string()),
+            },
+            timeout_socket: match self.timeout_socket {
+                Some(t) if t == Duration::from_secs(0) => {
+                    return Err("timeout_socket must be above 0".to_string())
+                }
+                Some(t) => t,
+                None => return Err("timeout_socket must be initialized".to_string()),
+            },
+            tcp_keepalive: self.tcp_keepalive,
+            compression: self.compression.ok_or("compression must be initialized")?,
+            banner_only: self.banner_only.ok_or("banner_only must be initialized")?,
+            channel_parallelism: self
+                .channel_parallelism
+                .ok_or("channel_parallelism must be initialized")?,
+            agent_connections_pool: match (agent_permits, self.agent_parallelism) {
+                (Some(shared), _) => shared,
+                (None, Some(n)) if n < 1 => {
+                    return Err("agent_connections_pool must be at least 1".to_string())
+                }
+                (None, Some(n)) => Arc::new(Semaphore::new(n as usize)),
+                (None, None) => return Err("agent_parallelism must be initialized".to_string()),
+            },
+            read_permits: match self.max_concurrent_reads {
+                Some(n) if n as isize > connections => {
+                    return Err(format!(
+                        "max_concurrent_reads ({}) must not exceed maximum_connections ({})",
+                        n, connections
+                    ))
+                }
+                Some(0) => return Err("max_concurrent_reads must be at least 1".to_string()),
+                _ => self.read_permits.clone(),
+            },
+            tcp_threads_number: connections,
+            workers: ThreadPoolBuilder::new()
+                .num_threads(connections as usize)
+                .build()
+                .map(Arc::new)
+                .map_err(|e| format!("Failed creating the worker threads: {}", e))?,
+            user: self.user.clone().ok_or("user must be initialized")?,
+            become_root: self.become_root.ok_or("become_root must be initialized")?,
+            become_user: match &self.become_user {
+                Some(user) if user.is_empty() => return Err("become_user is empty".to_string()),
+                Some(user) => user.clone(),
+                None => return Err("become_user must be initialized".to_string()),
+            },
+            become_method: match (self.become_method, &self.become_password) {
+                (Some(BecomeMethod::Doas), Some(_)) => {
+                    return Err("become_method doas takes no become_password".to_string())
+                }
+                (Some(method), _) => method,
+                (None, _) => return Err("become_method must be initialized".to_string()),
+            },
+            become_password: self.become_password.clone(),
+            become_pty: self.become_pty.ok_or("become_pty must be initialized")?,
+            request_pty: match &self.request_pty {
+                Some(pty) => pty.validate().map(|_| Some(pty.clone()))?,
+                None => None,
+            },
+            strip_ansi: self.strip_ansi.ok_or("strip_ansi must be initialized")?,
+            session_pool: self.session_pool.clone(),
+            local_loopback: self
+                .local_loopback
+                .ok_or("local_loopback must be initialized")?,
+            group: self.group.clone(),
+            remote_shell: self
+                .remote_shell
+                .ok_or("remote_shell must be initialized")?,
+            auth_chain: self
+                .auth_chain
+                .clone()
+                .ok_or("auth_chain must be initialized")?,
+            proxy: self.proxy.clone(),
+            bind_addresses: self
+                .bind_addresses
+                .ok_or("bind_addresses must be initialized")?,
+            skip_bind_mismatch: self
+                .skip_bind_mismatch
+                .ok_or("skip_bind_mismatch must be initialized")?,
+            deduplicate: self.deduplicate.ok_or("deduplicate must be initialized")?,
+            templated: self.templated.ok_or("templated must be initialized")?,
+            canary_hosts: self
+                .canary_hosts
+                .ok_or("canary_hosts must be initialized")?,
+            serial: match self.serial {
+                Some(serial) => serial.validate().map(|_| Some(serial))?,
+                None => None,
+            },
+            batch_pause: self.batch_pause,
+            batch_hook: self.batch_hook.clone(),
+            failure_breaker: match self.failure_threshold {
+                Some(threshold) => threshold
+                    .validate()
+                    .map(|_| Some(Arc::new(FailureBreaker::new(threshold))))?,
+                None => None,
+            },
+            allow_empty: self.allow_empty.ok_or("allow_empty must be initialized")?,
+            host_selection: self.host_selection.unwrap_or_default(),
+            host_key_store: self.host_key_store.clone(),
+            run_context: self.run_context.clone(),
+            gather_facts: match &self.gather_facts {
+                Some(_) if self.run_context.is_none() => {
+                    return Err("gather_facts needs a run_context to keep the facts".to_string())
+                }
+                Some(prefix) if prefix.is_empty() || prefix.contains(char::is_whitespace) => {
+                    return Err(format!("invalid gather_facts prefix {:?}", prefix))
+                }
+                gather_facts => gather_facts.clone(),
+            },
+            host_key_policy: self
+                .host_key_policy
+                .ok_or("host_key_policy must be initialized")?,
+            skip_if: self.skip_if.clone(),
+            guard: self.guard.clone(),
+            post_condition: self.post_condition.clone(),
+            workdir: self.workdir.clone(),
+            create_workdir: self
+                .create_workdir
+                .ok_or("create_workdir must be initialized")?,
+            output_encoding: self.output_encoding,
+            keep_output: self.keep_output.ok_or("keep_output must be initialized")?,
+            output_hash: self.output_hash,
+            exit_code_classes: {
+                let classes = self
+                    .exit_code_classes
+                    .clone()
+                    .ok_or("exit_code_classes must be initialized")?;
+                classes.validate()?;
+                classes
+            },
+            pass_through: match &self.pass_through {
+                Some(_) if self.keep_output != Some(OutputKeep::All) => {
+                    return Err("pass_through_output keeps no output, so keep_output \
+                                cannot be set with it"
+                        .to_string())
+                }
+                Some(_) if self.output_encoding.is_some() => {
+                    return Err("pass_through_output does not decode output, so \
+                                output_encoding cannot be set with it"
+                        .to_string())
+                }
+                Some(OutputPassThrough::Events) if events.is_none() => {
+                    return Err("pass_through_output to events needs the event stream of \
+                                build_with_events"
+                        .to_string())
+                }
+                pass_through => pass_through.clone(),
+            },
+            redactor: self.redactor.clone(),
+            transfer: None,
+            followups: None,
+            unredacted_responses: self
+                .unredacted_responses
+                .ok_or("unredacted_responses must be initialized")?,
+            verbose_config: self
+                .verbose_config
+                .ok_or("verbose_config must be initialized")?,
+            retry_policy: {
+                let policy = self
+                    .retry_policy
+                    .clone()
+                    .ok_or("retry_policy must be initialized")?;
+                policy.validate()?;
+                policy
+            },
+            arg_assigner: self.arg_assigner.clone(),
+            tags: {
+                let tags = self.tags.clone().ok_or("tags must be initialized")?;
+                tags::validate_tags(&tags)?;
+                tags
+            },
+            class_weights: match &self.class_weights {
+                Some(weights) => match weights.iter().find(|(_, weight)| **weight == 0) {
+                    Some((class, _)) => {
+                        return Err(format!("weight of class {} must be at least 1", class))
+                    }
+                    None => Some(weights.clone()),
+                },
+                None => None,
+            },
+            agent_latency_warning: self.agent_latency_warning,
+            limits: RunHandle::new(connections as usize, self.connect_rate)?,
+            dns_cache,
+            progress,
+            run_id,
+            preset: self.preset,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            sender: tx,
+            events,
+        })
+    }
+}
+
+#[derive(Clone)]
+pub struct ParallelSshPropsBuilder {
+    agent_parallelism: Option<isize>,
+    max_concurrent_reads: Option<usize>,
+    read_permits: Option<Arc<Semaphore>>,
+    timeout_socket: Option<Duration>,
+    tcp_keepalive: Option<TcpKeepaliveConfig>,
+    timeout_ssh: Option<Duration>,
+    timeouts: Option<Timeouts>,
+    compression: Option<bool>,
+    banner_only: Option<bool>,
+    channel_parallelism: Option<usize>,
+    progress_hook: Option<ProgressHook>,
+    heartbeat_interval: Option<Duration>,
+    run_id: Option<String>,
+    preset: Option<Preset>,
+    tcp_threads_number: Option<isize>,
+    user: Option<String>,
+    become_root: Option<bool>,
+    become_user: Option<String>,
+    become_method: Option<BecomeMethod>,
+    become_password: Option<SecretProvider>,
+    become_pty: Option<bool>,
+    request_pty: Option<PtyRequest>,
+    strip_ansi: Option<bool>,
+    session_pool: Option<Arc<SessionPool>>,
+    local_loopback: Option<bool>,
+    group: Option<String>,
+    remote_shell: Option<RemoteShell>,
+    auth_chain: Option<Vec<AuthMethod>>,
+    proxy: Option<ProxyConfig>,
+    bind_addresses: Option<BindAddresses>,
+    skip_bind_mismatch: Option<bool>,
+    deduplicate: Option<bool>,
+    templated: Option<bool>,
+    canary_hosts: Option<usize>,
+    serial: Option<Serial>,
+    batch_pause: Option<Duration>,
+    batch_hook: Option<BatchHook>,
+    failure_threshold: Option<FailureThreshold>,
+    allow_empty: Option<bool>,
+    host_selection: Option<HostSelection>,
+    host_key_store: Option<Arc<HostKeyStore>>,
+    run_context: Option<Arc<RunContext>>,
+    gather_facts: Option<String>,
+    host_key_policy: Option<HostKeyPolicy>,
+    skip_if: Option<SkipCheck>,
+    guard: Option<Guard>,
+    post_condition: Option<PostCondition>,
+    workdir: Option<String>,
+    create_workdir: Option<bool>,
+    output_encoding: Option<OutputEncoding>,
+    keep_output: Option<OutputKeep>,
+    output_hash: Option<OutputHashAlgorithm>,
+    exit_code_classes: Option<ExitCodeClasses>,
+    pass_through: Option<OutputPassThrough>,
+    redactor: Option<Redactor>,
+    unredacted_responses: Option<bool>,
+    verbose_config: Option<bool>,
+    retry_policy: Option<RetryPolicy>,
+    arg_assigner: Option<ArgAssigner>,
+    tags: Option<BTreeMap<String, String>>,
+    class_weights: Option<BTreeMap<String, u32>>,
+    agent_latency_warning: Option<Duration>,
+    connect_rate: Option<f64>,
+    dns_cache_ttl: Option<Duration>,
+    dns_negative_ttl: Option<Duration>,
+}
+
+/// Runs a checked host and sends its response. Returns the outcome, `None` for an alias
+/// whose response comes from another host's execution.
+fn process_host(
+    host: HostTarget,
+    ip: Result<Target, HostError>,
+    timings: HostTimings,
+    command: String,
+    assigned_args: Vec<String>,
+    options: HostOptions,
+    props: &ParallelSshProps,
+    dedup: Option<&Deduplicator>,
+) -> Option<Result<(), ErrorKind>> {
+    let mut target = ip.and_then(|t| check_bind(t, props));
+    if let Some(e) = props.cancellation() {
+        target = Err(e);
+    }
+    let (command, render_error) = host_command(&host, &command, &assigned_args, &options, props);
+    if let Some(e) = render_error {
+        target = target.and(Err(e));
+    }
+    let mut commands = host_commands(command, &options, props);
+    commands.process = local_process(&host, &options, props);
+    match props.escalation(&host.to_string()) {
+        Ok(escalation) => commands.escalation = escalation,
+        Err(e) => target = target.and(Err(e)),
+    }
+    if let Some(followups) = &props.followups {
+        let settings = props.host_settings(&options);
+        for given in followups.iter() {
+            let given = match render_command(&host, given, &options, props) {
+                Ok(rendered) => rendered,
+                Err(e) => {
+                    target = target.and(Err(e));
+                    given.clone()
+                }
+            };
+            let command = prepare_command(
+                given.clone(),
+                settings.remote_shell,
+                settings.workdir,
+                props,
+            );
+            commands.followups.push((given, command));
+        }
+    }
+    // The responses of a sequence are not shared with other hosts.
+    let dedup = dedup.filter(|_| commands.followups.is_empty());
+    let dedup = match (dedup, &target) {
+        (Some(dedup), Ok(Target::Resolved(addr))) => {
+            let user = props.host_settings(&options).user.to_string();
+            let key: DedupKey = (*addr, user, commands.command.clone());
+            if !dedup.claim(&key, &host.to_string(), props) {
+                return None;
+            }
+            Some((dedup, key))
+        }
+        _ => None,
+    };
+
+    let mut facts = HostFacts {
+        timings,
+        ..HostFacts::default()
+    };
+    let res = run_host(
+        host,
+        target,
+        &commands,
+        &options,
+        assigned_args,
+        props,
+        &mut facts,
+    );
+    if let Some((dedup, key)) = dedup {
+        dedup.finish(key, &res, props);
+    }
+    let outcome = res.error_kind.map_or(Ok(()), Err);
+    if let Some(context) = &props.run_context {
+        context.record(&res, props.gather_facts.as_deref());
+    }
+    let followups = followup_responses(&res, &commands, &mut facts, props);
+    props.send_response(res);
+    for response in followups {
+        props.send_response(response);
+    }
+    // event!(`
+    //     Level::INFO,
+    //     "processed :{}, id: {:#?}\nAGENT: {}\n",
+    //     hostname,
+    //     thread::current().id(),
+    //     agent_pool.available_permits()
+    // );
+    Some(outcome)
+}
+
+/// Command line of `host`: `command` rendered as `render_command` says, and the `args`
+/// assigned to the host appended only then, so that they reach it untouched. When it
+/// cannot be rendered, `command` is used as given and the error comes along.
+pub(crate) fn host_command(
+    host: &HostTarget,
+    command: &str,
+    args: &[String],
+    options: &HostOptions,
+    props: &ParallelSshProps,
+) -> (String, Option<HostError>) {
+    let (command, error) = match render_command(host, command, options, props) {
+        Ok(rendered) => (rendered, None),
+        Err(e) => (command.to_string(), Some(e)),
+    };
+    let command = RemoteCommand::raw(command).args(args.iter().cloned());
+    (command.to_string(), error)
+}
+
+/// `command` rendered for `host` with its vars when the props are templated, and with the
+/// facts of the run context.
+fn render_command(
+    host: &HostTarget,
+    command: &str,
+    options: &HostOptions,
+    props: &ParallelSshProps,
+) -> Result<String, HostError> {
+    let command = if props.templated {
+        render_template(command, &options.vars)
+            .map_err(|e| HostError::new(ErrorKind::Template, format!("{}: {}", host, e)))?
+    } else {
+        command.to_string()
+    };
+    match &props.run_context {
+        Some(context) => context
+            .render(&host.to_string(), &command)
+            .map_err(|e| HostError::new(ErrorKind::MissingFact, e)),
+        None => Ok(command),
+    }
+}
+
+/// Command lines of a host, with its workdir and shell applied.
+pub(crate) fn host_commands(
+    command: String,
+    options: &HostOptions,
+    props: &ParallelSshProps,
+) -> HostCommands {
+    let settings = props.host_settings(options);
+    let (shell, workdir) = (settings.remote_shell, settings.workdir);
+    HostCommands {
+        given: command.clone(),
+        skip_check: props
+            .skip_if
+            .as_ref()
+            .map(|check| shell.wrap(check.command())),
+        command: prepare_command(command, shell, workdir, props),
+        guard: props
+            .guard
+            .as_ref()
+            .map(|guard| prepare_command(guard.command().to_string(), shell, workdir, props)),
+        post_condition: options
+            .post_condition
+            .as_ref()
+            .or_else(|| props.post_condition.as_ref())
+            .map(|condition| {
+                let check = condition
+                    .command()
+                    .map(|check| prepare_command(check.to_string(), shell, workdir, props));
+                (condition.clone(), check)
+            }),
+        escalation: None,
+        followups: Vec::new(),
+        process: None,
+    }
+}
+
+/// Runs `commands` on a host, retrying as the retry policy says, and builds its response.
+pub(crate) fn run_host(
+    host: HostTarget,
+    mut target: Result<Target, HostError>,
+    commands: &HostCommands,
+    options: &HostOptions,
+    assigned_args: Vec<String>,
+    props: &ParallelSshProps,
+    facts: &mut HostFacts,
+) -> Response {
+    let settings = props.host_settings(options);
+    let workdir = settings.workdir.map(str::to_string);
+    let (shell, user, auth_chain) = (settings.remote_shell, settings.user, &settings.auth_chain);
+    let effective_config = if props.verbose_config {
+        Some(props.effective_config(&host, options))
+    } else {
+        None
+    };
+    let tags = tags::merge(&props.tags, &options.tags);
+    let start_time = Instant::now();
+    let host_deadline = props.timeout_ssh.map(|t| start_time + t);
+    let mut attempt_history = Vec::new();
+    let name = response_name(&host, &target);
+    let progress = props
+        .progress
+        .start(
+            match &target {
+                Ok(t) => t.to_string(),
+                Err(_) => host.to_string(),
+            },
+            options.class.clone(),
+        )
+        .with_events(props.events.clone(), name.clone());
+    let result: Result<HostOutput, HostError> = loop {
+        let attempt = attempt_history.len() as u32 + 1;
+        let timestamp = SystemTime::now();
+        let attempt_start = Instant::now();
+        let result = match &target {
+            Ok(t) => process_host_inner(
+                &name,
+                t,
+                commands,
+                shell,
+                user,
+                auth_chain,
+                props,
+                host_deadline,
+                facts,
+                &progress,
+            )
+            .map_err(|e| match (host_deadline, props.timeout_ssh) {
+                (Some(deadline), Some(limit)) if Instant::now() >= deadline => HostError::new(
+                    ErrorKind::Timeout,
+                    format!(
+                        "Gave up on the host after {}: {}",
+                        humantime::format_duration(limit),
+                        e.message
+                    ),
+                ),
+                _ => e,
+            }),
+            Err(e) => Err(e.clone()),
+        };
+        let error_kind = result.as_ref().err().map(|e| e.kind);
+        let retry = error_kind.and_then(|kind| props.retry_policy.for_kind(kind));
+        let backoff = error_kind.and_then(|kind| props.retry_policy.next_delay(kind, attempt));
+        attempt_history.push(AttemptRecord {
+            attempt,
+            error_kind,
+            duration: attempt_start.elapsed(),
+            timestamp,
+            max_attempts: retry.map_or(1, |r| r.max_attempts),
+            backoff,
+        });
+        match backoff {
+            Some(_) if props.is_cancelled() => break result,
+            Some(delay) if host_deadline.map_or(false, |d| Instant::now() + delay >= d) => {
+                break result
+            }
+            None => break result,
+            Some(delay) => {
+                thread::sleep(delay);
+                progress.set_phase(Phase::Connecting);
+                if let Some(steps) = &mut facts.steps {
+                    steps.set_attempt(attempt + 1);
+                }
+                facts.timings = facts.timings.precheck();
+                // A failed precheck is repeated as a whole, the target may resolve now.
+                if target.is_err() {
+                    let timings = &mut facts.timings;
+                    target = timed(&mut facts.steps, Step::Precheck, || {
+                        smol::run(check_host(
+                            &host,
+                            props.proxy.as_ref(),
+                            &props.dns_cache,
+                            props.session_pool.as_deref(),
+                            props.timeout_socket,
+                            timings,
+                        ))
+                        .and_then(|t| check_bind(t, props))
+                    });
+                }
+            }
+        }
+    };
+    drop(progress);
+    let process_time = match &target {
+        Ok(_) => start_time.elapsed(),
+        Err(_) => Duration::default(),
+    };
+    let hostname = response_name(&host, &target);
+    let address = match (&target, &host) {
+        (Ok(t), _) => t.to_string(),
+        (Err(_), HostTarget::Name(name)) => name.clone(),
+        (Err(_), HostTarget::Address { address, .. }) => address.to_string(),
+    };
+    let mut response = match result {
+        Ok(out) => Response {
+            result: out.output,
+            stderr: out.stderr,
+            hostname,
+            address,
+            user: user.to_string(),
+            command: commands.given.clone(),
+            process_time,
+            timings: facts.timings,
+            status: true,
+            outcome: HostStatus::Success,
+            result_class: props.exit_code_classes.classify(None, out.exit_code),
+            error_kind: None,
+            run_id: props.run_id.clone(),
+            group: props.group.clone(),
+            class: options.class.clone(),
+            exit_code: out.exit_code,
+            connection: out.connection,
+            workdir,
+            encoding: out.encoding,
+            output_hash: out.output_hash,
+            server_banner: facts.banner.take(),
+            auth_banner: facts.auth_banner.take(),
+            host_key: facts.host_key.take(),
+            skip_check: facts.skip_check.take(),
+            guard: facts.guard.take(),
+            post_condition: facts.post_condition.take(),
+            discarded: Some(out.discarded).filter(|d| d.lines > 0),
+            passed_through: out.passed_through,
+            upload: out.upload,
+            fetch: out.fetch,
+            redactions: None,
+            deduplicated_with: None,
+            assigned_args,
+            tags,
+            effective_config,
+            attempts: attempt_history.len() as u32,
+            attempt_history,
+        },
+        Err(e) => Response {
+            result: e.to_string(),
+            stderr: String::new(),
+            hostname,
+            address,
+            user: user.to_string(),
+            command: commands.given.clone(),
+            process_time,
+            timings: facts.timings,
+            status: false,
+            outcome: HostStatus::of(Some(e.kind)),
+            result_class: ResultClass::Error,
+            error_kind: Some(e.kind),
+            run_id: props.run_id.clone(),
+            group: props.group.clone(),
+            class: options.class.clone(),
+            exit_code: None,
+            connection: None,
+            workdir,
+            encoding: None,
+            output_hash: None,
+            server_banner: facts.banner.take(),
+            auth_banner: facts.auth_banner.take(),
+            host_key: facts.host_key.take(),
+            skip_check: facts.skip_check.take(),
+            guard: facts.guard.take(),
+            post_condition: facts.post_condition.take(),
+            discarded: None,
+            passed_through: None,
+            upload: None,
+            fetch: None,
+            redactions: None,
+            deduplicated_with: None,
+            assigned_args,
+            tags,
+            effective_config,
+            attempts: attempt_history.len() as u32,
+            attempt_history,
+        },
+    };
+    redact_response(&mut response, props);
+    response
+}
+
+/// Responses of the follow-up commands of a host, built from `main`, the response of its
+/// first command. Commands which did not get to run are skipped.
+fn followup_responses(
+    main: &Response,
+    commands: &HostCommands,
+    facts: &mut HostFacts,
+    props: &ParallelSshProps,
+) -> Vec<Response> {
+    let mut outputs = facts.followups.drain(..);
+    // Why the commands left over were not run.
+    let mut not_run = match main.outcome {
+        HostStatus::Success => "the host ran no command",
+        HostStatus::Skipped => "the host was skipped",
+        HostStatus::Cancelled => "the host was cancelled",
+        HostStatus::Failed => "an earlier command of the sequence failed",
+    };
+    commands
+        .followups
+        .iter()
+        .map(|(given, _)| {
+            let base = Response {
+                command: given.clone(),
+                timings: HostTimings::default(),
+                skip_check: None,
+                guard: None,
+                post_condition: None,
+                passed_through: None,
+                redactions: None,
+                ..main.clone()
+            };
+            let mut response = match outputs.next() {
+                Some(FollowupOutput {
+                    duration,
+                    result: Ok(out),
+                }) => Response {
+                    result: out.output,
+                    stderr: out.stderr,
+                    process_time: duration,
+                    status: true,
+                    outcome: HostStatus::Success,
+                    result_class: props.exit_code_classes.classify(None, Some(out.exit_code)),
+                    error_kind: None,
+                    exit_code: Some(out.exit_code),
+                    encoding: Some(out.encoding),
+                    output_hash: out.output_hash,
+                    discarded: Some(out.discarded).filter(|d| d.lines > 0),
+                    ..base
+                },
+                Some(FollowupOutput {
+                    duration,
+                    result: Err(e),
+                }) => {
+                    not_run = "an earlier command of the sequence failed";
+                    Response {
+                        result: e.to_string(),
+                        stderr: String::new(),
+                        process_time: duration,
+                        status: false,
+                        outcome: HostStatus::of(Some(e.kind)),
+                        result_class: ResultClass::Error,
+                        error_kind: Some(e.kind),
+                        exit_code: None,
+                        encoding: None,
+                        output_hash: None,
+                        discarded: None,
+                        ..base
+                    }
+                }
+                None => Response {
+                    result: format!("Not run, {}", not_run),
+                    stderr: String::new(),
+                    process_time: Duration::default(),
+                    status: false,
+                    outcome: HostStatus::of(Some(ErrorKind::Skipped)),
+                    result_class: ResultClass::Error,
+                    error_kind: Some(ErrorKind::Skipped),
+                    exit_code: None,
+                    encoding: None,
+                    output_hash: None,
+                    discarded: None,
+                    ..base
+                },
+            };
+            redact_response(&mut response, props);
+            response
+        })
+        .collect()
+}
+
+/// Redacts the texts of `response` a host may have printed secrets into, unless responses
+/// stay unredacted. Every writer of responses gets them through here.
+fn redact_response(response: &mut Response, props: &ParallelSshProps) {
+    let redactor = match &props.redactor {
+        Some(redactor) if !props.unredacted_responses => redactor,
+        _ => return,
+    };
+    let mut count = redactor.redact(&mut response.result);
+    count += redactor.redact(&mut response.stderr);
+    count += redactor.redact(&mut response.command);
+    if let Some(check) = &mut response.skip_check {
+        count += redactor.redact(&mut check.marker);
+    }
+    if let Some(guard) = &mut response.guard {
+        count += redactor.redact(&mut guard.output);
+    }
+    if let Some(condition) = &mut response.post_condition {
+        count += redactor.redact(&mut condition.last_result);
+    }
+    if let Some(passed) = &response.passed_through {
+        count += passed.redactions.unwrap_or(0);
+    }
+    response.redactions = Some(count);
+}
+
+/// Name of a host in its response: the name given with its address, the target connected
+/// to, or the host as given when it did not resolve.
+fn response_name(host: &HostTarget, target: &Result<Target, HostError>) -> String {
+    match (host.display_name(), target) {
+        (Some(name), _) => name.to_string(),
+        (None, Ok(t)) => t.to_string(),
+        (None, Err(_)) => host.to_string(),
+    }
+}
+
+/// Suspected misconfiguration when every canary host failed the same way on auth.
+fn canary_verdict(outcomes: &[Option<Result<(), ErrorKind>>]) -> Option<String> {
+    let mut kinds = outcomes.iter().flatten();
+    let kind = match kinds.next()? {
+        Err(kind) if *kind == ErrorKind::Auth || *kind == ErrorKind::Agent => *kind,
+        _ => return None,
+    };
+    if !kinds.all(|outcome| *outcome == Err(kind)) {
+        return None;
+    }
+    let cause = if kind == ErrorKind::Agent {
+        "the ssh-agent is unreachable or offers no usable key"
+    } else {
+        "the user or key is probably wrong"
+    };
+    Some(format!(
+        "all {} canary hosts failed with {}: {}",
+        outcomes.len(),
+        kind,
+        cause
+    ))
+}
+
+impl ParallelSshProps {
+    pub fn parallel_ssh_process<A: 'static, C, I: 'static>(&self, hosts: I) -> Result<(), RunError>
+    where
+        A: IntoTarget,
+        C: Into<RemoteCommand>,
+        I: IntoIterator<Item = (A, C)> + std::marker::Send,
+    {
+        self.start_run();
+        let (tx, rx) = bounded(self.tcp_threads_number as usize * 2);
+        let props = self.clone();
+        spawn(move || {
+            let hosts = hosts
+                .into_iter()
+                .map(|(host, command)| (host, command, HostOptions::default()));
+            check_hosts(stream::iter(hosts), &props, tx)
+        });
+        self.process_checked(rx)
+    }
+
+    /// Like `parallel_ssh_process`, with per-host overrides of the props' settings.
+    pub fn parallel_ssh_process_with_options<A: 'static, C, I: 'static>(
+        &self,
+        hosts: I,
+    ) -> Result<(), RunError>
+    where
+        A: IntoTarget,
+        C: Into<RemoteCommand>,
+        I: IntoIterator<Item = (A, C, HostOptions)> + std::marker::Send,
+    {
+        self.start_run();
+        let (tx, rx) = bounded(self.tcp_threads_number as usize * 2);
+        let props = self.clone();
+        spawn(move || match &props.class_weights {
+            Some(weights) => {
+                let hosts = classes::interleave(hosts.into_iter().collect(), weights, |host| {
+                    host.2.class.as_deref()
+                });
+                check_hosts(stream::iter(hosts), &props, tx)
+            }
+            None => check_hosts(stream::iter(hosts), &props, tx),
+        });
+        self.process_checked(rx)
+    }
+
+    /// Runs `template` on hosts given with their vars, rendered for each host as
+    /// `ParallelSshPropsBuilder::templated` says, e.g. `systemctl restart {{ service }}`
+    /// with a `service` var per host. The vars are also the hosts' `HostOptions::vars`.
+    pub fn parallel_ssh_templated<A: 'static, I>(
+        &self,
+        hosts: I,
+        template: &str,
+    ) -> Result<(), RunError>
+    where
+        A: IntoTarget,
+        I: IntoIterator<Item = (A, BTreeMap<String, String>)>,
+        I::IntoIter: Send + 'static,
+    {
+        let props = ParallelSshProps {
+            templated: true,
+            ..self.clone()
+        };
+        let template = RemoteCommand::raw(template);
+        let hosts = hosts.into_iter().map(move |(host, vars)| {
+            let options = HostOptions {
+                vars,
+                ..HostOptions::default()
+            };
+            (host, template.clone(), options)
+        });
+        props.parallel_ssh_process_with_options(hosts)
+    }
+
+    /// Copies `local_path` to `remote_path` on `hosts` over SFTP instead of running a
+    /// command, replacing the file. Each host gets a response with an `upload` report of
+    /// the bytes sent and their sha256, compared with the host's copy when it has
+    /// `sha256sum`; a copy that differs fails with `ErrorKind::ChecksumMismatch`.
+    ///
+    /// A relative `remote_path` is relative to the login directory, and the file is
+    /// written as the login user, without `become`. The skip check and guard of the props
+    /// still decide whether a host gets the file; post conditions are not waited for.
+    pub fn parallel_upload<A: 'static, I>(
+        &self,
+        hosts: I,
+        local_path: &Path,
+        remote_path: &str,
+    ) -> Result<(), RunError>
+    where
+        A: IntoTarget,
+        I: IntoIterator<Item = A>,
+        I::IntoIter: Send + 'static,
+    {
+        let transfer = Transfer::Upload {
+            local_path: local_path.to_path_buf(),
+            remote_path: remote_path.to_string(),
+        };
+        let command = format!("upload {} {}", local_path.display(), remote_path);
+        self.transfer(hosts, transfer, command)
+    }
+
+    /// Copies `remote_path` from `hosts` over SFTP into `local_dir/<host>/`, under its file
+    /// name, instead of running a command. `<host>` is the name of the host's response with
+    /// characters unsafe in a path replaced by `_`; hosts whose names end up the same get
+    /// `-2`, `-3`, ... appended in the order they finish connecting.
+    ///
+    /// Each host gets a response with a `fetch` report, as for `parallel_upload`. Hosts
+    /// fail on their own: a file is only saved once complete and verified, so a host that
+    /// failed leaves no file, and the files of the others are kept.
+    pub fn parallel_fetch<A: 'static, I>(
+        &self,
+        hosts: I,
+        remote_path: &str,
+        local_dir: &Path,
+    ) -> Result<(), RunError>
+    where
+        A: IntoTarget,
+        I: IntoIterator<Item = A>,
+        I::IntoIter: Send + 'static,
+    {
+        let transfer = Transfer::Fetch {
+            remote_path: remote_path.to_string(),
+            local_dir: local_dir.to_path_buf(),
+            dirs: Default::default(),
+        };
+        let command = format!("fetch {} {}", remote_path, local_dir.display());
+        self.transfer(hosts, transfer, command)
+    }
+
+    /// Runs `transfer` on `hosts`, `command` standing for it in their responses.
+    fn transfer<A: 'static, I>(
+        &self,
+        hosts: I,
+        transfer: Transfer,
+        command: String,
+    ) -> Result<(), RunError>
+    where
+        A: IntoTarget,
+        I: IntoIterator<Item = A>,
+        I::IntoIter: Send + 'static,
+    {
+        let props = ParallelSshProps {
+            transfer: Some(Arc::new(transfer)),
+            ..self.clone()
+        };
+        let hosts = hosts.into_iter().map(move |host| (host, command.clone()));
+        props.parallel_ssh_process(hosts)
+    }
+
+    /// Runs `command` on hosts as `hosts` yields them, returning once the stream has ended
+    /// and every host is done.
+    ///
+    /// The stream is only polled when a worker is about to become free, so a fast source
+    /// is not buffered ahead of the run. As the number of hosts is not known up front,
+    /// `ProgressTracker::started` gives the count so far.
+    pub fn parallel_ssh_process_stream<A: 'static, C, S: 'static>(
+        &self,
+        hosts: S,
+        command: C,
+    ) -> Result<(), RunError>
+    where
+        A: IntoTarget,
+        C: Into<RemoteCommand>,
+        S: Stream<Item = A> + Send,
+    {
+        self.start_run();
+        let (tx, rx) = bounded(1);
+        let props = self.clone();
+        let command = command.into();
+        spawn(move || {
+            let hosts = hosts.map(move |host| (host, command.clone(), HostOptions::default()));
+            check_hosts(hosts, &props, tx)
+        });
+        self.process_checked(rx)
+    }
+
+    /// Like `parallel_ssh_process`, returning the responses of this run as a stream, in the
+    /// order the hosts finish, instead of sending them to the props' own stream.
+    ///
+    /// The run goes on in the background, hosts starting as the props' limits allow
+    /// whether or not the stream is polled. Dropping the stream cancels the rest of the
+    /// run: hosts not started yet fail with `ErrorKind::Cancelled` without being connected
+    /// to, and hosts in flight finish. The stream ends once every host has its response; a
+    /// `RunError` of the run is not reported, e.g. no hosts make an empty stream.
+    pub fn parallel_ssh_responses<A: 'static, C, I: 'static>(
+        &self,
+        hosts: I,
+    ) -> impl Stream<Item = Response> + Unpin
+    where
+        A: IntoTarget,
+        C: Into<RemoteCommand>,
+        I: IntoIterator<Item = (A, C)> + std::marker::Send,
+    {
+        let (tx, rx) = unbounded();
+        let props = ParallelSshProps {
+            sender: tx,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            ..self.clone()
+        };
+        let cancelled = props.cancelled.clone();
+        let (responses, stream) = futures::channel::mpsc::unbounded();
+        spawn(move || props.parallel_ssh_process(hosts));
+        spawn(move || {
+            for response in rx {
+                if responses.unbounded_send(response).is_err() {
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+            }
+        });
+        stream
+    }
+
+    /// Runs `commands` one after the other on each host over a single session, so the
+    /// handshake and authentication are paid once per host rather than once per command.
+    /// Returns the responses of each host, one per command in order, the hosts in the
+    /// order they finish; the props' own stream does not get them.
+    ///
+    /// Every command gets its own channel and response, and runs whatever the exit code of
+    /// the one before. A command that cannot be started or read ends the sequence, as do
+    /// the skip check and guard of the props skipping the host; the commands left over get
+    /// `ErrorKind::Skipped`. Retries repeat the whole sequence, and only when the first
+    /// command fails; the post condition is waited for after the last command. No
+    /// commands make an empty result without connecting, and a `RunError` drops the
+    /// responses.
+    pub fn run_commands<A: 'static, I>(
+        &self,
+        hosts: I,
+        commands: Vec<String>,
+    ) -> Result<Vec<Vec<Response>>, RunError>
+    where
+        A: IntoTarget,
+        I: IntoIterator<Item = A>,
+        I::IntoIter: Send + 'static,
+    {
+        let mut commands = commands.into_iter();
+        let first = match commands.next() {
+            Some(first) => first,
+            None => return Ok(Vec::new()),
+        };
+        let (tx, rx) = unbounded();
+        let props = ParallelSshProps {
+            sender: tx,
+            followups: Some(Arc::new(commands.collect())),
+            ..self.clone()
+        };
+        let hosts = hosts
+            .into_iter()
+            .map(move |host| (host, RemoteCommand::raw(first.as_str())));
+        props.parallel_ssh_process(hosts)?;
+        let mut by_host: Vec<Vec<Response>> = Vec::new();
+        let mut index: HashMap<String, usize> = HashMap::new();
+        for response in rx.try_iter() {
+            let i = *index.entry(response.hostname.clone()).or_insert_with(|| {
+                by_host.push(Vec::new());
+                by_host.len() - 1
+            });
+            by_host[i].push(response);
+        }
+        Ok(by_host)
+    }
+
+    /// Runs `command` on `hosts`, sending each response into `sink` as it completes and
+    /// closing the sink at the end. Resolves to the totals of the run.
+    ///
+    /// Any `Sink` works, e.g. a `futures::channel::mpsc::Sender<Response>`. The props'
+    /// own stream does not get these responses. When the sink fails, e.g. because its
+    /// receiver was dropped, the rest of the run is cancelled: hosts not started yet fail
+    /// with `ErrorKind::Cancelled` without being connected to, and hosts in flight finish.
+    pub async fn run_into_channel<A: 'static, C, I, S>(
+        &self,
+        hosts: I,
+        command: C,
+        mut sink: S,
+    ) -> RunSummary
+    where
+        A: IntoTarget,
+        C: Into<RemoteCommand>,
+        I: IntoIterator<Item = A>,
+        I::IntoIter: Send + 'static,
+        S: Sink<Response> + Unpin,
+    {
+        let (mut responses, cancelled) = self.channel_run(hosts, command);
+        let mut summary = RunSummary::default();
+        let mut sink_open = true;
+        while let Some(response) = responses.next().await {
+            summary.record(&response);
+            if sink_open && sink.send(response).await.is_err() {
+                sink_open = false;
+                cancelled.store(true, Ordering::Relaxed);
+            }
+        }
+        if sink_open {
+            let _ = sink.close().await;
+        }
+        summary
+    }
+
+    /// Like `run_into_channel`, for a tokio channel. The sender is dropped at the end,
+    /// which closes the channel once its clones are gone too.
+    #[cfg(feature = "tokio")]
+    pub async fn run_into_tokio_channel<A, C, I>(
+        &self,
+        hosts: I,
+        command: C,
+        sender: tokio::sync::mpsc::Sender<Response>,
+    ) -> RunSummary
+    where
+        A: IntoTarget + 'static,
+        C: Into<RemoteCommand>,
+        I: IntoIterator<Item = A>,
+        I::IntoIter: Send + 'static,
+    {
+        let (mut responses, cancelled) = self.channel_run(hosts, command);
+        let mut summary = RunSummary::default();
+        let mut sender = Some(sender);
+        while let Some(response) = responses.next().await {
+            summary.record(&response);
+            if let Some(open) = &sender {
+                if open.send(response).await.is_err() {
+                    sender = None;
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+            }
+        }
+        summary
+    }
+
+    /// Starts `command` on `hosts` in the background, returning its responses and the
+    /// flag cancelling the rest of the run.
+    ///
+    /// A single thread moves the responses from the props' channel into the returned
+    /// one, so awaiting them holds no thread.
+    fn channel_run<A, C, I>(
+        &self,
+        hosts: I,
+        command: C,
+    ) -> (
+        futures::channel::mpsc::UnboundedReceiver<Response>,
+        Arc<AtomicBool>,
+    )
+    where
+        A: IntoTarget + 'static,
+        C: Into<RemoteCommand>,
+        I: IntoIterator<Item = A>,
+        I::IntoIter: Send + 'static,
+    {
+        let (tx, rx) = unbounded();
+        let props = ParallelSshProps {
+            sender: tx,
+            cancelled: Arc::new(AtomicBool::new(false)),
+            ..self.clone()
+        };
+        let cancelled = props.cancelled.clone();
+        let command = command.into().to_string();
+        let hosts = hosts.into_iter().map(move |host| (host, command.clone()));
+        let (responses, stream) = futures::channel::mpsc::unbounded();
+        spawn(move || props.parallel_ssh_process(hosts));
+        spawn(move || {
+            for response in rx {
+                // The receiver lives until the run is over.
+                let _ = responses.unbounded_send(response);
+            }
+        });
+        (stream, cancelled)
+    }
+
+    /// Checks the auth chain, the host key store, that `hosts` is non-empty and that its
+    /// first host resolves.
+    ///
+    /// Meant to run before `parallel_ssh_process`, so a broken setup fails once rather than
+    /// once per host.
+    pub fn preflight<A: IntoTarget + Clone>(&self, hosts: &[A]) -> PreflightReport {
+        let mut report = PreflightReport::default();
+        preflight::check_auth_chain(&mut report, &self.auth_chain);
+        let first = match hosts.first() {
+            Some(host) => host.clone().into_target(),
+            None => {
+                report.push("inventory", CheckStatus::Fail, "No hosts to run on");
+                return report;
+            }
+        };
+        report.push(
+            "inventory",
+            CheckStatus::Pass,
+            format!("{} hosts", hosts.len()),
+        );
+        if let Some(store) = &self.host_key_store {
+            let keys: Vec<String> = hosts
+                .iter()
+                .filter_map(|host| match host.clone().into_target() {
+                    HostTarget::Address { address, .. } => Some(address.to_string()),
+                    HostTarget::Name(name) => {
+                        name.parse::<SocketAddr>().ok().map(|a| a.to_string())
+                    }
+                })
+                .collect();
+            preflight::check_host_keys(&mut report, store, &keys);
+        }
+        let first = match first {
+            HostTarget::Name(name) if name.parse::<SocketAddr>().is_err() => name,
+            _ => {
+                report.push("dns", CheckStatus::Pass, "Hosts are given by address");
+                return report;
+            }
+        };
+        if self.proxy.as_ref().map_or(false, |p| p.remote_dns) {
+            report.push("dns", CheckStatus::Pass, "Names are resolved by the proxy");
+        } else {
+            match self.dns_cache.resolve(&first) {
+                Ok(addr) => report.push(
+                    "dns",
+                    CheckStatus::Pass,
+                    format!("{} resolved to {}", first, addr),
+                ),
+                Err(e) => report.push("dns", CheckStatus::Fail, format!("{}: {}", first, e)),
+            }
+        }
+        report
+    }
+
+    /// What the commands of `host` need when started with become, `None` when they need
+    /// nothing.
+    pub(crate) fn escalation(&self, host: &str) -> Result<Option<Escalation>, HostError> {
+        if !self.become_root {
+            return Ok(None);
+        }
+        let password = match &self.become_password {
+            Some(provider) => Some(provider(host).map_err(|e| {
+                HostError::new(
+                    ErrorKind::Become,
+                    format!("No become password for {}: {}", host, e),
+                )
+            })?),
+            None => None,
+        };
+        if password.is_none() && !self.become_pty {
+            return Ok(None);
+        }
+        Ok(Some(Escalation {
+            password,
+            pty: self.become_pty,
+        }))
+    }
+
+    /// Clears what the failure threshold counted in a previous run of the props.
+    fn start_run(&self) {
+        if let Some(breaker) = &self.failure_breaker {
+            breaker.reset();
+        }
+    }
+
+    /// Whether hosts are no longer started, because the run was cancelled or aborted by
+    /// its failure threshold.
+    pub(crate) fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed)
+            || self.failure_breaker.as_ref().map_or(false, |b| b.tripped())
+    }
+
+    /// Error of the hosts not started once `is_cancelled`.
+    pub(crate) fn cancellation(&self) -> Option<HostError> {
+        if self.cancelled.load(Ordering::Relaxed) {
+            return Some(HostError::new(
+                ErrorKind::Cancelled,
+                "The run was cancelled",
+            ));
+        }
+        let reason = self.failure_breaker.as_ref()?.reason()?;
+        Some(HostError::new(
+            ErrorKind::Cancelled,
+            format!("Not run, {}", reason),
+        ))
+    }
+
+    /// Cancels the runs of these props and their clones: hosts not started yet fail with
+    /// `ErrorKind::Cancelled` without being connected to, and hosts in flight finish.
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    /// Id recorded on every response of the stream.
+    pub fn run_id(&self) -> &str {
+        &self.run_id
+    }
+
+    /// Preset the props were built from, if any.
+    pub fn preset(&self) -> Option<Preset> {
+        self.preset
+    }
+
+    /// Hosts currently being processed, longest running first.
+    pub fn in_flight(&self) -> Vec<ProgressEvent> {
+        self.progress.in_flight()
+    }
+
+    /// Progress tracking shared by all props of a stream.
+    pub fn progress(&self) -> Arc<ProgressTracker> {
+        self.progress.clone()
+    }
+
+    /// Handle to change the limits of these props while they run.
+    pub fn handle(&self) -> RunHandle {
+        self.limits.clone()
+    }
+
+    /// Hits and misses of the name resolution cache, shared by all props of a stream.
+    pub fn dns_cache_stats(&self) -> DnsCacheStats {
+        self.dns_cache.stats()
+    }
+
+    /// Runs the checked hosts on the pool.
+    ///
+    /// With canary hosts set, the first ones run to completion before the others start;
+    /// when they all failed the same way on auth, the others are skipped and the run fails.
+    fn process_checked(&self, rx: Receiver<CheckedHost>) -> Result<(), RunError> {
+        let dedup = if self.deduplicate {
+            Some(Deduplicator::default())
+        } else {
+            None
+        };
+        let run = |(host, command, args, options, ip, timings): CheckedHost| {
+            let _slot = self.limits.acquire();
+            let outcome = process_host(
+                host,
+                ip,
+                timings,
+                command,
+                args,
+                options,
+                self,
+                dedup.as_ref(),
+            );
+            if let (Some(breaker), Some(result)) = (&self.failure_breaker, &outcome) {
+                breaker.record(result.err());
+            }
+            outcome
+        };
+        let first = match rx.recv() {
+            Ok(host) => host,
+            Err(_) if self.allow_empty => return Ok(()),
+            Err(_) => return Err(self.host_selection.into()),
+        };
+        let mut hosts = std::iter::once(first).chain(rx);
+        let result = self.workers.install(|| {
+            if self.canary_hosts > 0 {
+                let canaries: Vec<CheckedHost> = hosts.by_ref().take(self.canary_hosts).collect();
+                let outcomes: Vec<_> = canaries.into_par_iter().map(run).collect();
+                if let Some(reason) = canary_verdict(&outcomes) {
+                    self.skip_hosts(hosts, &reason);
+                    return Err(RunError::CanaryFailed(reason));
+                }
+            }
+            match self.serial {
+                Some(serial) => self.run_batches(serial, hosts, &run),
+                None => {
+                    hosts.par_bridge().for_each(|host| {
+                        run(host);
+                    });
+                    Ok(())
+                }
+            }
+        });
+        match self.failure_breaker.as_ref().and_then(|b| b.reason()) {
+            Some(reason) => result.and(Err(RunError::TooManyFailures(reason))),
+            None => result,
+        }
+    }
+
+    /// Runs `hosts` in the batches of `serial`, pausing and asking the batch hook between
+    /// them.
+    fn run_batches<I, F>(&self, serial: Serial, hosts: I, run: &F) -> Result<(), RunError>
+    where
+        I: Iterator<Item = CheckedHost> + Send,
+        F: Fn(CheckedHost) -> Option<Result<(), ErrorKind>> + Sync + Send,
+    {
+        let (size, batches, hosts): (_, _, Box<dyn Iterator<Item = CheckedHost> + Send>) =
+            match serial {
+                Serial::Hosts(n) => (n, None, Box::new(hosts)),
+                Serial::Percent(_) => {
+                    let all: Vec<CheckedHost> = hosts.collect();
+                    let size = serial.batch_size(all.len());
+                    let batches = (all.len() + size - 1) / size;
+                    (size, Some(batches), Box::new(all.into_iter()))
+                }
+            };
+        let mut hosts = hosts.peekable();
+        let mut batch = 0;
+        while hosts.peek().is_some() {
+            batch += 1;
+            let batch_hosts: Vec<CheckedHost> = hosts.by_ref().take(size).collect();
+            let outcomes: Vec<_> = batch_hosts.into_par_iter().map(run).collect();
+            if hosts.peek().is_none() {
+                break;
+            }
+            let report = BatchReport {
+                batch,
+                batches,
+                hosts: outcomes.len(),
+                failed: outcomes
+                    .iter()
+                    .filter(|outcome| matches!(outcome, Some(Err(_))))
+                    .count(),
+            };
+            if let Some(pause) = self.batch_pause {
+                thread::sleep(pause);
+            }
+            if let Some(hook) = &self.batch_hook {
+                if !hook(&report) {
+                    let reason = format!("stopped after batch {}", batch);
+                    self.skip_hosts(hosts, &reason);
+                    return Err(RunError::Stopped(reason));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Sends a response for each of `hosts` without running them, skipped for `reason`.
+    fn skip_hosts<I>(&self, hosts: I, reason: &str)
+    where
+        I: Iterator<Item = CheckedHost> + Send,
+    {
+        let skipped = HostError::new(ErrorKind::Skipped, format!("Not run, {}", reason));
+        hosts
+            .par_bridge()
+            .for_each(|(host, command, args, options, _, timings)| {
+                let ip = Err(skipped.clone());
+                process_host(host, ip, timings, command, args, options, self, None);
+            });
+    }
+
+    /// Sends a host's response to the stream, and as its `Finished` event to the event
+    /// stream.
+    pub(crate) fn send_response(&self, response: Response) {
+        if let Some(events) = &self.events {
+            let _ = events.send(RunEvent::Finished(response.clone()));
+        }
+        if let Err(e) = self.sender.send(response) {
+            eprintln!("Error sending to channel: {}", e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn build(builder: &mut ParallelSshPropsBuilder) -> Result<ParallelSshProps, String> {
+        builder.build().map(|(_, props)| props)
+    }
+
+    #[test]
+    fn conflicting_settings_are_rejected() {
+        let mut builder = ParallelSshPropsBuilder::default();
+        builder.tcp_connections_pool(4).max_concurrent_reads(5);
+        assert!(build(&mut builder)
+            .err()
+            .unwrap()
+            .starts_with("max_concurrent_reads (5)"));
+        builder.max_concurrent_reads(0);
+        assert!(build(&mut builder).is_err());
+
+        for connections in &[0, -1] {
+            let mut builder = ParallelSshPropsBuilder::default();
+            builder.tcp_connections_pool(*connections);
+            assert_eq!(
+                build(&mut builder).err().unwrap(),
+                "maximum_connections must be at least 1"
+            );
+        }
+
+        let mut builder = ParallelSshPropsBuilder::default();
+        builder.become_user(String::new());
+        assert_eq!(build(&mut builder).err().unwrap(), "become_user is empty");
+
+        let mut builder = ParallelSshPropsBuilder::default();
+        builder
+            .become_method(BecomeMethod::Doas)
+            .become_password(Arc::new(|_: &str| Ok("secret".to_string())));
+        assert!(build(&mut builder).is_err());
+
+        let mut builder = ParallelSshPropsBuilder::default();
+        builder
+            .keep_output(OutputKeep::Tail(10))
+            .pass_through_output(OutputPassThrough::Dir("out".into()));
+        assert!(build(&mut builder).is_err());
+
+        let mut weights = BTreeMap::new();
+        weights.insert("db".to_string(), 0);
+        let mut builder = ParallelSshPropsBuilder::default();
+        builder.class_weights(weights);
+        assert_eq!(
+            build(&mut builder).err().unwrap(),
+            "weight of class db must be at least 1"
+        );
+    }
+
+    #[test]
+    fn workers_follow_the_connection_pool() {
+        let props = build(ParallelSshPropsBuilder::default().tcp_connections_pool(3)).unwrap();
+        assert_eq!(props.workers.current_num_threads(), 3);
+        // Clones of the props share the pool.
+        assert!(Arc::ptr_eq(&props.workers, &props.clone().workers));
+    }
+
+    #[test]
+    fn agent_pool_is_shared_by_props_sharing_the_stream() {
+        assert_eq!(
+            build(ParallelSshPropsBuilder::default().agent_connections_pool(0))
+                .err()
+                .unwrap(),
+            "agent_connections_pool must be at least 1"
+        );
+        let props = build(ParallelSshPropsBuilder::default().agent_connections_pool(2)).unwrap();
+        let group = ParallelSshPropsBuilder::default()
+            .agent_connections_pool(5)
+            .build_sharing_stream(&props)
+            .unwrap();
+        assert!(Arc::ptr_eq(
+            &props.agent_connections_pool,
+            &group.agent_connections_pool
+        ));
+    }
+
+    #[test]
+    fn escalation_is_only_set_up_when_needed() {
+        let props = build(&mut ParallelSshPropsBuilder::default()).unwrap();
+        assert!(props.escalation("web-1").unwrap().is_none());
+
+        let props = build(ParallelSshPropsBuilder::default().become_root(true)).unwrap();
+        assert!(props.escalation("web-1").unwrap().is_none());
+
+        let props = build(
+            ParallelSshPropsBuilder::default()
+                .become_root(true)
+                .become_password(Arc::new(|host: &str| match host {
+                    "web-1" => Ok("secret".to_string()),
+                    _ => Err("unknown host".to_string()),
+                })),
+        )
+        .unwrap();
+        let escalation = props.escalation("web-1").unwrap().unwrap();
+        assert_eq!(escalation.password.as_deref(), Some("secret"));
+        let error = props.escalation("db-1").err().unwrap();
+        assert_eq!(error.kind, ErrorKind::Become);
+        assert!(error.message.contains("unknown host"), "{}", error.message);
+    }
+
+    #[test]
+    fn cancelled_props_stay_cancelled() {
+        let props = build(&mut ParallelSshPropsBuilder::default()).unwrap();
+        assert!(!props.is_cancelled());
+        props.clone().cancel();
+        assert!(props.is_cancelled());
+        props.start_run();
+        assert!(props.is_cancelled());
+    }
+}
@@ -0,0 +1,41 @@
+use std::sync::{Condvar, Mutex};
+
+/// Counting semaphore for the worker threads hosts run on.
+///
+/// Host work is blocking libssh2 I/O on rayon and plain threads, so waiting blocks the
+/// thread; no executor is involved, and none must call `access` from an async task.
+pub(crate) struct Semaphore {
+    available: Mutex<usize>,
+    released: Condvar,
+}
+
+impl Semaphore {
+    pub(crate) fn new(permits: usize) -> Self {
+        Semaphore {
+            available: Mutex::new(permits),
+            released: Condvar::new(),
+        }
+    }
+
+    /// Waits for a permit, held until the returned guard is dropped.
+    pub(crate) fn access(&self) -> SemaphoreGuard<'_> {
+        let mut available = self.available.lock().unwrap();
+        while *available == 0 {
+            available = self.released.wait(available).unwrap();
+        }
+        *available -= 1;
+        SemaphoreGuard { semaphore: self }
+    }
+}
+
+/// Permit of a `Semaphore`, given back on drop.
+pub(crate) struct SemaphoreGuard<'a> {
+    semaphore: &'a Semaphore,
+}
+
+impl Drop for SemaphoreGuard<'_> {
+    fn drop(&mut self) {
+        *self.semaphore.available.lock().unwrap() += 1;
+        self.semaphore.released.notify_one();
+    }
+}
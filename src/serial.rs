@@ -0,0 +1,107 @@
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::{self, Display};
+use std::str::FromStr;
+use std::sync::Arc;
+
+/// Size of the batches a serial run starts its hosts in, see
+/// `ParallelSshPropsBuilder::serial`. In the config, `serial = 10` or `serial = "25%"`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Serial {
+    Hosts(usize),
+    /// Percentage of the hosts of the run, rounded up to at least one host. The hosts are
+    /// all resolved and probed before the first batch starts, to count them.
+    Percent(f64),
+}
+
+impl Serial {
+    pub fn validate(&self) -> Result<(), String> {
+        match *self {
+            Serial::Hosts(0) => Err("serial must be at least 1 host".to_string()),
+            Serial::Percent(p) if !(p > 0.0 && p <= 100.0) => Err(format!(
+                "serial must be above 0% and at most 100%, got {}%",
+                p
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Hosts per batch out of `total`.
+    pub(crate) fn batch_size(&self, total: usize) -> usize {
+        match *self {
+            Serial::Hosts(n) => n,
+            Serial::Percent(p) => ((total as f64 * p / 100.0).ceil() as usize).max(1),
+        }
+    }
+}
+
+impl FromStr for Serial {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let serial = match s.trim().strip_suffix('%') {
+            Some(p) => Serial::Percent(
+                p.trim()
+                    .parse()
+                    .map_err(|_| format!("invalid serial {}", s))?,
+            ),
+            None => Serial::Hosts(
+                s.trim()
+                    .parse()
+                    .map_err(|_| format!("invalid serial {}", s))?,
+            ),
+        };
+        serial.validate()?;
+        Ok(serial)
+    }
+}
+
+impl Display for Serial {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Serial::Hosts(n) => write!(f, "{}", n),
+            Serial::Percent(p) => write!(f, "{}%", p),
+        }
+    }
+}
+
+impl Serialize for Serial {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        match self {
+            Serial::Hosts(n) => serializer.serialize_u64(*n as u64),
+            Serial::Percent(_) => serializer.serialize_str(&self.to_string()),
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for Serial {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Hosts(u64),
+            Text(String),
+        }
+        let serial = match Raw::deserialize(deserializer)? {
+            Raw::Hosts(n) => Serial::Hosts(n as usize),
+            Raw::Text(s) => return s.parse().map_err(serde::de::Error::custom),
+        };
+        serial.validate().map_err(serde::de::Error::custom)?;
+        Ok(serial)
+    }
+}
+
+/// How a batch of a serial run went, as handed to the batch hook.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BatchReport {
+    /// 1-based number of the batch.
+    pub batch: usize,
+    /// Number of batches of the run, when the number of hosts is known up front.
+    pub batches: Option<usize>,
+    pub hosts: usize,
+    /// Hosts of the batch which did not succeed.
+    pub failed: usize,
+}
+
+/// Asked after each batch but the last, past the batch pause, whether to go on with the
+/// next one; `false` skips the remaining hosts.
+pub type BatchHook = Arc<dyn Fn(&BatchReport) -> bool + Send + Sync>;
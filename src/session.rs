@@ -0,0 +1,1129 @@
+use crate::auth::{self, AuthMethod};
+use crate::command::RemoteCommand;
+use crate::diagnostics::{timed, Step, StepLog};
+use crate::escalation::Escalation;
+use crate::events::RunEvent;
+use crate::failure_threshold::FailureBreaker;
+use crate::guard::GuardResult;
+use crate::inventory::{check_bind, check_host, prepare_command, HostOptions};
+use crate::known_hosts::{self, HostKeyInfo, HostKeyPolicy};
+use crate::local::{run_local, LocalProcess};
+use crate::output::{
+    DiscardedOutput, OutputCollector, PassThroughEnd, PassThroughWriter, PassedThrough,
+};
+use crate::output_hash::OutputHasher;
+use crate::post_condition::{PostCondition, PostConditionResult};
+use crate::progress::{HostProgress, Permit, Phase};
+use crate::proxy::{self, Target};
+use crate::pty::{strip_ansi, PtyRequest};
+use crate::response::{CommandOutput, ConnectionInfo, ErrorKind, HostError, HostTimings};
+use crate::scheduler::ParallelSshProps;
+use crate::session_pool::PooledSession;
+use crate::sftp::{FetchReport, Transfer, TransferReport, UploadReport};
+use crate::shell::{check_no_nul, RemoteShell};
+use crate::skip_check::SkipCheckResult;
+use crate::socket;
+use crate::target::IntoTarget;
+use crate::timeouts::{Timeouts, DEFAULT_PHASE_TIMEOUT};
+use crate::transport::{ssh2_error_kind, Transport, TransportErrorKind};
+use smol::io;
+use ssh2::{Channel, MethodType, PtyModeOpcode, PtyModes, Session};
+use std::io::{Read, Write};
+use std::net::{SocketAddr, TcpStream};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Authenticated SSH connection to one host, for running several commands over it.
+///
+/// libssh2 serializes all I/O of a session, so commands are not read from in parallel.
+/// What `run_concurrent` does instead is start up to `channel_parallelism` commands at
+/// once on their own channels, so they run side by side on the host, and then collect
+/// their output one channel after the other. A command producing more output than the
+/// channel window holds is stalled by the host until its turn to be read comes.
+pub struct HostSession {
+    pub(crate) sess: Session,
+    connection: ConnectionInfo,
+    server_banner: Option<String>,
+    auth_banner: Option<String>,
+    host_key: Option<HostKeyInfo>,
+    pub(crate) shell: RemoteShell,
+    pub(crate) workdir: Option<String>,
+    pub(crate) escalation: Option<Escalation>,
+    pub(crate) props: ParallelSshProps,
+}
+
+impl ParallelSshProps {
+    /// Connects to and authenticates with `host`, applying `options` like a run would.
+    pub fn open_session<A>(&self, host: A, options: HostOptions) -> Result<HostSession, HostError>
+    where
+        A: IntoTarget,
+    {
+        let host = host.into_target();
+        let escalation = self.escalation(&host.to_string())?;
+        let target = smol::run(check_host(
+            &host,
+            self.proxy.as_ref(),
+            &self.dns_cache,
+            None,
+            self.timeout_socket,
+            &mut HostTimings::default(),
+        ))
+        .and_then(|t| check_bind(t, self))?;
+        let tcp = connect_tcp(&target, self, &self.timeouts)?;
+        let local_addr = tcp.local_addr().ok();
+        let mut server_banner = None;
+        let sess = handshake(tcp, self, &self.timeouts, &mut server_banner)?;
+        let mut host_key = None;
+        verify_host_key(&sess, &target, self, &mut host_key)?;
+        let settings = self.host_settings(&options);
+        let connection = authenticate(
+            &sess,
+            settings.user,
+            &settings.auth_chain,
+            self,
+            &self.timeouts,
+            local_addr,
+            None,
+        )?;
+        Ok(HostSession {
+            auth_banner: auth_banner(&sess),
+            sess,
+            connection,
+            server_banner,
+            host_key,
+            shell: settings.remote_shell,
+            workdir: settings.workdir.map(str::to_string),
+            escalation,
+            props: self.clone(),
+        })
+    }
+}
+
+impl HostSession {
+    pub fn connection(&self) -> &ConnectionInfo {
+        &self.connection
+    }
+
+    pub fn server_banner(&self) -> Option<&str> {
+        self.server_banner.as_deref()
+    }
+
+    /// Banner the server sent during authentication, e.g. a legal notice.
+    pub fn auth_banner(&self) -> Option<&str> {
+        self.auth_banner.as_deref()
+    }
+
+    /// Fingerprint of the host key, when the props have a host key store.
+    pub fn host_key(&self) -> Option<&HostKeyInfo> {
+        self.host_key.as_ref()
+    }
+
+    /// Runs a single command, failing with `ErrorKind::ReadTotalTimeout` after `timeout`.
+    pub fn run<C: Into<RemoteCommand>>(
+        &self,
+        command: C,
+        timeout: Option<Duration>,
+    ) -> Result<CommandOutput, HostError> {
+        self.run_concurrent(vec![(command, timeout)])
+            .pop()
+            .expect("one result per command")
+    }
+
+    /// Runs `commands` side by side, returning one result per command in the same order.
+    ///
+    /// Each command's timeout counts from its start and is applied independently; a
+    /// command timing out fails alone. Without a timeout, the props' `read_total` limit
+    /// applies.
+    pub fn run_concurrent<C: Into<RemoteCommand>>(
+        &self,
+        commands: Vec<(C, Option<Duration>)>,
+    ) -> Vec<Result<CommandOutput, HostError>> {
+        let commands: Vec<(String, Option<Duration>)> = commands
+            .into_iter()
+            .map(|(command, timeout)| {
+                let command = command.into().to_string();
+                let command =
+                    prepare_command(command, self.shell, self.workdir.as_deref(), &self.props);
+                (command, timeout.or(self.props.timeouts.read_total))
+            })
+            .collect();
+        let mut results = Vec::with_capacity(commands.len());
+        for batch in commands.chunks(self.props.channel_parallelism.max(1)) {
+            let started: Vec<_> = batch
+                .iter()
+                .map(|(command, timeout)| {
+                    let deadline = timeout.map(|t| Instant::now() + t);
+                    start_command(
+                        &self.sess,
+                        command,
+                        &self.props.timeouts,
+                        self.props.request_pty.as_ref(),
+                        self.escalation.as_ref(),
+                    )
+                    .map(|channel| (channel, deadline))
+                })
+                .collect();
+            for start in started {
+                results.push(start.and_then(|(channel, deadline)| {
+                    finish_command(
+                        &self.sess,
+                        channel,
+                        self.shell,
+                        &self.props,
+                        deadline,
+                        None,
+                        None,
+                    )
+                }));
+            }
+        }
+        results
+    }
+}
+
+/// What a successful run on a host produced.
+pub(crate) struct HostOutput {
+    pub(crate) output: String,
+    pub(crate) stderr: String,
+    pub(crate) encoding: Option<&'static str>,
+    pub(crate) discarded: DiscardedOutput,
+    /// `None` when no command was run.
+    pub(crate) exit_code: Option<i32>,
+    pub(crate) connection: Option<ConnectionInfo>,
+    pub(crate) passed_through: Option<PassedThrough>,
+    pub(crate) output_hash: Option<String>,
+    pub(crate) upload: Option<UploadReport>,
+    pub(crate) fetch: Option<FetchReport>,
+}
+
+impl HostOutput {
+    /// Output of a host on which no command was run.
+    fn empty(connection: Option<ConnectionInfo>) -> Self {
+        HostOutput {
+            output: String::new(),
+            stderr: String::new(),
+            encoding: None,
+            discarded: DiscardedOutput::default(),
+            exit_code: None,
+            connection,
+            passed_through: None,
+            output_hash: None,
+            upload: None,
+            fetch: None,
+        }
+    }
+}
+
+/// What was learned about a host on the way to running its command.
+#[derive(Default)]
+pub(crate) struct HostFacts {
+    pub(crate) banner: Option<String>,
+    pub(crate) auth_banner: Option<String>,
+    pub(crate) host_key: Option<HostKeyInfo>,
+    pub(crate) skip_check: Option<SkipCheckResult>,
+    pub(crate) guard: Option<GuardResult>,
+    pub(crate) post_condition: Option<PostConditionResult>,
+    /// Timed steps and negotiated algorithms, only recorded for `run_single`.
+    pub(crate) steps: Option<StepLog>,
+    pub(crate) timings: HostTimings,
+    /// Results of the follow-up commands run so far, in order.
+    pub(crate) followups: Vec<FollowupOutput>,
+}
+
+/// What a follow-up command of `run_commands` produced.
+pub(crate) struct FollowupOutput {
+    pub(crate) duration: Duration,
+    pub(crate) result: Result<CommandOutput, HostError>,
+}
+
+/// Command lines of a host, with workdir, become and shell applied.
+#[derive(Clone)]
+pub(crate) struct HostCommands {
+    /// Command as given, before the workdir and shell are applied.
+    pub(crate) given: String,
+    pub(crate) command: String,
+    /// Check of the props' skip check, run first.
+    pub(crate) skip_check: Option<String>,
+    /// Check of the props' guard, run before `command`.
+    pub(crate) guard: Option<String>,
+    /// Post condition of the host, with its check command when it has one.
+    pub(crate) post_condition: Option<(PostCondition, Option<String>)>,
+    /// What the guard, command and post condition check need with become; the skip
+    /// check runs without.
+    pub(crate) escalation: Option<Escalation>,
+    /// Commands run after `command` over the same session, as given and prepared.
+    pub(crate) followups: Vec<(String, String)>,
+    /// Process run in instead of an SSH channel, for hosts not reached over SSH.
+    pub(crate) process: Option<LocalProcess>,
+}
+
+/// Runs the commands on `target`, the host named `name` in its response. Facts are stored
+/// in `facts` as soon as they are known, so they are kept even when a later step fails.
+/// No phase runs past `host_deadline`; one cut short fails with its own timeout kind.
+pub(crate) fn process_host_inner(
+    name: &str,
+    target: &Target,
+    commands: &HostCommands,
+    shell: RemoteShell,
+    user: &str,
+    auth_chain: &[AuthMethod],
+    props: &ParallelSshProps,
+    host_deadline: Option<Instant>,
+    facts: &mut HostFacts,
+    progress: &HostProgress,
+) -> Result<HostOutput, HostError> {
+    if let Some(process) = &commands.process {
+        return run_local(
+            process,
+            commands,
+            shell,
+            props,
+            host_deadline,
+            facts,
+            progress,
+        );
+    }
+    let HostFacts {
+        banner,
+        auth_banner: userauth_banner,
+        host_key,
+        skip_check: skip_result,
+        guard: guard_result,
+        post_condition: condition_result,
+        steps,
+        timings,
+        followups: followup_results,
+    } = facts;
+    followup_results.clear();
+    let pool = props.session_pool.as_deref().filter(|_| !props.banner_only);
+    let key = (target.to_string(), user.to_string());
+    let (sess, connection) = match pool.and_then(|pool| pool.checkout(&key)) {
+        Some(pooled) => {
+            *banner = pooled.banner;
+            *userauth_banner = pooled.auth_banner;
+            *host_key = pooled.host_key;
+            progress.set_phase(Phase::Running);
+            let connection = ConnectionInfo {
+                reused: true,
+                ..pooled.connection
+            };
+            (pooled.sess, connection)
+        }
+        None => {
+            let tcp = timed(steps, Step::Connect, || {
+                HostTimings::time(&mut timings.tcp_connect, || {
+                    connect_tcp(target, props, &props.timeouts.within(host_deadline))
+                })
+            })?;
+            let local_addr = tcp.local_addr().ok();
+            progress.set_phase(Phase::Handshake);
+            progress.event(|hostname| RunEvent::Connected { hostname });
+            let sess = timed(steps, Step::Handshake, || {
+                HostTimings::time(&mut timings.handshake, || {
+                    handshake(tcp, props, &props.timeouts.within(host_deadline), banner)
+                })
+            })?;
+            if let Some(steps) = steps {
+                steps.algorithms = Some(sess.algorithms());
+            }
+            timed(steps, Step::HostKey, || {
+                verify_host_key(&sess, target, props, host_key)
+            })?;
+            if props.banner_only {
+                let timeouts = props.timeouts.within(host_deadline);
+                *userauth_banner = request_auth_banner(&sess, user, &timeouts);
+                return Ok(HostOutput::empty(None));
+            }
+            progress.set_phase(Phase::Authenticating);
+            let authenticated = timed(steps, Step::Auth, || {
+                HostTimings::time(&mut timings.auth, || {
+                    let timeouts = props.timeouts.within(host_deadline);
+                    authenticate(
+                        &sess,
+                        user,
+                        auth_chain,
+                        props,
+                        &timeouts,
+                        local_addr,
+                        Some(progress),
+                    )
+                })
+            });
+            *userauth_banner = auth_banner(&sess);
+            let connection = authenticated?;
+            progress.set_phase(Phase::Running);
+            progress.event(|hostname| RunEvent::AuthOk {
+                hostname,
+                user: user.to_string(),
+            });
+            if let (Some(limit), Some(latency)) =
+                (props.agent_latency_warning, connection.agent_latency)
+            {
+                if latency > limit {
+                    progress.event(|hostname| RunEvent::AgentLatencyHigh {
+                        hostname,
+                        latency,
+                        limit,
+                    });
+                }
+            }
+            (sess, connection)
+        }
+    };
+    // Run apart, so the session goes back to the pool only when everything on it worked.
+    let result = (|| -> Result<HostOutput, HostError> {
+        let timeouts = props.timeouts.within(host_deadline);
+        let deadline = timeouts.read_total.map(|t| Instant::now() + t);
+        if let (Some(skip_if), Some(check)) = (&props.skip_if, &commands.skip_check) {
+            let out = timed(steps, Step::SkipCheck, || {
+                let channel = start_command(&sess, check, &timeouts, None, None)?;
+                progress.event(|hostname| RunEvent::ExecStarted {
+                    hostname,
+                    command: check.clone(),
+                });
+                finish_command(&sess, channel, shell, props, deadline, Some(progress), None)
+            })?;
+            let skipped = skip_if.skips(out.exit_code, &out.output);
+            *skip_result = Some(SkipCheckResult {
+                command: check.clone(),
+                exit_code: out.exit_code,
+                marker: out.output.clone(),
+                skipped,
+            });
+            if skipped {
+                let marker = out.output.lines().next().unwrap_or("").trim();
+                return Err(HostError::new(
+                    ErrorKind::MaintenanceMode,
+                    if marker.is_empty() {
+                        format!(
+                            "Not run, host in maintenance (check exited with {})",
+                            out.exit_code
+                        )
+                    } else {
+                        format!("Not run, host in maintenance: {}", marker)
+                    },
+                ));
+            }
+        }
+        if let (Some(guard), Some(check)) = (&props.guard, &commands.guard) {
+            let out = timed(steps, Step::Guard, || {
+                let channel =
+                    start_command(&sess, check, &timeouts, None, commands.escalation.as_ref())?;
+                progress.event(|hostname| RunEvent::ExecStarted {
+                    hostname,
+                    command: check.clone(),
+                });
+                finish_command(&sess, channel, shell, props, deadline, Some(progress), None)
+            })?;
+            let skipped = guard.skips(out.exit_code, &out.output);
+            *guard_result = Some(GuardResult {
+                command: check.clone(),
+                exit_code: out.exit_code,
+                output: out.output,
+                skipped,
+            });
+            if skipped {
+                return Err(HostError::new(
+                    ErrorKind::GuardSatisfied,
+                    format!(
+                        "Not run, guard satisfied (check exited with {})",
+                        out.exit_code
+                    ),
+                ));
+            }
+        }
+        if let Some(transfer) = &props.transfer {
+            let step = match **transfer {
+                Transfer::Upload { .. } => Step::Upload,
+                Transfer::Fetch { .. } => Step::Fetch,
+            };
+            let report = timed(steps, step, || transfer.run(&sess, shell, props, name))?;
+            let output = HostOutput::empty(Some(connection.clone()));
+            return Ok(match report {
+                TransferReport::Upload(report) => HostOutput {
+                    upload: Some(report),
+                    ..output
+                },
+                TransferReport::Fetch(report) => HostOutput {
+                    fetch: Some(report),
+                    ..output
+                },
+            });
+        }
+        let mut pass_through = match &props.pass_through {
+            Some(pass_through) => Some(
+                PassThroughWriter::new(pass_through, name, props.redactor.as_ref()).map_err(
+                    |e| {
+                        HostError::new(
+                            ErrorKind::Read,
+                            format!("Error creating output file: {}", e),
+                        )
+                    },
+                )?,
+            ),
+            None => None,
+        };
+        let mut out = timed(steps, Step::Command, || {
+            let channel = HostTimings::time(&mut timings.exec, || {
+                start_command(
+                    &sess,
+                    &commands.command,
+                    &timeouts,
+                    props.request_pty.as_ref(),
+                    commands.escalation.as_ref(),
+                )
+            })?;
+            progress.event(|hostname| RunEvent::ExecStarted {
+                hostname,
+                command: commands.command.clone(),
+            });
+            HostTimings::time(&mut timings.read, || {
+                finish_command(
+                    &sess,
+                    channel,
+                    shell,
+                    props,
+                    deadline,
+                    Some(progress),
+                    pass_through.as_mut(),
+                )
+            })
+        })?;
+        let passed_through = match pass_through.map(PassThroughWriter::finish) {
+            Some(Ok(PassThroughEnd::Passed(passed))) => Some(passed),
+            Some(Ok(PassThroughEnd::Kept(bytes))) => {
+                let (output, encoding) = shell.decode_output(bytes, None).map_err(|e| {
+                    HostError::new(
+                        ErrorKind::Read,
+                        format!("Error reading result of work: {}", e),
+                    )
+                })?;
+                out.output = if props.strip_ansi {
+                    strip_ansi(&output)
+                } else {
+                    output
+                };
+                out.encoding = encoding;
+                None
+            }
+            Some(Err(e)) => {
+                return Err(HostError::new(
+                    ErrorKind::Read,
+                    format!("Error writing output: {}", e),
+                ))
+            }
+            None => None,
+        };
+        for (_, command) in &commands.followups {
+            let start = Instant::now();
+            let timeouts = props.timeouts.within(host_deadline);
+            let result = start_command(
+                &sess,
+                command,
+                &timeouts,
+                props.request_pty.as_ref(),
+                commands.escalation.as_ref(),
+            )
+            .and_then(|channel| {
+                progress.event(|hostname| RunEvent::ExecStarted {
+                    hostname,
+                    command: command.clone(),
+                });
+                let deadline = timeouts.read_total.map(|t| Instant::now() + t);
+                finish_command(&sess, channel, shell, props, deadline, Some(progress), None)
+            });
+            // The session may be gone, so a command that could not be run ends the sequence.
+            let failed = result.is_err();
+            followup_results.push(FollowupOutput {
+                duration: start.elapsed(),
+                result,
+            });
+            if failed {
+                break;
+            }
+        }
+        if let Some((condition, check)) = &commands.post_condition {
+            progress.set_phase(Phase::WaitingForCondition);
+            let result = timed(steps, Step::PostCondition, || {
+                Ok(wait_for_condition(
+                    &sess,
+                    target,
+                    condition,
+                    check.as_deref(),
+                    shell,
+                    props,
+                    commands.escalation.as_ref(),
+                    progress,
+                ))
+            })?;
+            let met = result.met;
+            let waited = result.waited;
+            *condition_result = Some(result);
+            if !met {
+                return Err(HostError::new(
+                    ErrorKind::PostConditionTimeout,
+                    format!(
+                        "Command exited with {}, but its post condition did not hold within {}",
+                        out.exit_code,
+                        humantime::format_duration(Duration::from_secs(waited.as_secs()))
+                    ),
+                ));
+            }
+        }
+        Ok(HostOutput {
+            output: out.output,
+            stderr: out.stderr,
+            encoding: Some(out.encoding),
+            discarded: out.discarded,
+            exit_code: Some(out.exit_code),
+            connection: Some(ConnectionInfo {
+                output_bytes: out.output_bytes,
+                ..connection.clone()
+            }),
+            passed_through,
+            output_hash: out.output_hash,
+            upload: None,
+            fetch: None,
+        })
+    })();
+    if let (Some(pool), Ok(_)) = (pool, &result) {
+        let pooled = PooledSession::new(
+            sess,
+            connection,
+            banner.clone(),
+            userauth_banner.clone(),
+            host_key.clone(),
+        );
+        pool.checkin(key, pooled);
+    }
+    result
+}
+
+/// Polls `condition` until it holds, it times out or the run is cancelled. `check` is the
+/// prepared check command of a command condition.
+fn wait_for_condition(
+    sess: &Session,
+    target: &Target,
+    condition: &PostCondition,
+    check: Option<&str>,
+    shell: RemoteShell,
+    props: &ParallelSshProps,
+    escalation: Option<&Escalation>,
+    progress: &HostProgress,
+) -> PostConditionResult {
+    let start = Instant::now();
+    let deadline = start + condition.timeout();
+    let mut polls = 0;
+    loop {
+        polls += 1;
+        let (met, last_result) = match (condition, check) {
+            (PostCondition::TcpPortOpen { port, .. }, _) => {
+                let probe = match target {
+                    Target::Resolved(addr) => Target::Resolved(SocketAddr::new(addr.ip(), *port)),
+                    Target::Unresolved { host, .. } => Target::Unresolved {
+                        host: host.clone(),
+                        port: *port,
+                    },
+                };
+                match connect_tcp(&probe, props, &props.timeouts) {
+                    Ok(_) => (true, String::new()),
+                    Err(e) => (false, e.to_string()),
+                }
+            }
+            (_, Some(check)) => {
+                let out = start_command(sess, check, &props.timeouts, None, escalation).and_then(
+                    |channel| {
+                        progress.event(|hostname| RunEvent::ExecStarted {
+                            hostname,
+                            command: check.to_string(),
+                        });
+                        finish_command(sess, channel, shell, props, Some(deadline), None, None)
+                    },
+                );
+                match out {
+                    Ok(out) => (condition.met_by(out.exit_code, &out.output), out.output),
+                    Err(e) => (false, e.to_string()),
+                }
+            }
+            (_, None) => (false, "No check command".to_string()),
+        };
+        let now = Instant::now();
+        let sleep = condition.jittered_interval();
+        if met || now + sleep >= deadline || props.is_cancelled() {
+            return PostConditionResult {
+                met,
+                waited: now - start,
+                polls,
+                last_result,
+            };
+        }
+        thread::sleep(sleep);
+    }
+}
+
+pub(crate) fn connect_tcp(
+    target: &Target,
+    props: &ParallelSshProps,
+    timeouts: &Timeouts,
+) -> Result<TcpStream, HostError> {
+    match (&props.proxy, target) {
+        (Some(proxy), _) => proxy::connect(
+            proxy,
+            target,
+            &props.bind_addresses,
+            props.tcp_keepalive.as_ref(),
+            timeouts.connect.unwrap_or(DEFAULT_PHASE_TIMEOUT),
+        ),
+        (None, Target::Resolved(addr)) => {
+            let bind = props
+                .bind_addresses
+                .for_target(addr)
+                .map_err(|e| HostError::new(ErrorKind::Bind, e))?;
+            let timeout = timeouts.connect;
+            socket::connect(addr, bind, timeout, props.tcp_keepalive.as_ref()).map_err(|e| {
+                let kind = if e.kind() == io::ErrorKind::TimedOut {
+                    ErrorKind::TcpTimeout
+                } else {
+                    ErrorKind::TcpConnect
+                };
+                HostError::new(kind, e.to_string())
+            })
+        }
+        (None, Target::Unresolved { .. }) => Err(HostError::new(
+            ErrorKind::Dns,
+            format!("{} was left unresolved without a proxy", target),
+        )),
+    }
+}
+
+pub(crate) fn handshake(
+    tcp: TcpStream,
+    props: &ParallelSshProps,
+    timeouts: &Timeouts,
+    server_banner: &mut Option<String>,
+) -> Result<Session, HostError> {
+    let timeout_ms = Timeouts::session_ms(timeouts.handshake);
+    let sess = <Session as Transport>::handshake(tcp, props.compression, timeout_ms).map_err(
+        |e| match e.kind {
+            TransportErrorKind::Init => {
+                HostError::new(ErrorKind::Session, "Error initializing session")
+            }
+            TransportErrorKind::Timeout => HostError::new(
+                ErrorKind::HandshakeTimeout,
+                format!("Failed establishing handshake: {}", e),
+            ),
+            _ => HostError::new(
+                ErrorKind::Handshake,
+                format!("Failed establishing handshake: {}", e),
+            ),
+        },
+    )?;
+    *server_banner = Transport::server_banner(&sess).map(|b| sanitize_banner(&b));
+    Ok(sess)
+}
+
+/// Lists the auth methods of `user`, which has the server send its userauth banner, and
+/// returns the banner. Nothing is authenticated or run; this is as far as banner
+/// collection goes.
+fn request_auth_banner(sess: &dyn Transport, user: &str, timeouts: &Timeouts) -> Option<String> {
+    sess.set_timeout(Timeouts::session_ms(timeouts.auth));
+    let _ = sess.auth_methods(user);
+    auth_banner(sess)
+}
+
+/// Userauth banner of the server, once it answered an auth request.
+pub(crate) fn auth_banner(sess: &dyn Transport) -> Option<String> {
+    sess.auth_banner().map(|b| sanitize_banner(&b))
+}
+
+/// Checks the host key against the props' host key store, if any, storing its
+/// fingerprint in `host_key`.
+pub(crate) fn verify_host_key(
+    sess: &Session,
+    target: &Target,
+    props: &ParallelSshProps,
+    host_key: &mut Option<HostKeyInfo>,
+) -> Result<(), HostError> {
+    let store = match &props.host_key_store {
+        Some(store) => store,
+        None => return Ok(()),
+    };
+    let fingerprint = known_hosts::fingerprint(sess)
+        .ok_or_else(|| HostError::new(ErrorKind::Handshake, "The server sent no host key"))?;
+    let key = target.to_string();
+    let previous = store.check(&key, &fingerprint).map_err(|e| {
+        HostError::new(
+            ErrorKind::Session,
+            format!(
+                "Failed updating host key store {}: {}",
+                store.path().display(),
+                e
+            ),
+        )
+    })?;
+    *host_key = Some(HostKeyInfo {
+        fingerprint: fingerprint.clone(),
+        previous: previous.clone(),
+    });
+    match previous {
+        None => Ok(()),
+        Some(previous) => {
+            let message = format!(
+                "Host key of {} changed from {} to {}",
+                key, previous, fingerprint
+            );
+            match props.host_key_policy {
+                HostKeyPolicy::Fail => Err(HostError::new(ErrorKind::HostKeyChanged, message)),
+                // The change is in the response's `host_key` for the caller to report.
+                HostKeyPolicy::Warn => Ok(()),
+            }
+        }
+    }
+}
+
+pub(crate) fn authenticate(
+    sess: &Session,
+    user: &str,
+    auth_chain: &[AuthMethod],
+    props: &ParallelSshProps,
+    timeouts: &Timeouts,
+    local_addr: Option<SocketAddr>,
+    progress: Option<&HostProgress>,
+) -> Result<ConnectionInfo, HostError> {
+    let compression = if props.compression {
+        Some(
+            sess.methods(MethodType::CompSc)
+                .unwrap_or("none")
+                .to_string(),
+        )
+    } else {
+        None
+    };
+    sess.set_timeout(Timeouts::session_ms(timeouts.auth));
+    let authenticated = auth::authenticate(
+        sess,
+        user,
+        auth_chain,
+        &props.agent_connections_pool,
+        &props.progress,
+        progress,
+    )?;
+    Ok(ConnectionInfo {
+        auth_method: authenticated.method.to_string(),
+        agent_latency: authenticated.agent_latency,
+        local_addr,
+        compression,
+        output_bytes: 0,
+        reused: false,
+    })
+}
+
+/// Opens a channel and starts `command` on it, without waiting for any output, on the
+/// pseudo terminal `pty` asks for if any. A command prepared with become gets what
+/// `escalation` says: a pseudo terminal, and its password on stdin.
+pub(crate) fn start_command(
+    sess: &Session,
+    command: &str,
+    timeouts: &Timeouts,
+    pty: Option<&PtyRequest>,
+    escalation: Option<&Escalation>,
+) -> Result<Channel, HostError> {
+    check_no_nul(command)?;
+    sess.set_timeout(Timeouts::session_ms(timeouts.exec));
+    let mut channel = sess.channel_session().map_err(|e| {
+        HostError::new(
+            ssh_error_kind(&e, ErrorKind::Channel, ErrorKind::ExecTimeout),
+            format!("Failed opening channel: {}", e),
+        )
+    })?;
+    let on_pty = pty.is_some() || escalation.map_or(false, |e| e.pty);
+    if on_pty {
+        // Echo off, or the password would come back in the output.
+        let mut modes = PtyModes::new();
+        if escalation.map_or(false, |e| e.password.is_some()) {
+            modes.set_boolean(PtyModeOpcode::ECHO, false);
+        }
+        let (term, dim) = match pty {
+            Some(pty) => (pty.term.as_str(), Some((pty.width, pty.height, 0, 0))),
+            None => ("dumb", None),
+        };
+        channel.request_pty(term, Some(modes), dim).map_err(|e| {
+            HostError::new(
+                ssh_error_kind(&e, ErrorKind::Channel, ErrorKind::ExecTimeout),
+                format!("Failed requesting a pty: {}", e),
+            )
+        })?;
+    }
+    channel.exec(command).map_err(|e| {
+        HostError::new(
+            ssh_error_kind(&e, ErrorKind::Exec, ErrorKind::ExecTimeout),
+            format!("Failed executing command in channel: {}", e),
+        )
+    })?;
+    if let Some(password) = escalation.and_then(|e| e.password.as_deref()) {
+        let sent = channel
+            .write_all(format!("{}\n", password).as_bytes())
+            .and_then(|_| channel.flush());
+        // Without a pty a wrong password ends sudo at EOF instead of waiting for another.
+        let sent = if on_pty {
+            sent
+        } else {
+            sent.and_then(|_| channel.send_eof().map_err(io::Error::from))
+        };
+        sent.map_err(|e| {
+            HostError::new(
+                ErrorKind::Exec,
+                format!("Failed sending the become password: {}", e),
+            )
+        })?;
+    }
+    Ok(channel)
+}
+
+/// Shortest and longest pause of `read_streams` while neither stream has data.
+pub(crate) const POLL_MIN: Duration = Duration::from_millis(1);
+pub(crate) const POLL_MAX: Duration = Duration::from_millis(50);
+
+/// Reads stdout and stderr of `channel` side by side until both reach EOF, handing each
+/// chunk to `sink` with its stream id (0 for stdout, 1 for stderr).
+///
+/// Reading one stream to the end before the other deadlocks once the host fills the
+/// window with data of the stream not being read. The session is switched to
+/// non-blocking mode for the loop so whichever stream has data is read. Fails after
+/// `idle_limit` without any data, or at `deadline`, and stops once `breaker` aborted the
+/// run.
+fn read_streams<F>(
+    sess: &Session,
+    channel: &mut Channel,
+    idle_limit: Duration,
+    deadline: Option<Instant>,
+    breaker: Option<&FailureBreaker>,
+    mut sink: F,
+) -> Result<(), HostError>
+where
+    F: FnMut(i32, &[u8]),
+{
+    sess.set_blocking(false);
+    let result = (|| {
+        let mut chunk = [0u8; 8192];
+        let mut open = [true, true];
+        let mut last_data = Instant::now();
+        let mut pause = POLL_MIN;
+        while open.iter().any(|o| *o) {
+            let mut got_data = false;
+            for id in 0..2 {
+                let mut stream = channel.stream(id);
+                while open[id as usize] {
+                    match stream.read(&mut chunk) {
+                        Ok(0) => open[id as usize] = false,
+                        Ok(n) => {
+                            got_data = true;
+                            sink(id, &chunk[..n])
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::WouldBlock => break,
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => {
+                            return Err(HostError::new(
+                                ErrorKind::Read,
+                                format!("Error reading result of work: {}", e),
+                            ))
+                        }
+                    }
+                }
+            }
+            if deadline.map_or(false, |deadline| Instant::now() >= deadline) {
+                return Err(HostError::new(
+                    ErrorKind::ReadTotalTimeout,
+                    "Error reading result of work: the command ran out of time",
+                ));
+            }
+            if let Some(reason) = breaker.filter(|b| b.tripped()).and_then(|b| b.reason()) {
+                return Err(HostError::new(
+                    ErrorKind::Cancelled,
+                    format!("Stopped reading result of work, the run was {}", reason),
+                ));
+            }
+            if got_data {
+                last_data = Instant::now();
+                pause = POLL_MIN;
+                continue;
+            }
+            if last_data.elapsed() >= idle_limit {
+                return Err(HostError::new(
+                    ErrorKind::ReadIdleTimeout,
+                    "Error reading result of work: timed out waiting for output",
+                ));
+            }
+            thread::sleep(pause);
+            pause = (pause * 2).min(POLL_MAX);
+        }
+        Ok(())
+    })();
+    sess.set_blocking(true);
+    result
+}
+
+/// Reads the output of a started command until it exits, failing at `deadline`.
+///
+/// Stderr is read alongside stdout and kept apart from it, neither hashed nor passed
+/// through. Stderr which does not decode is kept lossily instead of failing the host.
+pub(crate) fn finish_command(
+    sess: &Session,
+    mut channel: Channel,
+    shell: RemoteShell,
+    props: &ParallelSshProps,
+    deadline: Option<Instant>,
+    progress: Option<&HostProgress>,
+    mut pass_through: Option<&mut PassThroughWriter>,
+) -> Result<CommandOutput, HostError> {
+    let mut collector = OutputCollector::new(props.keep_output);
+    let mut stderr = OutputCollector::new(props.keep_output);
+    let mut hasher = props.output_hash.map(OutputHasher::new);
+    let mut output_bytes = 0;
+    let idle_limit = props.timeouts.read_idle.unwrap_or(DEFAULT_PHASE_TIMEOUT);
+    let _read_permit = props.read_permits.as_ref().map(|permits| match progress {
+        Some(progress) => {
+            progress.set_phase(Phase::WaitingToRead);
+            let permit = progress.wait_for(Permit::Read, || permits.access());
+            progress.set_phase(Phase::Running);
+            permit
+        }
+        None => permits.access(),
+    });
+    let breaker = props.failure_breaker.as_deref();
+    read_streams(
+        sess,
+        &mut channel,
+        idle_limit,
+        deadline,
+        breaker,
+        |id, data| {
+            if let Some(progress) = progress {
+                progress.add_output(id, data);
+            }
+            if id == 0 {
+                output_bytes += data.len() as u64;
+                if let Some(hasher) = hasher.as_mut() {
+                    hasher.update(data);
+                }
+                match pass_through.as_mut() {
+                    Some(writer) => writer.feed(data),
+                    None => collector.feed(data),
+                }
+            } else {
+                stderr.feed(data);
+            }
+        },
+    )?;
+    let (channel_buffer, discarded) = collector.finish();
+    let (output, encoding) = shell
+        .decode_output(channel_buffer, props.output_encoding)
+        .map_err(|e| {
+            HostError::new(
+                ErrorKind::Read,
+                format!("Error reading result of work: {}", e),
+            )
+        })?;
+    let (stderr, _) = stderr.finish();
+    let stderr = match shell.decode_output(stderr.clone(), props.output_encoding) {
+        Ok((stderr, _)) => stderr,
+        Err(_) => String::from_utf8_lossy(&stderr).into_owned(),
+    };
+    let (output, stderr) = if props.strip_ansi {
+        (strip_ansi(&output), strip_ansi(&stderr))
+    } else {
+        (output, stderr)
+    };
+    sess.set_timeout(Timeouts::session_ms(props.timeouts.read_idle));
+    channel.wait_close().map_err(|e| {
+        HostError::new(
+            ssh_error_kind(&e, ErrorKind::Read, ErrorKind::ReadIdleTimeout),
+            format!("Failed closing channel: {}", e),
+        )
+    })?;
+    let exit_code = channel.exit_status().map_err(|e| {
+        HostError::new(
+            ssh_error_kind(&e, ErrorKind::Read, ErrorKind::ReadIdleTimeout),
+            format!("Failed reading exit status: {}", e),
+        )
+    })?;
+    Ok(CommandOutput {
+        output,
+        stderr,
+        encoding,
+        discarded,
+        exit_code,
+        output_bytes,
+        output_hash: hasher.map(OutputHasher::finish),
+    })
+}
+
+/// Longest banner kept in a `Response`, in bytes.
+const MAX_BANNER_LEN: usize = 1024;
+
+/// Drops control characters other than line breaks and tabs, and caps the length, so a
+/// banner is safe to print and store.
+fn sanitize_banner(banner: &str) -> String {
+    let mut out = String::new();
+    for c in banner.trim_end().chars() {
+        if c.is_control() && c != '\n' && c != '\t' {
+            continue;
+        }
+        if out.len() + c.len_utf8() > MAX_BANNER_LEN {
+            break;
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Maps a libssh2 error to `timeout` when the session timed out, `fallback` otherwise.
+pub(crate) fn ssh_error_kind(
+    e: &ssh2::Error,
+    fallback: ErrorKind,
+    timeout: ErrorKind,
+) -> ErrorKind {
+    if ssh2_error_kind(e) == TransportErrorKind::Timeout {
+        timeout
+    } else {
+        fallback
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn banner_loses_control_characters() {
+        assert_eq!(
+            sanitize_banner("Welcome\x1b[31m\r\n\tto\x07 web-1\n\n"),
+            "Welcome[31m\n\tto web-1"
+        );
+    }
+
+    #[test]
+    fn banner_is_capped_on_a_character_boundary() {
+        let banner = "é".repeat(MAX_BANNER_LEN);
+        let sanitized = sanitize_banner(&banner);
+        assert_eq!(sanitized.len(), MAX_BANNER_LEN);
+        assert!(sanitized.chars().all(|c| c == 'é'));
+
+        let banner = format!("a{}", "é".repeat(MAX_BANNER_LEN));
+        assert_eq!(sanitize_banner(&banner).len(), MAX_BANNER_LEN - 1);
+    }
+
+    #[test]
+    fn timeouts_get_their_own_kind() {
+        let timeout = ssh2::Error::new(ssh2::ErrorCode::Session(-9), "timed out");
+        let other = ssh2::Error::new(ssh2::ErrorCode::Session(-7), "socket send");
+        assert_eq!(
+            ssh_error_kind(&timeout, ErrorKind::Exec, ErrorKind::ExecTimeout),
+            ErrorKind::ExecTimeout
+        );
+        assert_eq!(
+            ssh_error_kind(&other, ErrorKind::Exec, ErrorKind::ExecTimeout),
+            ErrorKind::Exec
+        );
+    }
+}
@@ -0,0 +1,231 @@
+use crate::known_hosts::HostKeyInfo;
+use crate::response::ConnectionInfo;
+use serde::Serialize;
+use ssh2::Session;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Shortest sleep of the maintenance thread, whatever the idle TTL.
+const MIN_TICK: Duration = Duration::from_millis(100);
+
+/// Longest a keepalive may block on a host that stopped reading, in ms.
+const KEEPALIVE_TIMEOUT_MS: u32 = 5000;
+
+/// Authenticated sessions kept open across runs, so a tool sending command after command
+/// to the same hosts skips the connection, handshake and authentication.
+///
+/// A session goes back to the pool once its host succeeded, and the next host with the
+/// same address and user takes it instead of connecting. While idle, a session gets a
+/// keepalive every `keepalive` and is closed after `idle_ttl` without use, or as soon as a
+/// keepalive fails. One host uses a session at a time; hosts run side by side open their
+/// own, which the pool keeps too, up to `max_idle` sessions in all.
+///
+/// A background thread sends the keepalives; it stops once the pool is dropped.
+pub struct SessionPool {
+    inner: Arc<Inner>,
+}
+
+struct Inner {
+    idle: Mutex<HashMap<PoolKey, Vec<PooledSession>>>,
+    idle_ttl: Duration,
+    keepalive: Duration,
+    max_idle: AtomicUsize,
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evicted: AtomicU64,
+}
+
+/// Address connected to and user authenticated as.
+pub(crate) type PoolKey = (String, String);
+
+/// Authenticated session with what was learned connecting it.
+pub(crate) struct PooledSession {
+    pub(crate) sess: Session,
+    pub(crate) connection: ConnectionInfo,
+    pub(crate) banner: Option<String>,
+    pub(crate) auth_banner: Option<String>,
+    pub(crate) host_key: Option<HostKeyInfo>,
+    idle_since: Instant,
+    last_keepalive: Instant,
+}
+
+impl PooledSession {
+    pub(crate) fn new(
+        sess: Session,
+        connection: ConnectionInfo,
+        banner: Option<String>,
+        auth_banner: Option<String>,
+        host_key: Option<HostKeyInfo>,
+    ) -> Self {
+        PooledSession {
+            sess,
+            connection,
+            banner,
+            auth_banner,
+            host_key,
+            idle_since: Instant::now(),
+            last_keepalive: Instant::now(),
+        }
+    }
+
+    /// Sends a keepalive, `false` when the host is gone.
+    fn keepalive(&mut self) -> bool {
+        self.sess.set_timeout(KEEPALIVE_TIMEOUT_MS);
+        self.last_keepalive = Instant::now();
+        self.sess.keepalive_send().is_ok()
+    }
+}
+
+/// Counters of a `SessionPool`.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SessionPoolStats {
+    /// Hosts which ran on a pooled session.
+    pub hits: u64,
+    /// Hosts which found no pooled session and connected.
+    pub misses: u64,
+    /// Sessions closed for idling too long, failing a keepalive or finding the pool full.
+    pub evicted: u64,
+    /// Sessions idle in the pool now.
+    pub idle: usize,
+}
+
+impl SessionPool {
+    /// Pool closing sessions idle for `idle_ttl`, with a keepalive every `keepalive`
+    /// meanwhile, rounded up to whole seconds.
+    pub fn new(idle_ttl: Duration, keepalive: Duration) -> Result<Self, String> {
+        if idle_ttl == Duration::from_secs(0) {
+            return Err("session pool idle_ttl must be above 0".to_string());
+        }
+        if keepalive == Duration::from_secs(0) {
+            return Err("session pool keepalive must be above 0".to_string());
+        }
+        let inner = Arc::new(Inner {
+            idle: Mutex::new(HashMap::new()),
+            idle_ttl,
+            keepalive: Duration::from_secs(keepalive.as_secs().max(1)),
+            max_idle: AtomicUsize::new(256),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evicted: AtomicU64::new(0),
+        });
+        let tick = inner.keepalive.min(idle_ttl).max(MIN_TICK);
+        let weak = Arc::downgrade(&inner);
+        thread::spawn(move || maintain(weak, tick));
+        Ok(SessionPool { inner })
+    }
+
+    /// Keeps at most `max` sessions, 256 by default; one handed back to a full pool is
+    /// closed.
+    pub fn max_idle(self, max: usize) -> Self {
+        self.inner.max_idle.store(max, Ordering::Relaxed);
+        self
+    }
+
+    /// Closes every idle session.
+    pub fn clear(&self) {
+        let sessions = std::mem::take(&mut *self.inner.idle.lock().unwrap());
+        let closed: usize = sessions.values().map(Vec::len).sum();
+        self.inner
+            .evicted
+            .fetch_add(closed as u64, Ordering::Relaxed);
+    }
+
+    pub fn stats(&self) -> SessionPoolStats {
+        SessionPoolStats {
+            hits: self.inner.hits.load(Ordering::Relaxed),
+            misses: self.inner.misses.load(Ordering::Relaxed),
+            evicted: self.inner.evicted.load(Ordering::Relaxed),
+            idle: self.inner.idle.lock().unwrap().values().map(Vec::len).sum(),
+        }
+    }
+
+    /// Whether a session to `address` is idle in the pool, for any user.
+    pub(crate) fn has_idle(&self, address: &str) -> bool {
+        let idle = self.inner.idle.lock().unwrap();
+        idle.iter()
+            .any(|((addr, _), sessions)| addr == address && !sessions.is_empty())
+    }
+
+    /// Takes the most recently used live session of `key`, if any.
+    pub(crate) fn checkout(&self, key: &PoolKey) -> Option<PooledSession> {
+        let mut idle = self.inner.idle.lock().unwrap();
+        let sessions = idle.get_mut(key);
+        let found = sessions.and_then(|sessions| {
+            while let Some(session) = sessions.pop() {
+                if session.idle_since.elapsed() < self.inner.idle_ttl {
+                    return Some(session);
+                }
+                self.inner.evicted.fetch_add(1, Ordering::Relaxed);
+            }
+            None
+        });
+        if idle.get(key).map_or(false, Vec::is_empty) {
+            idle.remove(key);
+        }
+        match found {
+            Some(session) => {
+                self.inner.hits.fetch_add(1, Ordering::Relaxed);
+                Some(session)
+            }
+            None => {
+                self.inner.misses.fetch_add(1, Ordering::Relaxed);
+                None
+            }
+        }
+    }
+
+    /// Hands `session` back after a host succeeded on it.
+    pub(crate) fn checkin(&self, key: PoolKey, mut session: PooledSession) {
+        let mut idle = self.inner.idle.lock().unwrap();
+        if idle.values().map(Vec::len).sum::<usize>() >= self.inner.max_idle.load(Ordering::Relaxed)
+        {
+            self.inner.evicted.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        // libssh2 only sends keepalives once an interval is set.
+        session
+            .sess
+            .set_keepalive(false, self.inner.keepalive.as_secs() as u32);
+        session.idle_since = Instant::now();
+        session.last_keepalive = Instant::now();
+        idle.entry(key).or_insert_with(Vec::new).push(session);
+    }
+}
+
+/// Sends the keepalives of the pool behind `weak` and closes expired sessions, every
+/// `tick`, until the pool is dropped.
+fn maintain(weak: Weak<Inner>, tick: Duration) {
+    loop {
+        thread::sleep(tick);
+        let inner = match weak.upgrade() {
+            Some(inner) => inner,
+            None => return,
+        };
+        // Taken out, so a keepalive blocking on a slow host holds up no checkout.
+        let sessions = std::mem::take(&mut *inner.idle.lock().unwrap());
+        let mut kept: HashMap<PoolKey, Vec<PooledSession>> = HashMap::new();
+        for (key, sessions) in sessions {
+            for mut session in sessions {
+                let alive = session.idle_since.elapsed() < inner.idle_ttl
+                    && (session.last_keepalive.elapsed() < inner.keepalive || session.keepalive());
+                if alive {
+                    kept.entry(key.clone())
+                        .or_insert_with(Vec::new)
+                        .push(session);
+                } else {
+                    inner.evicted.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        let mut idle = inner.idle.lock().unwrap();
+        for (key, mut sessions) in kept {
+            let entry = idle.entry(key).or_insert_with(Vec::new);
+            // Handed back meanwhile, so more recently used.
+            sessions.append(entry);
+            *entry = sessions;
+        }
+    }
+}
@@ -0,0 +1,365 @@
+use crate::command::RemoteCommand;
+use crate::download::local_error;
+use crate::output::host_file_name;
+use crate::output_hash::{OutputHashAlgorithm, OutputHasher};
+use crate::response::{ErrorKind, HostError};
+use crate::scheduler::ParallelSshProps;
+use crate::session::{finish_command, ssh_error_kind, start_command, HostSession};
+use crate::shell::RemoteShell;
+use crate::timeouts::Timeouts;
+use serde::Serialize;
+use ssh2::{OpenFlags, OpenType, Session, Sftp};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// File transfer done on every host instead of running the command, see
+/// `ParallelSshProps::parallel_upload` and `ParallelSshProps::parallel_fetch`.
+pub(crate) enum Transfer {
+    Upload {
+        local_path: PathBuf,
+        remote_path: String,
+    },
+    Fetch {
+        remote_path: String,
+        local_dir: PathBuf,
+        dirs: Mutex<HostDirs>,
+    },
+}
+
+/// Directories of `local_dir` handed out to hosts, so hosts whose names map to the same
+/// directory name do not overwrite each other's files.
+#[derive(Default)]
+pub(crate) struct HostDirs {
+    by_host: BTreeMap<String, PathBuf>,
+    taken: BTreeSet<PathBuf>,
+}
+
+impl HostDirs {
+    /// Directory of `hostname` in `local_dir`, the same on every attempt of the host: the
+    /// host name made safe for a path, with `-2`, `-3`, ... appended when another host
+    /// already has that directory.
+    fn claim(&mut self, local_dir: &Path, hostname: &str) -> PathBuf {
+        if let Some(dir) = self.by_host.get(hostname) {
+            return dir.clone();
+        }
+        let name = match host_file_name(hostname) {
+            name if name.is_empty() || name == "." || name == ".." => "_".to_string(),
+            name => name,
+        };
+        let mut dir = local_dir.join(&name);
+        let mut n = 2;
+        while self.taken.contains(&dir) {
+            dir = local_dir.join(format!("{}-{}", name, n));
+            n += 1;
+        }
+        self.taken.insert(dir.clone());
+        self.by_host.insert(hostname.to_string(), dir.clone());
+        dir
+    }
+}
+
+/// What a transfer did on one host.
+pub(crate) enum TransferReport {
+    Upload(UploadReport),
+    Fetch(FetchReport),
+}
+
+impl Transfer {
+    pub(crate) fn run(
+        &self,
+        sess: &Session,
+        shell: RemoteShell,
+        props: &ParallelSshProps,
+        hostname: &str,
+    ) -> Result<TransferReport, HostError> {
+        match self {
+            Transfer::Upload {
+                local_path,
+                remote_path,
+            } => upload(sess, shell, props, local_path, remote_path).map(TransferReport::Upload),
+            Transfer::Fetch {
+                remote_path,
+                local_dir,
+                dirs,
+            } => {
+                let dir = dirs.lock().unwrap().claim(local_dir, hostname);
+                fetch(sess, shell, props, remote_path, &dir).map(TransferReport::Fetch)
+            }
+        }
+    }
+}
+
+/// What an upload to a host did.
+#[derive(Serialize, Debug, Clone)]
+pub struct UploadReport {
+    pub local_path: PathBuf,
+    pub remote_path: String,
+    pub bytes: u64,
+    pub duration: Duration,
+    /// Bytes per second over the whole upload.
+    pub throughput: u64,
+    /// `sha256:<hex>` of the bytes sent.
+    pub checksum: String,
+    /// Whether `checksum` was compared with the host's copy, which needs `sha256sum` there.
+    pub verified: bool,
+}
+
+/// What a fetch from a host did.
+#[derive(Serialize, Debug, Clone)]
+pub struct FetchReport {
+    pub remote_path: String,
+    /// Where the file of this host was saved, in its own directory of the local one.
+    pub local_path: PathBuf,
+    pub bytes: u64,
+    pub duration: Duration,
+    /// Bytes per second over the whole fetch.
+    pub throughput: u64,
+    /// `sha256:<hex>` of the bytes received.
+    pub checksum: String,
+    /// Whether `checksum` was compared with the host's file, which needs `sha256sum` there.
+    pub verified: bool,
+}
+
+impl HostSession {
+    /// Copies `local_path` to `remote_path` over SFTP, as `ParallelSshProps::parallel_upload`
+    /// does on each host.
+    pub fn upload(&self, local_path: &Path, remote_path: &str) -> Result<UploadReport, HostError> {
+        upload(&self.sess, self.shell, &self.props, local_path, remote_path)
+    }
+
+    /// Copies `remote_path` into `local_dir` over SFTP, keeping its file name.
+    pub fn fetch(&self, remote_path: &str, local_dir: &Path) -> Result<FetchReport, HostError> {
+        fetch(&self.sess, self.shell, &self.props, remote_path, local_dir)
+    }
+}
+
+fn open_sftp(sess: &Session, props: &ParallelSshProps) -> Result<Sftp, HostError> {
+    sess.set_timeout(Timeouts::session_ms(props.timeouts.read_idle));
+    sess.sftp().map_err(|e| {
+        HostError::new(
+            ssh_error_kind(&e, ErrorKind::Channel, ErrorKind::ReadIdleTimeout),
+            format!("Failed starting SFTP: {}", e),
+        )
+    })
+}
+
+/// Permission bits of a local file; without Unix modes, 0o644, or 0o444 when read-only.
+fn permission_bits(metadata: &fs::Metadata) -> u32 {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        metadata.permissions().mode() & 0o777
+    }
+    #[cfg(not(unix))]
+    {
+        if metadata.permissions().readonly() {
+            0o444
+        } else {
+            0o644
+        }
+    }
+}
+
+/// Copies `local_path` to `remote_path` on the host of `sess`, replacing it, with the
+/// permission bits of the local file for a new one. A relative `remote_path` is relative
+/// to the login directory, not the workdir, and the file is written as the login user.
+///
+/// The copy is then verified with `sha256sum` unless the host lacks it or runs a Windows
+/// shell, failing with `ErrorKind::ChecksumMismatch` when it differs.
+pub(crate) fn upload(
+    sess: &Session,
+    shell: RemoteShell,
+    props: &ParallelSshProps,
+    local_path: &Path,
+    remote_path: &str,
+) -> Result<UploadReport, HostError> {
+    let start = Instant::now();
+    let mut local = File::open(local_path).map_err(|e| local_error(local_path, e))?;
+    let mode = permission_bits(&local.metadata().map_err(|e| local_error(local_path, e))?);
+    let sftp = open_sftp(sess, props)?;
+    let mut remote = sftp
+        .open_mode(
+            Path::new(remote_path),
+            OpenFlags::WRITE | OpenFlags::CREATE | OpenFlags::TRUNCATE,
+            mode as i32,
+            OpenType::File,
+        )
+        .map_err(|e| {
+            HostError::new(
+                ssh_error_kind(&e, ErrorKind::Upload, ErrorKind::ReadIdleTimeout),
+                format!("Failed opening {}: {}", remote_path, e),
+            )
+        })?;
+    let write_error = |e: io::Error| {
+        HostError::new(
+            ErrorKind::Upload,
+            format!("Error writing {}: {}", remote_path, e),
+        )
+    };
+    let mut hasher = OutputHasher::new(OutputHashAlgorithm::Sha256);
+    let mut bytes = 0;
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let n = match local.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(local_error(local_path, e)),
+        };
+        hasher.update(&buffer[..n]);
+        remote.write_all(&buffer[..n]).map_err(write_error)?;
+        bytes += n as u64;
+    }
+    remote.close().map_err(|e| write_error(e.into()))?;
+    let checksum = hasher.finish();
+    let verified = match remote_checksum(sess, shell, props, remote_path)? {
+        Some(remote) if remote != checksum => {
+            return Err(HostError::new(
+                ErrorKind::ChecksumMismatch,
+                format!(
+                    "{} has {} on the host but {} was sent",
+                    remote_path, remote, checksum
+                ),
+            ))
+        }
+        Some(_) => true,
+        None => false,
+    };
+    let duration = start.elapsed();
+    Ok(UploadReport {
+        local_path: local_path.to_path_buf(),
+        remote_path: remote_path.to_string(),
+        bytes,
+        duration,
+        throughput: (bytes as f64 / duration.as_secs_f64().max(1e-3)) as u64,
+        checksum,
+        verified,
+    })
+}
+
+/// Copies `remote_path` from the host of `sess` into `local_dir`, created if needed,
+/// under the file name of `remote_path`. The file is written as `<name>.part` and only
+/// renamed once complete and verified, so a failed fetch leaves no file behind that
+/// looks whole; an older copy of the file is kept until then.
+///
+/// Like an upload, a relative `remote_path` is relative to the login directory and the
+/// file is verified with `sha256sum` when the host has it.
+pub(crate) fn fetch(
+    sess: &Session,
+    shell: RemoteShell,
+    props: &ParallelSshProps,
+    remote_path: &str,
+    local_dir: &Path,
+) -> Result<FetchReport, HostError> {
+    let start = Instant::now();
+    let name = Path::new(remote_path).file_name().ok_or_else(|| {
+        HostError::new(
+            ErrorKind::LocalFile,
+            format!("{} does not name a file", remote_path),
+        )
+    })?;
+    let local_path = local_dir.join(name);
+    let mut part_name = name.to_os_string();
+    part_name.push(".part");
+    let part_path = local_dir.join(part_name);
+    fs::create_dir_all(local_dir).map_err(|e| local_error(local_dir, e))?;
+    let sftp = open_sftp(sess, props)?;
+    let mut remote = sftp.open(Path::new(remote_path)).map_err(|e| {
+        HostError::new(
+            ssh_error_kind(&e, ErrorKind::Read, ErrorKind::ReadIdleTimeout),
+            format!("Failed opening {}: {}", remote_path, e),
+        )
+    })?;
+    let received = (|| {
+        let mut local = File::create(&part_path).map_err(|e| local_error(&part_path, e))?;
+        let mut hasher = OutputHasher::new(OutputHashAlgorithm::Sha256);
+        let mut bytes = 0;
+        let mut buffer = vec![0u8; 64 * 1024];
+        loop {
+            let n = match remote.read(&mut buffer) {
+                Ok(0) => break,
+                Ok(n) => n,
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    return Err(HostError::new(
+                        ErrorKind::Read,
+                        format!("Error reading {}: {}", remote_path, e),
+                    ))
+                }
+            };
+            hasher.update(&buffer[..n]);
+            local
+                .write_all(&buffer[..n])
+                .map_err(|e| local_error(&part_path, e))?;
+            bytes += n as u64;
+        }
+        local.flush().map_err(|e| local_error(&part_path, e))?;
+        let checksum = hasher.finish();
+        let verified = match remote_checksum(sess, shell, props, remote_path)? {
+            Some(remote) if remote != checksum => {
+                return Err(HostError::new(
+                    ErrorKind::ChecksumMismatch,
+                    format!(
+                        "{} has {} on the host but {} was received",
+                        remote_path, remote, checksum
+                    ),
+                ))
+            }
+            Some(_) => true,
+            None => false,
+        };
+        fs::rename(&part_path, &local_path).map_err(|e| local_error(&local_path, e))?;
+        Ok((bytes, checksum, verified))
+    })();
+    let (bytes, checksum, verified) = match received {
+        Ok(received) => received,
+        Err(e) => {
+            let _ = fs::remove_file(&part_path);
+            return Err(e);
+        }
+    };
+    let duration = start.elapsed();
+    Ok(FetchReport {
+        remote_path: remote_path.to_string(),
+        local_path,
+        bytes,
+        duration,
+        throughput: (bytes as f64 / duration.as_secs_f64().max(1e-3)) as u64,
+        checksum,
+        verified,
+    })
+}
+
+/// `sha256:<hex>` of `remote_path` by the host's `sha256sum`; `None` when the host has no
+/// `sha256sum` or runs a Windows shell.
+fn remote_checksum(
+    sess: &Session,
+    shell: RemoteShell,
+    props: &ParallelSshProps,
+    remote_path: &str,
+) -> Result<Option<String>, HostError> {
+    if let RemoteShell::Cmd | RemoteShell::PowerShell = shell {
+        return Ok(None);
+    }
+    let command = RemoteCommand::new("sha256sum")
+        .arg("--")
+        .arg(remote_path)
+        .to_string();
+    let channel = start_command(sess, &command, &props.timeouts, None, None)?;
+    let out = finish_command(sess, channel, shell, props, None, None, None)?;
+    match out.exit_code {
+        0 => Ok(Some(format!(
+            "sha256:{}",
+            out.output.split_whitespace().next().unwrap_or("")
+        ))),
+        127 => Ok(None),
+        code => Err(HostError::new(
+            ErrorKind::Read,
+            format!("sha256sum of {} exited with {}", remote_path, code),
+        )),
+    }
+}
@@ -0,0 +1,84 @@
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// File several processes append whole records to, e.g. shards of one inventory writing
+/// their results into one NDJSON file.
+///
+/// Each record is appended while holding an exclusive lock on the file, so records of
+/// different writers never interleave. On Unix the lock is advisory and only keeps apart
+/// writers going through this type. The system releases it when a writer exits or crashes, so a
+/// dead writer never blocks the others; a record it was cut off in the middle of stays
+/// behind as a partial line, and the next record starts on a new line after it.
+pub struct SharedFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl SharedFile {
+    /// Opens `path` for appending, creating it when missing.
+    pub fn open(path: &Path) -> io::Result<Self> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        Ok(SharedFile {
+            file,
+            path: path.to_path_buf(),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Appends `record`, newline terminated, as one piece.
+    pub fn append(&mut self, record: &[u8]) -> io::Result<()> {
+        let mut file = &self.file;
+        let _lock = FileLock::exclusive(file)?;
+        let mut data = Vec::with_capacity(record.len() + 1);
+        if !ends_with_newline(file)? {
+            data.push(b'\n');
+        }
+        data.extend_from_slice(record);
+        file.write_all(&data)
+    }
+}
+
+/// Whether `file` is empty or ends with a complete line.
+fn ends_with_newline(mut file: &File) -> io::Result<bool> {
+    let len = file.seek(SeekFrom::End(0))?;
+    if len == 0 {
+        return Ok(true);
+    }
+    file.seek(SeekFrom::Start(len - 1))?;
+    let mut last = [0u8];
+    file.read_exact(&mut last)?;
+    Ok(last[0] == b'\n')
+}
+
+/// Exclusive lock on the whole file, `flock` on Unix and `LockFileEx` on Windows, held
+/// until dropped.
+struct FileLock<'a> {
+    file: &'a File,
+}
+
+impl<'a> FileLock<'a> {
+    fn exclusive(file: &'a File) -> io::Result<Self> {
+        loop {
+            match file.lock_exclusive() {
+                Ok(()) => return Ok(FileLock { file }),
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+impl Drop for FileLock<'_> {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
@@ -0,0 +1,212 @@
+use crate::encoding::OutputEncoding;
+use crate::response::{ErrorKind, HostError};
+use serde::{Deserialize, Serialize};
+use std::str::FromStr;
+
+/// Shell the remote command is handed to.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum RemoteShell {
+    /// `sh -c '<command>'`.
+    Posix,
+    /// `cmd /S /C "<command>"`, for Windows hosts.
+    Cmd,
+    /// `powershell -NoProfile -NonInteractive -EncodedCommand ...`, for Windows hosts.
+    PowerShell,
+    /// The command string is executed untouched by the login shell.
+    Raw,
+}
+
+impl Default for RemoteShell {
+    fn default() -> Self {
+        RemoteShell::Raw
+    }
+}
+
+impl FromStr for RemoteShell {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "posix" => Ok(RemoteShell::Posix),
+            "cmd" => Ok(RemoteShell::Cmd),
+            "powershell" => Ok(RemoteShell::PowerShell),
+            "raw" => Ok(RemoteShell::Raw),
+            _ => Err(format!("Unknown remote shell: {}", s)),
+        }
+    }
+}
+
+impl RemoteShell {
+    /// Wraps `command` so that it is run by this shell and its exit code is reported back.
+    pub fn wrap(self, command: &str) -> String {
+        match self {
+            RemoteShell::Raw => command.to_string(),
+            RemoteShell::Posix => format!("sh -c {}", shell_quote(command)),
+            RemoteShell::Cmd => format!("cmd /S /C \"{}\"", command),
+            RemoteShell::PowerShell => {
+                // The OpenSSH server hands the command line to cmd.exe first, which makes
+                // quoting a script for -Command fragile. An encoded script sidesteps both
+                // parsers. $LASTEXITCODE is only set by native commands, so a failed cmdlet
+                // is reported as exit code 1.
+                let script = format!(
+                    "& {{ {} }}; $ok = $?; if ($LASTEXITCODE) {{ exit $LASTEXITCODE }}; if (-not $ok) {{ exit 1 }}; exit 0",
+                    command
+                );
+                let utf16: Vec<u8> = script
+                    .encode_utf16()
+                    .flat_map(|c| c.to_le_bytes().to_vec())
+                    .collect();
+                format!(
+                    "powershell -NoProfile -NonInteractive -EncodedCommand {}",
+                    base64_encode(&utf16)
+                )
+            }
+        }
+    }
+
+    /// Prefixes `command` with a change into `dir`, so it only runs once that succeeded.
+    ///
+    /// With `create` the directory is created first. `Raw` assumes a POSIX login shell.
+    /// For `Cmd`, characters which cannot be part of a Windows path are left out of `dir`.
+    pub fn in_workdir(self, command: &str, dir: &str, create: bool) -> String {
+        match self {
+            RemoteShell::Raw | RemoteShell::Posix => {
+                let dir = shell_quote(dir);
+                if create {
+                    format!("mkdir -p -- {0} && cd -- {0} && {1}", dir, command)
+                } else {
+                    format!("cd -- {} && {}", dir, command)
+                }
+            }
+            RemoteShell::Cmd => {
+                // Quotes, redirections, wildcards and control characters cannot be part of
+                // a Windows path; dropping them leaves nothing to break out of the quotes.
+                let dir: String = dir
+                    .chars()
+                    .filter(|c| !"\"<>|?*".contains(*c) && !c.is_control())
+                    .collect();
+                let dir = format!("\"{}\"", dir);
+                if create {
+                    format!(
+                        "(if not exist {0} mkdir {0}) && cd /d {0} && {1}",
+                        dir, command
+                    )
+                } else {
+                    format!("cd /d {} && {}", dir, command)
+                }
+            }
+            RemoteShell::PowerShell => {
+                let dir = format!("'{}'", dir.replace('\'', "''"));
+                if create {
+                    format!(
+                        "New-Item -ItemType Directory -Force -Path {0} | Out-Null; Set-Location -LiteralPath {0} -ErrorAction Stop; {1}",
+                        dir, command
+                    )
+                } else {
+                    format!(
+                        "Set-Location -LiteralPath {} -ErrorAction Stop; {}",
+                        dir, command
+                    )
+                }
+            }
+        }
+    }
+
+    /// Turns the raw channel output into text, returning it with the encoding used.
+    ///
+    /// Without an explicit `encoding`, POSIX output has to be valid UTF-8, while Windows
+    /// shells get UTF-16LE output detected and anything else decoded lossily. Windows
+    /// `\r\n` line endings are normalized.
+    pub fn decode_output(
+        self,
+        bytes: Vec<u8>,
+        encoding: Option<OutputEncoding>,
+    ) -> Result<(String, &'static str), String> {
+        match self {
+            RemoteShell::Raw | RemoteShell::Posix => {
+                encoding.unwrap_or(OutputEncoding::Strict).decode(&bytes)
+            }
+            RemoteShell::Cmd | RemoteShell::PowerShell => {
+                let (text, used) = if looks_like_utf16le(&bytes) {
+                    (decode_utf16le(&bytes), "UTF-16LE")
+                } else {
+                    encoding
+                        .unwrap_or(OutputEncoding::Utf8Lossy)
+                        .decode(&bytes)?
+                };
+                Ok((text.replace("\r\n", "\n"), used))
+            }
+        }
+    }
+}
+
+/// Refuses a command holding a NUL byte: the host's shell gets it as a C string, cut at
+/// the NUL, so the host would run something else than what was asked.
+pub(crate) fn check_no_nul(command: &str) -> Result<(), HostError> {
+    if command.contains('\0') {
+        return Err(HostError::new(
+            ErrorKind::Exec,
+            "Command contains a NUL byte, which no shell can receive".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Quotes `s` as a single POSIX shell word.
+pub fn shell_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "'\\''"))
+}
+
+/// A BOM, or NUL high bytes in most of the leading characters, mean UTF-16LE.
+fn looks_like_utf16le(bytes: &[u8]) -> bool {
+    if bytes.starts_with(&[0xFF, 0xFE]) {
+        return true;
+    }
+    if bytes.len() < 2 || bytes.len() % 2 != 0 {
+        return false;
+    }
+    let sample = &bytes[..bytes.len().min(512)];
+    let pairs = sample.len() / 2;
+    let nul_high = sample.chunks(2).filter(|c| c[1] == 0).count();
+    nul_high * 2 > pairs
+}
+
+fn decode_utf16le(bytes: &[u8]) -> String {
+    let bytes = if bytes.starts_with(&[0xFF, 0xFE]) {
+        &bytes[2..]
+    } else {
+        bytes
+    };
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|c| u16::from_le_bytes([c[0], *c.get(1).unwrap_or(&0)]))
+        .collect();
+    String::from_utf16_lossy(&units)
+}
+
+fn base64_encode(data: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = String::with_capacity((data.len() + 2) / 3 * 4);
+    for chunk in data.chunks(3) {
+        let b = [
+            chunk[0],
+            *chunk.get(1).unwrap_or(&0),
+            *chunk.get(2).unwrap_or(&0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        out.push(ALPHABET[(n >> 18) as usize & 63] as char);
+        out.push(ALPHABET[(n >> 12) as usize & 63] as char);
+        out.push(if chunk.len() > 1 {
+            ALPHABET[(n >> 6) as usize & 63] as char
+        } else {
+            '='
+        });
+        out.push(if chunk.len() > 2 {
+            ALPHABET[n as usize & 63] as char
+        } else {
+            '='
+        });
+    }
+    out
+}
@@ -0,0 +1,86 @@
+use regex::Regex;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+
+/// Fleet policy check run on every host before anything else, skipping hosts which are
+/// in maintenance.
+///
+/// Unlike the guard, the check runs as the login user, without become and outside the
+/// workdir, so it reads the same marker whatever the command. It runs in the host's
+/// shell, and its time counts toward the host's `read_total` limit. In TOML:
+///
+/// ```toml
+/// skip_if = { succeeds = "test -e /etc/nomaint" }
+/// skip_if = { output_matches = { command = "consul kv get maint/$(hostname)", pattern = "." } }
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(try_from = "SkipCheckConfig", into = "SkipCheckConfig")]
+pub enum SkipCheck {
+    /// Skip the host when the check exits with 0.
+    Succeeds(String),
+    /// Skip the host when the output of the check matches the pattern.
+    OutputMatches(String, Regex),
+}
+
+/// Config form of `SkipCheck`; TOML has no tuples of mixed types.
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+enum SkipCheckConfig {
+    Succeeds(String),
+    OutputMatches { command: String, pattern: String },
+}
+
+impl TryFrom<SkipCheckConfig> for SkipCheck {
+    type Error = regex::Error;
+
+    fn try_from(config: SkipCheckConfig) -> Result<Self, Self::Error> {
+        Ok(match config {
+            SkipCheckConfig::Succeeds(command) => SkipCheck::Succeeds(command),
+            SkipCheckConfig::OutputMatches { command, pattern } => {
+                SkipCheck::OutputMatches(command, Regex::new(&pattern)?)
+            }
+        })
+    }
+}
+
+impl From<SkipCheck> for SkipCheckConfig {
+    fn from(check: SkipCheck) -> Self {
+        match check {
+            SkipCheck::Succeeds(command) => SkipCheckConfig::Succeeds(command),
+            SkipCheck::OutputMatches(command, pattern) => SkipCheckConfig::OutputMatches {
+                command,
+                pattern: pattern.as_str().to_string(),
+            },
+        }
+    }
+}
+
+impl SkipCheck {
+    /// The check command.
+    pub fn command(&self) -> &str {
+        match self {
+            SkipCheck::Succeeds(command) | SkipCheck::OutputMatches(command, _) => command,
+        }
+    }
+
+    /// Whether the host is skipped after the check exited with `exit_code`, printing
+    /// `output`.
+    pub fn skips(&self, exit_code: i32, output: &str) -> bool {
+        match self {
+            SkipCheck::Succeeds(_) => exit_code == 0,
+            SkipCheck::OutputMatches(_, pattern) => pattern.is_match(output),
+        }
+    }
+}
+
+/// What the skip check of a host found, recorded in its response.
+#[derive(Serialize, Debug, Clone)]
+pub struct SkipCheckResult {
+    /// Check command as sent to the host.
+    pub command: String,
+    pub exit_code: i32,
+    /// Output of the check, the content of the marker.
+    pub marker: String,
+    /// Whether the host was skipped as in maintenance.
+    pub skipped: bool,
+}
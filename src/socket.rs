@@ -0,0 +1,133 @@
+use serde::{Deserialize, Serialize};
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::time::Duration;
+
+/// Local addresses outgoing connections are bound to, one per address family.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct BindAddresses {
+    pub v4: Option<Ipv4Addr>,
+    pub v6: Option<Ipv6Addr>,
+}
+
+impl BindAddresses {
+    pub fn is_empty(&self) -> bool {
+        self.v4.is_none() && self.v6.is_none()
+    }
+
+    pub fn set(&mut self, addr: IpAddr) {
+        match addr {
+            IpAddr::V4(a) => self.v4 = Some(a),
+            IpAddr::V6(a) => self.v6 = Some(a),
+        }
+    }
+
+    /// Bind address matching the family of `target`.
+    ///
+    /// `Ok(None)` when nothing is configured; `Err` when only the other family is.
+    pub fn for_target(&self, target: &SocketAddr) -> Result<Option<IpAddr>, String> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        match target {
+            SocketAddr::V4(_) => self
+                .v4
+                .map(|a| Some(IpAddr::V4(a)))
+                .ok_or_else(|| format!("No IPv4 bind address for target {}", target)),
+            SocketAddr::V6(_) => self
+                .v6
+                .map(|a| Some(IpAddr::V6(a)))
+                .ok_or_else(|| format!("No IPv6 bind address for target {}", target)),
+        }
+    }
+}
+
+/// OS-level TCP keepalive probing of idle connections.
+///
+/// Only the idle time can be set on every platform; the probe interval and count are
+/// applied on Linux and left at the system defaults elsewhere.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct TcpKeepaliveConfig {
+    /// Idle seconds before the first probe.
+    pub idle_secs: u64,
+    /// Seconds between unanswered probes.
+    pub interval_secs: u64,
+    /// Unanswered probes after which the connection is dropped.
+    pub retries: u32,
+}
+
+fn set_keepalive(socket: &Socket, keepalive: &TcpKeepaliveConfig) -> io::Result<()> {
+    socket.set_keepalive(Some(Duration::from_secs(keepalive.idle_secs)))?;
+    #[cfg(target_os = "linux")]
+    {
+        use std::os::unix::io::AsRawFd;
+
+        let set = |option, value: libc::c_int| {
+            let ret = unsafe {
+                libc::setsockopt(
+                    socket.as_raw_fd(),
+                    libc::IPPROTO_TCP,
+                    option,
+                    &value as *const libc::c_int as *const libc::c_void,
+                    std::mem::size_of::<libc::c_int>() as libc::socklen_t,
+                )
+            };
+            if ret == -1 {
+                Err(io::Error::last_os_error())
+            } else {
+                Ok(())
+            }
+        };
+        set(libc::TCP_KEEPINTVL, keepalive.interval_secs as libc::c_int)?;
+        set(libc::TCP_KEEPCNT, keepalive.retries as libc::c_int)?;
+    }
+    Ok(())
+}
+
+/// Connects to `addr`, from `bind` when given.
+///
+/// A connect interrupted by a signal or refused outright is retried once right away, so a
+/// transient failure does not get the host reported as down.
+pub fn connect(
+    addr: &SocketAddr,
+    bind: Option<IpAddr>,
+    timeout: Option<Duration>,
+    keepalive: Option<&TcpKeepaliveConfig>,
+) -> io::Result<TcpStream> {
+    match connect_once(addr, bind, timeout, keepalive) {
+        Err(e)
+            if e.kind() == io::ErrorKind::Interrupted
+                || e.kind() == io::ErrorKind::ConnectionRefused =>
+        {
+            connect_once(addr, bind, timeout, keepalive)
+        }
+        res => res,
+    }
+}
+
+fn connect_once(
+    addr: &SocketAddr,
+    bind: Option<IpAddr>,
+    timeout: Option<Duration>,
+    keepalive: Option<&TcpKeepaliveConfig>,
+) -> io::Result<TcpStream> {
+    let domain = match addr {
+        SocketAddr::V4(_) => Domain::ipv4(),
+        SocketAddr::V6(_) => Domain::ipv6(),
+    };
+    let socket = Socket::new(domain, Type::stream(), Some(Protocol::tcp()))?;
+    if let Some(local) = bind {
+        socket.bind(&SockAddr::from(SocketAddr::new(local, 0)))?;
+    }
+    let remote = SockAddr::from(*addr);
+    match timeout {
+        Some(t) => socket.connect_timeout(&remote, t)?,
+        None => socket.connect(&remote)?,
+    }
+    if let Some(keepalive) = keepalive {
+        set_keepalive(&socket, keepalive)?;
+    }
+    Ok(socket.into_tcp_stream())
+}
@@ -0,0 +1,180 @@
+use crate::response::Response;
+use serde::Serialize;
+use std::collections::{BinaryHeap, HashMap};
+use std::env;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+
+/// How much output `PendingResponses` kept in memory and on disk.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct SpillStats {
+    /// Memory budget of the outputs, `None` when they are all kept in memory.
+    pub budget_bytes: Option<u64>,
+    /// Most output bytes held in memory at once.
+    pub peak_memory_bytes: u64,
+    /// Output bytes moved to disk.
+    pub spilled_bytes: u64,
+    /// Outputs moved to disk.
+    pub spilled_outputs: u64,
+}
+
+/// Responses of a run waiting for the final outputs, with the outputs they hold in memory
+/// kept within a budget. Past it, the largest outputs are moved to files of a temporary
+/// directory of the run, and read back one response at a time as `drain` hands the
+/// responses to the outputs.
+///
+/// Without a budget, responses are kept as they are. The directory is removed once the
+/// responses are dropped, also when unwinding from a panic; a killed process leaves it
+/// behind, named after its run id.
+pub struct PendingResponses {
+    responses: Vec<Response>,
+    budget: Option<usize>,
+    dir: PathBuf,
+    dir_created: bool,
+    /// Outputs in memory as (size, index), largest first.
+    in_memory: BinaryHeap<(usize, usize)>,
+    memory_bytes: usize,
+    /// Files of the spilled outputs, by index.
+    spilled: HashMap<usize, PathBuf>,
+    stats: SpillStats,
+}
+
+impl PendingResponses {
+    pub fn new(budget: Option<usize>, run_id: &str) -> Self {
+        PendingResponses {
+            responses: Vec::new(),
+            budget,
+            dir: env::temp_dir().join(format!("ansible-rs-spill-{}", run_id)),
+            dir_created: false,
+            in_memory: BinaryHeap::new(),
+            memory_bytes: 0,
+            spilled: HashMap::new(),
+            stats: SpillStats {
+                budget_bytes: budget.map(|b| b as u64),
+                ..SpillStats::default()
+            },
+        }
+    }
+
+    /// Adds `response`, then spills the largest outputs until the budget holds again.
+    pub fn push(&mut self, response: Response) -> io::Result<()> {
+        let index = self.responses.len();
+        let size = response.result.len();
+        self.responses.push(response);
+        let budget = match self.budget {
+            Some(budget) => budget,
+            None => return Ok(()),
+        };
+        self.in_memory.push((size, index));
+        self.memory_bytes += size;
+        self.stats.peak_memory_bytes = self.stats.peak_memory_bytes.max(self.memory_bytes as u64);
+        while self.memory_bytes > budget {
+            let (size, index) = match self.in_memory.pop() {
+                Some(largest) => largest,
+                None => break,
+            };
+            if !self.dir_created {
+                fs::create_dir_all(&self.dir)?;
+                self.dir_created = true;
+            }
+            let path = self.dir.join(format!("{}.out", self.stats.spilled_outputs));
+            fs::write(&path, &self.responses[index].result)?;
+            self.responses[index].result = String::new();
+            self.spilled.insert(index, path);
+            self.memory_bytes -= size;
+            self.stats.spilled_bytes += size as u64;
+            self.stats.spilled_outputs += 1;
+        }
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.responses.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.responses.is_empty()
+    }
+
+    /// The responses, with an empty `result` for those whose output is on disk; enough for
+    /// anything but their output.
+    pub fn responses(&self) -> &[Response] {
+        &self.responses
+    }
+
+    /// The responses with their outputs, all read back into memory at once.
+    pub fn to_vec(&self) -> io::Result<Vec<Response>> {
+        (0..self.responses.len())
+            .map(|index| self.restored(index))
+            .collect()
+    }
+
+    /// Keeps only the responses `f` accepts, without reading spilled outputs back.
+    pub fn retain<F: FnMut(&Response) -> bool>(&mut self, mut f: F) {
+        let responses = std::mem::take(&mut self.responses);
+        let mut spilled = HashMap::new();
+        let mut in_memory = BinaryHeap::new();
+        self.memory_bytes = 0;
+        for (index, response) in responses.into_iter().enumerate() {
+            let path = self.spilled.remove(&index);
+            if !f(&response) {
+                if let Some(path) = path {
+                    let _ = fs::remove_file(path);
+                }
+                continue;
+            }
+            let kept = self.responses.len();
+            match path {
+                Some(path) => {
+                    spilled.insert(kept, path);
+                }
+                None if self.budget.is_some() => {
+                    in_memory.push((response.result.len(), kept));
+                    self.memory_bytes += response.result.len();
+                }
+                None => {}
+            }
+            self.responses.push(response);
+        }
+        self.spilled = spilled;
+        self.in_memory = in_memory;
+    }
+
+    /// Hands out the responses in order, each with its output, removing spilled files as
+    /// they are read back.
+    pub fn drain(self) -> impl Iterator<Item = io::Result<Response>> {
+        let mut pending = self;
+        let responses = std::mem::take(&mut pending.responses);
+        responses
+            .into_iter()
+            .enumerate()
+            .map(move |(index, mut response)| {
+                if let Some(path) = pending.spilled.remove(&index) {
+                    response.result = fs::read_to_string(&path)?;
+                    let _ = fs::remove_file(path);
+                }
+                Ok(response)
+            })
+    }
+
+    pub fn stats(&self) -> SpillStats {
+        self.stats
+    }
+
+    fn restored(&self, index: usize) -> io::Result<Response> {
+        let mut response = self.responses[index].clone();
+        if let Some(path) = self.spilled.get(&index) {
+            response.result = fs::read_to_string(path)?;
+        }
+        Ok(response)
+    }
+}
+
+impl Drop for PendingResponses {
+    fn drop(&mut self) {
+        if self.dir_created {
+            let _ = fs::remove_dir_all(&self.dir);
+        }
+    }
+}
@@ -0,0 +1,192 @@
+use crate::misc::SortOrder;
+use crate::prelude::{HostStatus, Response, ResultClass, RunPlan};
+use std::cmp::Reverse;
+use std::env;
+use std::io::{self, IsTerminal, Write};
+use std::time::Duration;
+
+const HOSTNAME_MAX_WIDTH: usize = 40;
+const OUTPUT_MAX_WIDTH: usize = 60;
+const HEADERS: [&str; 5] = ["HOST", "STATUS", "EXIT", "DURATION", "OUTPUT"];
+const PLAN_HEADERS: [&str; 5] = ["HOST", "ADDRESS", "USER", "GROUP", "COMMAND"];
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const RESET: &str = "\x1b[0m";
+
+/// Whether a table written to `stream` gets colors: only on a terminal, and not when
+/// `NO_COLOR` is set to anything but an empty string.
+pub fn colors_wanted<S: IsTerminal>(stream: &S) -> bool {
+    stream.is_terminal() && env::var_os("NO_COLOR").map_or(true, |v| v.is_empty())
+}
+
+pub fn sort_responses(data: &mut Vec<&Response>, order: SortOrder) {
+    match order {
+        SortOrder::None => {}
+        SortOrder::Hostname => data.sort_by(|a, b| a.hostname.cmp(&b.hostname)),
+        SortOrder::DurationDesc => data.sort_by_key(|r| Reverse(r.process_time)),
+        SortOrder::FailuresFirst => data.sort_by_key(|r| r.status),
+    }
+}
+
+/// Cuts `s` down to `width` characters, marking the cut with an ellipsis.
+fn truncate(s: &str, width: usize) -> String {
+    if s.chars().count() <= width {
+        return s.to_string();
+    }
+    if width == 0 {
+        return String::new();
+    }
+    let mut cut: String = s.chars().take(width - 1).collect();
+    cut.push('…');
+    cut
+}
+
+fn pad(s: &str, width: usize) -> String {
+    let len = s.chars().count();
+    format!("{}{}", s, " ".repeat(width.saturating_sub(len)))
+}
+
+fn format_duration(d: Duration) -> String {
+    format!("{:.3}s", d.as_secs_f64())
+}
+
+fn status_cell(r: &Response) -> String {
+    match (r.outcome, r.error_kind) {
+        (HostStatus::Success, _) => match r.result_class {
+            ResultClass::Ok => "OK".to_string(),
+            ResultClass::Warning => "WARNING".to_string(),
+            ResultClass::Error => "ERROR".to_string(),
+        },
+        (HostStatus::Skipped, _) => "SKIPPED".to_string(),
+        (HostStatus::Cancelled, _) => "CANCELLED".to_string(),
+        (HostStatus::Failed, Some(kind)) => format!("FAILED {}", kind),
+        (HostStatus::Failed, None) => "FAILED".to_string(),
+    }
+}
+
+fn first_line(s: &str) -> &str {
+    s.lines().next().unwrap_or("").trim_end()
+}
+
+/// Renders responses as an aligned plain-text table.
+///
+/// Failed hosts get their full output printed below the table when `expand_failed` is set.
+/// The status column is colored by result class when `colors` is set, see `colors_wanted`.
+pub fn write_table<W: Write>(
+    out: &mut W,
+    data: &[Response],
+    sort: SortOrder,
+    expand_failed: bool,
+    colors: bool,
+) -> io::Result<()> {
+    if data.is_empty() {
+        return writeln!(out, "No results");
+    }
+    let mut rows: Vec<&Response> = data.iter().collect();
+    sort_responses(&mut rows, sort);
+
+    let cells: Vec<[String; 5]> = rows
+        .iter()
+        .map(|r| {
+            [
+                truncate(&r.hostname, HOSTNAME_MAX_WIDTH),
+                status_cell(r),
+                r.exit_code
+                    .map(|c| c.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                format_duration(r.process_time),
+                truncate(first_line(&r.result), OUTPUT_MAX_WIDTH),
+            ]
+        })
+        .collect();
+    let mut widths = [0usize; 5];
+    for (i, header) in HEADERS.iter().enumerate() {
+        widths[i] = cells
+            .iter()
+            .map(|row| row[i].chars().count())
+            .chain(std::iter::once(header.len()))
+            .max()
+            .unwrap_or(0);
+    }
+
+    let header: Vec<String> = HEADERS
+        .iter()
+        .zip(widths.iter())
+        .map(|(h, w)| pad(h, *w))
+        .collect();
+    writeln!(out, "{}", header.join("  ").trim_end())?;
+    for (row, response) in cells.iter().zip(rows.iter()) {
+        let color = match response.result_class {
+            ResultClass::Ok => GREEN,
+            ResultClass::Warning => YELLOW,
+            ResultClass::Error => RED,
+        };
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .enumerate()
+            .map(|(i, (cell, w))| {
+                if i == 1 && colors {
+                    format!("{}{}{}", color, pad(cell, *w), RESET)
+                } else {
+                    pad(cell, *w)
+                }
+            })
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end())?;
+    }
+
+    if expand_failed {
+        for response in rows.iter().filter(|r| !r.status) {
+            writeln!(out, "\n--- {} ---", response.hostname)?;
+            writeln!(out, "{}", response.result.trim_end())?;
+        }
+    }
+    Ok(())
+}
+
+/// Renders a dry-run plan as an aligned plain-text table, one row per host.
+pub fn write_plan_table<W: Write>(out: &mut W, plan: &RunPlan) -> io::Result<()> {
+    if plan.hosts.is_empty() {
+        return writeln!(out, "No hosts");
+    }
+    let cells: Vec<[String; 5]> = plan
+        .hosts
+        .iter()
+        .map(|h| {
+            [
+                truncate(&h.target, HOSTNAME_MAX_WIDTH),
+                h.address
+                    .map(|a| a.to_string())
+                    .unwrap_or_else(|| "-".to_string()),
+                h.config.user.clone(),
+                h.config.group.clone().unwrap_or_else(|| "-".to_string()),
+                match (&h.render_error, &h.deduplicated_with) {
+                    (Some(e), _) => format!("(fails, {})", e),
+                    (None, Some(first)) => format!("(runs once, with {})", first),
+                    (None, None) => h.command.clone(),
+                },
+            ]
+        })
+        .collect();
+    let mut widths = [0usize; 5];
+    for (i, header) in PLAN_HEADERS.iter().enumerate() {
+        widths[i] = cells
+            .iter()
+            .map(|row| row[i].chars().count())
+            .chain(std::iter::once(header.len()))
+            .max()
+            .unwrap_or(0);
+    }
+    for row in std::iter::once(PLAN_HEADERS.map(String::from)).chain(cells) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, w)| pad(cell, *w))
+            .collect();
+        writeln!(out, "{}", line.join("  ").trim_end())?;
+    }
+    writeln!(out, "\n{} hosts, run id {}", plan.hosts.len(), plan.run_id)
+}
@@ -0,0 +1,54 @@
+use std::collections::BTreeMap;
+
+/// Longest tag key accepted.
+const MAX_KEY_LEN: usize = 64;
+
+/// Checks tag keys are non-empty and made of ASCII letters, digits, `_`, `-` and `.`, so
+/// they can be used as labels and partition names as they are.
+pub fn validate_tags(tags: &BTreeMap<String, String>) -> Result<(), String> {
+    for key in tags.keys() {
+        if key.is_empty() || key.len() > MAX_KEY_LEN {
+            return Err(format!(
+                "Tag key '{}' must be 1 to {} characters long",
+                key, MAX_KEY_LEN
+            ));
+        }
+        if !key
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || "_-.".contains(c))
+        {
+            return Err(format!(
+                "Tag key '{}' may only contain ASCII letters, digits, '_', '-' and '.'",
+                key
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Parses `key=value` pairs separated by commas, e.g. `team=infra,change=CHG0012345`.
+pub fn parse_tags(s: &str) -> Result<BTreeMap<String, String>, String> {
+    let tags = s
+        .split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(|pair| {
+            let mut kv = pair.splitn(2, '=');
+            match (kv.next(), kv.next()) {
+                (Some(k), Some(v)) => Ok((k.trim().to_string(), v.trim().to_string())),
+                _ => Err(format!("Tag '{}' is not of the form key=value", pair)),
+            }
+        })
+        .collect::<Result<_, _>>()?;
+    validate_tags(&tags)?;
+    Ok(tags)
+}
+
+/// Tags of a host: the run's tags, overridden by the host's own where keys collide.
+pub(crate) fn merge(
+    run: &BTreeMap<String, String>,
+    host: &BTreeMap<String, String>,
+) -> BTreeMap<String, String> {
+    let mut tags = run.clone();
+    tags.extend(host.iter().map(|(k, v)| (k.clone(), v.clone())));
+    tags
+}
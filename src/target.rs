@@ -0,0 +1,168 @@
+use crate::inventory::HostOptions;
+use std::fmt::{self, Display};
+use std::net::{IpAddr, SocketAddr, SocketAddrV4, SocketAddrV6};
+use std::str::FromStr;
+
+/// Host as handed to a run: a `host:port` name to resolve, or an address used as is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HostTarget {
+    /// Resolved through the DNS cache, or by the proxy with `remote_dns`. The response is
+    /// named after the address connected to.
+    Name(String),
+    /// Connected to without any resolution. The response is named `name` when given, after
+    /// the address otherwise.
+    Address {
+        address: SocketAddr,
+        name: Option<String>,
+    },
+}
+
+impl HostTarget {
+    /// `address`, reported as `name` in its response.
+    pub fn named<N: Into<String>>(address: SocketAddr, name: N) -> Self {
+        HostTarget::Address {
+            address,
+            name: Some(name.into()),
+        }
+    }
+
+    /// Name the caller gave an address.
+    pub(crate) fn display_name(&self) -> Option<&str> {
+        match self {
+            HostTarget::Address { name, .. } => name.as_deref(),
+            HostTarget::Name(_) => None,
+        }
+    }
+}
+
+/// Shows the host as given: the name, or the address when it has none.
+impl Display for HostTarget {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HostTarget::Name(name) => f.write_str(name),
+            HostTarget::Address {
+                name: Some(name), ..
+            } => f.write_str(name),
+            HostTarget::Address { address, .. } => write!(f, "{}", address),
+        }
+    }
+}
+
+/// Anything a run accepts as a host. Strings are `host:port` names, socket addresses skip
+/// resolution altogether.
+pub trait IntoTarget {
+    fn into_target(self) -> HostTarget;
+}
+
+impl IntoTarget for HostTarget {
+    fn into_target(self) -> HostTarget {
+        self
+    }
+}
+
+impl IntoTarget for String {
+    fn into_target(self) -> HostTarget {
+        HostTarget::Name(self)
+    }
+}
+
+impl IntoTarget for &String {
+    fn into_target(self) -> HostTarget {
+        HostTarget::Name(self.clone())
+    }
+}
+
+impl IntoTarget for &str {
+    fn into_target(self) -> HostTarget {
+        HostTarget::Name(self.to_string())
+    }
+}
+
+impl IntoTarget for SocketAddr {
+    fn into_target(self) -> HostTarget {
+        HostTarget::Address {
+            address: self,
+            name: None,
+        }
+    }
+}
+
+impl IntoTarget for SocketAddrV4 {
+    fn into_target(self) -> HostTarget {
+        SocketAddr::from(self).into_target()
+    }
+}
+
+impl IntoTarget for SocketAddrV6 {
+    fn into_target(self) -> HostTarget {
+        SocketAddr::from(self).into_target()
+    }
+}
+
+impl IntoTarget for (IpAddr, u16) {
+    fn into_target(self) -> HostTarget {
+        SocketAddr::from(self).into_target()
+    }
+}
+
+/// Host given as `[user@]host[:port]`, e.g. `deploy@web1:2222` or `root@10.0.0.5`, for
+/// fleets whose hosts log in as different users. The port is 22 when not given.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostSpec {
+    /// Login user of the host, the props' user when `None`.
+    pub user: Option<String>,
+    pub target: HostTarget,
+}
+
+impl HostSpec {
+    /// Host, command and options as taken by `parallel_ssh_process_with_options`, the
+    /// user as the host's override.
+    pub fn with_command<C>(self, command: C) -> (HostTarget, C, HostOptions) {
+        let options = HostOptions {
+            user: self.user,
+            ..HostOptions::default()
+        };
+        (self.target, command, options)
+    }
+}
+
+impl FromStr for HostSpec {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (user, host) = match s.rfind('@') {
+            Some(at) => (Some(&s[..at]), &s[at + 1..]),
+            None => (None, s),
+        };
+        if user == Some("") {
+            return Err(format!("{}: empty user", s));
+        }
+        if host.is_empty() {
+            return Err(format!("{}: empty host", s));
+        }
+        let target = if let Ok(address) = host.parse::<SocketAddr>() {
+            address.into_target()
+        } else if let Ok(ip) = host
+            .trim_matches(|c| c == '[' || c == ']')
+            .parse::<IpAddr>()
+        {
+            (ip, 22).into_target()
+        } else {
+            match host.rsplit_once(':') {
+                Some((name, port)) => {
+                    port.parse::<u16>()
+                        .map_err(|_| format!("{}: invalid port {}", s, port))?;
+                    if name.is_empty() {
+                        return Err(format!("{}: empty host", s));
+                    }
+                    HostTarget::Name(host.to_string())
+                }
+                None => HostTarget::Name(format!("{}:22", host)),
+            }
+        };
+        Ok(HostSpec {
+            user: user.map(str::to_string),
+            target,
+        })
+    }
+}
@@ -0,0 +1,56 @@
+use crate::command::needs_quoting;
+use crate::shell::shell_quote;
+use std::collections::BTreeMap;
+
+/// Renders the command template `template` against the vars of a host.
+///
+/// `{{ name }}` is replaced by the var `name` as one shell word, quoted when it needs to
+/// be, and `{{ name | raw }}` by the var as it is, for vars holding shell syntax. A var the
+/// host does not have, or another filter, is an error. `{{facts.NAME}}` is left for the run
+/// context, and braces around anything but a name, e.g. docker's `{{.Names}}`, are left as
+/// they are.
+pub fn render_template(template: &str, vars: &BTreeMap<String, String>) -> Result<String, String> {
+    let mut out = String::with_capacity(template.len());
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        out.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let (inner, end) = match after.find("}}") {
+            Some(end) => (&after[..end], end),
+            None => {
+                rest = &rest[start..];
+                break;
+            }
+        };
+        let mut filters = inner.split('|').map(str::trim);
+        let name = filters.next().unwrap_or("");
+        if !is_var_name(name) || name.starts_with("facts.") {
+            out.push_str("{{");
+            rest = after;
+            continue;
+        }
+        let value = vars
+            .get(name)
+            .ok_or_else(|| format!("no var {} for the command template", name))?;
+        match filters.next() {
+            None if needs_quoting(value) => out.push_str(&shell_quote(value)),
+            None => out.push_str(value),
+            Some("raw") => out.push_str(value),
+            Some(filter) => return Err(format!("unknown template filter {}", filter)),
+        }
+        if let Some(filter) = filters.next() {
+            return Err(format!("unknown template filter {}", filter));
+        }
+        rest = &after[end + 2..];
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+fn is_var_name(name: &str) -> bool {
+    let mut chars = name.chars();
+    chars
+        .next()
+        .map_or(false, |c| c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
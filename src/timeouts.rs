@@ -0,0 +1,113 @@
+use serde::{Deserialize, Serialize};
+use std::time::{Duration, Instant};
+
+/// Limit of the handshake, auth, exec and read idle phases when not set.
+pub(crate) const DEFAULT_PHASE_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Time limits of the phases of a host run. Unset limits keep the defaults noted on
+/// each field. In TOML, durations are strings:
+///
+/// ```toml
+/// [timeouts]
+/// connect = "5s"
+/// handshake = "10s"
+/// read_total = "30m"
+/// ```
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Timeouts {
+    /// TCP connect, proxy negotiation included. Unset, the OS default applies to direct
+    /// connections and 60s to connections through a proxy.
+    #[serde(with = "duration_str", skip_serializing_if = "Option::is_none")]
+    pub connect: Option<Duration>,
+    /// SSH handshake, 60s when unset.
+    #[serde(with = "duration_str", skip_serializing_if = "Option::is_none")]
+    pub handshake: Option<Duration>,
+    /// Each step of authentication, 60s when unset.
+    #[serde(with = "duration_str", skip_serializing_if = "Option::is_none")]
+    pub auth: Option<Duration>,
+    /// Opening the channel and starting the command, 60s when unset.
+    #[serde(with = "duration_str", skip_serializing_if = "Option::is_none")]
+    pub exec: Option<Duration>,
+    /// The command running from start to exit, unlimited when unset.
+    #[serde(with = "duration_str", skip_serializing_if = "Option::is_none")]
+    pub read_total: Option<Duration>,
+    /// Waiting for output, 60s when unset.
+    #[serde(with = "duration_str", skip_serializing_if = "Option::is_none")]
+    pub read_idle: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Rejects limits of 0; any positive duration is kept as is, sub-second ones included.
+    pub fn validate(&self) -> Result<(), String> {
+        let limits = [
+            ("connect", self.connect),
+            ("handshake", self.handshake),
+            ("auth", self.auth),
+            ("exec", self.exec),
+            ("read_total", self.read_total),
+            ("read_idle", self.read_idle),
+        ];
+        for (name, limit) in limits.iter() {
+            if *limit == Some(Duration::from_secs(0)) {
+                return Err(format!("timeouts.{} must be above 0", name));
+            }
+        }
+        Ok(())
+    }
+
+    /// These limits cut to the time left until `deadline`, so no phase runs past it.
+    pub(crate) fn within(&self, deadline: Option<Instant>) -> Timeouts {
+        let deadline = match deadline {
+            Some(deadline) => deadline,
+            None => return *self,
+        };
+        // A limit of 0 is refused by sockets and means none to libssh2.
+        let left = deadline
+            .saturating_duration_since(Instant::now())
+            .max(Duration::from_millis(1));
+        let cut = |limit: Option<Duration>| Some(limit.map_or(left, |limit| limit.min(left)));
+        let cut_phase = |limit: Option<Duration>| cut(limit.or(Some(DEFAULT_PHASE_TIMEOUT)));
+        Timeouts {
+            connect: cut(self.connect),
+            handshake: cut_phase(self.handshake),
+            auth: cut_phase(self.auth),
+            exec: cut_phase(self.exec),
+            read_total: cut(self.read_total),
+            read_idle: cut_phase(self.read_idle),
+        }
+    }
+
+    /// `limit` in milliseconds as libssh2 takes it, the default phase limit when unset.
+    pub(crate) fn session_ms(limit: Option<Duration>) -> u32 {
+        let ms = limit.unwrap_or(DEFAULT_PHASE_TIMEOUT).as_millis();
+        ms.max(1).min(u128::from(u32::MAX)) as u32
+    }
+}
+
+/// Optional durations as humantime strings such as `"90s"` or `"1h 30m"`.
+mod duration_str {
+    use serde::{Deserialize, Deserializer, Serializer};
+    use std::time::Duration;
+
+    pub fn serialize<S: Serializer>(
+        d: &Option<Duration>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match d {
+            Some(d) => serializer.serialize_str(&humantime::format_duration(*d).to_string()),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Option<Duration>, D::Error> {
+        match Option::<String>::deserialize(deserializer)? {
+            Some(s) => humantime::parse_duration(&s)
+                .map(Some)
+                .map_err(|e| serde::de::Error::custom(format!("{}: {}", s, e))),
+            None => Ok(None),
+        }
+    }
+}
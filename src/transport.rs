@@ -0,0 +1,217 @@
+use crate::diagnostics::Algorithms;
+use ssh2::{ErrorCode, HashType, MethodType, Session};
+use std::fmt;
+use std::net::TcpStream;
+use std::path::Path;
+
+const LIBSSH2_ERROR_SOCKET_SEND: ErrorCode = ErrorCode::Session(-7);
+const LIBSSH2_ERROR_TIMEOUT: ErrorCode = ErrorCode::Session(-9);
+const LIBSSH2_ERROR_SOCKET_DISCONNECT: ErrorCode = ErrorCode::Session(-13);
+const LIBSSH2_ERROR_AUTHENTICATION_FAILED: ErrorCode = ErrorCode::Session(-18);
+const LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED: ErrorCode = ErrorCode::Session(-19);
+const LIBSSH2_ERROR_SOCKET_TIMEOUT: ErrorCode = ErrorCode::Session(-30);
+const LIBSSH2_ERROR_AGENT_PROTOCOL: ErrorCode = ErrorCode::Session(-42);
+const LIBSSH2_ERROR_SOCKET_RECV: ErrorCode = ErrorCode::Session(-43);
+
+/// What went wrong in a transport, independent of the SSH implementation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TransportErrorKind {
+    /// The session could not be set up before anything was sent.
+    Init,
+    /// The session's timeout ran out.
+    Timeout,
+    /// The connection is gone; nothing more can be done over it.
+    Disconnected,
+    /// The server rejected the credentials.
+    Rejected,
+    /// The ssh-agent could not be talked to.
+    AgentUnreachable,
+    Other,
+}
+
+/// Error of a `Transport`, with the implementation's own message.
+#[derive(Debug, Clone)]
+pub(crate) struct TransportError {
+    pub(crate) kind: TransportErrorKind,
+    message: String,
+}
+
+impl TransportError {
+    /// Whether the session is unusable after the error.
+    pub(crate) fn is_fatal(&self) -> bool {
+        matches!(
+            self.kind,
+            TransportErrorKind::Timeout | TransportErrorKind::Disconnected
+        )
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+/// Connection steps of an SSH implementation: the handshake, the host key and
+/// authentication, up to where commands are run.
+pub(crate) trait Transport {
+    /// Sets up a session over `tcp` and runs the handshake, giving up on the server
+    /// after `timeout_ms`.
+    fn handshake(tcp: TcpStream, compress: bool, timeout_ms: u32) -> Result<Self, TransportError>
+    where
+        Self: Sized;
+
+    /// Limit for each following blocking call, in milliseconds.
+    fn set_timeout(&self, timeout_ms: u32);
+
+    /// Version string the server sent in the handshake.
+    fn server_banner(&self) -> Option<String>;
+
+    /// SHA256 hash of the key the server presented.
+    fn host_key_sha256(&self) -> Option<Vec<u8>>;
+
+    /// Algorithms negotiated in the handshake.
+    fn algorithms(&self) -> Algorithms;
+
+    /// Auth methods the server offers `user`, comma separated. Asking may already
+    /// authenticate, see `authenticated`.
+    fn auth_methods(&self, user: &str) -> Result<String, TransportError>;
+
+    fn authenticated(&self) -> bool;
+
+    /// Banner the server sent during authentication, once an auth request was answered.
+    fn auth_banner(&self) -> Option<String>;
+
+    /// Authenticates with the first identity of the running ssh-agent.
+    fn auth_agent(&self, user: &str) -> Result<(), TransportError>;
+
+    fn auth_key_file(
+        &self,
+        user: &str,
+        path: &Path,
+        passphrase: Option<&str>,
+    ) -> Result<(), TransportError>;
+
+    fn auth_password(&self, user: &str, password: &str) -> Result<(), TransportError>;
+}
+
+impl From<ssh2::Error> for TransportError {
+    fn from(e: ssh2::Error) -> Self {
+        TransportError {
+            kind: ssh2_error_kind(&e),
+            message: e.to_string(),
+        }
+    }
+}
+
+/// Classifies a libssh2 error.
+pub(crate) fn ssh2_error_kind(e: &ssh2::Error) -> TransportErrorKind {
+    match e.code() {
+        LIBSSH2_ERROR_TIMEOUT => TransportErrorKind::Timeout,
+        LIBSSH2_ERROR_SOCKET_SEND
+        | LIBSSH2_ERROR_SOCKET_DISCONNECT
+        | LIBSSH2_ERROR_SOCKET_TIMEOUT
+        | LIBSSH2_ERROR_SOCKET_RECV => TransportErrorKind::Disconnected,
+        LIBSSH2_ERROR_AUTHENTICATION_FAILED | LIBSSH2_ERROR_PUBLICKEY_UNVERIFIED => {
+            TransportErrorKind::Rejected
+        }
+        LIBSSH2_ERROR_AGENT_PROTOCOL => TransportErrorKind::AgentUnreachable,
+        _ => TransportErrorKind::Other,
+    }
+}
+
+/// libssh2, through the `ssh2` crate.
+impl Transport for Session {
+    fn handshake(tcp: TcpStream, compress: bool, timeout_ms: u32) -> Result<Self, TransportError> {
+        let mut sess = Session::new().map_err(|e| TransportError {
+            kind: TransportErrorKind::Init,
+            message: e.to_string(),
+        })?;
+        sess.set_tcp_stream(tcp);
+        sess.set_timeout(timeout_ms);
+        sess.set_compress(compress);
+        Session::handshake(&mut sess)?;
+        Ok(sess)
+    }
+
+    fn set_timeout(&self, timeout_ms: u32) {
+        Session::set_timeout(self, timeout_ms)
+    }
+
+    fn server_banner(&self) -> Option<String> {
+        self.banner_bytes()
+            .map(|b| String::from_utf8_lossy(b).into_owned())
+    }
+
+    fn host_key_sha256(&self) -> Option<Vec<u8>> {
+        self.host_key_hash(HashType::Sha256).map(<[u8]>::to_vec)
+    }
+
+    fn algorithms(&self) -> Algorithms {
+        let method = |t| self.methods(t).map(str::to_string);
+        Algorithms {
+            kex: method(MethodType::Kex),
+            host_key: method(MethodType::HostKey),
+            cipher_cs: method(MethodType::CryptCs),
+            cipher_sc: method(MethodType::CryptSc),
+            mac_cs: method(MethodType::MacCs),
+            mac_sc: method(MethodType::MacSc),
+            compression_cs: method(MethodType::CompCs),
+            compression_sc: method(MethodType::CompSc),
+        }
+    }
+
+    fn auth_methods(&self, user: &str) -> Result<String, TransportError> {
+        Ok(Session::auth_methods(self, user)?.to_string())
+    }
+
+    fn authenticated(&self) -> bool {
+        Session::authenticated(self)
+    }
+
+    fn auth_banner(&self) -> Option<String> {
+        self.userauth_banner().ok().flatten().map(str::to_string)
+    }
+
+    fn auth_agent(&self, user: &str) -> Result<(), TransportError> {
+        Ok(self.userauth_agent(user)?)
+    }
+
+    fn auth_key_file(
+        &self,
+        user: &str,
+        path: &Path,
+        passphrase: Option<&str>,
+    ) -> Result<(), TransportError> {
+        Ok(self.userauth_pubkey_file(user, None, path, passphrase)?)
+    }
+
+    fn auth_password(&self, user: &str, password: &str) -> Result<(), TransportError> {
+        Ok(self.userauth_password(user, password)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn libssh2_errors_are_classified() {
+        let kind = |code| TransportError::from(ssh2::Error::new(ErrorCode::Session(code), "")).kind;
+        assert_eq!(kind(-9), TransportErrorKind::Timeout);
+        assert_eq!(kind(-43), TransportErrorKind::Disconnected);
+        assert_eq!(kind(-18), TransportErrorKind::Rejected);
+        assert_eq!(kind(-19), TransportErrorKind::Rejected);
+        assert_eq!(kind(-42), TransportErrorKind::AgentUnreachable);
+        assert_eq!(kind(-1), TransportErrorKind::Other);
+    }
+
+    #[test]
+    fn only_a_lost_connection_is_fatal() {
+        let error = |code| TransportError::from(ssh2::Error::new(ErrorCode::Session(code), ""));
+        assert!(error(-9).is_fatal());
+        assert!(error(-7).is_fatal());
+        assert!(!error(-18).is_fatal());
+        assert!(!error(-42).is_fatal());
+    }
+}
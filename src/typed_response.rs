@@ -0,0 +1,144 @@
+use crate::known_hosts::HostKeyInfo;
+use crate::response::{ErrorKind, HostStatus, HostTimings, Response};
+use crate::result_class::ResultClass;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// Response of a mode which gathers data about a host instead of running a command, with
+/// the data as a typed `payload` rather than fields of a `Response` to pick out.
+///
+/// Serialized, the payload is a nested object. Converting between `Response` and
+/// `TypedResponse` goes through `ResponsePayload`, so writers which only know plain
+/// responses still take typed ones.
+#[derive(Serialize, Debug, Clone)]
+pub struct TypedResponse<T> {
+    pub hostname: String,
+    pub run_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub class: Option<String>,
+    pub process_time: Duration,
+    pub outcome: HostStatus,
+    #[serde(rename = "error_code", skip_serializing_if = "Option::is_none")]
+    pub error_kind: Option<ErrorKind>,
+    /// What went wrong, when the host failed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub tags: BTreeMap<String, String>,
+    /// What was gathered; parts a failed host did not get to are missing.
+    pub payload: T,
+}
+
+/// Data a gathering mode records in a `Response`.
+pub trait ResponsePayload: Sized {
+    /// Moves the payload out of the fields of `response` holding it.
+    fn take(response: &mut Response) -> Self;
+    /// Stores the payload in the fields of `response`.
+    fn put(self, response: &mut Response);
+}
+
+/// What banner collection, see `ParallelSshPropsBuilder::banner_only`, learns of a host.
+#[derive(Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct BannerReport {
+    /// Version string the server sent in the handshake.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub banner: Option<String>,
+    /// Banner the server sent in answer to the auth methods request, if any.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub auth_banner: Option<String>,
+    /// Host key fingerprint, when a host key store is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub host_key: Option<HostKeyInfo>,
+}
+
+impl ResponsePayload for BannerReport {
+    fn take(response: &mut Response) -> Self {
+        BannerReport {
+            banner: response.server_banner.take(),
+            auth_banner: response.auth_banner.take(),
+            host_key: response.host_key.take(),
+        }
+    }
+
+    fn put(self, response: &mut Response) {
+        response.server_banner = self.banner;
+        response.auth_banner = self.auth_banner;
+        response.host_key = self.host_key;
+    }
+}
+
+impl<T: ResponsePayload> From<Response> for TypedResponse<T> {
+    fn from(mut response: Response) -> Self {
+        let payload = T::take(&mut response);
+        let error = match response.error_kind {
+            Some(_) => Some(response.result),
+            None => None,
+        };
+        TypedResponse {
+            error,
+            hostname: response.hostname,
+            run_id: response.run_id,
+            group: response.group,
+            class: response.class,
+            process_time: response.process_time,
+            outcome: response.outcome,
+            error_kind: response.error_kind,
+            attempts: response.attempts,
+            tags: response.tags,
+            payload,
+        }
+    }
+}
+
+impl<T: ResponsePayload> From<TypedResponse<T>> for Response {
+    fn from(typed: TypedResponse<T>) -> Self {
+        let mut response = Response {
+            result: typed.error.unwrap_or_default(),
+            stderr: String::new(),
+            hostname: typed.hostname,
+            address: String::new(),
+            user: String::new(),
+            command: String::new(),
+            process_time: typed.process_time,
+            timings: HostTimings::default(),
+            status: typed.outcome == HostStatus::Success,
+            outcome: typed.outcome,
+            result_class: match typed.outcome {
+                HostStatus::Success => ResultClass::Ok,
+                _ => ResultClass::Error,
+            },
+            error_kind: typed.error_kind,
+            run_id: typed.run_id,
+            group: typed.group,
+            class: typed.class,
+            exit_code: None,
+            connection: None,
+            workdir: None,
+            encoding: None,
+            output_hash: None,
+            server_banner: None,
+            auth_banner: None,
+            host_key: None,
+            skip_check: None,
+            guard: None,
+            post_condition: None,
+            discarded: None,
+            passed_through: None,
+            upload: None,
+            fetch: None,
+            redactions: None,
+            deduplicated_with: None,
+            assigned_args: Vec::new(),
+            tags: typed.tags,
+            effective_config: None,
+            attempts: typed.attempts,
+            attempt_history: Vec::new(),
+        };
+        typed.payload.put(&mut response);
+        response
+    }
+}
@@ -0,0 +1,58 @@
+//! Commands run as another user through sudo or doas.
+
+use ansible_rs::prelude::*;
+use std::net::TcpListener;
+use std::sync::Arc;
+
+#[test]
+fn invalid_become_settings_are_refused() {
+    assert!(ParallelSshPropsBuilder::default()
+        .become_user(String::new())
+        .build()
+        .is_err());
+    assert!(ParallelSshPropsBuilder::default()
+        .become_method(BecomeMethod::Doas)
+        .become_password(Arc::new(|_: &str| Ok("secret".to_string())))
+        .build()
+        .is_err());
+}
+
+#[test]
+fn password_is_asked_for_on_stdin() {
+    let (_, props) = ParallelSshPropsBuilder::default()
+        .become_root(true)
+        .become_user("deploy".to_string())
+        .become_password(Arc::new(|_: &str| Ok("secret".to_string())))
+        .build()
+        .unwrap();
+    let plan = props.plan(vec![("10.0.0.1:22", "id -u", HostOptions::default())]);
+    let command = &plan.hosts[0].command;
+    assert!(
+        command.contains("sudo -S -k -p '' -u 'deploy' -- sh -c 'id -u'"),
+        "{}",
+        command
+    );
+    assert!(!command.contains("secret"), "{}", command);
+}
+
+#[test]
+fn failing_secret_provider_fails_the_host() {
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .become_root(true)
+        .become_password(Arc::new(|host: &str| {
+            Err(format!("no password for {}", host))
+        }))
+        .build()
+        .unwrap();
+    // Passes the port probe, and would fail the handshake if the host got that far.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = listener.local_addr().unwrap();
+    props.parallel_ssh_process(vec![(host, "id -u")]).unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.error_kind, Some(ErrorKind::Become));
+    assert!(
+        response.result.contains("no password"),
+        "{}",
+        response.result
+    );
+}
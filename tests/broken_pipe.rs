@@ -0,0 +1,64 @@
+//! Console output of the CLI into a pipe whose reader is gone, as with `| head`.
+
+use std::env;
+use std::fs;
+use std::process::{Command, Stdio};
+
+const CONFIG: &str = r#"
+threads = 4
+agent_parallelism = 1
+command = "true"
+timeout = "200ms"
+
+[output]
+save_to_file = false
+pretty_format = true
+show_progress = false
+"#;
+
+/// Runs the CLI on 20 hosts which refuse the connection, with its stdout already closed;
+/// returns its exit status and stderr.
+fn run_into_closed_pipe(format: &str) -> (bool, String) {
+    let dir = env::temp_dir().join(format!("ansible-rs-pipe-{}-{}", format, std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let config = dir.join("config.toml");
+    let hosts = dir.join("hosts");
+    fs::write(&config, CONFIG).unwrap();
+    let lines: Vec<String> = (1..=20).map(|i| format!("127.0.0.{}", i)).collect();
+    fs::write(&hosts, lines.join("\n")).unwrap();
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_ansible-rs"))
+        .arg("-c")
+        .arg(&config)
+        .arg("--hosts")
+        .arg(&hosts)
+        .args(&["--skip-preflight", "--output", format])
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    drop(child.stdout.take());
+    let output = child.wait_with_output().unwrap();
+    fs::remove_dir_all(&dir).unwrap();
+    (
+        output.status.success(),
+        String::from_utf8_lossy(&output.stderr).to_string(),
+    )
+}
+
+fn assert_clean_exit((success, stderr): (bool, String)) {
+    assert!(!stderr.contains("panicked"), "{}", stderr);
+    assert!(success, "{}", stderr);
+    // The summary still comes out on stderr.
+    assert!(stderr.contains("Hosts: 20"), "{}", stderr);
+}
+
+#[test]
+fn json_into_closed_pipe_exits_cleanly() {
+    assert_clean_exit(run_into_closed_pipe("json"));
+}
+
+#[test]
+fn table_into_closed_pipe_exits_cleanly() {
+    assert_clean_exit(run_into_closed_pipe("table"));
+}
@@ -0,0 +1,18 @@
+//! Runs cancelled from outside, as on Ctrl-C.
+
+mod common;
+
+use ansible_rs::prelude::*;
+use common::unreachable_hosts;
+
+#[test]
+fn cancelled_props_start_no_host() {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    props.clone().cancel();
+    props.parallel_ssh_process(unreachable_hosts(3)).unwrap();
+    let responses: Vec<Response> = rx.try_iter().collect();
+    assert_eq!(responses.len(), 3);
+    assert!(responses
+        .iter()
+        .all(|r| r.error_kind == Some(ErrorKind::Cancelled) && r.timings.is_empty()));
+}
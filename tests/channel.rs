@@ -0,0 +1,55 @@
+//! Responses of a run published into a channel of the caller.
+
+mod common;
+
+use ansible_rs::prelude::*;
+use common::unreachable_addrs;
+use futures::channel::mpsc;
+use smol::stream::StreamExt;
+
+#[test]
+fn channel_gets_every_response_and_is_closed() {
+    let (_rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let (tx, rx) = mpsc::channel(1);
+    let (summary, responses) = smol::run(futures::future::join(
+        props.run_into_channel(unreachable_addrs(20), "true", tx),
+        rx.collect::<Vec<Response>>(),
+    ));
+    assert_eq!(summary.total, 20);
+    assert_eq!(summary.failed, 20);
+    assert_eq!(responses.len(), 20);
+}
+
+#[test]
+fn dropped_receiver_cancels_the_rest() {
+    let (_rx, props) = ParallelSshPropsBuilder::default()
+        .tcp_connections_pool(1)
+        .build()
+        .unwrap();
+    let (tx, rx) = mpsc::channel(0);
+    drop(rx);
+    let summary = smol::run(props.run_into_channel(unreachable_addrs(500), "true", tx));
+    // Every host is still accounted for, most of them without being started.
+    assert_eq!(summary.total, 500);
+    assert!(summary.cancelled > 0, "{:?}", summary);
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn tokio_channel_gets_every_response() {
+    let (_rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let (tx, mut rx) = tokio::sync::mpsc::channel(1);
+    let receive = async move {
+        let mut responses = Vec::new();
+        while let Some(response) = rx.recv().await {
+            responses.push(response);
+        }
+        responses
+    };
+    let (summary, responses) = smol::run(futures::future::join(
+        props.run_into_tokio_channel(unreachable_addrs(20), "true", tx),
+        receive,
+    ));
+    assert_eq!(summary.total, 20);
+    assert_eq!(responses.len(), 20);
+}
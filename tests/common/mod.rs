@@ -0,0 +1,185 @@
+//! Fixtures of the integration tests: hosts nothing listens on, and an OpenSSH server in
+//! a docker container.
+//!
+//! The sshd tests only run with `ANSIBLE_RS_IT=1` and a reachable docker daemon; otherwise
+//! `TestSshServer::spawn` returns `None` after saying why, and the test passes without
+//! doing anything.
+
+#![allow(dead_code)]
+
+use std::io::Read;
+use std::net::{Ipv4Addr, SocketAddr, TcpListener, TcpStream};
+use std::path::Path;
+use std::process::{Command, Output};
+use std::sync::Once;
+use std::thread;
+use std::time::{Duration, Instant};
+
+const IMAGE: &str = "ansible-rs-it-sshd";
+/// How long a new container gets to accept SSH connections.
+const STARTUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// User and password every server is created with.
+pub const USER: &str = "tester";
+pub const PASSWORD: &str = "tester-password";
+
+/// `n` local addresses nothing listens on, so a host at each fails right away.
+pub fn unreachable_addrs(n: u16) -> Vec<SocketAddr> {
+    (1..=n)
+        .map(|i| SocketAddr::from(([127, 0, (i / 256) as u8, (i % 256) as u8], 1)))
+        .collect()
+}
+
+/// `n` hosts nothing listens on, each running `true`.
+pub fn unreachable_hosts(n: u16) -> Vec<(SocketAddr, &'static str)> {
+    unreachable_addrs(n)
+        .into_iter()
+        .map(|addr| (addr, "true"))
+        .collect()
+}
+
+/// Running sshd container, removed on drop.
+pub struct TestSshServer {
+    id: String,
+    addresses: Vec<SocketAddr>,
+}
+
+impl TestSshServer {
+    /// Starts a server reachable on one local port, with `USER` provisioned.
+    pub fn spawn() -> Option<Self> {
+        Self::spawn_with_ports(1)
+    }
+
+    /// Starts a server reachable on `ports` distinct local ports, so one container can
+    /// stand in for as many hosts.
+    pub fn spawn_with_ports(ports: usize) -> Option<Self> {
+        if !enabled() {
+            return None;
+        }
+        let addresses: Vec<SocketAddr> = (0..ports).map(|_| free_local_port()).collect();
+        let mut args = vec!["run".to_string(), "-d".to_string(), "--rm".to_string()];
+        for addr in &addresses {
+            args.push("-p".to_string());
+            args.push(format!("{}:22", addr));
+        }
+        args.push(IMAGE.to_string());
+        let out = docker(&args);
+        assert!(out.status.success(), "docker run failed: {}", stderr(&out));
+        let server = TestSshServer {
+            id: String::from_utf8_lossy(&out.stdout).trim().to_string(),
+            addresses,
+        };
+        for addr in &server.addresses {
+            wait_for_banner(*addr);
+        }
+        server.add_user(USER, PASSWORD);
+        Some(server)
+    }
+
+    /// First local address of the server.
+    pub fn address(&self) -> SocketAddr {
+        self.addresses[0]
+    }
+
+    /// Every local address of the server.
+    pub fn addresses(&self) -> &[SocketAddr] {
+        &self.addresses
+    }
+
+    /// Creates `user` with `password`, for password authentication.
+    pub fn add_user(&self, user: &str, password: &str) {
+        self.exec(&format!(
+            "adduser -D {user} && echo '{user}:{password}' | chpasswd",
+            user = user,
+            password = password
+        ));
+    }
+
+    /// Authorizes `public_key`, one `authorized_keys` line, for `user`.
+    pub fn add_key(&self, user: &str, public_key: &str) {
+        self.exec(&format!(
+            "mkdir -p /home/{user}/.ssh && echo '{key}' >> /home/{user}/.ssh/authorized_keys \
+             && chown -R {user} /home/{user}/.ssh && chmod -R go-rwx /home/{user}/.ssh",
+            user = user,
+            key = public_key.trim()
+        ));
+    }
+
+    /// Runs `script` as root in the container, panicking when it fails.
+    pub fn exec(&self, script: &str) -> String {
+        let out = docker(&["exec", &self.id, "sh", "-c", script]);
+        assert!(
+            out.status.success(),
+            "{} failed in the container: {}",
+            script,
+            stderr(&out)
+        );
+        String::from_utf8_lossy(&out.stdout).into_owned()
+    }
+}
+
+impl Drop for TestSshServer {
+    fn drop(&mut self) {
+        let _ = docker(&["rm", "-f", &self.id]);
+    }
+}
+
+/// Whether the integration tests run, building the server image on the first call.
+pub fn enabled() -> bool {
+    static BUILD: Once = Once::new();
+    if std::env::var("ANSIBLE_RS_IT").ok().as_deref() != Some("1") {
+        eprintln!("Skipped, set ANSIBLE_RS_IT=1 to run the sshd integration tests");
+        return false;
+    }
+    match Command::new("docker").arg("info").output() {
+        Ok(out) if out.status.success() => {}
+        _ => {
+            eprintln!("Skipped, docker is not available");
+            return false;
+        }
+    }
+    BUILD.call_once(|| {
+        let context = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/sshd");
+        let out = docker(&["build", "-q", "-t", IMAGE, &context.to_string_lossy()]);
+        assert!(
+            out.status.success(),
+            "docker build failed: {}",
+            stderr(&out)
+        );
+    });
+    true
+}
+
+fn docker<S: AsRef<std::ffi::OsStr>>(args: &[S]) -> Output {
+    Command::new("docker")
+        .args(args)
+        .output()
+        .expect("failed running docker")
+}
+
+fn stderr(out: &Output) -> String {
+    String::from_utf8_lossy(&out.stderr).into_owned()
+}
+
+/// Local port nothing listens on right now.
+fn free_local_port() -> SocketAddr {
+    TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+        .and_then(|listener| listener.local_addr())
+        .expect("no free local port")
+}
+
+/// Waits until `addr` sends an SSH banner; docker accepts on the port before sshd runs.
+fn wait_for_banner(addr: SocketAddr) {
+    let start = Instant::now();
+    while start.elapsed() < STARTUP_TIMEOUT {
+        if let Ok(mut stream) = TcpStream::connect_timeout(&addr, Duration::from_secs(1)) {
+            let _ = stream.set_read_timeout(Some(Duration::from_secs(1)));
+            let mut banner = [0u8; 4];
+            if stream.read_exact(&mut banner).is_ok() && &banner == b"SSH-" {
+                return;
+            }
+        }
+        thread::sleep(Duration::from_millis(200));
+    }
+    panic!("sshd did not come up on {}", addr);
+}
@@ -0,0 +1,80 @@
+//! Hosts with the docker and podman connections run in containers through `<runtime> exec`,
+//! and `k8s://` hosts in pods through `kubectl exec`, or the exec API with the `kube` feature.
+
+use ansible_rs::prelude::*;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+
+#[test]
+fn container_connections_are_parsed() {
+    assert_eq!("docker".parse::<Connection>(), Ok(Connection::Docker));
+    assert_eq!("podman".parse::<Connection>(), Ok(Connection::Podman));
+}
+
+#[test]
+fn command_runs_through_the_runtime() {
+    // Stand-in runtimes printing what they were asked to run.
+    let dir = env::temp_dir().join(format!("ansible-rs-container-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    for runtime in &["docker", "podman", "kubectl"] {
+        let path = dir.join(runtime);
+        fs::write(&path, format!("#!/bin/sh\necho {} \"$@\"\n", runtime)).unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+    let path = format!("{}:{}", dir.display(), env::var("PATH").unwrap_or_default());
+    env::set_var("PATH", path);
+
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let docker = HostOptions {
+        connection: Some(Connection::Docker),
+        container: Some("web-1".to_string()),
+        ..HostOptions::default()
+    };
+    let podman = HostOptions {
+        connection: Some(Connection::Podman),
+        ..HostOptions::default()
+    };
+    props
+        .parallel_ssh_process_with_options(vec![
+            ("10.255.255.1:22", "hostname", docker),
+            ("db-1:22", "hostname", podman),
+            (
+                "k8s://default/web-0/nginx",
+                "hostname",
+                HostOptions::default(),
+            ),
+            ("k8s://default", "hostname", HostOptions::default()),
+        ])
+        .unwrap();
+    let responses: Vec<Response> = rx.try_iter().collect();
+    fs::remove_dir_all(&dir).unwrap();
+    let response = |address: &str| responses.iter().find(|r| r.address == address).unwrap();
+
+    assert_eq!(responses.len(), 4);
+    let invalid = response("k8s://default");
+    assert_eq!(invalid.error_kind, Some(ErrorKind::Dns));
+    assert!(
+        invalid.result.contains("Invalid pod address"),
+        "{}",
+        invalid.result
+    );
+    let docker = response("10.255.255.1:22");
+    assert_eq!(docker.result, "docker exec -i web-1 sh -c hostname\n");
+    assert_eq!(docker.connection.as_ref().unwrap().auth_method, "docker");
+    let pod = response("k8s://default/web-0/nginx");
+    if cfg!(feature = "kube") {
+        // No cluster to reach here; kubectl is not run either way.
+        assert!(pod.error_kind.is_some(), "{}", pod.result);
+        assert!(!pod.result.contains("kubectl"), "{}", pod.result);
+    } else {
+        assert_eq!(
+            pod.result,
+            "kubectl exec -i --namespace default web-0 --container nginx -- sh -c hostname\n"
+        );
+    }
+    assert_eq!(
+        response("db-1:22").result,
+        "podman exec -i db-1 sh -c hostname\n"
+    );
+}
@@ -0,0 +1,51 @@
+//! Debug output of settings never shows secrets.
+
+use ansible_rs::prelude::*;
+use std::path::PathBuf;
+
+const SECRET: &str = "hunter2";
+
+#[test]
+fn auth_methods_hide_passwords_and_passphrases() {
+    let chain = vec![
+        AuthMethod::Agent,
+        AuthMethod::KeyFile {
+            path: PathBuf::from("/root/.ssh/id_ed25519"),
+            passphrase: Some(SECRET.to_string()),
+        },
+        AuthMethod::Password {
+            password: SECRET.to_string(),
+        },
+    ];
+    let debug = format!("{:?}", chain);
+    assert!(!debug.contains(SECRET), "{}", debug);
+    assert!(debug.contains("id_ed25519"), "{}", debug);
+    assert!(debug.contains(REDACTED), "{}", debug);
+}
+
+#[test]
+fn proxy_hides_its_password() {
+    let proxy = ProxyConfig {
+        host: "proxy.example.com".to_string(),
+        port: 1080,
+        username: Some("scan".to_string()),
+        password: Some(SECRET.to_string()),
+        remote_dns: false,
+    };
+    let debug = format!("{:?}", proxy);
+    assert!(!debug.contains(SECRET), "{}", debug);
+    assert!(debug.contains("proxy.example.com"), "{}", debug);
+}
+
+#[test]
+fn host_options_hide_the_host_password() {
+    let options = HostOptions {
+        password: Some(SECRET.to_string()),
+        auth_chain: Some(vec![AuthMethod::Password {
+            password: SECRET.to_string(),
+        }]),
+        ..HostOptions::default()
+    };
+    let debug = format!("{:#?}", options);
+    assert!(!debug.contains(SECRET), "{}", debug);
+}
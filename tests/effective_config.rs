@@ -0,0 +1,158 @@
+//! Settings of a host after the global config, its group and its inventory vars.
+
+use ansible_rs::misc::{group_builder, Config, InventoryHost};
+use ansible_rs::prelude::*;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::time::Duration;
+
+const CONFIG: &str = r#"
+threads = 4
+agent_parallelism = 1
+command = "uptime"
+timeout = 500
+user = "global"
+remote_shell = "posix"
+workdir = "/srv/global"
+auth_chain = [{ method = "agent" }]
+timeouts = { connect = "5s" }
+proxy = { host = "127.0.0.1", port = 1080, password = "proxy-secret" }
+
+[output]
+save_to_file = false
+pretty_format = false
+
+[groups.web]
+user = "group"
+timeout = 900
+become = true
+"#;
+
+fn host(last: u8, group: Option<&str>, vars: &[(&str, &str)]) -> InventoryHost {
+    InventoryHost {
+        addr: Ipv4Addr::new(10, 0, 0, last).to_string(),
+        group: group.map(str::to_string),
+        parents: Vec::new(),
+        vars: vars
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect::<BTreeMap<_, _>>(),
+    }
+}
+
+/// Effective config and plan of every host, by address.
+fn resolve(hosts: &[InventoryHost]) -> BTreeMap<Ipv4Addr, (EffectiveHostConfig, PlannedHost)> {
+    let config: Config = toml::from_str(CONFIG).unwrap();
+    let mut resolved = BTreeMap::new();
+    for plan in config.plan_groups(hosts).unwrap() {
+        let mut builder = group_builder(&config, &plan.settings, false, &None);
+        if let Some(group) = &plan.name {
+            builder.group(group.clone());
+        }
+        let (_, props) = builder.build().unwrap();
+        for (addr, options) in plan.hosts {
+            let addr: Ipv4Addr = addr.parse().unwrap();
+            let target = SocketAddr::from((addr, 22));
+            let effective = props.effective_config(&target.into_target(), &options);
+            let planned = props
+                .plan(vec![(target, "uptime", options)])
+                .hosts
+                .remove(0);
+            resolved.insert(addr, (effective, planned));
+        }
+    }
+    resolved
+}
+
+#[test]
+fn host_vars_win_over_group_over_global() {
+    let hosts = [
+        host(
+            1,
+            Some("web"),
+            &[
+                ("user", "deploy"),
+                ("workdir", "/srv/host"),
+                ("remote_shell", "raw"),
+                ("auth_chain", "password:host-secret"),
+                ("class", "appliance"),
+            ],
+        ),
+        host(2, Some("web"), &[]),
+        host(3, None, &[]),
+    ];
+    let resolved = resolve(&hosts);
+
+    // Host vars over the group and the global config.
+    let (web1, _) = &resolved[&Ipv4Addr::new(10, 0, 0, 1)];
+    assert_eq!(web1.user, "deploy");
+    assert_eq!(web1.workdir.as_deref(), Some("/srv/host"));
+    assert_eq!(web1.remote_shell, RemoteShell::Raw);
+    assert_eq!(
+        web1.auth_chain,
+        vec![AuthMethod::Password {
+            password: REDACTED.to_string()
+        }]
+    );
+    assert_eq!(web1.class.as_deref(), Some("appliance"));
+    // The group over the global config.
+    assert!(web1.become_root);
+    assert_eq!(web1.probe_timeout, Duration::from_millis(900));
+    assert_eq!(web1.group.as_deref(), Some("web"));
+    // Only set globally.
+    assert_eq!(web1.timeouts.connect, Some(Duration::from_secs(5)));
+    assert_eq!(web1.port, 22);
+
+    // Group members without vars fall back to the global config for the rest.
+    let (web2, _) = &resolved[&Ipv4Addr::new(10, 0, 0, 2)];
+    assert_eq!(web2.user, "group");
+    assert_eq!(web2.workdir.as_deref(), Some("/srv/global"));
+    assert_eq!(web2.remote_shell, RemoteShell::Posix);
+    assert_eq!(web2.auth_chain, vec![AuthMethod::Agent]);
+
+    // Hosts outside groups only see the global config.
+    let (other, _) = &resolved[&Ipv4Addr::new(10, 0, 0, 3)];
+    assert_eq!(other.user, "global");
+    assert!(!other.become_root);
+    assert_eq!(other.probe_timeout, Duration::from_millis(500));
+    assert_eq!(other.group, None);
+}
+
+#[test]
+fn plan_lists_the_effective_config() {
+    let hosts = [
+        host(1, Some("web"), &[("workdir", "/srv/host")]),
+        host(3, None, &[("auth_chain", "agent,key_file:/root/key")]),
+    ];
+    for (effective, planned) in resolve(&hosts).values() {
+        assert_eq!(&planned.config, effective);
+    }
+}
+
+#[test]
+fn secrets_are_redacted() {
+    let resolved = resolve(&[host(3, None, &[("auth_chain", "password:host-secret")])]);
+    let (config, _) = &resolved[&Ipv4Addr::new(10, 0, 0, 3)];
+    assert_eq!(
+        config.proxy.as_ref().unwrap().password.as_deref(),
+        Some(REDACTED)
+    );
+    let key = AuthMethod::KeyFile {
+        path: PathBuf::from("/root/key"),
+        passphrase: Some("key-secret".to_string()),
+    };
+    let (_, props) = ParallelSshPropsBuilder::default()
+        .auth_chain(vec![key])
+        .build()
+        .unwrap();
+    let config = props.effective_config(&"web1:2222".into_target(), &HostOptions::default());
+    assert_eq!(config.port, 2222);
+    assert_eq!(
+        config.auth_chain,
+        vec![AuthMethod::KeyFile {
+            path: PathBuf::from("/root/key"),
+            passphrase: Some(REDACTED.to_string()),
+        }]
+    );
+}
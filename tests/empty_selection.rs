@@ -0,0 +1,104 @@
+//! Runs given no hosts at all.
+
+use ansible_rs::prelude::*;
+use smol::stream;
+use std::net::SocketAddr;
+
+fn no_hosts() -> Vec<(SocketAddr, String)> {
+    Vec::new()
+}
+
+fn none_given() -> RunError {
+    RunError::NoHostsSelected {
+        loaded: 0,
+        after_groups: 0,
+        after_limit: 0,
+        after_exclude: 0,
+    }
+}
+
+#[test]
+fn empty_run_fails() {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    assert_eq!(props.parallel_ssh_process(no_hosts()), Err(none_given()));
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn empty_run_with_options_fails() {
+    let (_, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let hosts: Vec<(SocketAddr, String, HostOptions)> = Vec::new();
+    assert_eq!(
+        props.parallel_ssh_process_with_options(hosts),
+        Err(none_given())
+    );
+}
+
+#[test]
+fn empty_stream_fails() {
+    let (_, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let hosts = stream::iter(Vec::<SocketAddr>::new());
+    assert_eq!(
+        props.parallel_ssh_process_stream(hosts, "true"),
+        Err(none_given())
+    );
+}
+
+#[test]
+fn empty_run_is_allowed() {
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .allow_empty(true)
+        .build()
+        .unwrap();
+    assert_eq!(props.parallel_ssh_process(no_hosts()), Ok(()));
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn error_names_the_step_which_emptied_the_selection() {
+    assert_eq!(
+        none_given().to_string(),
+        "no hosts selected, none were loaded"
+    );
+    let error = |after_groups, after_limit, after_exclude| {
+        RunError::NoHostsSelected {
+            loaded: 5,
+            after_groups,
+            after_limit,
+            after_exclude,
+        }
+        .to_string()
+    };
+    assert_eq!(
+        error(0, 0, 0),
+        "no hosts selected, --limit matched none of the 5 loaded"
+    );
+    assert_eq!(
+        error(0, 3, 0),
+        "no hosts selected, --limit excluded all 3 it matched"
+    );
+    assert_eq!(
+        error(0, 3, 2),
+        "no hosts selected, no group run has any of the 2 left"
+    );
+}
+
+#[test]
+fn error_has_the_selection_of_the_props() {
+    let selection = HostSelection {
+        loaded: 8,
+        after_groups: 0,
+        after_limit: 3,
+        after_exclude: 0,
+    };
+    let (_, props) = ParallelSshPropsBuilder::default()
+        .host_selection(selection)
+        .build()
+        .unwrap();
+    let error = props.parallel_ssh_process(no_hosts()).unwrap_err();
+    assert_eq!(error, RunError::from(selection));
+    assert_eq!(
+        error.to_string(),
+        "no hosts selected, --limit excluded all 3 it matched"
+    );
+}
@@ -0,0 +1,114 @@
+//! Error codes are stable: each `ErrorKind` has one, and none ever changes.
+
+#![cfg(feature = "cli")]
+
+use ansible_rs::prelude::*;
+use std::collections::HashSet;
+
+/// The published codes. The match is exhaustive, so this test stops compiling when a kind
+/// is added without its code being listed here; existing lines must never change.
+fn published_code(kind: ErrorKind) -> &'static str {
+    match kind {
+        ErrorKind::Dns => "E_DNS",
+        ErrorKind::TcpConnect => "E_TCP_CONNECT",
+        ErrorKind::TcpTimeout => "E_TCP_TIMEOUT",
+        ErrorKind::Session => "E_SESSION",
+        ErrorKind::Handshake => "E_HANDSHAKE",
+        ErrorKind::Auth => "E_AUTH",
+        ErrorKind::Agent => "E_AGENT",
+        ErrorKind::Channel => "E_CHANNEL",
+        ErrorKind::Exec => "E_EXEC",
+        ErrorKind::Read => "E_READ",
+        ErrorKind::Timeout => "E_TIMEOUT",
+        ErrorKind::Cancelled => "E_CANCELLED",
+        ErrorKind::Proxy => "E_PROXY",
+        ErrorKind::Bind => "E_BIND",
+        ErrorKind::Skipped => "E_SKIPPED",
+        ErrorKind::HandshakeTimeout => "E_HANDSHAKE_TIMEOUT",
+        ErrorKind::AuthTimeout => "E_AUTH_TIMEOUT",
+        ErrorKind::ExecTimeout => "E_EXEC_TIMEOUT",
+        ErrorKind::ReadIdleTimeout => "E_READ_IDLE_TIMEOUT",
+        ErrorKind::ReadTotalTimeout => "E_READ_TOTAL_TIMEOUT",
+        ErrorKind::HostKeyChanged => "E_HOST_KEY_CHANGED",
+        ErrorKind::GuardSatisfied => "E_GUARD_SATISFIED",
+        ErrorKind::MaintenanceMode => "E_MAINTENANCE_MODE",
+        ErrorKind::PostConditionTimeout => "E_POST_CONDITION_TIMEOUT",
+        ErrorKind::ChecksumMismatch => "E_CHECKSUM_MISMATCH",
+        ErrorKind::LocalFile => "E_LOCAL_FILE",
+        ErrorKind::MissingFact => "E_MISSING_FACT",
+        ErrorKind::Upload => "E_UPLOAD",
+        ErrorKind::Template => "E_TEMPLATE",
+        ErrorKind::Become => "E_BECOME",
+    }
+}
+
+/// Every kind, in declaration order; a kind added to `published_code` goes here too.
+const KINDS: [ErrorKind; 30] = [
+    ErrorKind::Dns,
+    ErrorKind::TcpConnect,
+    ErrorKind::TcpTimeout,
+    ErrorKind::Session,
+    ErrorKind::Handshake,
+    ErrorKind::Auth,
+    ErrorKind::Agent,
+    ErrorKind::Channel,
+    ErrorKind::Exec,
+    ErrorKind::Read,
+    ErrorKind::Timeout,
+    ErrorKind::Cancelled,
+    ErrorKind::Proxy,
+    ErrorKind::Bind,
+    ErrorKind::Skipped,
+    ErrorKind::HandshakeTimeout,
+    ErrorKind::AuthTimeout,
+    ErrorKind::ExecTimeout,
+    ErrorKind::ReadIdleTimeout,
+    ErrorKind::ReadTotalTimeout,
+    ErrorKind::HostKeyChanged,
+    ErrorKind::GuardSatisfied,
+    ErrorKind::MaintenanceMode,
+    ErrorKind::PostConditionTimeout,
+    ErrorKind::ChecksumMismatch,
+    ErrorKind::LocalFile,
+    ErrorKind::MissingFact,
+    ErrorKind::Upload,
+    ErrorKind::Template,
+    ErrorKind::Become,
+];
+
+#[test]
+fn every_kind_keeps_its_published_code() {
+    for kind in KINDS.iter() {
+        assert_eq!(kind.code(), published_code(*kind), "{:?}", kind);
+        assert_eq!(kind.to_string(), kind.code());
+    }
+}
+
+#[test]
+fn codes_are_unique_and_parse_back() {
+    let mut seen = HashSet::new();
+    for kind in KINDS.iter() {
+        let code = kind.code();
+        assert!(seen.insert(code), "{} used twice", code);
+        assert!(
+            code.starts_with("E_")
+                && code[2..]
+                    .chars()
+                    .all(|c| c.is_ascii_uppercase() || c == '_'),
+            "{}",
+            code
+        );
+        assert_eq!(code.parse::<ErrorKind>(), Ok(*kind));
+    }
+    assert!("E_NOT_A_KIND".parse::<ErrorKind>().is_err());
+}
+
+#[test]
+fn codes_are_what_gets_serialized() {
+    for kind in KINDS.iter() {
+        let json = serde_json::to_string(kind).unwrap();
+        assert_eq!(json, format!("\"{}\"", kind.code()));
+        let back: ErrorKind = serde_json::from_str(&json).unwrap();
+        assert_eq!(back, *kind);
+    }
+}
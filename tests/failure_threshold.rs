@@ -0,0 +1,71 @@
+//! Runs aborted once too many hosts failed.
+
+mod common;
+
+use ansible_rs::prelude::*;
+use common::unreachable_hosts;
+
+#[test]
+fn threshold_is_checked() {
+    for threshold in &[
+        FailureThreshold::default(),
+        FailureThreshold {
+            max_fail_percent: Some(100.0),
+            ..FailureThreshold::default()
+        },
+    ] {
+        assert!(threshold.validate().is_err());
+        assert!(ParallelSshPropsBuilder::default()
+            .failure_threshold(*threshold)
+            .build()
+            .is_err());
+    }
+}
+
+#[test]
+fn hosts_after_the_threshold_are_cancelled() {
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .serial(Serial::Hosts(1))
+        .failure_threshold(FailureThreshold {
+            max_failures: Some(2),
+            ..FailureThreshold::default()
+        })
+        .build()
+        .unwrap();
+    let error = props
+        .parallel_ssh_process(unreachable_hosts(6))
+        .unwrap_err();
+    assert!(matches!(error, RunError::TooManyFailures(_)), "{}", error);
+
+    let responses: Vec<Response> = rx.try_iter().collect();
+    assert_eq!(responses.len(), 6);
+    let failed = responses
+        .iter()
+        .filter(|r| r.error_kind == Some(ErrorKind::TcpConnect))
+        .count();
+    assert_eq!(failed, 3);
+    let cancelled: Vec<_> = responses
+        .iter()
+        .filter(|r| r.error_kind == Some(ErrorKind::Cancelled))
+        .collect();
+    assert_eq!(cancelled.len(), 3);
+    assert!(cancelled[0].result.contains("max_failures 2"));
+
+    // The next run of the props starts counting anew.
+    props.parallel_ssh_process(unreachable_hosts(2)).unwrap();
+}
+
+#[test]
+fn percent_waits_for_enough_hosts() {
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .failure_threshold(FailureThreshold {
+            max_fail_percent: Some(50.0),
+            min_completed: 5,
+            ..FailureThreshold::default()
+        })
+        .build()
+        .unwrap();
+    props.parallel_ssh_process(unreachable_hosts(4)).unwrap();
+    assert_eq!(rx.try_iter().count(), 4);
+    assert!(props.parallel_ssh_process(unreachable_hosts(5)).is_err());
+}
@@ -0,0 +1,81 @@
+//! Names of output files are valid on Windows and Unix alike, whatever the host or run id.
+#![cfg(feature = "cli")]
+
+use ansible_rs::misc::sanitize_file_name;
+
+/// What Windows refuses in a file name.
+fn valid_on_windows(name: &str) -> bool {
+    let stem = name.split('.').next().unwrap().to_ascii_uppercase();
+    let device = ["CON", "PRN", "AUX", "NUL"].contains(&stem.as_str())
+        || ((stem.starts_with("COM") || stem.starts_with("LPT"))
+            && stem.len() == 4
+            && stem.as_bytes()[3].is_ascii_digit()
+            && stem.as_bytes()[3] != b'0');
+    !name.is_empty()
+        && !device
+        && !name.ends_with('.')
+        && !name.ends_with(' ')
+        && !name
+            .chars()
+            .any(|c| c.is_control() || "<>:\"/\\|?*".contains(c))
+}
+
+#[test]
+fn reserved_characters_and_separators_are_replaced() {
+    assert_eq!(sanitize_file_name("CHG:1"), "CHG_1");
+    assert_eq!(sanitize_file_name("a/b\\c"), "a_b_c");
+    assert_eq!(sanitize_file_name("<>:\"|?*"), "_______");
+    assert_eq!(sanitize_file_name("tab\there\nnul\0"), "tab_here_nul_");
+    assert_eq!(sanitize_file_name("../../etc/passwd"), ".._.._etc_passwd");
+}
+
+#[test]
+fn trailing_dots_and_spaces_are_dropped() {
+    assert_eq!(sanitize_file_name("results. . "), "results");
+    assert_eq!(sanitize_file_name("."), "_");
+    assert_eq!(sanitize_file_name(".."), "_");
+    assert_eq!(sanitize_file_name(" "), "_");
+    assert_eq!(sanitize_file_name(""), "_");
+    assert_eq!(sanitize_file_name(".hidden"), ".hidden");
+}
+
+#[test]
+fn device_names_get_a_prefix_whatever_the_case_and_extension() {
+    assert_eq!(sanitize_file_name("NUL"), "_NUL");
+    assert_eq!(sanitize_file_name("nul.json"), "_nul.json");
+    assert_eq!(sanitize_file_name("Com1.txt"), "_Com1.txt");
+    assert_eq!(sanitize_file_name("lpt9"), "_lpt9");
+    assert_eq!(sanitize_file_name("CON."), "_CON");
+    // Only the exact names are devices.
+    assert_eq!(sanitize_file_name("console"), "console");
+    assert_eq!(sanitize_file_name("COM10"), "COM10");
+    assert_eq!(sanitize_file_name("nul_hosts.txt"), "nul_hosts.txt");
+}
+
+#[test]
+fn unicode_is_kept() {
+    assert_eq!(sanitize_file_name("Größe-日本語-🚀"), "Größe-日本語-🚀");
+}
+
+#[test]
+fn every_name_becomes_valid() {
+    let pieces = [
+        "", ".", " ", "..", ":", "/", "\\", "NUL", "com1", "a", "é", "\u{7}", "*", "x.",
+    ];
+    for a in &pieces {
+        for b in &pieces {
+            for c in &pieces {
+                let name = format!("{}{}{}", a, b, c);
+                let sanitized = sanitize_file_name(&name);
+                assert!(
+                    valid_on_windows(&sanitized),
+                    "{:?} -> {:?}",
+                    name,
+                    sanitized
+                );
+                // Sanitizing is idempotent, so a sanitized name is kept as it is.
+                assert_eq!(sanitize_file_name(&sanitized), sanitized);
+            }
+        }
+    }
+}
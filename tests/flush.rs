@@ -0,0 +1,123 @@
+//! What a writer coalescing its records loses when it is killed mid-run.
+//!
+//! The writer runs as a child process, this test binary run again with `WRITER_ENV` set.
+//! It writes a record every `RECORD_INTERVAL` and prints the number of each record to
+//! stdout once it is buffered, so the test knows how far it got when it kills it.
+
+use ansible_rs::prelude::*;
+use ansible_rs::shared_file::SharedFile;
+use std::env;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const WRITER_ENV: &str = "ANSIBLE_RS_FLUSH_WRITER";
+const RECORD_INTERVAL: Duration = Duration::from_millis(5);
+
+fn policy_of(spec: &str) -> FlushPolicy {
+    let mut fields = spec.split(',').map(|f| f.parse().unwrap());
+    FlushPolicy {
+        max_records: fields.next().unwrap() as usize,
+        max_bytes: 1 << 30,
+        max_delay_ms: fields.next().unwrap(),
+    }
+}
+
+/// Body of the child process; returns at once in a normal test run.
+#[test]
+fn writer() {
+    let (path, spec) = match env::var(WRITER_ENV) {
+        Ok(value) => {
+            let mut parts = value.splitn(2, ';');
+            let path = PathBuf::from(parts.next().unwrap());
+            (path, parts.next().unwrap().to_string())
+        }
+        Err(_) => return,
+    };
+    let mut file = SharedFile::open(&path).unwrap();
+    let mut coalescer = Coalescer::new(policy_of(&spec));
+    let stdout = std::io::stdout();
+    for seq in 0.. {
+        coalescer.push(format!("{{\"seq\":{}}}\n", seq).into_bytes());
+        if coalescer.due() {
+            file.append(&coalescer.take().concat()).unwrap();
+        }
+        writeln!(stdout.lock(), "{}", seq).unwrap();
+        thread::sleep(RECORD_INTERVAL);
+    }
+}
+
+/// Kills a writer with `policy` after about a second; returns the number of the last
+/// record it buffered and the records found in its file.
+fn kill_writer(name: &str, policy: &str) -> (u64, Vec<u64>) {
+    let path = env::temp_dir().join(format!(
+        "ansible-rs-flush-{}-{}.ndjson",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&path);
+    let mut child = Command::new(env::current_exe().unwrap())
+        .args(&["--exact", "writer", "--nocapture", "--test-threads=1"])
+        .env(WRITER_ENV, format!("{};{}", path.display(), policy))
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = BufReader::new(child.stdout.take().unwrap()).lines();
+    let start = Instant::now();
+    let mut buffered = None;
+    while start.elapsed() < Duration::from_secs(1) {
+        // The test harness prints lines of its own around the writer's.
+        if let Some(Ok(seq)) = lines.next().map(|l| l.unwrap().parse::<u64>()) {
+            buffered = Some(seq);
+        }
+    }
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let content = fs::read_to_string(&path).unwrap_or_default();
+    let _ = fs::remove_file(&path);
+    let written = content
+        .lines()
+        .map(|line| {
+            line.trim_start_matches("{\"seq\":")
+                .trim_end_matches('}')
+                .parse()
+                .unwrap_or_else(|_| panic!("partial record {:?}", line))
+        })
+        .collect();
+    (buffered.expect("the writer printed nothing"), written)
+}
+
+fn assert_in_order(written: &[u64]) {
+    for (i, seq) in written.iter().enumerate() {
+        assert_eq!(*seq, i as u64, "records lost before the last flush");
+    }
+}
+
+#[test]
+fn killed_writer_loses_at_most_max_records() {
+    let (buffered, written) = kill_writer("records", "10,60000");
+    assert_in_order(&written);
+    assert!(
+        buffered + 1 - written.len() as u64 <= 10,
+        "{} records buffered, {} written",
+        buffered + 1,
+        written.len()
+    );
+}
+
+#[test]
+fn killed_writer_loses_at_most_max_delay() {
+    let (buffered, written) = kill_writer("delay", "1000000,100");
+    assert_in_order(&written);
+    // 100ms of records, with room for a slow scheduler.
+    let bound = 2 * 100 / RECORD_INTERVAL.as_millis() as u64 + 5;
+    assert!(
+        buffered + 1 - written.len() as u64 <= bound,
+        "{} records buffered, {} written",
+        buffered + 1,
+        written.len()
+    );
+}
@@ -0,0 +1,120 @@
+//! Per-host passwords kept apart from the inventory.
+
+use ansible_rs::misc::{host_options, load_host_passwords, InventoryHost};
+use ansible_rs::prelude::*;
+use std::collections::BTreeMap;
+use std::env;
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::PathBuf;
+
+fn passwords_file(name: &str, content: &str, mode: u32) -> PathBuf {
+    let path = env::temp_dir().join(format!("ansible-rs-{}-{}.csv", name, std::process::id()));
+    fs::write(&path, content).unwrap();
+    fs::set_permissions(&path, fs::Permissions::from_mode(mode)).unwrap();
+    path
+}
+
+/// Inventory of hosts named by `addrs`.
+fn inventory(addrs: &[&str]) -> Vec<InventoryHost> {
+    addrs
+        .iter()
+        .map(|addr| InventoryHost {
+            addr: addr.to_string(),
+            group: None,
+            parents: Vec::new(),
+            vars: BTreeMap::new(),
+        })
+        .collect()
+}
+
+#[test]
+fn passwords_are_read_by_inventory_host() {
+    let path = passwords_file(
+        "passwords",
+        "10.0.0.1,s3cret\nswitch-1.example.com,\"a,b\"\nfe80::1,v6\n",
+        0o600,
+    );
+    let hosts = inventory(&["10.0.0.1", "switch-1.example.com", "fe80::1", "10.0.0.9"]);
+    let passwords = load_host_passwords(&path, &hosts);
+    fs::remove_file(&path).unwrap();
+    let passwords = passwords.unwrap();
+    assert_eq!(passwords["10.0.0.1"], "s3cret");
+    assert_eq!(passwords["switch-1.example.com"], "a,b");
+    assert_eq!(passwords["fe80::1"], "v6");
+    assert_eq!(passwords.len(), 3);
+}
+
+#[test]
+fn password_of_a_host_not_in_the_inventory_is_refused() {
+    let path = passwords_file(
+        "passwords-unknown",
+        "10.0.0.1,s3cret\nswitch-9,s3cret\n",
+        0o600,
+    );
+    let passwords = load_host_passwords(&path, &inventory(&["10.0.0.1", "switch-1"]));
+    fs::remove_file(&path).unwrap();
+    let error = passwords.unwrap_err();
+    assert!(error.contains("row 2 names no host"), "{}", error);
+    assert!(!error.contains("s3cret"), "{}", error);
+}
+
+#[test]
+fn inventory_var_sets_the_password() {
+    let mut vars = BTreeMap::new();
+    vars.insert("ansible_password".to_string(), "s3cret".to_string());
+    vars.insert("class".to_string(), "switch".to_string());
+    let options = host_options(&vars).unwrap();
+    assert_eq!(options.password.as_deref(), Some("s3cret"));
+    assert!(!options.vars.contains_key("ansible_password"));
+    assert_eq!(options.vars["class"], "switch");
+}
+
+#[test]
+fn file_readable_by_others_is_refused() {
+    let path = passwords_file("passwords-open", "10.0.0.1,s3cret\n", 0o644);
+    let passwords = load_host_passwords(&path, &inventory(&["10.0.0.1"]));
+    fs::remove_file(&path).unwrap();
+    assert!(passwords.unwrap_err().contains("chmod 600"));
+}
+
+#[test]
+fn errors_do_not_show_the_row() {
+    let path = passwords_file(
+        "passwords-bad",
+        "10.0.0.1,s3cret\n10.0.0.2,s3cret,extra\n",
+        0o600,
+    );
+    let passwords = load_host_passwords(&path, &inventory(&["10.0.0.1", "10.0.0.2"]));
+    fs::remove_file(&path).unwrap();
+    let error = passwords.unwrap_err();
+    assert!(error.contains("row 2"), "{}", error);
+    assert!(!error.contains("s3cret"), "{}", error);
+}
+
+#[test]
+fn host_password_replaces_the_password_of_the_chain() {
+    let (_, props) = ParallelSshPropsBuilder::default()
+        .auth_chain(vec![
+            AuthMethod::Password {
+                password: "shared".to_string(),
+            },
+            AuthMethod::Agent,
+        ])
+        .build()
+        .unwrap();
+    let options = HostOptions {
+        password: Some("own".to_string()),
+        ..HostOptions::default()
+    };
+    let config = props.effective_config(&"10.0.0.1".into_target(), &options);
+    assert_eq!(
+        config.auth_chain,
+        vec![
+            AuthMethod::Agent,
+            AuthMethod::Password {
+                password: REDACTED.to_string()
+            },
+        ]
+    );
+}
@@ -0,0 +1,51 @@
+//! Hosts given as `[user@]host[:port]`.
+
+use ansible_rs::prelude::*;
+use std::net::SocketAddr;
+
+fn spec(s: &str) -> HostSpec {
+    s.parse().unwrap()
+}
+
+#[test]
+fn user_and_port_are_split_off() {
+    assert_eq!(
+        spec("deploy@web1:2222"),
+        HostSpec {
+            user: Some("deploy".to_string()),
+            target: HostTarget::Name("web1:2222".to_string()),
+        }
+    );
+    assert_eq!(
+        spec("web1"),
+        HostSpec {
+            user: None,
+            target: HostTarget::Name("web1:22".to_string()),
+        }
+    );
+}
+
+#[test]
+fn addresses_skip_resolution() {
+    let addr: SocketAddr = "10.0.0.5:22".parse().unwrap();
+    assert_eq!(spec("root@10.0.0.5").target, addr.into_target());
+    let addr: SocketAddr = "[::1]:2200".parse().unwrap();
+    assert_eq!(spec("root@[::1]:2200").target, addr.into_target());
+    let addr: SocketAddr = "[::1]:22".parse().unwrap();
+    assert_eq!(spec("::1").target, addr.into_target());
+}
+
+#[test]
+fn invalid_specs_are_refused() {
+    for s in &["@web1", "deploy@", "web1:ssh", ":22", "web1:70000"] {
+        assert!(s.parse::<HostSpec>().is_err(), "{}", s);
+    }
+}
+
+#[test]
+fn user_becomes_the_host_override() {
+    let (target, command, options) = spec("deploy@web1").with_command("uptime");
+    assert_eq!(target, HostTarget::Name("web1:22".to_string()));
+    assert_eq!(command, "uptime");
+    assert_eq!(options.user.as_deref(), Some("deploy"));
+}
@@ -0,0 +1,34 @@
+//! Overall time limit of a host, `timeout_ssh`.
+
+use ansible_rs::prelude::*;
+use std::net::TcpListener;
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[test]
+fn hung_handshake_times_out_the_host() {
+    // Accepts connections and keeps them open without saying a word.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let open: Vec<_> = listener.incoming().collect();
+        drop(open);
+    });
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .timeout_ssh(Duration::from_millis(500))
+        .build()
+        .unwrap();
+    let start = Instant::now();
+    props.parallel_ssh_process(vec![(host, "true")]).unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.error_kind, Some(ErrorKind::Timeout));
+    assert!(start.elapsed() < Duration::from_secs(10));
+}
+
+#[test]
+fn zero_limit_is_refused() {
+    assert!(ParallelSshPropsBuilder::default()
+        .timeout_ssh(Duration::from_secs(0))
+        .build()
+        .is_err());
+}
@@ -0,0 +1,138 @@
+//! `type = "http"` inventories, fetched from a stand-in server answering once.
+#![cfg(feature = "http-inventory")]
+
+use ansible_rs::http_inventory::HttpInventory;
+use ansible_rs::inventory_source::InventorySource;
+use std::io::{Read, Write};
+use std::net::TcpListener;
+use std::thread;
+
+const HOSTS: &str = r#"{"data": [{"ip": "10.0.0.1", "role": "web"}, {"ip": "10.0.0.2"}]}"#;
+
+/// Serves `response` to one request, returning the URL and the head of the request.
+fn serve(response: Vec<u8>) -> (String, thread::JoinHandle<String>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/api/hosts", listener.local_addr().unwrap());
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut request = Vec::new();
+        let mut buffer = [0; 1024];
+        while !request.ends_with(b"\r\n\r\n") {
+            let n = stream.read(&mut buffer).unwrap();
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buffer[..n]);
+        }
+        stream.write_all(&response).unwrap();
+        String::from_utf8(request).unwrap()
+    });
+    (url, server)
+}
+
+fn inventory(url: String) -> HttpInventory {
+    let mut source: HttpInventory =
+        toml::from_str(&format!("url = {:?}\nhosts_pointer = \"/data\"", url)).unwrap();
+    source.fields.address = "/ip".to_string();
+    source.fields.group = Some("/role".to_string());
+    source.timeout_secs = 5;
+    source
+}
+
+fn load(source: &HttpInventory) -> Result<Vec<(String, Option<String>)>, String> {
+    let inventory = smol::run(source.load())?;
+    Ok(inventory
+        .hosts
+        .into_iter()
+        .map(|host| (host.addr.to_string(), host.group))
+        .collect())
+}
+
+fn expected() -> Vec<(String, Option<String>)> {
+    vec![
+        ("10.0.0.1".to_string(), Some("web".to_string())),
+        ("10.0.0.2".to_string(), None),
+    ]
+}
+
+#[test]
+fn content_length_body_is_read_to_its_length() {
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nContent-Type: application/json\r\nContent-Length: {}\r\n\r\n{}",
+        HOSTS.len(),
+        HOSTS
+    );
+    let (url, server) = serve(response.into_bytes());
+    let mut source = inventory(url);
+    source.auth_header = Some("Authorization: Bearer 0123abcd".to_string());
+    assert_eq!(load(&source), Ok(expected()));
+
+    let request = server.join().unwrap();
+    assert!(
+        request.starts_with("GET /api/hosts HTTP/1.1\r\n"),
+        "{}",
+        request
+    );
+    assert!(
+        request
+            .to_ascii_lowercase()
+            .contains("authorization: bearer 0123abcd\r\n"),
+        "{}",
+        request
+    );
+}
+
+#[test]
+fn chunked_body_is_joined() {
+    let (first, second) = HOSTS.split_at(17);
+    let response = format!(
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x};ext=1\r\n{}\r\n{:X}\r\n{}\r\n0\r\n\r\n",
+        first.len(),
+        first,
+        second.len(),
+        second
+    );
+    let (url, server) = serve(response.into_bytes());
+    assert_eq!(load(&inventory(url)), Ok(expected()));
+    server.join().unwrap();
+}
+
+#[test]
+fn truncated_bodies_are_errors() {
+    let short = format!(
+        "HTTP/1.1 200 OK\r\nContent-Length: {}\r\n\r\n{}",
+        HOSTS.len() + 10,
+        HOSTS
+    );
+    let (url, server) = serve(short.into_bytes());
+    assert!(load(&inventory(url)).is_err());
+    server.join().unwrap();
+
+    let cut = format!(
+        "HTTP/1.1 200 OK\r\nTransfer-Encoding: chunked\r\n\r\n{:x}\r\n{}",
+        HOSTS.len() + 10,
+        HOSTS
+    );
+    let (url, server) = serve(cut.into_bytes());
+    assert!(load(&inventory(url)).is_err());
+    server.join().unwrap();
+}
+
+#[test]
+fn error_status_is_reported() {
+    let response = b"HTTP/1.1 403 Forbidden\r\nContent-Length: 0\r\n\r\n".to_vec();
+    let (url, server) = serve(response);
+    let error = load(&inventory(url)).unwrap_err();
+    assert!(error.contains("403"), "{}", error);
+    server.join().unwrap();
+}
+
+#[test]
+fn urls_are_validated() {
+    let mut source = inventory("https://cmdb.internal/api/hosts".to_string());
+    assert_eq!(source.validate(), Ok(()));
+    source.url = "ftp://cmdb.internal/hosts".to_string();
+    assert!(source.validate().is_err());
+    source.url = "http:///hosts".to_string();
+    assert!(source.validate().is_err());
+}
@@ -0,0 +1,157 @@
+//! Networks and ranges in inventory entries.
+
+use ansible_rs::inventory::expand;
+use ansible_rs::inventory_source::Inventory;
+use ansible_rs::misc::{grouped_hosts_builder, Config};
+use ansible_rs::prelude::*;
+use std::env;
+use std::fs;
+use std::net::SocketAddr;
+
+fn targets(entry: &str) -> Vec<String> {
+    expand(entry)
+        .unwrap()
+        .into_iter()
+        .map(|spec| spec.target.to_string())
+        .collect()
+}
+
+#[test]
+fn network_leaves_out_network_and_broadcast() {
+    assert_eq!(targets("10.0.0.0/30"), vec!["10.0.0.1:22", "10.0.0.2:22"]);
+    assert_eq!(targets("10.0.0.7/32"), vec!["10.0.0.7:22"]);
+    assert_eq!(targets("10.0.0.5/31"), vec!["10.0.0.4:22", "10.0.0.5:22"]);
+    assert_eq!(expand("10.0.0.0/24").unwrap().len(), 254);
+}
+
+#[test]
+fn user_and_port_apply_to_every_host() {
+    let specs = expand("deploy@10.0.0.0/30:2222").unwrap();
+    let addr: SocketAddr = "10.0.0.2:2222".parse().unwrap();
+    assert_eq!(specs[1].target, addr.into_target());
+    assert!(specs.iter().all(|s| s.user.as_deref() == Some("deploy")));
+    let specs = expand("root@db[1:2]:2200").unwrap();
+    assert_eq!(specs[1].target, HostTarget::Name("db2:2200".to_string()));
+    assert_eq!(specs[1].user.as_deref(), Some("root"));
+}
+
+#[test]
+fn ranges_keep_padding_and_multiply() {
+    assert_eq!(
+        targets("web[08:10].prod.example.com"),
+        vec![
+            "web08.prod.example.com:22",
+            "web09.prod.example.com:22",
+            "web10.prod.example.com:22"
+        ]
+    );
+    assert_eq!(
+        targets("rack[a:b]-[1:5:2]"),
+        vec![
+            "racka-1:22",
+            "racka-3:22",
+            "racka-5:22",
+            "rackb-1:22",
+            "rackb-3:22",
+            "rackb-5:22"
+        ]
+    );
+    assert_eq!(
+        targets("10.0.[1:2].0/31"),
+        vec!["10.0.1.0:22", "10.0.1.1:22", "10.0.2.0:22", "10.0.2.1:22"]
+    );
+}
+
+#[test]
+fn plain_entries_are_single_hosts() {
+    assert_eq!(targets("web1"), vec!["web1:22"]);
+    assert_eq!(targets("[::1]:2200"), vec!["[::1]:2200"]);
+}
+
+#[test]
+fn invalid_and_huge_entries_are_refused() {
+    for entry in &[
+        "10.0.0.0/33",
+        "web[5:1]",
+        "web[1:3:0]",
+        "10.0.0.0/8",
+        "h[0:999]-[0:999]",
+        "::/64",
+        "10.0.0.0/30:ssh",
+    ] {
+        assert!(expand(entry).is_err(), "{}", entry);
+    }
+}
+
+#[test]
+fn hosts_file_expands_addresses() {
+    let path = env::temp_dir().join(format!("ansible-rs-expand-{}", std::process::id()));
+    fs::write(
+        &path,
+        "[web]\ndeploy@10.0.0.0/30 class=web\n10.0.1.[5:6]\nweb[1:3].example.com\n",
+    )
+    .unwrap();
+    let hosts = grouped_hosts_builder(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let addrs: Vec<&str> = hosts.iter().map(|h| h.addr.as_str()).collect();
+    assert_eq!(
+        addrs,
+        vec![
+            "10.0.0.1",
+            "10.0.0.2",
+            "10.0.1.5",
+            "10.0.1.6",
+            "web1.example.com",
+            "web2.example.com",
+            "web3.example.com",
+        ]
+    );
+    assert_eq!(hosts[1].vars["user"], "deploy");
+    assert_eq!(hosts[1].vars["class"], "web");
+    assert_eq!(hosts[2].group.as_deref(), Some("web"));
+}
+
+#[test]
+fn hostname_range_with_a_port_reaches_the_run() {
+    let path = env::temp_dir().join(format!("ansible-rs-expand-run-{}", std::process::id()));
+    fs::write(
+        &path,
+        "[web]\nweb[01:02].example.invalid:2222 connection=local\n10.0.1.7:2200 connection=local\n",
+    )
+    .unwrap();
+    let hosts = grouped_hosts_builder(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    let config = Config::default();
+    let mut inventory = Inventory {
+        hosts,
+        ..Inventory::default()
+    };
+    let plans = config.select_hosts(&mut inventory, None).unwrap();
+    let hosts: Vec<_> = plans
+        .iter()
+        .flat_map(|plan| plan.targets(&inventory.commands))
+        .map(|(host, _, options)| (host, "echo ran", options))
+        .collect();
+
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    props.parallel_ssh_process_with_options(hosts).unwrap();
+    let mut ran: Vec<(String, String)> = rx
+        .try_iter()
+        .map(|response| (response.address, response.result))
+        .collect();
+    ran.sort();
+    assert_eq!(
+        ran,
+        vec![
+            ("10.0.1.7:2200".to_string(), "ran\n".to_string()),
+            (
+                "web01.example.invalid:2222".to_string(),
+                "ran\n".to_string()
+            ),
+            (
+                "web02.example.invalid:2222".to_string(),
+                "ran\n".to_string()
+            ),
+        ]
+    );
+}
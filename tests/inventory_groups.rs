@@ -0,0 +1,132 @@
+//! Group children, group vars and `--limit` of the hosts file.
+
+use ansible_rs::inventory_source::Inventory;
+use ansible_rs::misc::{grouped_hosts_builder, host_options, host_port, Config, InventoryHost};
+use std::collections::BTreeSet;
+use std::env;
+use std::fs;
+
+const HOSTS: &str = "
+# ansible style
+[web]
+10.0.0.1
+10.0.0.2 ansible_port=2222
+
+[db]
+db1 ansible_host=10.0.1.1 ansible_user=postgres
+
+[staging]
+10.0.0.2
+
+[prod:children]
+web
+db
+
+[all:vars]
+class=linux
+
+[prod:vars]
+ansible_user=deploy
+class=prod
+
+[db:vars]
+class=database
+";
+
+fn load(test: &str) -> Vec<InventoryHost> {
+    let path = env::temp_dir().join(format!("ansible-rs-{}-{}", test, std::process::id()));
+    fs::write(&path, HOSTS).unwrap();
+    let hosts = grouped_hosts_builder(&path).unwrap();
+    fs::remove_file(&path).unwrap();
+    hosts
+}
+
+fn addrs(inventory: &Inventory) -> Vec<&str> {
+    let addrs: BTreeSet<_> = inventory.hosts.iter().map(|h| h.addr.as_str()).collect();
+    addrs.into_iter().collect()
+}
+
+#[test]
+fn children_and_vars_apply_to_hosts() {
+    let hosts = load("children");
+    assert_eq!(hosts.len(), 4);
+    assert_eq!(hosts[0].parents, vec!["prod"]);
+    assert_eq!(hosts[0].vars["class"], "prod");
+    assert_eq!(hosts[0].vars["ansible_user"], "deploy");
+    let db = &hosts[2];
+    assert_eq!(db.addr, "10.0.1.1");
+    assert_eq!(db.vars["class"], "database");
+    let options = host_options(&db.vars).unwrap();
+    assert_eq!(options.user.as_deref(), Some("postgres"));
+    assert_eq!(host_port(&hosts[1].vars), Ok(2222));
+    assert_eq!(host_port(&db.vars), Ok(22));
+    // Staging is in no parent group.
+    assert!(hosts[3].parents.is_empty());
+    assert_eq!(hosts[3].vars["class"], "linux");
+}
+
+#[test]
+fn limit_selects_groups_with_their_children() {
+    let mut inventory = Inventory {
+        hosts: load("limit"),
+        ..Inventory::default()
+    };
+    assert_eq!(inventory.limit("prod:!staging"), Ok((3, 2)));
+    assert_eq!(addrs(&inventory), vec!["10.0.0.1", "10.0.1.1"]);
+
+    let mut inventory = Inventory {
+        hosts: load("limit-2"),
+        ..Inventory::default()
+    };
+    inventory.limit("db,10.0.0.2").unwrap();
+    assert_eq!(addrs(&inventory), vec!["10.0.0.2", "10.0.1.1"]);
+}
+
+#[test]
+fn limit_refuses_unknown_names() {
+    let mut inventory = Inventory {
+        hosts: load("unknown"),
+        ..Inventory::default()
+    };
+    let error = inventory.limit("web,webservers").unwrap_err();
+    assert!(error.contains("webservers"), "{}", error);
+}
+
+#[test]
+fn empty_selection_counts_the_hosts_left_after_each_step() {
+    let config = Config::default();
+    let select = |hosts: Vec<InventoryHost>, limit: Option<&str>| {
+        let mut inventory = Inventory {
+            hosts,
+            ..Inventory::default()
+        };
+        config.select_hosts(&mut inventory, limit)
+    };
+
+    let error = select(Vec::new(), None).unwrap_err();
+    assert_eq!(
+        error,
+        "no hosts selected, none were loaded (allow_empty to run anyway)"
+    );
+    let error = select(load("select-exclude"), Some("staging:!web")).unwrap_err();
+    assert_eq!(
+        error,
+        "no hosts selected, --limit excluded all 1 it matched (allow_empty to run anyway)"
+    );
+    let error = select(load("select-all"), Some("all:!prod:!staging")).unwrap_err();
+    assert!(error.contains("excluded all 3 it matched"), "{}", error);
+
+    let plans = select(load("select"), Some("prod:!staging")).unwrap();
+    let planned: usize = plans.iter().map(|plan| plan.hosts.len()).sum();
+    assert_eq!(planned, 2);
+
+    let config = Config {
+        allow_empty: true,
+        ..Config::default()
+    };
+    let mut inventory = Inventory::default();
+    assert!(config
+        .select_hosts(&mut inventory, None)
+        .unwrap()
+        .is_empty());
+}
@@ -0,0 +1,116 @@
+//! Ansible YAML inventories.
+
+use ansible_rs::inventory_source::Inventory;
+use ansible_rs::misc::{host_options, host_port, yaml_hosts_builder, InventoryHost};
+use std::env;
+use std::fs;
+use std::io;
+
+const HOSTS: &str = "
+all:
+  hosts:
+    10.0.9.1:
+  vars:
+    class: linux
+  children:
+    web:
+      hosts:
+        web[01:02].example.com:
+        10.0.0.3:
+          ansible_port: 2222
+          ansible_user: deploy
+      vars:
+        ansible_user: www
+    prod:
+      vars:
+        class: prod
+      children:
+        web:
+        db:
+          hosts:
+            db1:
+              ansible_host: 10.0.1.1
+              primary: true
+              tags: [a, b]
+          vars:
+            class: database
+";
+
+fn load(test: &str, content: &str) -> io::Result<Vec<InventoryHost>> {
+    let path = env::temp_dir().join(format!("ansible-rs-{}-{}.yml", test, std::process::id()));
+    fs::write(&path, content).unwrap();
+    let hosts = yaml_hosts_builder(&path);
+    fs::remove_file(&path).unwrap();
+    hosts
+}
+
+fn host<'a>(hosts: &'a [InventoryHost], addr: &str) -> &'a InventoryHost {
+    hosts.iter().find(|h| h.addr == addr).unwrap()
+}
+
+#[test]
+fn groups_children_and_vars_apply_to_hosts() {
+    let hosts = load("yaml", HOSTS).unwrap();
+    assert_eq!(hosts.len(), 5);
+
+    let ungrouped = host(&hosts, "10.0.9.1");
+    assert_eq!(ungrouped.group, None);
+    assert_eq!(ungrouped.vars["class"], "linux");
+
+    let web = host(&hosts, "web02.example.com");
+    assert_eq!(web.group.as_deref(), Some("web"));
+    assert_eq!(web.parents, vec!["prod"]);
+    assert_eq!(web.vars["class"], "prod");
+    assert_eq!(
+        host_options(&web.vars).unwrap().user.as_deref(),
+        Some("www")
+    );
+    assert_eq!(host_port(&web.vars), Ok(22));
+
+    let web3 = host(&hosts, "10.0.0.3");
+    assert_eq!(
+        host_options(&web3.vars).unwrap().user.as_deref(),
+        Some("deploy")
+    );
+    assert_eq!(host_port(&web3.vars), Ok(2222));
+
+    let db = host(&hosts, "10.0.1.1");
+    assert_eq!(db.group.as_deref(), Some("db"));
+    assert_eq!(db.vars["class"], "database");
+    assert_eq!(db.vars["primary"], "true");
+    assert!(!db.vars.contains_key("tags"));
+}
+
+#[test]
+fn limit_selects_yaml_groups() {
+    let mut inventory = Inventory {
+        hosts: load("yaml-limit", HOSTS).unwrap(),
+        ..Inventory::default()
+    };
+    assert_eq!(inventory.limit("prod:!db"), Ok((4, 3)));
+    let mut addrs: Vec<&str> = inventory.hosts.iter().map(|h| h.addr.as_str()).collect();
+    addrs.sort();
+    assert_eq!(
+        addrs,
+        vec!["10.0.0.3", "web01.example.com", "web02.example.com"]
+    );
+}
+
+#[test]
+fn top_level_groups_need_no_all() {
+    let hosts = load(
+        "yaml-top",
+        "db:\n  hosts:\n    10.0.1.2:\n    10.0.1.3:5432:\n",
+    )
+    .unwrap();
+    assert_eq!(hosts.len(), 2);
+    assert!(hosts.iter().all(|h| h.group.as_deref() == Some("db")));
+    assert_eq!(host_port(&hosts[1].vars), Ok(5432));
+}
+
+#[test]
+fn unknown_group_keys_are_refused() {
+    let error = load("yaml-typo", "web:\n  host:\n    10.0.0.1:\n").unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    assert!(error.to_string().contains("host"), "{}", error);
+}
@@ -0,0 +1,34 @@
+//! Inventories are pulled from as hosts finish, not read whole up front.
+
+use ansible_rs::prelude::*;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+#[test]
+fn inventory_is_pulled_as_hosts_finish() {
+    let threads = 2;
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .tcp_connections_pool(threads)
+        .build()
+        .unwrap();
+    let pulled = Arc::new(AtomicUsize::new(0));
+    let ahead = Arc::new(AtomicUsize::new(0));
+    let (seen, most) = (pulled.clone(), ahead.clone());
+    let responses = rx.clone();
+    // Nothing listens on port 1, so each host fails right away.
+    let address: SocketAddr = "127.0.0.1:1".parse().unwrap();
+    let hosts = (0..2000).map(move |_| {
+        let pulled = seen.fetch_add(1, Ordering::SeqCst) + 1;
+        most.fetch_max(pulled - responses.len(), Ordering::SeqCst);
+        (address, "true")
+    });
+    props.parallel_ssh_process(hosts).unwrap();
+
+    assert_eq!(pulled.load(Ordering::SeqCst), 2000);
+    assert_eq!(rx.try_iter().count(), 2000);
+    // Probes in flight, hosts queued for the workers and hosts being run.
+    let bound = threads as usize * 4 + 1;
+    let ahead = ahead.load(Ordering::SeqCst);
+    assert!(ahead <= bound, "{} hosts pulled ahead", ahead);
+}
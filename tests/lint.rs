@@ -0,0 +1,88 @@
+//! Which commands each lint rule flags, and which it lets through.
+
+use ansible_rs::prelude::*;
+
+fn ids(command: &str) -> Vec<&'static str> {
+    CommandLint::default().check(command, &[])
+}
+
+#[test]
+fn dangerous_commands_are_flagged() {
+    let cases = [
+        ("rm -rf $TARGET", "rm-variable-path"),
+        ("cd /srv && sudo -n rm -fr \"${DIR}\"/*", "rm-variable-path"),
+        ("rm --recursive --force $X/", "rm-variable-path"),
+        ("rm -rf /", "rm-root"),
+        ("rm -r -f /*", "rm-root"),
+        ("mkfs.ext4 /dev/sdb1", "mkfs"),
+        ("dd if=/dev/zero of=/dev/nvme0n1 bs=1M", "dd-to-disk"),
+        (":(){ :|:& };:", "fork-bomb"),
+        ("bomb() { bomb | bomb & }; bomb", "fork-bomb"),
+        ("ls $LOGDIR/*.log", "unquoted-variable-glob"),
+    ];
+    for (command, id) in cases.iter() {
+        assert!(
+            ids(command).contains(id),
+            "{} not flagged as {}",
+            command,
+            id
+        );
+    }
+}
+
+#[test]
+fn ordinary_commands_pass() {
+    let commands = [
+        "rm -rf /var/tmp/build",
+        "rm -f $PIDFILE",
+        "rm -rf \"$HOME/.cache/app\"",
+        "dd if=/dev/sda of=/tmp/mbr bs=512 count=1",
+        "dd if=/dev/zero of=/dev/null count=1",
+        "ls \"$LOGDIR\"/*.log",
+        "systemctl restart app; echo $?",
+        "uptime",
+    ];
+    for command in commands.iter() {
+        assert_eq!(ids(command), Vec::<&str>::new(), "{}", command);
+    }
+}
+
+#[test]
+fn empty_assigned_args_are_flagged() {
+    let lint = CommandLint::default();
+    assert_eq!(
+        lint.check("deploy --shard", &["".to_string()]),
+        vec!["empty-value"]
+    );
+    assert!(lint.check("deploy --shard", &["3".to_string()]).is_empty());
+}
+
+#[test]
+fn disabled_rules_are_skipped() {
+    let lint = CommandLint {
+        strict: true,
+        disabled: vec!["mkfs".to_string()],
+    };
+    assert!(lint.check("mkfs.xfs /dev/vdb", &[]).is_empty());
+    assert!(CommandLint {
+        disabled: vec!["no-such-rule".to_string()],
+        ..CommandLint::default()
+    }
+    .validate()
+    .is_err());
+}
+
+#[test]
+fn plan_report_names_the_hosts() {
+    let (_, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let plan = props.plan(vec![
+        ("10.0.0.1:22", "rm -rf $DIR", HostOptions::default()),
+        ("10.0.0.2:22", "uptime", HostOptions::default()),
+    ]);
+    let report = CommandLint::default().check_plan(&plan);
+    assert_eq!(
+        report.findings.get("rm-variable-path"),
+        Some(&vec!["10.0.0.1:22".to_string()])
+    );
+    assert_eq!(report.findings.len(), 1);
+}
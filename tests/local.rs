@@ -0,0 +1,73 @@
+//! Hosts with the local connection run on this machine instead of over SSH.
+
+use ansible_rs::prelude::*;
+use std::time::Duration;
+
+fn local() -> HostOptions {
+    HostOptions {
+        connection: Some(Connection::Local),
+        ..HostOptions::default()
+    }
+}
+
+#[test]
+fn connection_is_parsed() {
+    assert_eq!("local".parse::<Connection>(), Ok(Connection::Local));
+    assert_eq!("ssh".parse::<Connection>(), Ok(Connection::Ssh));
+    assert!("winrm".parse::<Connection>().is_err());
+}
+
+#[test]
+fn local_host_runs_without_connecting() {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    // Nothing listens on port 1, so an SSH connection would fail.
+    props
+        .parallel_ssh_process_with_options(vec![(
+            "10.255.255.1:1",
+            "echo hi; echo err >&2; exit 3",
+            local(),
+        )])
+        .unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.error_kind, None, "{}", response.result);
+    assert_eq!(response.result, "hi\n");
+    assert_eq!(response.stderr, "err\n");
+    assert_eq!(response.exit_code, Some(3));
+    assert_eq!(response.connection.unwrap().auth_method, "local");
+}
+
+#[test]
+fn loopback_runs_locally_once_enabled() {
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .local_loopback(true)
+        .build()
+        .unwrap();
+    props
+        .parallel_ssh_process(vec![("127.0.0.1:1", "echo local")])
+        .unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.result, "local\n");
+
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    props
+        .parallel_ssh_process(vec![("127.0.0.1:1", "echo local")])
+        .unwrap();
+    assert!(rx.recv().unwrap().error_kind.is_some());
+}
+
+#[test]
+fn local_command_is_killed_at_read_total() {
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .timeouts(Timeouts {
+            read_total: Some(Duration::from_millis(200)),
+            ..Timeouts::default()
+        })
+        .build()
+        .unwrap();
+    props
+        .parallel_ssh_process_with_options(vec![("10.255.255.1:1", "sleep 5", local())])
+        .unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.error_kind, Some(ErrorKind::ReadTotalTimeout));
+    assert!(response.process_time < Duration::from_secs(5));
+}
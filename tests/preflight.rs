@@ -0,0 +1,84 @@
+//! Preflight checks the host key store before any host connects.
+
+use ansible_rs::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+const FINGERPRINT: &str = "SHA256:nThbg6kXUpJWGl7E1IGOCspRomTxdCARLviKw6E5SY8";
+
+fn dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "ansible-rs-preflight-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+fn known_hosts_checks(store: HostKeyStore) -> Vec<(CheckStatus, String)> {
+    let (_rx, props) = ParallelSshPropsBuilder::default()
+        .host_key_store(Arc::new(store))
+        .build()
+        .unwrap();
+    props
+        .preflight(&["10.0.0.1:22", "10.0.0.2:22", "web-1:22"])
+        .checks
+        .into_iter()
+        .filter(|c| c.name.starts_with("known_hosts"))
+        .map(|c| (c.status, c.details))
+        .collect()
+}
+
+#[test]
+fn trusted_hosts_are_counted_and_bad_lines_reported() {
+    let dir = dir("lines");
+    let path = dir.join("known_hosts");
+    fs::write(
+        &path,
+        format!(
+            "# comment\n10.0.0.1:22 {}\n10.0.0.2:22 ssh-ed25519 AAAA\n",
+            FINGERPRINT
+        ),
+    )
+    .unwrap();
+    let checks = known_hosts_checks(HostKeyStore::open(&path).unwrap());
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert_eq!(checks.len(), 2, "{:?}", checks);
+    assert_eq!(checks[0].0, CheckStatus::Warn);
+    assert!(checks[0].1.contains("Lines 3 "), "{}", checks[0].1);
+    assert_eq!(checks[1].0, CheckStatus::Pass);
+    assert_eq!(
+        checks[1].1,
+        "1 of 2 hosts given by address have a trusted key"
+    );
+}
+
+#[test]
+fn unreadable_or_unwritable_store_fails() {
+    let dir = dir("fail");
+    let path = dir.join("known_hosts");
+    fs::write(&path, "").unwrap();
+    let store = HostKeyStore::open(&path).unwrap();
+    // Replaced by a directory once opened, as reading it then fails.
+    fs::remove_file(&path).unwrap();
+    fs::create_dir(&path).unwrap();
+    let checks = known_hosts_checks(store);
+    assert_eq!(checks.len(), 1);
+    assert_eq!(checks[0].0, CheckStatus::Fail);
+    assert!(checks[0].1.starts_with("Unreadable"), "{}", checks[0].1);
+
+    let store = HostKeyStore::open(dir.join("missing").join("known_hosts")).unwrap();
+    let checks = known_hosts_checks(store);
+    fs::remove_dir_all(&dir).unwrap();
+    assert_eq!(checks.len(), 1);
+    assert_eq!(checks[0].0, CheckStatus::Fail);
+    assert!(
+        checks[0].1.starts_with("2 hosts have no key yet"),
+        "{}",
+        checks[0].1
+    );
+}
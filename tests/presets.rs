@@ -0,0 +1,39 @@
+//! Every preset is a valid configuration on its own.
+
+use ansible_rs::prelude::*;
+use ansible_rs::preset::PRESETS;
+
+#[test]
+fn every_preset_builds() {
+    for (preset, _) in PRESETS.iter() {
+        let mut builder = ParallelSshPropsBuilder::default();
+        let (_, props) = builder
+            .preset(*preset)
+            .build()
+            .unwrap_or_else(|e| panic!("{:?}: {}", preset, e));
+        assert_eq!(props.preset(), Some(*preset));
+    }
+}
+
+#[test]
+fn preset_values_are_consistent() {
+    for (preset, values) in PRESETS.iter() {
+        assert_eq!(preset.values(), *values);
+        assert!(values.connections >= 1, "{:?}", preset);
+        assert!(
+            (1..=values.connections).contains(&values.agent_parallelism),
+            "{:?}: agent parallelism above the connections",
+            preset
+        );
+        values
+            .retry_policy()
+            .validate()
+            .unwrap_or_else(|e| panic!("{:?}: {}", preset, e));
+        let timeouts = values.timeouts;
+        let connect = timeouts.connect.expect("connect timeout");
+        assert!(values.probe_timeout <= connect, "{:?}", preset);
+        for phase in &[timeouts.handshake, timeouts.auth, timeouts.exec] {
+            assert!(connect <= phase.expect("phase timeout"), "{:?}", preset);
+        }
+    }
+}
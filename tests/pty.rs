@@ -0,0 +1,43 @@
+//! Commands on a pseudo terminal, and their output without ANSI escapes.
+
+use ansible_rs::prelude::*;
+use ansible_rs::pty::strip_ansi;
+
+#[test]
+fn escapes_are_stripped() {
+    assert_eq!(
+        strip_ansi("\u{1b}[1;31merror\u{1b}[0m: disk \u{1b}[Kfull\r\n"),
+        "error: disk full\r\n"
+    );
+    assert_eq!(
+        strip_ansi("\u{1b}]0;user@host: ~\u{7}$ ls\u{1b}(B\u{1b}=done"),
+        "$ lsdone"
+    );
+    assert_eq!(strip_ansi("\u{1b}]2;title\u{1b}\\plain"), "plain");
+    assert_eq!(strip_ansi("tab\tand ünïcode"), "tab\tand ünïcode");
+    assert_eq!(strip_ansi("cut \u{1b}[3"), "cut ");
+}
+
+#[test]
+fn pty_request_is_checked() {
+    let pty = PtyRequest::default();
+    assert_eq!(
+        (pty.term.as_str(), pty.width, pty.height),
+        ("xterm", 80, 24)
+    );
+    for pty in [
+        PtyRequest {
+            term: String::new(),
+            ..PtyRequest::default()
+        },
+        PtyRequest {
+            width: 0,
+            ..PtyRequest::default()
+        },
+    ] {
+        assert!(ParallelSshPropsBuilder::default()
+            .request_pty(pty)
+            .build()
+            .is_err());
+    }
+}
@@ -0,0 +1,220 @@
+//! Values quoted for a remote shell reach the command as exactly the bytes given, whatever
+//! quotes, substitutions or newlines they hold.
+
+use ansible_rs::prelude::*;
+use ansible_rs::shell::shell_quote;
+use ansible_rs::template::render_template;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+const ADVERSARIAL: [&str; 18] = [
+    "",
+    "'",
+    "''",
+    "\"",
+    "'\\''",
+    "it's \"quoted\"",
+    "$(touch pwned)",
+    "`touch pwned`",
+    "${HOME}",
+    "'; touch pwned; '",
+    "\"; touch pwned; \"",
+    "a\nb\n",
+    "\n'$(touch pwned)'\n",
+    "back\\slash\\",
+    "* ? [a-z] ~",
+    "a && b || c | d > e < f & g",
+    "\t-n -e %s %%",
+    "Größe 日本語 🚀",
+];
+
+/// Directory of its own under the temp dir, for `name`.
+fn base(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "ansible-rs-quoting-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// Runs `command` in `dir` the way the login shell of a host would.
+fn sh(command: &str, dir: &PathBuf) -> (bool, String) {
+    let out = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    (out.status.success(), String::from_utf8(out.stdout).unwrap())
+}
+
+/// Deterministic strings built from the characters shells treat specially.
+fn generated() -> Vec<String> {
+    let alphabet: Vec<char> = "'\"`$()\\;&|<>*?[]{}~#!\n\t -=%aZ9ü".chars().collect();
+    let mut state: u64 = 0x9e37_79b9_7f4a_7c15;
+    let mut next = move || {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        state
+    };
+    (0..300)
+        .map(|_| {
+            let len = (next() % 24) as usize;
+            (0..len)
+                .map(|_| alphabet[(next() % alphabet.len() as u64) as usize])
+                .collect()
+        })
+        .collect()
+}
+
+fn cases() -> Vec<String> {
+    ADVERSARIAL
+        .iter()
+        .map(|s| s.to_string())
+        .chain(generated())
+        .collect()
+}
+
+#[test]
+fn shell_quote_is_one_literal_word() {
+    let dir = base("quote");
+    for value in cases() {
+        let (ok, out) = sh(&format!("printf %s {}", shell_quote(&value)), &dir);
+        assert!(ok, "{:?}", value);
+        assert_eq!(out, value);
+        // Quoted twice, as `RemoteShell::Posix` does around an already quoted command.
+        let twice = RemoteShell::Posix.wrap(&format!("printf %s {}", shell_quote(&value)));
+        assert_eq!(sh(&twice, &dir), (true, value.clone()));
+    }
+    assert!(!dir.join("pwned").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn command_arguments_and_template_values_stay_literal() {
+    let dir = base("args");
+    for value in cases() {
+        let command = RemoteCommand::new("printf").arg("%s").arg(value.as_str());
+        assert_eq!(sh(&command.to_string(), &dir), (true, value.clone()));
+
+        let mut vars = BTreeMap::new();
+        vars.insert("v".to_string(), value.clone());
+        let rendered = render_template("printf %s {{ v }}", &vars).unwrap();
+        assert_eq!(sh(&rendered, &dir), (true, value.clone()));
+    }
+    assert!(!dir.join("pwned").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn posix_wrap_runs_the_command_unchanged() {
+    let dir = base("wrap");
+    for value in cases() {
+        let command = format!("printf %s {}; exit 7", shell_quote(&value));
+        let out = Command::new("sh")
+            .arg("-c")
+            .arg(RemoteShell::Posix.wrap(&command))
+            .current_dir(&dir)
+            .output()
+            .unwrap();
+        assert_eq!(out.status.code(), Some(7));
+        assert_eq!(String::from_utf8(out.stdout).unwrap(), value);
+    }
+    assert!(!dir.join("pwned").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn nul_truncated_quote_does_not_run() {
+    // The server cuts the command at the first NUL; what is left has an unclosed quote.
+    let dir = base("nul");
+    let quoted = shell_quote("a\0'; touch pwned; '");
+    let truncated = quoted.split('\0').next().unwrap();
+    let (ok, _) = sh(&format!("printf %s {}; touch pwned", truncated), &dir);
+    assert!(!ok);
+    assert!(!dir.join("pwned").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn command_with_nul_is_refused() {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let dir = base("refused");
+    let command = format!(
+        "cd {} && touch pwned\0; true",
+        shell_quote(&dir.to_string_lossy())
+    );
+    let local = HostOptions {
+        connection: Some(Connection::Local),
+        ..HostOptions::default()
+    };
+    props
+        .parallel_ssh_process_with_options(vec![("10.255.255.1:1", command, local)])
+        .unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.error_kind, Some(ErrorKind::Exec));
+    assert!(response.result.contains("NUL"), "{}", response.result);
+    assert!(!dir.join("pwned").exists());
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn cmd_wrap_keeps_the_command_verbatim() {
+    // With /S, cmd strips exactly the first and the last quote and runs what is between.
+    for value in cases() {
+        let command = format!("echo {}", value);
+        let wrapped = RemoteShell::Cmd.wrap(&command);
+        assert!(wrapped.starts_with("cmd /S /C \""));
+        assert!(wrapped.ends_with('"'));
+        assert_eq!(&wrapped["cmd /S /C \"".len()..wrapped.len() - 1], command);
+    }
+}
+
+fn base64_decode(s: &str) -> Vec<u8> {
+    const TABLE: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut out = Vec::new();
+    let mut buffer = 0u32;
+    let mut bits = 0;
+    for c in s.bytes().filter(|&c| c != b'=') {
+        let v = TABLE
+            .iter()
+            .position(|&t| t == c)
+            .expect("base64 character") as u32;
+        buffer = buffer << 6 | v;
+        bits += 6;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+    out
+}
+
+#[test]
+fn powershell_script_is_encoded_whole() {
+    const PREFIX: &str = "powershell -NoProfile -NonInteractive -EncodedCommand ";
+    for value in cases() {
+        let command = format!("Write-Output '{}'", value.replace('\'', "''"));
+        let wrapped = RemoteShell::PowerShell.wrap(&command);
+        assert!(wrapped.starts_with(PREFIX));
+        // Nothing of the script is left for cmd.exe or the SSH server to interpret.
+        let encoded = &wrapped[PREFIX.len()..];
+        assert!(encoded
+            .bytes()
+            .all(|c| c.is_ascii_alphanumeric() || c == b'+' || c == b'/' || c == b'='));
+
+        let bytes = base64_decode(encoded);
+        let units: Vec<u16> = bytes
+            .chunks(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .collect();
+        let script = String::from_utf16(&units).unwrap();
+        assert!(script.starts_with(&format!("& {{ {} }}; ", command)));
+    }
+}
@@ -0,0 +1,128 @@
+//! Every selected host gets exactly one record in the results, however it ended.
+#![cfg(feature = "cli")]
+
+mod common;
+
+use ansible_rs::misc::{save_to_file, Config};
+use ansible_rs::prelude::*;
+use common::unreachable_hosts;
+use crossbeam_channel::Receiver;
+use std::collections::BTreeSet;
+use std::fs;
+use std::sync::Arc;
+
+fn unreachable(n: u16) -> Vec<(String, &'static str, HostOptions)> {
+    unreachable_hosts(n)
+        .into_iter()
+        .map(|(addr, command)| (addr.to_string(), command, HostOptions::default()))
+        .collect()
+}
+
+fn local(name: &str, command: &'static str) -> (String, &'static str, HostOptions) {
+    let options = HostOptions {
+        connection: Some(Connection::Local),
+        ..HostOptions::default()
+    };
+    (name.to_string(), command, options)
+}
+
+/// Runs `hosts`, saves the responses as the CLI does and checks the file has one record
+/// per host. Returns the responses.
+fn run_and_save(
+    name: &str,
+    (rx, props): (Receiver<Response>, ParallelSshProps),
+    hosts: Vec<(String, &'static str, HostOptions)>,
+) -> Vec<Response> {
+    let inventory: BTreeSet<String> = hosts.iter().map(|(host, _, _)| host.clone()).collect();
+    // The run may fail as a whole; its hosts still get their responses.
+    let _ = props.parallel_ssh_process_with_options(hosts);
+    let responses: Vec<Response> = rx.try_iter().collect();
+    assert_eq!(responses.len(), inventory.len(), "{}", name);
+
+    let path = std::env::temp_dir().join(format!(
+        "ansible-rs-records-{}-{}.json",
+        name,
+        std::process::id()
+    ));
+    let mut conf = Config::default();
+    conf.output.filename = Some(path.to_string_lossy().to_string());
+    save_to_file(&conf, responses.clone(), false).unwrap();
+    let records: Vec<serde_json::Value> =
+        serde_json::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+    fs::remove_file(&path).unwrap();
+
+    assert_eq!(records.len(), inventory.len(), "{}", name);
+    let addresses: BTreeSet<String> = records
+        .iter()
+        .map(|r| r["address"].as_str().unwrap().to_string())
+        .collect();
+    assert_eq!(addresses, inventory, "{}", name);
+    let summary = RunSummary::of(&responses);
+    assert_eq!(summary.total, inventory.len(), "{}", name);
+    assert_eq!(
+        summary.succeeded + summary.failed,
+        summary.total,
+        "{}",
+        name
+    );
+    responses
+}
+
+fn count(responses: &[Response], outcome: HostStatus) -> usize {
+    responses.iter().filter(|r| r.outcome == outcome).count()
+}
+
+#[test]
+fn failures_and_successes() {
+    let mut hosts = unreachable(5);
+    hosts.push(local("10.255.255.1:1", "true"));
+    hosts.push(local("10.255.255.2:1", "exit 3"));
+    let props = ParallelSshPropsBuilder::default().build().unwrap();
+    let responses = run_and_save("mixed", props, hosts);
+    // A command exiting non-zero still ran, so it is no failure of the host.
+    assert_eq!(count(&responses, HostStatus::Success), 2);
+    assert_eq!(count(&responses, HostStatus::Failed), 5);
+}
+
+#[test]
+fn hosts_skipped_after_a_declined_batch() {
+    let responses = run_and_save(
+        "batch",
+        ParallelSshPropsBuilder::default()
+            .serial(Serial::Hosts(2))
+            .batch_hook(Arc::new(|_: &BatchReport| false))
+            .build()
+            .unwrap(),
+        unreachable(8),
+    );
+    assert_eq!(count(&responses, HostStatus::Failed), 2);
+    assert_eq!(count(&responses, HostStatus::Skipped), 6);
+}
+
+#[test]
+fn hosts_cancelled_by_the_failure_threshold() {
+    let responses = run_and_save(
+        "threshold",
+        ParallelSshPropsBuilder::default()
+            .serial(Serial::Hosts(1))
+            .failure_threshold(FailureThreshold {
+                max_failures: Some(1),
+                ..FailureThreshold::default()
+            })
+            .build()
+            .unwrap(),
+        unreachable(6),
+    );
+    assert_eq!(count(&responses, HostStatus::Failed), 2);
+    assert_eq!(count(&responses, HostStatus::Cancelled), 4);
+}
+
+#[test]
+fn hosts_of_a_cancelled_run() {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    props.cancel();
+    let mut hosts = unreachable(3);
+    hosts.push(local("10.255.255.1:1", "true"));
+    let responses = run_and_save("cancelled", (rx, props), hosts);
+    assert_eq!(count(&responses, HostStatus::Cancelled), 4);
+}
@@ -0,0 +1,28 @@
+//! Responses of a run as a stream.
+
+mod common;
+
+use ansible_rs::prelude::*;
+use common::unreachable_hosts;
+use smol::stream::StreamExt;
+
+#[test]
+fn stream_has_every_response_of_the_run() {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let responses: Vec<Response> =
+        smol::run(props.parallel_ssh_responses(unreachable_hosts(4)).collect());
+    assert_eq!(responses.len(), 4);
+    assert!(responses
+        .iter()
+        .all(|r| r.error_kind == Some(ErrorKind::TcpConnect)));
+    // The props' own stream does not get them.
+    assert!(rx.try_recv().is_err());
+}
+
+#[test]
+fn empty_run_is_an_empty_stream() {
+    let (_rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let responses: Vec<Response> =
+        smol::run(props.parallel_ssh_responses(unreachable_hosts(0)).collect());
+    assert!(responses.is_empty());
+}
@@ -0,0 +1,70 @@
+//! Delays between the attempts of a host.
+
+use ansible_rs::prelude::*;
+use std::time::Duration;
+
+fn policy(multiplier: f64, max_backoff_ms: Option<u64>, jitter: f64) -> RetryPolicy {
+    RetryPolicy {
+        max_attempts: 6,
+        backoff_ms: 100,
+        backoff_multiplier: multiplier,
+        max_backoff_ms,
+        jitter,
+        ..RetryPolicy::default()
+    }
+}
+
+#[test]
+fn delay_is_constant_by_default() {
+    let policy = RetryPolicy {
+        max_attempts: 3,
+        ..RetryPolicy::default()
+    };
+    assert_eq!(
+        policy.next_delay(ErrorKind::TcpTimeout, 1),
+        Some(Duration::from_secs(1))
+    );
+    assert_eq!(
+        policy.next_delay(ErrorKind::TcpTimeout, 2),
+        Some(Duration::from_secs(1))
+    );
+    assert_eq!(policy.next_delay(ErrorKind::TcpTimeout, 3), None);
+}
+
+#[test]
+fn delay_grows_up_to_the_cap() {
+    let policy = policy(2.0, Some(500), 0.0);
+    let delays: Vec<_> = (1..6)
+        .map(|attempt| policy.next_delay(ErrorKind::Agent, attempt).unwrap())
+        .collect();
+    let expected: Vec<_> = [100, 200, 400, 500, 500]
+        .iter()
+        .map(|ms| Duration::from_millis(*ms))
+        .collect();
+    assert_eq!(delays, expected);
+    assert_eq!(policy.next_delay(ErrorKind::Agent, 6), None);
+    // Not retryable at all.
+    assert_eq!(policy.next_delay(ErrorKind::Auth, 1), None);
+}
+
+#[test]
+fn jitter_only_shortens_the_delay() {
+    let policy = policy(2.0, None, 0.5);
+    for _ in 0..100 {
+        let delay = policy.next_delay(ErrorKind::TcpTimeout, 3).unwrap();
+        assert!(delay >= Duration::from_millis(200), "{:?}", delay);
+        assert!(delay <= Duration::from_millis(400), "{:?}", delay);
+    }
+}
+
+#[test]
+fn invalid_growth_and_jitter_are_refused() {
+    assert!(policy(0.5, None, 0.0).validate().is_err());
+    assert!(policy(2.0, None, 1.5).validate().is_err());
+    let error = ParallelSshPropsBuilder::default()
+        .retry_policy(policy(f64::NAN, None, 0.0))
+        .build()
+        .err()
+        .unwrap();
+    assert!(error.contains("backoff_multiplier"), "{}", error);
+}
@@ -0,0 +1,40 @@
+//! Several commands per host over one session.
+
+use ansible_rs::prelude::*;
+use std::net::SocketAddr;
+
+#[test]
+fn commands_of_an_unreachable_host_are_not_run() {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    // Nothing listens on port 1.
+    let hosts: Vec<SocketAddr> = vec!["127.0.0.1:1".parse().unwrap()];
+    let commands = vec![
+        "uptime".to_string(),
+        "df -h".to_string(),
+        "free".to_string(),
+    ];
+    let by_host = props.run_commands(hosts, commands).unwrap();
+    assert_eq!(by_host.len(), 1);
+    let responses = &by_host[0];
+    let sent: Vec<&str> = responses.iter().map(|r| r.command.as_str()).collect();
+    assert_eq!(sent, vec!["uptime", "df -h", "free"]);
+    assert_eq!(responses[0].error_kind, Some(ErrorKind::TcpConnect));
+    for response in &responses[1..] {
+        assert_eq!(response.error_kind, Some(ErrorKind::Skipped));
+        assert_eq!(response.hostname, responses[0].hostname);
+        assert!(
+            response.result.contains("earlier command"),
+            "{}",
+            response.result
+        );
+    }
+    // The props' own stream gets none of them.
+    assert_eq!(rx.try_iter().count(), 0);
+}
+
+#[test]
+fn no_commands_is_an_empty_result() {
+    let (_, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    let hosts: Vec<SocketAddr> = vec!["127.0.0.1:1".parse().unwrap()];
+    assert!(props.run_commands(hosts, Vec::new()).unwrap().is_empty());
+}
@@ -0,0 +1,59 @@
+//! Facts kept by a `RunContext` and rendered into commands.
+
+use ansible_rs::prelude::*;
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn facts_are_rendered_into_commands() {
+    let context = RunContext::new(1024);
+    context.set_fact("web1", "os_release.id", "debian").unwrap();
+    assert_eq!(
+        context.render("web1", "install-{{ facts.os_release.id }}.sh"),
+        Ok("install-debian.sh".to_string())
+    );
+}
+
+#[test]
+fn other_placeholders_are_kept() {
+    let context = RunContext::new(1024);
+    let command = "docker ps --format '{{.Names}}' {{";
+    assert_eq!(context.render("web1", command), Ok(command.to_string()));
+}
+
+#[test]
+fn missing_fact_fails_rendering() {
+    let context = RunContext::new(1024);
+    context.set_fact("web1", "os_release.id", "debian").unwrap();
+    assert!(context
+        .render("web2", "echo {{facts.os_release.id}}")
+        .is_err());
+}
+
+#[test]
+fn facts_of_a_host_are_capped() {
+    let context = RunContext::new(16);
+    context.set_fact("web1", "a", "0123456789").unwrap();
+    assert!(context.set_fact("web1", "b", "0123456789").is_err());
+    // Replacing a fact only counts the difference.
+    context.set_fact("web1", "a", "01234567890123").unwrap();
+    context.set_fact("web2", "b", "0123456789").unwrap();
+    let host = context.host("web1").unwrap();
+    assert_eq!(host.facts.len(), 1);
+    assert_eq!(host.dropped_facts, 1);
+}
+
+#[test]
+fn snapshot_drops_hosts_past_their_ttl() {
+    let context = RunContext::new(1024);
+    context.set_fact("fresh", "id", "1").unwrap();
+    context.set_fact("stale", "id", "2").unwrap();
+    let mut snapshot = context.snapshot();
+    snapshot.hosts.get_mut("stale").unwrap().updated =
+        SystemTime::now() - Duration::from_secs(7200);
+    let reloaded = RunContext::from_snapshot(snapshot, 1024, Duration::from_secs(3600));
+    assert_eq!(
+        reloaded.facts("fresh").get("id").map(String::as_str),
+        Some("1")
+    );
+    assert!(reloaded.host("stale").is_none());
+}
@@ -0,0 +1,69 @@
+//! Limits of a run changed through its `RunHandle`.
+
+mod common;
+
+use ansible_rs::prelude::*;
+use common::unreachable_hosts;
+use std::time::{Duration, Instant};
+
+fn props(threads: isize) -> ParallelSshProps {
+    let (_, props) = ParallelSshPropsBuilder::default()
+        .tcp_connections_pool(threads)
+        .build()
+        .unwrap();
+    props
+}
+
+#[test]
+fn max_connections_stay_within_the_pool() {
+    let props = props(8);
+    let handle = props.handle();
+    assert_eq!(handle.ceiling(), 8);
+    assert_eq!(handle.set_max_connections(4), 4);
+    assert_eq!(handle.set_max_connections(0), 1);
+    assert_eq!(handle.set_max_connections(100), 8);
+    // Clones control the same props.
+    props.handle().set_max_connections(2);
+    assert_eq!(handle.max_connections(), 2);
+}
+
+#[test]
+fn changes_are_recorded() {
+    let props = props(8);
+    let handle = props.handle();
+    assert!(handle.changes().is_empty());
+    handle.set_max_connections(4);
+    handle.set_max_connections(4);
+    handle.set_connect_rate(Some(2.5)).unwrap();
+    let changes = handle.changes();
+    assert_eq!(changes.len(), 2);
+    assert_eq!(changes[0].max_connections, 4);
+    assert_eq!(changes[0].connect_rate, None);
+    assert_eq!(changes[1].connect_rate, Some(2.5));
+    assert!(changes[0].at <= changes[1].at);
+}
+
+#[test]
+fn invalid_connect_rate_is_refused() {
+    let props = props(8);
+    assert!(props.handle().set_connect_rate(Some(0.0)).is_err());
+    assert!(props.handle().set_connect_rate(Some(f64::NAN)).is_err());
+    assert!(ParallelSshPropsBuilder::default()
+        .connect_rate(-1.0)
+        .build()
+        .is_err());
+}
+
+#[test]
+fn connect_rate_spaces_host_starts() {
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .tcp_connections_pool(8)
+        .connect_rate(20.0)
+        .build()
+        .unwrap();
+    let started = Instant::now();
+    props.parallel_ssh_process(unreachable_hosts(5)).unwrap();
+    assert!(started.elapsed() >= Duration::from_millis(200));
+    assert_eq!(rx.try_iter().count(), 5);
+    assert_eq!(props.handle().running(), 0);
+}
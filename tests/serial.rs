@@ -0,0 +1,74 @@
+//! Hosts run in batches, one after the other.
+
+mod common;
+
+use ansible_rs::prelude::*;
+use common::unreachable_hosts;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn serial_is_parsed_and_checked() {
+    assert_eq!("10".parse::<Serial>(), Ok(Serial::Hosts(10)));
+    assert_eq!("25%".parse::<Serial>(), Ok(Serial::Percent(25.0)));
+    for s in &["0", "0%", "150%", "ten", "-5%"] {
+        assert!(s.parse::<Serial>().is_err(), "{}", s);
+    }
+    assert!(ParallelSshPropsBuilder::default()
+        .serial(Serial::Hosts(0))
+        .build()
+        .is_err());
+}
+
+#[test]
+fn declined_batch_skips_the_rest() {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let seen = reports.clone();
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .serial(Serial::Hosts(2))
+        .batch_hook(Arc::new(move |report: &BatchReport| {
+            seen.lock().unwrap().push(*report);
+            report.batch < 2
+        }))
+        .build()
+        .unwrap();
+    let error = props
+        .parallel_ssh_process(unreachable_hosts(5))
+        .unwrap_err();
+    assert_eq!(
+        error,
+        RunError::Stopped("stopped after batch 2".to_string())
+    );
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 2);
+    assert_eq!(reports[0].hosts, 2);
+    assert_eq!(reports[0].failed, 2);
+    assert_eq!(reports[0].batches, None);
+
+    let responses: Vec<Response> = rx.try_iter().collect();
+    assert_eq!(responses.len(), 5);
+    let skipped = responses
+        .iter()
+        .filter(|r| r.error_kind == Some(ErrorKind::Skipped))
+        .count();
+    assert_eq!(skipped, 1);
+}
+
+#[test]
+fn percent_counts_the_hosts_first() {
+    let reports = Arc::new(Mutex::new(Vec::new()));
+    let seen = reports.clone();
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .serial(Serial::Percent(40.0))
+        .batch_hook(Arc::new(move |report: &BatchReport| {
+            seen.lock().unwrap().push(*report);
+            true
+        }))
+        .build()
+        .unwrap();
+    props.parallel_ssh_process(unreachable_hosts(5)).unwrap();
+    // Batches of 2, 2 and 1; there is nothing to ask after the last one.
+    let reports = reports.lock().unwrap();
+    assert_eq!(reports.len(), 2);
+    assert!(reports.iter().all(|r| r.batches == Some(3) && r.hosts == 2));
+    assert_eq!(rx.try_iter().count(), 5);
+}
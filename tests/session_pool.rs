@@ -0,0 +1,44 @@
+//! Sessions kept open across runs.
+
+use ansible_rs::prelude::*;
+use std::net::TcpListener;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+#[test]
+fn zero_durations_are_refused() {
+    let second = Duration::from_secs(1);
+    assert!(SessionPool::new(Duration::from_secs(0), second).is_err());
+    assert!(SessionPool::new(second, Duration::from_secs(0)).is_err());
+}
+
+#[test]
+fn failed_host_leaves_nothing_in_the_pool() {
+    // Accepts connections and closes them without saying a word.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            drop(stream);
+        }
+    });
+    let pool =
+        Arc::new(SessionPool::new(Duration::from_secs(60), Duration::from_secs(15)).unwrap());
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .session_pool(pool.clone())
+        .build()
+        .unwrap();
+    props.parallel_ssh_process(vec![(host, "true")]).unwrap();
+    let response = rx.recv().unwrap();
+    assert!(!response.status);
+    assert_eq!(
+        pool.stats(),
+        SessionPoolStats {
+            hits: 0,
+            misses: 1,
+            evicted: 0,
+            idle: 0,
+        }
+    );
+}
@@ -0,0 +1,74 @@
+//! Concurrent appends to one `SharedFile`.
+
+use ansible_rs::shared_file::SharedFile;
+use std::collections::BTreeSet;
+use std::fs;
+use std::thread;
+
+const RECORDS: usize = 500;
+/// Larger than a pipe buffer, so a record takes several writes without the lock.
+const PADDING: usize = 100_000;
+
+fn record(writer: usize, seq: usize) -> String {
+    format!(
+        "{{\"writer\":{},\"seq\":{},\"pad\":\"{}\"}}\n",
+        writer,
+        seq,
+        "x".repeat(PADDING)
+    )
+}
+
+#[test]
+fn two_writers_produce_whole_lines() {
+    let path = std::env::temp_dir().join(format!(
+        "ansible-rs-shared-file-{}.ndjson",
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&path);
+    let writers: Vec<_> = (0..2)
+        .map(|writer| {
+            let path = path.clone();
+            thread::spawn(move || {
+                // A file of its own per writer, as a separate process would have.
+                let mut file = SharedFile::open(&path).unwrap();
+                for seq in 0..RECORDS {
+                    file.append(record(writer, seq).as_bytes()).unwrap();
+                }
+            })
+        })
+        .collect();
+    for writer in writers {
+        writer.join().unwrap();
+    }
+    let content = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    let mut seen = BTreeSet::new();
+    for line in content.lines() {
+        let mut fields = line
+            .split(|c| c == ':' || c == ',')
+            .filter_map(|f| f.parse::<usize>().ok());
+        let (writer, seq) = (fields.next().unwrap(), fields.next().unwrap());
+        assert_eq!(
+            line,
+            record(writer, seq).trim_end(),
+            "line is not a whole record"
+        );
+        assert!(seen.insert((writer, seq)), "record written twice");
+    }
+    assert_eq!(seen.len(), 2 * RECORDS);
+}
+
+#[test]
+fn record_after_a_partial_line_starts_a_new_line() {
+    let path = std::env::temp_dir().join(format!(
+        "ansible-rs-shared-file-partial-{}.ndjson",
+        std::process::id()
+    ));
+    // A writer which crashed in the middle of a record.
+    fs::write(&path, "{\"cut\":").unwrap();
+    let mut file = SharedFile::open(&path).unwrap();
+    file.append(b"{\"whole\":true}\n").unwrap();
+    let content = fs::read_to_string(&path).unwrap();
+    let _ = fs::remove_file(&path);
+    assert_eq!(content, "{\"cut\":\n{\"whole\":true}\n");
+}
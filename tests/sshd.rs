@@ -0,0 +1,460 @@
+//! Runs against a real OpenSSH server, see `common`. Skipped unless `ANSIBLE_RS_IT=1`.
+
+mod common;
+
+use ansible_rs::prelude::*;
+use common::{TestSshServer, PASSWORD, USER};
+use std::sync::Arc;
+use std::time::Duration;
+
+fn builder(password: &str) -> ParallelSshPropsBuilder {
+    let mut builder = ParallelSshPropsBuilder::default();
+    builder
+        .user(USER.to_string())
+        .auth_chain(vec![AuthMethod::Password {
+            password: password.to_string(),
+        }]);
+    builder
+}
+
+#[test]
+fn exec_reports_output_and_exit_code() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let (_, props) = builder(PASSWORD).build().unwrap();
+    let response = props
+        .run_single_blocking(server.address(), "echo hello; exit 3")
+        .response;
+    assert_eq!(response.error_kind, None, "{}", response.result);
+    assert_eq!(response.result, "hello\n");
+    assert_eq!(response.exit_code, Some(3));
+}
+
+#[test]
+fn stderr_is_kept_apart_from_stdout() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let (_, events, props) = builder(PASSWORD).build_with_events().unwrap();
+    let response = props
+        .run_single_blocking(server.address(), "echo out; echo err >&2")
+        .response;
+    assert_eq!(response.result, "out\n");
+    assert_eq!(response.stderr, "err\n");
+    let stderr: Vec<u8> = events
+        .try_iter()
+        .filter_map(|event| match event {
+            RunEvent::OutputChunk {
+                stderr: true, data, ..
+            } => Some(data),
+            _ => None,
+        })
+        .flatten()
+        .collect();
+    assert_eq!(stderr, b"err\n");
+}
+
+#[test]
+fn read_total_timeout_stops_the_command() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let (_, props) = builder(PASSWORD)
+        .timeouts(Timeouts {
+            read_total: Some(Duration::from_secs(1)),
+            ..Timeouts::default()
+        })
+        .build()
+        .unwrap();
+    let response = props
+        .run_single_blocking(server.address(), "sleep 30")
+        .response;
+    assert_eq!(response.error_kind, Some(ErrorKind::ReadTotalTimeout));
+    assert!(response.process_time < Duration::from_secs(10));
+}
+
+#[test]
+fn wrong_password_fails_auth() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let (_, props) = builder("not-the-password").build().unwrap();
+    let detailed = props.run_single_blocking(server.address(), "true");
+    assert_eq!(detailed.response.error_kind, Some(ErrorKind::Auth));
+    assert_eq!(detailed.steps.last().map(|s| s.step), Some(Step::Auth));
+}
+
+#[test]
+fn changed_host_key_fails_the_host() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let path = std::env::temp_dir().join(format!(
+        "ansible-rs-it-known-hosts-{}-{}",
+        std::process::id(),
+        server.address().port()
+    ));
+    let store = Arc::new(HostKeyStore::open(&path).unwrap());
+    store
+        .check(&server.address().to_string(), "SHA256:not-the-host-key")
+        .unwrap();
+    let (_, props) = builder(PASSWORD).host_key_store(store).build().unwrap();
+    let response = props.run_single_blocking(server.address(), "true").response;
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(response.error_kind, Some(ErrorKind::HostKeyChanged));
+    assert_eq!(
+        response.host_key.and_then(|k| k.previous).as_deref(),
+        Some("SHA256:not-the-host-key")
+    );
+}
+
+#[test]
+fn fifty_hosts_at_once() {
+    let server = match TestSshServer::spawn_with_ports(50) {
+        Some(server) => server,
+        None => return,
+    };
+    let (rx, props) = builder(PASSWORD).tcp_connections_pool(50).build().unwrap();
+    let hosts: Vec<_> = server
+        .addresses()
+        .iter()
+        .map(|addr| (*addr, "echo ok"))
+        .collect();
+    props.parallel_ssh_process(hosts).unwrap();
+    let responses: Vec<Response> = rx.try_iter().collect();
+    assert_eq!(responses.len(), 50);
+    for response in &responses {
+        assert_eq!(response.error_kind, None, "{}", response.result);
+        assert_eq!(response.result, "ok\n");
+    }
+}
+
+#[test]
+fn post_condition_waits_and_times_out() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let (_, props) = builder(PASSWORD)
+        .post_condition(PostCondition::CommandSucceeds {
+            command: "test -e /tmp/ready".to_string(),
+            timeout: Duration::from_secs(10),
+            interval: Duration::from_millis(200),
+        })
+        .build()
+        .unwrap();
+    let response = props
+        .run_single_blocking(server.address(), "(sleep 1; touch /tmp/ready) &")
+        .response;
+    assert_eq!(response.error_kind, None, "{}", response.result);
+    let condition = response.post_condition.unwrap();
+    assert!(condition.met && condition.polls > 1);
+
+    let (_, props) = builder(PASSWORD)
+        .post_condition(PostCondition::TcpPortOpen {
+            port: 1,
+            timeout: Duration::from_secs(1),
+            interval: Duration::from_millis(200),
+        })
+        .build()
+        .unwrap();
+    let response = props.run_single_blocking(server.address(), "true").response;
+    assert_eq!(response.error_kind, Some(ErrorKind::PostConditionTimeout));
+    assert!(!response.post_condition.unwrap().met);
+}
+
+#[test]
+fn chunked_download_matches_the_remote_file() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    server.exec("dd if=/dev/urandom of=/tmp/bundle bs=1000000 count=3 && chmod 644 /tmp/bundle");
+    let (_, props) = builder(PASSWORD).build().unwrap();
+    let session = props
+        .open_session(server.address(), HostOptions::default())
+        .unwrap();
+    let local = std::env::temp_dir().join(format!(
+        "ansible-rs-it-bundle-{}-{}",
+        std::process::id(),
+        server.address().port()
+    ));
+    let report = session.download(
+        "/tmp/bundle",
+        &local,
+        &DownloadOptions {
+            chunks: 3,
+            chunk_parallelism: 2,
+        },
+    );
+    let size = std::fs::metadata(&local).map(|m| m.len());
+    let _ = std::fs::remove_file(&local);
+    let report = report.unwrap();
+    assert_eq!(report.strategy, DownloadStrategy::Chunked { chunks: 3 });
+    assert!(report.verified);
+    assert_eq!(size.unwrap(), 3_000_000);
+    let remote = server.exec("sha256sum /tmp/bundle");
+    assert_eq!(
+        report.checksum,
+        format!("sha256:{}", remote.split_whitespace().next().unwrap())
+    );
+}
+
+#[test]
+fn upload_writes_and_verifies_the_file() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let local = std::env::temp_dir().join(format!(
+        "ansible-rs-it-upload-{}-{}",
+        std::process::id(),
+        server.address().port()
+    ));
+    std::fs::write(&local, "listen 8080;\n").unwrap();
+    let (rx, props) = builder(PASSWORD).build().unwrap();
+    let result = props.parallel_upload(vec![server.address()], &local, "/tmp/uploaded.conf");
+    let _ = std::fs::remove_file(&local);
+    result.unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.error_kind, None, "{}", response.result);
+    let report = response.upload.unwrap();
+    assert_eq!(report.bytes, 13);
+    assert!(report.verified);
+    assert_eq!(server.exec("cat /tmp/uploaded.conf"), "listen 8080;\n");
+}
+
+#[test]
+fn fetch_saves_the_file_under_the_host() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    server.exec("echo 'started' > /tmp/fetched.log");
+    let local = std::env::temp_dir().join(format!(
+        "ansible-rs-it-fetch-{}-{}",
+        std::process::id(),
+        server.address().port()
+    ));
+    let (rx, props) = builder(PASSWORD).build().unwrap();
+    props
+        .parallel_fetch(vec![server.address()], "/tmp/fetched.log", &local)
+        .unwrap();
+    let response = rx.recv().unwrap();
+    let content = response
+        .fetch
+        .as_ref()
+        .map(|report| std::fs::read_to_string(&report.local_path));
+    let _ = std::fs::remove_dir_all(&local);
+    assert_eq!(response.error_kind, None, "{}", response.result);
+    let report = response.fetch.unwrap();
+    assert!(report.local_path.starts_with(&local));
+    assert!(report.verified);
+    assert_eq!(content.unwrap().unwrap(), "started\n");
+}
+
+#[test]
+fn become_feeds_sudo_the_password() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    server.exec(&format!(
+        "echo '{} ALL=(ALL) ALL' > /etc/sudoers.d/it",
+        USER
+    ));
+    for pty in &[false, true] {
+        let (_, props) = builder(PASSWORD)
+            .become_root(true)
+            .become_pty(*pty)
+            .become_password(Arc::new(|_: &str| Ok(PASSWORD.to_string())))
+            .build()
+            .unwrap();
+        let response = props
+            .run_single_blocking(server.address(), "id -u")
+            .response;
+        assert_eq!(response.error_kind, None, "{}", response.result);
+        assert_eq!(response.result.trim(), "0");
+    }
+
+    let (_, props) = builder(PASSWORD)
+        .become_root(true)
+        .become_password(Arc::new(|_: &str| Ok("wrong".to_string())))
+        .build()
+        .unwrap();
+    let response = props
+        .run_single_blocking(server.address(), "id -u")
+        .response;
+    assert!(!response.status);
+    assert_ne!(response.result.trim(), "0");
+}
+
+#[test]
+fn command_runs_on_the_requested_pty() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let (_, props) = builder(PASSWORD)
+        .request_pty(PtyRequest {
+            term: "vt100".to_string(),
+            width: 132,
+            height: 50,
+        })
+        .strip_ansi(true)
+        .build()
+        .unwrap();
+    let response = props
+        .run_single_blocking(
+            server.address(),
+            "test -t 1 && echo $TERM $(stty size) && printf '\\033[31mred\\033[0m\\n'",
+        )
+        .response;
+    assert_eq!(response.error_kind, None, "{}", response.result);
+    assert_eq!(response.result, "vt100 50 132\r\nred\r\n");
+}
+
+#[test]
+fn commands_share_one_session() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let (_, props) = builder(PASSWORD).build().unwrap();
+    let commands = vec![
+        "echo one".to_string(),
+        "false".to_string(),
+        "echo $((1 + 1))".to_string(),
+    ];
+    let by_host = props
+        .run_commands(vec![server.address()], commands)
+        .unwrap();
+    assert_eq!(by_host.len(), 1);
+    let responses = &by_host[0];
+    let exit_codes: Vec<Option<i32>> = responses.iter().map(|r| r.exit_code).collect();
+    assert_eq!(exit_codes, vec![Some(0), Some(1), Some(0)]);
+    assert_eq!(responses[0].result, "one\n");
+    assert_eq!(responses[2].result, "2\n");
+    let local_addrs: Vec<_> = responses
+        .iter()
+        .map(|r| r.connection.as_ref().unwrap().local_addr)
+        .collect();
+    assert!(local_addrs.iter().all(|addr| *addr == local_addrs[0]));
+}
+
+#[test]
+fn pooled_session_is_reused_by_the_next_run() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let pool =
+        Arc::new(SessionPool::new(Duration::from_secs(60), Duration::from_secs(15)).unwrap());
+    let (rx, props) = builder(PASSWORD)
+        .session_pool(pool.clone())
+        .build()
+        .unwrap();
+    for _ in 0..2 {
+        props
+            .parallel_ssh_process(vec![(server.address(), "echo ok")])
+            .unwrap();
+    }
+    let responses: Vec<Response> = rx.try_iter().collect();
+    assert_eq!(responses.len(), 2);
+    for response in &responses {
+        assert_eq!(response.error_kind, None, "{}", response.result);
+        assert_eq!(response.result, "ok\n");
+    }
+    let connections: Vec<&ConnectionInfo> = responses
+        .iter()
+        .map(|r| r.connection.as_ref().unwrap())
+        .collect();
+    assert!(!connections[0].reused && connections[1].reused);
+    assert_eq!(connections[0].local_addr, connections[1].local_addr);
+    let stats = pool.stats();
+    assert_eq!((stats.hits, stats.misses, stats.idle), (1, 1, 1));
+}
+
+/// 10 MiB, many times the SSH window and the pipe buffers of the server.
+const STRESS_BYTES: usize = 10 * 1024 * 1024;
+
+fn stress_builder() -> ParallelSshPropsBuilder {
+    let mut builder = builder(PASSWORD);
+    // A deadlocked read fails the host instead of hanging the test.
+    builder.timeouts(Timeouts {
+        read_total: Some(Duration::from_secs(120)),
+        ..Timeouts::default()
+    });
+    builder
+}
+
+#[test]
+fn ten_megabytes_of_stderr_before_stdout() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    let command = format!(
+        "head -c {} /dev/zero | tr '\\0' e >&2; echo done",
+        STRESS_BYTES
+    );
+    let (_, props) = stress_builder().build().unwrap();
+    let response = props
+        .run_single_blocking(server.address(), command.as_str())
+        .response;
+    assert_eq!(response.error_kind, None, "{}", response.result);
+    assert_eq!(response.result, "done\n");
+    assert_eq!(response.stderr.len(), STRESS_BYTES);
+    assert!(response.stderr.bytes().all(|b| b == b'e'));
+}
+
+#[test]
+fn interleaved_streams_arrive_whole_in_order() {
+    let server = match TestSshServer::spawn() {
+        Some(server) => server,
+        None => return,
+    };
+    // Alternating 64 KiB blocks, so each stream stalls while the other is written.
+    let command = format!(
+        "i=0; while [ $i -lt {} ]; do \
+         head -c 65536 /dev/zero | tr '\\0' o; \
+         head -c 65536 /dev/zero | tr '\\0' e >&2; \
+         i=$((i + 1)); done",
+        STRESS_BYTES / 65536
+    );
+    let (_, events, props) = stress_builder().build_with_events().unwrap();
+    let response = props
+        .run_single_blocking(server.address(), command.as_str())
+        .response;
+    assert_eq!(response.error_kind, None, "{}", response.result);
+    assert_eq!(response.result.len(), STRESS_BYTES);
+    assert!(response.result.bytes().all(|b| b == b'o'));
+    assert_eq!(response.stderr.len(), STRESS_BYTES);
+    assert!(response.stderr.bytes().all(|b| b == b'e'));
+
+    // The streamed chunks went through the same loop as the buffered output.
+    let (mut stdout, mut stderr) = (0, 0);
+    for event in events.try_iter() {
+        if let RunEvent::OutputChunk {
+            stderr: is_stderr,
+            data,
+            ..
+        } = event
+        {
+            let expected = if is_stderr { b'e' } else { b'o' };
+            assert!(data.iter().all(|&b| b == expected));
+            if is_stderr {
+                stderr += data.len();
+            } else {
+                stdout += data.len();
+            }
+        }
+    }
+    assert_eq!((stdout, stderr), (STRESS_BYTES, STRESS_BYTES));
+}
@@ -0,0 +1,56 @@
+//! The console table of results.
+
+mod common;
+
+use ansible_rs::misc::SortOrder;
+use ansible_rs::prelude::*;
+use ansible_rs::table::write_table;
+use common::unreachable_hosts;
+
+/// Responses of hosts nothing listens on, failing right away.
+fn failed_responses(n: u16) -> Vec<Response> {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    props.parallel_ssh_process(unreachable_hosts(n)).unwrap();
+    rx.try_iter().collect()
+}
+
+fn render(data: &[Response], sort: SortOrder, colors: bool) -> String {
+    let mut out = Vec::new();
+    write_table(&mut out, data, sort, true, colors).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn zero_results_print_a_note() {
+    assert_eq!(render(&[], SortOrder::Hostname, true), "No results\n");
+    assert_eq!(render(&[], SortOrder::DurationDesc, false), "No results\n");
+}
+
+#[test]
+fn very_long_hostnames_are_cut() {
+    let mut data = failed_responses(2);
+    data[0].hostname = "h".repeat(10_000);
+    data[1].hostname = "ü".repeat(5_000);
+    for sort in &[
+        SortOrder::None,
+        SortOrder::Hostname,
+        SortOrder::DurationDesc,
+        SortOrder::FailuresFirst,
+    ] {
+        let table = render(&data, *sort, false);
+        let rows: Vec<&str> = table.lines().skip(1).take(2).collect();
+        assert_eq!(rows.len(), 2, "{}", table);
+        for row in rows {
+            let host = row.split_whitespace().next().unwrap();
+            assert_eq!(host.chars().count(), 40, "{}", row);
+            assert!(host.ends_with('…'), "{}", row);
+        }
+    }
+}
+
+#[test]
+fn colors_only_when_asked() {
+    let data = failed_responses(1);
+    assert!(!render(&data, SortOrder::None, false).contains('\u{1b}'));
+    assert!(render(&data, SortOrder::None, true).contains("\u{1b}[31m"));
+}
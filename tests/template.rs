@@ -0,0 +1,105 @@
+//! Commands rendered against the vars of their host.
+
+use ansible_rs::prelude::*;
+use ansible_rs::template::render_template;
+use std::collections::BTreeMap;
+use std::net::TcpListener;
+
+fn vars(pairs: &[(&str, &str)]) -> BTreeMap<String, String> {
+    pairs
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect()
+}
+
+#[test]
+fn vars_are_rendered_as_shell_words() {
+    let vars = vars(&[("service", "nginx"), ("message", "it's done; rm -rf /")]);
+    assert_eq!(
+        render_template("systemctl restart {{ service }}", &vars).unwrap(),
+        "systemctl restart nginx"
+    );
+    assert_eq!(
+        render_template("echo {{message}}", &vars).unwrap(),
+        "echo 'it'\\''s done; rm -rf /'"
+    );
+    assert_eq!(
+        render_template("echo {{ message | raw }}", &vars).unwrap(),
+        "echo it's done; rm -rf /"
+    );
+}
+
+#[test]
+fn other_braces_are_left_alone() {
+    let command = "docker ps --format '{{.Names}}' && cat {{facts.os.id}} {{";
+    assert_eq!(render_template(command, &BTreeMap::new()).unwrap(), command);
+}
+
+#[test]
+fn missing_vars_and_unknown_filters_are_errors() {
+    let vars = vars(&[("service", "nginx")]);
+    let error = render_template("restart {{ unit }}", &vars).unwrap_err();
+    assert!(error.contains("unit"), "{}", error);
+    assert!(render_template("restart {{ service | upper }}", &vars).is_err());
+}
+
+#[test]
+fn host_without_the_var_fails_before_connecting() {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    // Passes the port probe, and would fail the handshake if the host got that far.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = listener.local_addr().unwrap();
+    props
+        .parallel_ssh_templated(vec![(host, BTreeMap::new())], "restart {{ service }}")
+        .unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.error_kind, Some(ErrorKind::Template));
+    assert_eq!(response.attempts, 1);
+}
+
+#[test]
+fn assigned_args_are_passed_on_unrendered() {
+    let (rx, props) = ParallelSshPropsBuilder::default()
+        .templated(true)
+        .arg_assigner(ArgAssigner::custom(|_, _| vec!["{{x}}".to_string()]))
+        .build()
+        .unwrap();
+    let local = HostOptions {
+        connection: Some(Connection::Local),
+        vars: vars(&[("x", "1")]),
+        ..HostOptions::default()
+    };
+    props
+        .parallel_ssh_process_with_options(vec![("10.255.255.1:22", "printf %s {{ x }}-", local)])
+        .unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.error_kind, None, "{}", response.result);
+    assert_eq!(response.result, "1-{{x}}");
+}
+
+#[test]
+fn plan_has_the_rendered_command() {
+    let (_, props) = ParallelSshPropsBuilder::default()
+        .templated(true)
+        .build()
+        .unwrap();
+    let host = |dir: Option<&str>| HostOptions {
+        vars: dir.map_or_else(BTreeMap::new, |dir| vars(&[("dir", dir)])),
+        ..HostOptions::default()
+    };
+    let plan = props.plan(vec![
+        ("10.0.0.1:22", "rm -rf /{{ dir }}", host(Some("srv/app"))),
+        ("10.0.0.2:22", "rm -rf /{{ dir }}", host(Some(""))),
+        ("10.0.0.3:22", "rm -rf /{{ dir }}", host(None)),
+    ]);
+    assert_eq!(plan.hosts[0].given, "rm -rf /srv/app");
+    assert_eq!(plan.hosts[1].given, "rm -rf /''");
+    assert!(plan.hosts[2].render_error.as_ref().unwrap().contains("dir"));
+    assert_eq!(plan.hosts[2].given, "rm -rf /{{ dir }}");
+
+    let report = CommandLint::default().check_plan(&plan);
+    assert_eq!(
+        report.findings.get("rm-root"),
+        Some(&vec!["10.0.0.2:22".to_string()])
+    );
+}
@@ -0,0 +1,86 @@
+//! Sub-second probe and connect limits against a port which never accepts.
+
+use ansible_rs::prelude::*;
+use ansible_rs::socket;
+use socket2::{Domain, Protocol, SockAddr, Socket, Type};
+use std::io;
+use std::net::{SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+
+const LIMIT: Duration = Duration::from_millis(250);
+
+/// Listener whose backlog is full, so further connects hang until their limit. Keeps the
+/// connection filling the backlog alive with it.
+struct Blackhole {
+    _listener: Socket,
+    _filler: TcpStream,
+    address: SocketAddr,
+}
+
+impl Blackhole {
+    fn new() -> Blackhole {
+        let listener = Socket::new(Domain::ipv4(), Type::stream(), Some(Protocol::tcp())).unwrap();
+        listener
+            .bind(&SockAddr::from(
+                "127.0.0.1:0".parse::<SocketAddr>().unwrap(),
+            ))
+            .unwrap();
+        listener.listen(0).unwrap();
+        let address = listener.local_addr().unwrap().as_inet().unwrap().into();
+        let filler = TcpStream::connect(address).unwrap();
+        Blackhole {
+            _listener: listener,
+            _filler: filler,
+            address,
+        }
+    }
+}
+
+#[test]
+fn connect_stops_at_a_sub_second_limit() {
+    let blackhole = Blackhole::new();
+    let start = Instant::now();
+    let err = socket::connect(&blackhole.address, None, Some(LIMIT), None).unwrap_err();
+    let elapsed = start.elapsed();
+    assert_eq!(err.kind(), io::ErrorKind::TimedOut);
+    assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(900), "{:?}", elapsed);
+}
+
+#[test]
+fn probe_stops_at_a_sub_second_limit() {
+    let blackhole = Blackhole::new();
+    let mut builder = ParallelSshPropsBuilder::default();
+    let (_, props) = builder
+        .user("nobody".to_string())
+        .timeout_socket(LIMIT)
+        .build()
+        .unwrap();
+    let detailed = props.run_single_blocking(blackhole.address, "true");
+    assert_eq!(detailed.response.error_kind, Some(ErrorKind::TcpTimeout));
+    let probe = &detailed.steps[0];
+    assert_eq!(probe.step, Step::Precheck);
+    assert_eq!(probe.limit, Some(LIMIT));
+    assert!(probe.duration >= Duration::from_millis(200), "{:?}", probe);
+    assert!(probe.duration < Duration::from_millis(900), "{:?}", probe);
+}
+
+#[test]
+fn zero_limits_are_rejected() {
+    let mut builder = ParallelSshPropsBuilder::default();
+    assert!(builder
+        .timeout_socket(Duration::from_secs(0))
+        .build()
+        .is_err());
+    let mut builder = ParallelSshPropsBuilder::default();
+    let zero_connect = Timeouts {
+        connect: Some(Duration::from_secs(0)),
+        ..Timeouts::default()
+    };
+    assert!(builder.timeouts(zero_connect).build().is_err());
+    let short_connect = Timeouts {
+        connect: Some(LIMIT),
+        ..Timeouts::default()
+    };
+    assert_eq!(short_connect.validate(), Ok(()));
+}
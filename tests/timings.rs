@@ -0,0 +1,43 @@
+//! Phases of a host timed in `Response::timings`.
+
+use ansible_rs::prelude::*;
+use std::net::{SocketAddr, TcpListener};
+use std::thread;
+
+#[test]
+fn refused_host_is_timed_up_to_its_probe() {
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    // Nothing listens on port 1.
+    let host: SocketAddr = "127.0.0.1:1".parse().unwrap();
+    props.parallel_ssh_process(vec![(host, "true")]).unwrap();
+    let response = rx.recv().unwrap();
+    assert_eq!(response.error_kind, Some(ErrorKind::TcpConnect));
+    let timings = response.timings;
+    assert!(timings.probe.is_some());
+    assert_eq!(timings.dns, None);
+    assert_eq!(timings.tcp_connect, None);
+    assert_eq!(timings.handshake, None);
+}
+
+#[test]
+fn failed_handshake_is_timed() {
+    // Accepts connections and closes them without saying a word.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let host = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            drop(stream);
+        }
+    });
+    let (rx, props) = ParallelSshPropsBuilder::default().build().unwrap();
+    props.parallel_ssh_process(vec![(host, "true")]).unwrap();
+    let response = rx.recv().unwrap();
+    assert!(!response.status);
+    let timings = response.timings;
+    assert!(timings.probe.is_some());
+    assert!(timings.tcp_connect.is_some());
+    assert!(timings.handshake.is_some());
+    assert_eq!(timings.auth, None);
+    assert_eq!(timings.exec, None);
+    assert_eq!(timings.read, None);
+}
@@ -0,0 +1,128 @@
+//! Working directories reach the shell as one literal path, however they are spelled.
+
+use ansible_rs::prelude::*;
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Directory of its own under the temp dir, for `name`.
+fn base(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!(
+        "ansible-rs-workdir-{}-{}",
+        name,
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir.canonicalize().unwrap()
+}
+
+/// Runs `command` in `dir` the way the login shell of a host would.
+fn sh(command: &str, dir: &PathBuf) -> (bool, String) {
+    let out = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(dir)
+        .output()
+        .unwrap();
+    (out.status.success(), String::from_utf8(out.stdout).unwrap())
+}
+
+const NASTY: [&str; 9] = [
+    "with space",
+    "it's",
+    "'; touch pwned; '",
+    "$(touch pwned)",
+    "`touch pwned`",
+    "new\nline",
+    "-starts-with-dash",
+    "Größe/日本語/🚀",
+    "back\\slash",
+];
+
+#[test]
+fn posix_workdirs_are_created_and_entered_literally() {
+    let base = base("posix");
+    for shell in &[RemoteShell::Posix, RemoteShell::Raw] {
+        for dir in NASTY.iter() {
+            let command = shell.wrap(&shell.in_workdir("pwd", dir, true));
+            let (ok, pwd) = sh(&command, &base);
+            assert!(ok, "{:?}: {}", dir, command);
+            assert_eq!(pwd, format!("{}/{}\n", base.display(), dir), "{}", command);
+        }
+    }
+    assert!(!base.join("pwned").exists());
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+fn separators_and_dot_dot_resolve_like_cd() {
+    let base = base("dots");
+    let command = RemoteShell::Posix.in_workdir("pwd", "a/b/../c/./d", true);
+    let (ok, pwd) = sh(&command, &base);
+    assert!(ok, "{}", command);
+    assert_eq!(pwd, format!("{}/a/c/d\n", base.display()));
+    assert!(base.join("a/b").is_dir());
+
+    let command = RemoteShell::Posix.in_workdir("pwd", "a/..", false);
+    let (ok, pwd) = sh(&command, &base);
+    assert!(ok, "{}", command);
+    assert_eq!(pwd, format!("{}\n", base.display()));
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+fn missing_workdir_fails_without_running_the_command() {
+    let base = base("missing");
+    let command = RemoteShell::Posix.in_workdir("touch ran", "no/such dir", false);
+    let (ok, _) = sh(&command, &base);
+    assert!(!ok);
+    assert!(!base.join("ran").exists());
+    fs::remove_dir_all(&base).unwrap();
+}
+
+#[test]
+fn cmd_workdir_stays_one_quoted_path() {
+    for dir in &[
+        r"C:\Program Files\app",
+        r"..\..\Windows",
+        "D:/mixed/separators",
+        "Größe\\日本語",
+        "\"& del /q *",
+        "a\r\nb",
+        "x|y<z>w?*",
+    ] {
+        let command = RemoteShell::Cmd.in_workdir("dir", dir, false);
+        let quoted = command
+            .strip_prefix("cd /d \"")
+            .and_then(|rest| rest.strip_suffix("\" && dir"))
+            .unwrap_or_else(|| panic!("{:?}", command));
+        assert!(
+            !quoted.contains(|c: char| "\"<>|?*".contains(c) || c.is_control()),
+            "{:?}",
+            quoted
+        );
+    }
+    let command = RemoteShell::Cmd.in_workdir("dir", r"..\Größe\app", false);
+    assert_eq!(command, "cd /d \"..\\Größe\\app\" && dir");
+}
+
+#[test]
+fn powershell_workdir_is_a_literal_string() {
+    for dir in &[
+        r"..\..\Windows",
+        "Größe\\日本語",
+        "it's",
+        "$(Remove-Item x)",
+        "a\nb",
+    ] {
+        let command = RemoteShell::PowerShell.in_workdir("Get-Location", dir, false);
+        let literal = command
+            .strip_prefix("Set-Location -LiteralPath '")
+            .and_then(|rest| rest.strip_suffix("' -ErrorAction Stop; Get-Location"))
+            .unwrap_or_else(|| panic!("{:?}", command));
+        // Inside single quotes only a doubled quote is special.
+        assert_eq!(literal.replace("''", "'"), *dir);
+        assert!(!literal.replace("''", "").contains('\''), "{:?}", literal);
+    }
+}